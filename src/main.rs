@@ -1,8 +1,9 @@
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser};
 use colored::*;
 use csv::ReaderBuilder;
 use reqwest::Client;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use serde_json::Value;
 use std::collections::HashMap;
@@ -12,7 +13,8 @@ use std::fs::read_to_string;
 use std::fs::{create_dir_all, OpenOptions};
 use std::io::Cursor;
 use std::io::{BufWriter, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use ta::indicators::{BollingerBands, MovingAverageConvergenceDivergence, RelativeStrengthIndex};
 use ta::Next;
 use zeroize::Zeroizing; // ← 追加
@@ -21,7 +23,13 @@ use chrono::TimeZone;
 use chrono_tz::Tz;
 
 type BuildCfgResult = Result<(Config, String, HashMap<String, String>), Box<dyn std::error::Error>>;
-const EMA_EQ_EPS: f64 = 0.01; // 短期-長期の絶対差が±0.01未満なら「同値圏」
+type ReplayResult<T> = Result<(Vec<(BacktestBar, T)>, usize), Box<dyn std::error::Error>>;
+// 価格水準に依存しない乖離判定（%）：EMA/SMA/一目/VWAP 共通
+const GAP_MILD_PCT: f64 = 0.5; // これを超えたら ±1
+const GAP_STRONG_PCT: f64 = 2.0; // これを超えたら ±2
+// MACD-Signal 差（終値比%）の異常乖離・大幅乖離の目安
+const MACD_DIFF_EXTREME_PCT: f64 = 3.0;
+const MACD_DIFF_WIDE_PCT: f64 = 1.0;
                               //const NEUTRAL_DEADBAND: f64 = 0.05; // 中立の揺れ幅（±5% 未満なら見送り/様子見）
 
 /// コマンドライン引数の構造定義
@@ -31,7 +39,7 @@ struct Args {
     #[arg(
         short = 't',
         long,
-        required_unless_present = "show_log_header",
+        required_unless_present_any = ["show_log_header", "validate_signal_rules", "list_presets", "watchlist", "paper"],
         help = "Specify ticker symbol (e.g., AAPL, MSFT, 7203.T). Note: If the ticker contains special characters (e.g., '&'), enclose it in quotes. Example: 'S&P500'"
     )]
     ticker: Option<String>,
@@ -60,14 +68,14 @@ struct Args {
     sell_rsi: f64,
     #[arg(
         long,
-        default_value_t = 2.0,
-        help = "Threshold for MACD small difference (default: 2)"
+        default_value_t = 0.1,
+        help = "Threshold for MACD small difference in % of close (default: 0.1)"
     )]
     macd_diff_low: f64,
     #[arg(
         long,
-        default_value_t = 10.0,
-        help = "Threshold for MACD medium difference (default: 10)"
+        default_value_t = 0.5,
+        help = "Threshold for MACD medium difference in % of close (default: 0.5)"
     )]
     macd_diff_mid: f64,
     #[arg(short = 'O', long, help = "Skip LLM access completely")]
@@ -129,6 +137,25 @@ struct Args {
     vwap: bool,
     #[arg(long, help = "Enable Ichiomku analysis")]
     ichimoku: bool,
+    #[arg(long, help = "Enable candlestick pattern recognition")]
+    candlestick: bool,
+    #[arg(long, help = "Enable swing-based support/resistance analysis")]
+    support_resistance: bool,
+    #[arg(long, help = "Enable relative strength / beta / correlation against a benchmark")]
+    relative_strength: bool,
+    #[arg(
+        long,
+        help = "Benchmark ticker for relative strength (default: 1306.T for JP tickers, SPY otherwise)"
+    )]
+    benchmark: Option<String>,
+    #[arg(long, help = "Path to user-defined indicator expressions (JSON)")]
+    custom_indicators: Option<String>,
+    #[arg(long, help = "Path to external indicator plugin definitions (JSON)")]
+    plugins: Option<String>,
+    #[arg(long, help = "Path to basic MACD/RSI signal scoring rules (JSON). Default: built-in rules")]
+    signal_rules: Option<String>,
+    #[arg(long, value_name = "PATH", help = "Validate a signal rules file (JSON) and exit")]
+    validate_signal_rules: Option<String>,
     #[arg(
         long,
         default_value_t = 8.0,
@@ -195,11 +222,104 @@ struct Args {
         help = "Weight multiplier for Ichimoku score (0.5-3.0)"
     )]
     weight_ichimoku: f64,
+    #[arg(
+        long,
+        default_value_t = 1.0,
+        help = "Weight multiplier for Candlestick pattern score (0.5-3.0)"
+    )]
+    weight_candlestick: f64,
+    #[arg(
+        long,
+        default_value_t = 1.0,
+        help = "Weight multiplier for Support/Resistance score (0.5-3.0)"
+    )]
+    weight_support_resistance: f64,
+    #[arg(
+        long,
+        default_value_t = 1.0,
+        help = "Weight multiplier for Relative Strength score (0.5-3.0)"
+    )]
+    weight_relative_strength: f64,
 
     #[arg(long, value_parser = ["buyer","seller","holder"], default_value = "holder",
       help = "視点を選択: buyer|seller|holder（既定: holder）")]
     stance: String,
 
+    #[arg(long, help = "Strategy preset name (built-in: swing, long-term, contrarian; or from --presets-file)")]
+    preset: Option<String>,
+    #[arg(long, help = "Path to user-defined strategy presets (JSON)")]
+    presets_file: Option<String>,
+    #[arg(long, help = "List available strategy presets and exit")]
+    list_presets: bool,
+    #[arg(long, help = "Adjust weights by detected market regime (trending / ranging)")]
+    regime_weighting: bool,
+    #[arg(long, default_value_t = 0,
+      help = "Smooth the score ratio with an EMA over the last N bars (0 = off)")]
+    smoothing_bars: usize,
+    #[arg(long,
+      help = "Hysteresis margin (score ratio, 0-0.2) before the judgement changes band (0 = off, overrides SCORE_HYSTERESIS)")]
+    hysteresis: Option<f64>,
+
+    #[arg(long, value_parser = ["exclude","neutral","fail"], default_value = "exclude",
+      help = "How to treat indicators whose score could not be computed: exclude|neutral|fail")]
+    missing_score_policy: String,
+
+    #[arg(long, help = "Replay the scoring model bar by bar over a long history and report trading results")]
+    backtest: bool,
+    #[arg(long, value_parser = ["6mo","1y","2y","5y","10y","max"], default_value = "2y",
+      help = "Price history range for --backtest / --optimize / --hit-rate")]
+    bt_range: String,
+    #[arg(long, value_parser = ["threshold","stance"], default_value = "threshold",
+      help = "Backtest entry/exit rule: threshold (score ratio) | stance (stance judgement)")]
+    bt_rule: String,
+    #[arg(long, default_value_t = 0.2, allow_hyphen_values = true,
+      help = "Backtest: enter long when the score ratio is at or above this value (threshold rule)")]
+    bt_entry: f64,
+    #[arg(long, default_value_t = -0.2, allow_hyphen_values = true,
+      help = "Backtest: exit when the score ratio is at or below this value (threshold rule)")]
+    bt_exit: f64,
+    #[arg(long, default_value_t = 0.1, help = "Backtest: fee per side (% of traded value)")]
+    bt_fee_pct: f64,
+    #[arg(long, default_value_t = 0.05, help = "Backtest: slippage per side (% of price)")]
+    bt_slippage_pct: f64,
+    #[arg(long, help = "Search weights and RSI/Bollinger thresholds with walk-forward validation (uses the --bt-* settings)")]
+    optimize: bool,
+    #[arg(long, value_parser = ["random","grid"], default_value = "random",
+      help = "Optimization search method: random | grid")]
+    opt_method: String,
+    #[arg(long, default_value_t = 100, help = "Optimization: number of random candidates (random search)")]
+    opt_trials: usize,
+    #[arg(long, default_value_t = 3, help = "Optimization: number of walk-forward folds (1-10)")]
+    opt_folds: usize,
+    #[arg(long, value_parser = ["return","return-dd"], default_value = "return",
+      help = "Optimization objective on the training window: return | return-dd (return / max drawdown)")]
+    opt_metric: String,
+    #[arg(long, default_value_t = 42, help = "Optimization: random seed (same seed, same candidates)")]
+    opt_seed: u64,
+    #[arg(long, value_parser = ["env","preset"], default_value = "env",
+      help = "Optimization result format: env (tickwise.env fragment) | preset (presets file JSON)")]
+    opt_output: String,
+    #[arg(long, help = "Report forward returns (1/5/20 bars) and hit rates for each indicator score level over history")]
+    hit_rate: bool,
+    #[arg(long, help = "Grade past logged judgements against the realized 1-week and 1-month returns")]
+    track_outcomes: bool,
+    #[arg(long, help = "Watchlist file: one ticker per line (# for comments). Used instead of --ticker")]
+    watchlist: Option<String>,
+    #[arg(long, default_value_t = 4,
+      help = "Batch mode (--watchlist or -t A,B,...): maximum number of concurrent price/news fetches (1-16)")]
+    concurrency: usize,
+    #[arg(long, value_parser = ["auto","buy","sell","report","reset"],
+      help = "Paper trading: auto (trade on the judgement) | buy | sell (single ticker only) | report (ledger report) | reset (start a new ledger)")]
+    paper: Option<String>,
+    #[arg(long, help = "Paper trading ledger file (JSON; default: <log_dir>/paper_ledger.json)")]
+    paper_file: Option<String>,
+    #[arg(long, default_value_t = 1_000_000.0, help = "Paper trading: starting cash for a new ledger")]
+    paper_cash: f64,
+    #[arg(long, default_value_t = 20.0, help = "Paper trading: position size per buy (% of starting cash, 1-100)")]
+    paper_size_pct: f64,
+    #[arg(long, help = "Paper trading: number of shares for --paper buy/sell (default: sized by --paper-size-pct / whole position)")]
+    paper_units: Option<u64>,
+
     #[arg(
         long,
         help = "Specify your Brave API key (if not using environment variable)"
@@ -266,6 +386,15 @@ struct Args {
         help = "Output log (CSV or JSON) to standard output instead of file"
     )]
     stdout_log: bool,
+    #[arg(long, value_parser = ["csv", "json"],
+      help = "Also write per-bar history of every enabled indicator and score (csv|json)")]
+    series_output: Option<String>,
+    #[arg(
+        long,
+        default_value_t = 7,
+        help = "Compare scores with the logged run N days ago as well as the previous run (0 = off)"
+    )]
+    history_days: usize,
     #[arg(
         long,
         help = "Show only CSV header row based on current options and exit"
@@ -281,6 +410,9 @@ struct Args {
         help = "Display parsed command-line arguments (for debugging purposes)"
     )]
     debug_args: bool,
+    // コマンドラインで明示した引数のID（既定値と同じ値でもプリセットより優先する）
+    #[arg(skip)]
+    cli_explicit: Vec<String>,
 }
 /// 拡張テクニカル指標の列挙型（ユーザ指定をベクタで受ける）
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -294,6 +426,11 @@ enum ExtensionIndicator {
     Fibonacci,
     Vwap,
     Ichimoku,
+    Candlestick,
+    SupportResistance,
+    RelativeStrength,
+    Custom(usize), // ユーザー定義式（Config.custom_indicators の添字）
+    Plugin(usize), // 外部プロセス（Config.plugins の添字）
 }
 /// 拡張指標のカテゴリ分類
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -302,6 +439,48 @@ enum IndicatorCategory {
     Oscillator,    // オシレーター系指標
     Volatility,    // ボラティリティ系指標
     SupportResist, // サポート・レジスタンスなど補助指標
+    Candlestick,   // ローソク足パターン
+    Custom,        // ユーザー定義式
+    Plugin,        // 外部プロセスのプラグイン
+}
+
+impl IndicatorCategory {
+    // 表示・集計の順序
+    const ALL: [IndicatorCategory; 7] = [
+        IndicatorCategory::Trend,
+        IndicatorCategory::Oscillator,
+        IndicatorCategory::Volatility,
+        IndicatorCategory::SupportResist,
+        IndicatorCategory::Candlestick,
+        IndicatorCategory::Custom,
+        IndicatorCategory::Plugin,
+    ];
+
+    /// ログ用のキー（CSV列名・JSONキー）
+    fn key(&self) -> &'static str {
+        match self {
+            IndicatorCategory::Trend => "trend",
+            IndicatorCategory::Oscillator => "oscillator",
+            IndicatorCategory::Volatility => "volatility",
+            IndicatorCategory::SupportResist => "support_resist",
+            IndicatorCategory::Candlestick => "candlestick",
+            IndicatorCategory::Custom => "custom",
+            IndicatorCategory::Plugin => "plugin",
+        }
+    }
+
+    /// 内訳表示用の名称
+    fn label(&self) -> &'static str {
+        match self {
+            IndicatorCategory::Trend => "トレンド系",
+            IndicatorCategory::Oscillator => "オシレーター系",
+            IndicatorCategory::Volatility => "ボラティリティ系",
+            IndicatorCategory::SupportResist => "サポレジ系",
+            IndicatorCategory::Candlestick => "ローソク足",
+            IndicatorCategory::Custom => "カスタム",
+            IndicatorCategory::Plugin => "プラグイン",
+        }
+    }
 }
 
 impl std::str::FromStr for ExtensionIndicator {
@@ -318,6 +497,9 @@ impl std::str::FromStr for ExtensionIndicator {
             "fibonacci" => Ok(ExtensionIndicator::Fibonacci),
             "vwap" => Ok(ExtensionIndicator::Vwap),
             "ichimoku" => Ok(ExtensionIndicator::Ichimoku),
+            "candlestick" => Ok(ExtensionIndicator::Candlestick),
+            "support_resistance" | "sr" => Ok(ExtensionIndicator::SupportResistance),
+            "relative_strength" | "rs" => Ok(ExtensionIndicator::RelativeStrength),
 
             _ => Err(format!("❌ 未知の指標: {}", s)),
         }
@@ -325,13 +507,35 @@ impl std::str::FromStr for ExtensionIndicator {
 }
 
 impl ExtensionIndicator {
+    /// プリセット・環境変数で使う名前（組み込み指標のみ）
+    fn key(&self) -> Option<&'static str> {
+        match self {
+            ExtensionIndicator::Ema => Some("ema"),
+            ExtensionIndicator::Sma => Some("sma"),
+            ExtensionIndicator::Bollinger => Some("bollinger"),
+            ExtensionIndicator::Roc => Some("roc"),
+            ExtensionIndicator::Adx => Some("adx"),
+            ExtensionIndicator::Stochastics => Some("stochastics"),
+            ExtensionIndicator::Fibonacci => Some("fibonacci"),
+            ExtensionIndicator::Vwap => Some("vwap"),
+            ExtensionIndicator::Ichimoku => Some("ichimoku"),
+            ExtensionIndicator::Candlestick => Some("candlestick"),
+            ExtensionIndicator::SupportResistance => Some("support_resistance"),
+            ExtensionIndicator::RelativeStrength => Some("relative_strength"),
+            ExtensionIndicator::Custom(_) | ExtensionIndicator::Plugin(_) => None,
+        }
+    }
+
     /// 拡張指標ごとのカテゴリを返す
     fn category(&self) -> IndicatorCategory {
         match self {
+            ExtensionIndicator::Custom(_) => IndicatorCategory::Custom,
+            ExtensionIndicator::Plugin(_) => IndicatorCategory::Plugin,
             ind if TREND_INDICATORS.contains(ind) => IndicatorCategory::Trend,
             ind if OSCILLATOR_INDICATORS.contains(ind) => IndicatorCategory::Oscillator,
             ind if VOLATILITY_INDICATORS.contains(ind) => IndicatorCategory::Volatility,
             ind if SUPPORT_INDICATORS.contains(ind) => IndicatorCategory::SupportResist,
+            ind if CANDLESTICK_INDICATORS.contains(ind) => IndicatorCategory::Candlestick,
             _ => unreachable!("未分類のExtensionIndicatorが存在します"),
         }
     }
@@ -345,11 +549,95 @@ const TREND_INDICATORS: &[ExtensionIndicator] = &[
     ExtensionIndicator::Roc,
     ExtensionIndicator::Ichimoku,
     ExtensionIndicator::Vwap,
+    ExtensionIndicator::RelativeStrength,
 ];
 
 const OSCILLATOR_INDICATORS: &[ExtensionIndicator] = &[ExtensionIndicator::Stochastics];
 const VOLATILITY_INDICATORS: &[ExtensionIndicator] = &[ExtensionIndicator::Bollinger];
-const SUPPORT_INDICATORS: &[ExtensionIndicator] = &[ExtensionIndicator::Fibonacci];
+const SUPPORT_INDICATORS: &[ExtensionIndicator] = &[
+    ExtensionIndicator::Fibonacci,
+    ExtensionIndicator::SupportResistance,
+];
+const CANDLESTICK_INDICATORS: &[ExtensionIndicator] = &[ExtensionIndicator::Candlestick];
+
+// ローソク足パターンを探索する直近本数（この本数内で完成したパターンのみ採用）
+const CANDLE_LOOKBACK: usize = 3;
+
+// スイングピボット判定の左右本数（前後3本より高い/安い足をピボットとする）
+const SWING_PIVOT_SPAN: usize = 3;
+// ピボットを同一水準とみなす価格差（%）
+const SR_CLUSTER_TOLERANCE_PCT: f64 = 1.5;
+// 水準に「接近」とみなす終値からの距離（%）
+const SR_NEAR_LEVEL_PCT: f64 = 2.0;
+// フィボナッチのアンカーとみなすスイングの最小値幅（%）
+const FIB_MIN_SWING_PCT: f64 = 5.0;
+
+// クロスの鮮度判定：この本数以内は「鮮度の高いクロス」
+const CROSS_FRESH_BARS: usize = 3;
+// この本数以上経過したクロスは「成熟トレンド」
+const CROSS_STALE_BARS: usize = 20;
+
+// 相対力（対ベンチマーク）の比較期間（本）
+const RS_LOOKBACK: usize = 20;
+// ベータ・相関の算出に使う日次リターンの最大本数
+const RS_BETA_WINDOW: usize = 60;
+// 超過リターン（%）の判定幅：これを超えたら ±1 / ±2
+const RS_EXCESS_MILD_PCT: f64 = 2.0;
+const RS_EXCESS_STRONG_PCT: f64 = 5.0;
+
+// プラグイン連携のプロトコル版数（stdin の "version"）
+const PLUGIN_PROTOCOL_VERSION: u32 = 1;
+// プラグインのタイムアウト上限（ミリ秒）
+const PLUGIN_MAX_TIMEOUT_MS: u64 = 60_000;
+// プラグイン出力の最大サイズ（バイト）と説明行数
+const PLUGIN_MAX_OUTPUT_BYTES: usize = 1_000_000;
+const PLUGIN_MAX_DESCRIPTION_LINES: usize = 20;
+
+// 年率換算に使う年間営業日数
+const TRADING_DAYS_PER_YEAR: f64 = 252.0;
+// シャープ/ソルティノレシオを算出する直近本数
+const RISK_ROLLING_WINDOW: usize = 60;
+
+// 相場局面の判定：ADXがこれ以上かつ傾きが十分ならトレンド、これ未満ならレンジ候補
+const REGIME_ADX_TREND: f64 = 25.0;
+const REGIME_ADX_RANGE: f64 = 20.0;
+// 相場局面の判定：SMA20の傾き（終値比%/本、直近5本平均）がこれ以上ならトレンド方向あり
+const REGIME_SLOPE_TREND_PCT: f64 = 0.1;
+const REGIME_SLOPE_SPAN: usize = 5;
+
+// 組み込みの戦略プリセット（ユーザー定義ファイルと同じ形式。同名はユーザー定義が優先）
+const BUILTIN_PRESETS: &str = r#"[
+  {
+    "name": "swing",
+    "description": "数日〜数週間のスイングトレード向け（短期トレンド＋オシレーター重視）",
+    "extensions": ["ema", "adx", "stochastics", "bollinger", "candlestick", "support_resistance"],
+    "weights": { "basic": 1.5, "ema": 1.5, "adx": 1.0, "stochastics": 1.5, "bollinger": 1.0, "candlestick": 1.0, "support_resistance": 1.0 },
+    "buy_rsi": 35.0,
+    "sell_rsi": 65.0,
+    "stance": "holder"
+  },
+  {
+    "name": "long-term",
+    "description": "数か月単位の中長期保有向け（長期トレンド・相対力重視、局面補正あり）",
+    "extensions": ["sma", "adx", "ichimoku", "fibonacci", "relative_strength"],
+    "weights": { "basic": 1.0, "sma": 2.0, "adx": 1.0, "ichimoku": 2.0, "fibonacci": 0.5, "relative_strength": 1.5 },
+    "macd_diff_low": 0.2,
+    "macd_diff_mid": 1.0,
+    "regime_weighting": true,
+    "stance": "holder"
+  },
+  {
+    "name": "contrarian",
+    "description": "売られすぎからの反発狙いの逆張り向け（オシレーター・バンド・節目重視）",
+    "extensions": ["stochastics", "bollinger", "fibonacci", "candlestick", "support_resistance"],
+    "weights": { "basic": 2.0, "stochastics": 2.0, "bollinger": 2.0, "fibonacci": 1.0, "candlestick": 1.0, "support_resistance": 1.5 },
+    "buy_rsi": 25.0,
+    "sell_rsi": 75.0,
+    "macd_minus_ok": true,
+    "bb_bandwidth_squeeze_pct": 6.0,
+    "stance": "buyer"
+  }
+]"#;
 
 /// ユーザ視点の列挙型
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -370,6 +658,94 @@ impl std::fmt::Display for Stance {
     }
 }
 
+/// スコアを算出できなかった指標の扱い
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum MissingScorePolicy {
+    Exclude, // 分母から除外（残りの指標だけで比率を出す）
+    Neutral, // 0点として分母に含める
+    Fail,    // 総合判定を出さずにエラー終了
+}
+
+impl std::fmt::Display for MissingScorePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MissingScorePolicy::Exclude => write!(f, "除外"),
+            MissingScorePolicy::Neutral => write!(f, "中立(0点)扱い"),
+            MissingScorePolicy::Fail => write!(f, "エラー"),
+        }
+    }
+}
+
+/// バックテストの売買ルール
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum BacktestRule {
+    Threshold, // スコア比率が bt_entry 以上で買い、bt_exit 以下で手仕舞い
+    Stance,    // スタンス別の判定（買う/売る側の色）で売買
+}
+
+impl std::fmt::Display for BacktestRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BacktestRule::Threshold => write!(f, "スコア比率の閾値"),
+            BacktestRule::Stance => write!(f, "スタンス別の判定"),
+        }
+    }
+}
+
+/// 最適化の探索方法
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum OptMethod {
+    Random, // 範囲内から乱数で候補を生成
+    Grid,   // 各パラメータの代表値の全組み合わせ
+}
+
+impl std::fmt::Display for OptMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OptMethod::Random => write!(f, "ランダムサーチ"),
+            OptMethod::Grid => write!(f, "グリッドサーチ"),
+        }
+    }
+}
+
+/// 最適化で学習期間の成績を比べる指標
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum OptMetric {
+    Return,         // 総リターン
+    ReturnDrawdown, // 総リターン ÷ 最大ドローダウン
+}
+
+impl std::fmt::Display for OptMetric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OptMetric::Return => write!(f, "総リターン"),
+            OptMetric::ReturnDrawdown => write!(f, "総リターン÷最大ドローダウン"),
+        }
+    }
+}
+
+/// ペーパートレードの操作
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum PaperAction {
+    Auto,   // 今回の判定に従って建玉・手仕舞い
+    Buy,    // 明示的に買う（買い増し可）
+    Sell,   // 明示的に売る（既定は全株）
+    Report, // 台帳の報告のみ
+    Reset,  // 台帳を初期化（旧台帳は .bak に退避）
+}
+
+impl std::fmt::Display for PaperAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaperAction::Auto => write!(f, "判定に連動"),
+            PaperAction::Buy => write!(f, "手動の買い"),
+            PaperAction::Sell => write!(f, "手動の売り"),
+            PaperAction::Report => write!(f, "台帳の報告"),
+            PaperAction::Reset => write!(f, "台帳の初期化"),
+        }
+    }
+}
+
 /// 設定情報
 #[derive(Debug, Clone)]
 struct Config {
@@ -383,6 +759,35 @@ struct Config {
     bb_bandwidth_squeeze_pct: f64,
 
     stance: Stance,
+    missing_score_policy: MissingScorePolicy,
+    regime_weighting: bool, // 相場局面に応じた重み補正
+    smoothing_bars: usize,  // スコア比率のEMA平滑化の期間（0 で無効）
+    hysteresis: f64,        // 判定の帯を切り替えるのに必要な超過幅（スコア比率、0 で無効）
+    backtest: bool,         // バックテストモード（通常の分析・ニュース・LLMは行わない）
+    bt_range: String,       // バックテストに使う価格履歴の期間（Yahoo の range）
+    bt_rule: BacktestRule,
+    bt_entry: f64,          // 閾値ルールの買いエントリー（スコア比率）
+    bt_exit: f64,           // 閾値ルールの手仕舞い（スコア比率）
+    bt_fee_pct: f64,        // 片道手数料（約定金額の%）
+    bt_slippage_pct: f64,   // 片道スリッページ（価格の%）
+    optimize: bool,         // 重み・閾値の最適化モード（ウォークフォワード検証）
+    opt_method: OptMethod,
+    opt_trials: usize,      // ランダムサーチの候補数
+    opt_folds: usize,       // ウォークフォワードの分割数
+    opt_metric: OptMetric,
+    opt_seed: u64,
+    opt_output: String,     // "env"|"preset"
+    hit_rate: bool,         // 指標スコア別の先読みリターン集計モード
+    track_outcomes: bool,   // 過去ログの判定の答え合わせモード
+    watchlist: Vec<String>, // ウォッチリストの銘柄（--watchlist または -t A,B,… 指定時のみ）
+    watchlist_file: Option<String>,
+    concurrency: usize,         // 一括実行で同時に走らせる取得の上限
+    paper: Option<PaperAction>, // ペーパートレードの操作（--paper 指定時のみ）
+    paper_file: Option<String>, // 台帳ファイル（JSON、未指定なら <log_dir>/paper_ledger.json）
+    paper_cash: f64,            // 新規台帳の初期資金
+    paper_size_pct: f64,        // 1回の買いに充てる金額（初期資金の%）
+    paper_units: Option<u64>,   // 明示的な売買株数
+    preset: Option<String>, // 適用した戦略プリセット名
 
     weight_basic: f64,
     weight_ema: f64,
@@ -394,6 +799,14 @@ struct Config {
     weight_fibonacci: f64,
     weight_vwap: f64,
     weight_ichimoku: f64,
+    weight_candlestick: f64,
+    weight_support_resistance: f64,
+    weight_relative_strength: f64,
+    benchmark: String,
+    benchmark_auto: bool, // ベンチマークを取引所から自動選択した（銘柄ごとに選び直す）
+    custom_indicators: Vec<CustomIndicator>,
+    plugins: Vec<PluginSpec>,
+    signal_rules: Vec<SignalRule>,
     brave_api_key: String,
     llm_provider: String,
     openai_model: String,
@@ -408,6 +821,8 @@ struct Config {
     save_technical_log: bool,
     log_format: String,
     log_dir: String,
+    series_output: Option<String>, // 全期間の時系列出力（"csv"|"json"）
+    history_days: usize,           // 過去ログ比較で「N日前」とみなす日数（0 で過去ログ比較なし）
     silent: bool,
     stdout_log: bool,
     max_note_length: usize,
@@ -434,6 +849,9 @@ struct MarketData {
     timestamp: Option<i64>,
     #[serde(default)]
     timezone: Option<String>, // 追加: IANA TZ (exchangeTimezoneName)
+    #[serde(default)]
+    currency: Option<String>, // 取引通貨（meta.currency、例: JPY, USD）
+    open: f64,
     high: f64,
     low: f64,
     close: f64,
@@ -447,3327 +865,9972 @@ struct HardcodedInfo {
     //    query: &'static str,
 }
 
-/// テクニカル指標の分析結果を保持する構造体
-struct AnalysisResult {
-    indicator_name: String,   // 例: "基本テクニカル分析", "EMA", "SMA"
-    description: Vec<String>, // 表示用の複数行テキスト（\n区切りでOK）
-    score: f64,               // 元のスコア（-2〜+2の整数値、f64型）
+/// フィボナッチのアンカーとなるスイング（高値/安値と方向）
+#[derive(Debug, Clone)]
+struct FibonacciSwing {
+    high: f64,
+    high_date: String,
+    low: f64,
+    low_date: String,
+    uptrend: bool, // true: 安値→高値の上昇スイング / false: 高値→安値の下降スイング
 }
-// ==== 追加：最終スコアのスナップショット（唯一の真実） ====
-struct FinalScoreSnapshot {
-    total_score: f64,  // Σ(各スコア×重み)
-    total_weight: f64, // 2×Σ(有効指標の重み)  ※各指標レンジ[-2,2]
-    score_ratio: f64,  // total_score / total_weight（-1..+1）
+
+/// クロスの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CrossKind {
+    Golden, // 短期線が長期線を上抜け
+    Dead,   // 短期線が長期線を下抜け
 }
 
-/// 分析結果を保持する構造体（全出力・ログ・LLMプロンプト共通）
-#[derive(Debug)]
-struct TechnicalDataEntry {
-    ticker: String,                 // ティッカー記号（例: AAPL, MSFT, 7203.T）
-    name: String,                   // 企業名（例: NVIDIA Corp、ソフトバンク）
-    date: String,                   // データ日付（例: 2025-05-09）
-    datetime: Option<String>,       // データ日時（例: 2025-05-09T15:30:00Z）
-    timestamp: Option<i64>,         // データタイムスタンプ（UNIX時間）
-    timezone: String,               // IANA TZ (exchangeTimezoneName)
-    close: f64,                     // 終値
-    previous_close: f64,            // 前日終値
-    price_diff: f64,                // 前日比（差額）
-    price_diff_percent: f64,        // 前日比（%）
-    macd: f64,                      // MACD値
-    signal: f64,                    // MACDシグナル値
-    prev_macd: f64,                 // 前MACD値（前日比計算用）
-    prev_signal: f64,               // 前MACDシグナル値（前日比計算用）
-    rsi: f64,                       // RSI値
-    ema_short: f64,                 // EMA短期（例: 5日）
-    ema_long: f64,                  // EMA長期（例: 20日）
-    sma_short: f64,                 // SMA短期
-    sma_long: f64,                  // SMA長期
-    roc: Option<f64>,               // ROC（変化率）※拡張指標
-    adx: Option<f64>,               // ADX（トレンド強度）※拡張指標
-    stochastics_k: Option<f64>,     // ストキャスティクス %K
-    stochastics_d: Option<f64>,     // ストキャスティクス %D
-    bb_upper: f64,                  // ボリンジャーバンド上限
-    bb_lower: f64,                  // ボリンジャーバンド下限
-    bb_percent_b: f64,              // %Bインジケータ
-    bb_bandwidth: f64,              // Bandwidth(%) を 0–100 の実数で保持
-    fibo_38_2: Option<f64>,         // フィボナッチ 38.2%
-    fibo_50_0: Option<f64>,         // フィボナッチ 50.0%
-    fibo_61_8: Option<f64>,         // フィボナッチ 61.8%
-    vwap: Option<f64>,              // Vwap
-    tenkan_sen: Option<f64>,        // 一目均衡表転換線
-    kijun_sen: Option<f64>,         // 一目均衡表基準線
-    ema_score: Option<f64>,         // EMAによるスコア
-    sma_score: Option<f64>,         // SMAによるスコア
-    roc_score: Option<f64>,         // ROCによるスコア
-    adx_score: Option<f64>,         // ADXによるスコア
-    stochastics_score: Option<f64>, // ストキャスティクスによるスコア
-    bollinger_score: Option<f64>,   // ボリンジャーバンドによるスコア
-    fibonacci_score: Option<f64>,   // フィボナッチによるスコア
-    vwap_score: Option<f64>,        // Vwapによるスコア
-    ichimoku_score: Option<f64>,    // 一目均衡表スコア
-    signal_score: f64,              // 基本シグナルスコア（MACD/RSIによる）
+/// クロスイベント（短期線と長期線の交差）
+#[derive(Debug, Clone)]
+struct CrossEvent {
+    kind: CrossKind,
+    date: String,    // クロスが発生した足の日付
+    bars_ago: usize, // 最新足から何本前か（0 = 最新足）
+    slope: f64,      // クロス時点の短期線の傾き（1本あたりの変化量）
 }
 
-/// TechnicalDataEntry を安全に制御するラッパー構造体   
-struct TechnicalDataGuard {
-    entry: TechnicalDataEntry, // 内部保持（書き込みは許可された関数のみ）
+/// ベンチマーク比較の結果（相対力・ベータ・相関）
+#[derive(Debug, Clone)]
+struct RelativeStrengthStats {
+    benchmark: String,        // 比較対象のティッカー
+    stock_return_pct: f64,    // 銘柄の直近 RS_LOOKBACK 本の騰落率
+    bench_return_pct: f64,    // ベンチマークの同期間の騰落率
+    excess_return_pct: f64,   // 超過リターン（銘柄 − ベンチマーク）
+    beta: Option<f64>,        // ベータ（ベンチマーク分散が0なら None）
+    correlation: Option<f64>, // 日次リターンの相関係数
+    samples: usize,           // ベータ・相関に使った日次リターン数
+    beta_prev: Option<f64>,   // RS_LOOKBACK 本前の時点の同じ窓幅のベータ（ローリングの変化を見る）
 }
 
-impl TechnicalDataGuard {
-    /// 新しいガード付き構造体を初期化
-    fn new(ticker: String, date: String) -> Self {
-        TechnicalDataGuard {
-            entry: TechnicalDataEntry {
-                ticker,
-                name: String::new(),
-                date,
-                datetime: None,
-                timestamp: None,
-                timezone: "UTC".to_string(), // 追加（未取得時の既定）
-                close: 0.0,
-                previous_close: 0.0,
-                price_diff: 0.0,
-                price_diff_percent: 0.0,
-                macd: 0.0,
-                signal: 0.0,
-                prev_macd: 0.0,
-                prev_signal: 0.0,
-                rsi: 0.0,
-                ema_short: 0.0,
-                ema_long: 0.0,
-                sma_short: 0.0,
-                sma_long: 0.0,
-                bb_upper: 0.0,
-                bb_lower: 0.0,
-                bb_percent_b: 0.0, // ← 新規追加
-                bb_bandwidth: 0.0, // ← 新規追加
-                roc: None,
-                adx: None,
-                stochastics_k: None,
-                stochastics_d: None,
-                fibo_38_2: None,
-                fibo_50_0: None,
-                fibo_61_8: None,
-                vwap: None,
-                tenkan_sen: None,
-                kijun_sen: None,
-                ema_score: None,
-                sma_score: None,
-                adx_score: None,
-                roc_score: None,
-                stochastics_score: None,
-                bollinger_score: None,
-                fibonacci_score: None,
-                vwap_score: None,
-                ichimoku_score: None,
-                signal_score: 0.0,
-            },
+/// 取得期間の終値から算出したリスク統計
+#[derive(Debug, Clone)]
+struct RiskStats {
+    samples: usize,              // 日次リターンの本数
+    volatility_annual_pct: f64,  // 年率ヒストリカル・ボラティリティ（%）
+    max_drawdown_pct: f64,       // 期間内の最大ドローダウン（%、0以下）
+    max_drawdown_peak: String,   // 最大ドローダウンの起点（高値日）
+    max_drawdown_trough: String, // 最大ドローダウンの底（安値日）
+    current_drawdown_pct: f64,   // 期間内高値からの現在の下落率（%、0以下）
+    peak_date: String,           // 期間内高値の日付
+    sharpe: Option<f64>,         // 年率シャープレシオ（無リスク金利0）
+    sortino: Option<f64>,        // 年率ソルティノレシオ（下方偏差ベース）
+    rolling_window: usize,       // シャープ/ソルティノに使った本数
+    worst_day_pct: f64,          // 最悪の日次変動（%）
+    worst_day_date: String,      // 最悪の日次変動の日付
+}
+
+/// 基本シグナルのMACD方向条件（macd-minus-ok を反映した判定）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum MacdCondition {
+    #[default]
+    Any,
+    Up,
+    Down,
+}
+
+/// 基本シグナルのRSI条件（high = sell_rsi 以上、low = buy_rsi 以下）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RsiCondition {
+    #[default]
+    Any,
+    High,
+    Low,
+    Neutral,
+}
+
+/// MACD差の閾値指定（JSON では数値% または "low" / "mid" / "extreme"）
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum DiffThresholdSpec {
+    Pct(f64),
+    Named(String),
+}
+
+/// MACD差（終値比%）の閾値：設定値を参照するものは評価時に解決する
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DiffThreshold {
+    Low,     // config.macd_diff_low
+    Mid,     // config.macd_diff_mid
+    Extreme, // MACD_DIFF_EXTREME_PCT
+    Pct(f64),
+}
+
+impl DiffThreshold {
+    fn resolve(self, config: &Config) -> f64 {
+        match self {
+            DiffThreshold::Low => config.macd_diff_low,
+            DiffThreshold::Mid => config.macd_diff_mid,
+            DiffThreshold::Extreme => MACD_DIFF_EXTREME_PCT,
+            DiffThreshold::Pct(v) => v,
         }
     }
-    fn set_name(&mut self, value: &str) {
-        self.entry.name = value.to_string();
-    }
-    fn set_datetime(&mut self, value: &str) {
-        self.entry.datetime = Some(value.to_string());
-    }
-    fn set_timestamp(&mut self, value: i64) {
-        self.entry.timestamp = Some(value);
-    }
-    fn set_timezone(&mut self, value: &str) {
-        self.entry.timezone = value.to_string();
+
+    fn label(self) -> String {
+        match self {
+            DiffThreshold::Low => "low".to_string(),
+            DiffThreshold::Mid => "mid".to_string(),
+            DiffThreshold::Extreme => "extreme".to_string(),
+            DiffThreshold::Pct(v) => format!("{v}%"),
+        }
     }
-    fn set_close(&mut self, value: f64) {
-        self.entry.close = value;
+}
+
+/// シグナルルールファイルの1エントリ（JSON）
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SignalRuleSpec {
+    #[serde(default)]
+    macd: MacdCondition,
+    #[serde(default)]
+    rsi: RsiCondition,
+    #[serde(default)]
+    diff_above: Option<DiffThresholdSpec>,
+    #[serde(default)]
+    diff_below: Option<DiffThresholdSpec>,
+    score: f64,
+    #[serde(default)]
+    note: Option<String>,
+}
+
+/// 基本シグナルのルール（上から順に評価し、最初に一致したルールのスコアを採用）
+#[derive(Debug, Clone)]
+struct SignalRule {
+    macd: MacdCondition,
+    rsi: RsiCondition,
+    diff_above: Option<DiffThreshold>, // MACD差がこれを超える（>）
+    diff_below: Option<DiffThreshold>, // MACD差がこれ未満（<）
+    score: f64,
+    note: String,
+}
+
+impl SignalRule {
+    fn new(macd: MacdCondition, rsi: RsiCondition, score: f64, note: &str) -> Self {
+        SignalRule {
+            macd,
+            rsi,
+            diff_above: None,
+            diff_below: None,
+            score,
+            note: note.to_string(),
+        }
     }
-    fn set_previous_close(&mut self, value: f64) {
-        self.entry.previous_close = value;
+
+    fn matches(
+        &self,
+        config: &Config,
+        macd_up: bool,
+        macd_down: bool,
+        rsi_high: bool,
+        rsi_low: bool,
+        macd_diff: f64,
+    ) -> bool {
+        let macd_ok = match self.macd {
+            MacdCondition::Any => true,
+            MacdCondition::Up => macd_up,
+            MacdCondition::Down => macd_down,
+        };
+        let rsi_ok = match self.rsi {
+            RsiCondition::Any => true,
+            RsiCondition::High => rsi_high,
+            RsiCondition::Low => rsi_low,
+            RsiCondition::Neutral => !rsi_high && !rsi_low,
+        };
+        let above_ok = self.diff_above.is_none_or(|t| macd_diff > t.resolve(config));
+        let below_ok = self.diff_below.is_none_or(|t| macd_diff < t.resolve(config));
+        macd_ok && rsi_ok && above_ok && below_ok
+    }
+
+    /// 一致したときの理由コード（ルールが条件にした RSI/MACD の状態）
+    fn reason_codes(&self) -> Vec<String> {
+        let mut codes = Vec::new();
+        match self.rsi {
+            RsiCondition::High => codes.push("RSI_OVERBOUGHT".to_string()),
+            RsiCondition::Low => codes.push("RSI_OVERSOLD".to_string()),
+            RsiCondition::Any | RsiCondition::Neutral => {}
+        }
+        match self.macd {
+            MacdCondition::Up => codes.push("MACD_ABOVE_SIGNAL".to_string()),
+            MacdCondition::Down => codes.push("MACD_BELOW_SIGNAL".to_string()),
+            MacdCondition::Any => {}
+        }
+        codes
     }
-    fn set_price_diff(&mut self, value: f64) {
-        self.entry.price_diff = value;
+
+    /// 条件の表示用ラベル（検証コマンド・ログ用）
+    fn condition_label(&self) -> String {
+        let mut parts: Vec<String> = Vec::new();
+        match self.macd {
+            MacdCondition::Any => {}
+            MacdCondition::Up => parts.push("MACD上昇".to_string()),
+            MacdCondition::Down => parts.push("MACD下降".to_string()),
+        }
+        match self.rsi {
+            RsiCondition::Any => {}
+            RsiCondition::High => parts.push("RSI過熱".to_string()),
+            RsiCondition::Low => parts.push("RSI割安".to_string()),
+            RsiCondition::Neutral => parts.push("RSI中立".to_string()),
+        }
+        if let Some(t) = self.diff_above {
+            parts.push(format!("MACD差>{}", t.label()));
+        }
+        if let Some(t) = self.diff_below {
+            parts.push(format!("MACD差<{}", t.label()));
+        }
+        if parts.is_empty() {
+            "常に一致".to_string()
+        } else {
+            parts.join(" かつ ")
+        }
+    }
+}
+
+/// 既定の基本シグナルルール（従来のハードコード判定と同一の評価順・スコア）
+fn default_signal_rules() -> Vec<SignalRule> {
+    use MacdCondition as M;
+    use RsiCondition as R;
+    vec![
+        SignalRule {
+            diff_above: Some(DiffThreshold::Extreme),
+            ..SignalRule::new(M::Up, R::High, -2.0, "RSI過熱でMACD上昇・異常乖離 → 売り")
+        },
+        SignalRule::new(M::Up, R::High, -1.0, "RSI過熱でMACD上昇 → 売り"),
+        SignalRule::new(M::Down, R::High, -1.0, "RSI過熱かつMACD下降 → 売り"),
+        SignalRule::new(M::Up, R::Low, 2.0, "RSI割安かつMACD上昇 → 買い（強いシグナル）"),
+        SignalRule::new(M::Down, R::Low, 1.0, "RSI割安でもMACD下降 → 反発期待"),
+        SignalRule {
+            diff_below: Some(DiffThreshold::Low),
+            ..SignalRule::new(M::Up, R::Neutral, 1.0, "RSI中立・MACD上昇（乖離小）→ 様子見")
+        },
+        SignalRule {
+            diff_below: Some(DiffThreshold::Mid),
+            ..SignalRule::new(M::Up, R::Neutral, 2.0, "RSI中立・MACD上昇（乖離中）→ 買い")
+        },
+        SignalRule::new(M::Up, R::Neutral, 2.0, "RSI中立・MACD上昇（乖離大）→ 買い"),
+        SignalRule {
+            diff_below: Some(DiffThreshold::Low),
+            ..SignalRule::new(M::Down, R::Neutral, 0.0, "RSI中立・MACD下降（乖離小）→ 様子見")
+        },
+        SignalRule::new(M::Down, R::Neutral, -1.0, "RSI中立・MACD下降 → 弱含み"),
+        SignalRule::new(M::Any, R::Any, 0.0, "それ以外は中立"),
+    ]
+}
+
+/// 相場局面の分類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RegimeKind {
+    Trending,   // トレンド相場
+    Ranging,    // レンジ相場
+    Transition, // どちらとも言えない（重み補正なし）
+    Unknown,    // データ不足で判定不可（重み補正なし）
+}
+
+impl RegimeKind {
+    /// ログ用のキー
+    fn key(&self) -> &'static str {
+        match self {
+            RegimeKind::Trending => "trending",
+            RegimeKind::Ranging => "ranging",
+            RegimeKind::Transition => "transition",
+            RegimeKind::Unknown => "unknown",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            RegimeKind::Trending => "📈 トレンド相場",
+            RegimeKind::Ranging => "↔️ レンジ相場",
+            RegimeKind::Transition => "⚪️ 移行期（判定保留）",
+            RegimeKind::Unknown => "❔ 判定不可（データ不足）",
+        }
+    }
+
+    /// カテゴリごとの重み倍率（トレンド相場ではオシレーターを、レンジ相場ではトレンド系を割り引く）
+    fn weight_multiplier(&self, category: IndicatorCategory) -> f64 {
+        match (self, category) {
+            (RegimeKind::Trending, IndicatorCategory::Trend) => 1.5,
+            (RegimeKind::Trending, IndicatorCategory::Oscillator) => 0.5,
+            (RegimeKind::Trending, IndicatorCategory::Volatility) => 0.75,
+            (RegimeKind::Trending, IndicatorCategory::SupportResist) => 0.75,
+            (RegimeKind::Ranging, IndicatorCategory::Trend) => 0.5,
+            (RegimeKind::Ranging, IndicatorCategory::Oscillator) => 1.5,
+            (RegimeKind::Ranging, IndicatorCategory::Volatility) => 1.25,
+            (RegimeKind::Ranging, IndicatorCategory::SupportResist) => 1.5,
+            _ => 1.0,
+        }
+    }
+}
+
+/// 相場局面の判定結果と、適用した実効Weight
+#[derive(Debug, Clone)]
+struct MarketRegime {
+    kind: RegimeKind,
+    adx: Option<f64>,                     // ADX（14期間）
+    bandwidth_pct: Option<f64>,           // ボリンジャーバンド幅（%）
+    slope_pct: Option<f64>,               // SMA20の傾き（終値比%/本）
+    effective_weights: Vec<(String, f64)>, // 拡張指標ごとの実効Weight（ログ名, 値）
+}
+
+/// 直近の足のスコア比率（平滑化・ヒステリシス用。今回の足は含まない、古い順）
+#[derive(Debug, Clone)]
+struct ScoreSmoothing {
+    past_ratios: Vec<f64>,  // その足までのデータで評価したスコア比率
+    bars: usize,            // EMA の期間（0 なら平滑化なし）
+    hysteresis: f64,        // 帯の切り替えに必要な超過幅
+    plugins_skipped: bool,  // 過去の足はプラグインを除いて評価した（プラグイン有効時）
+}
+
+/// バックテスト用の足ごとの評価結果
+#[derive(Debug, Clone)]
+struct BacktestBar {
+    date: String,
+    open: f64,
+    close: f64,
+    score_ratio: f64, // その足の終値までのデータで算出したスコア比率
+}
+
+/// バックテストの1トレード（買い → 手仕舞い）
+#[derive(Debug, Clone)]
+struct BacktestTrade {
+    entry_date: String,
+    entry_price: f64, // スリッページ込みの約定値
+    exit_date: String,
+    exit_price: f64,
+    return_pct: f64,   // 手数料・スリッページ込みの損益率（%）
+    bars_held: usize,  // 保有本数
+    open_at_end: bool, // 期末時点で保有中（最終終値で評価）
+}
+
+/// 資産曲線の1点（足ごと）
+#[derive(Debug, Clone)]
+struct EquityPoint {
+    date: String,
+    close: f64,
+    score_ratio: f64,     // 判定に使ったスコア比率（平滑化指定時は平滑化後）
+    action: &'static str, // その足の始値で約定した売買（"buy" / "sell" / ""）
+    in_position: bool,    // 足の終値時点で保有中か
+    equity: f64,          // 資産（開始時 1.0）
+    drawdown_pct: f64,    // 資産の高値からの下落率（%、0以下）
+}
+
+/// バックテストの集計結果
+#[derive(Debug, Clone)]
+struct BacktestReport {
+    trades: Vec<BacktestTrade>,
+    curve: Vec<EquityPoint>,
+    total_return_pct: f64,
+    cagr_pct: Option<f64>, // 期間が1日未満なら None
+    max_drawdown_pct: f64,
+    buy_hold_return_pct: f64, // 同期間のバイ&ホールド（終値ベース、コストなし）
+    exposure_pct: f64,        // 保有していた足の割合
+}
+
+/// 過去ログから読み戻した1回分の実行結果
+#[derive(Debug, Clone)]
+struct ScoreHistoryRecord {
+    date: String,                              // データ日付
+    final_score: f64,                          // 総合スコア
+    score_ratio: Option<f64>,                  // スコア比率（score_ratio 列がない古いログは None）
+    stance: Option<Stance>,                    // 判定に使ったスタンス（stance 列がない古いログは None）
+    judgement: Option<(String, Option<bool>)>, // 記録された判定と向き（judgement / direction 列がない古いログは None）
+    indicator_scores: Vec<(String, f64)>,      // 指標ごとのスコア（ログ名, 値）
+}
+
+/// 過去ログとの比較材料（前回の実行と N日前の実行）
+#[derive(Debug, Clone)]
+struct ScoreHistory {
+    previous: Option<ScoreHistoryRecord>, // 直近に記録された実行
+    days_ago: Option<ScoreHistoryRecord>, // 「N日前」以前で最も新しい実行
+    days: usize,                          // N
+    skipped_rows: usize,                  // 列構成が一致せず読めなかったCSV行の数
+}
+
+/// 戦略プリセット（拡張指標・重み・閾値・スタンスの組み合わせ）。未指定の項目は通常の設定のまま
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StrategyPreset {
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    extensions: Option<Vec<String>>, // 有効にする拡張指標（指定時は環境変数の有効化を置き換え）
+    #[serde(default)]
+    weights: HashMap<String, f64>, // "basic" または拡張指標名 → 重み
+    #[serde(default)]
+    buy_rsi: Option<f64>,
+    #[serde(default)]
+    sell_rsi: Option<f64>,
+    #[serde(default)]
+    macd_diff_low: Option<f64>,
+    #[serde(default)]
+    macd_diff_mid: Option<f64>,
+    #[serde(default)]
+    macd_minus_ok: Option<bool>,
+    #[serde(default)]
+    bb_bandwidth_squeeze_pct: Option<f64>,
+    #[serde(default)]
+    regime_weighting: Option<bool>,
+    #[serde(default)]
+    stance: Option<String>,
+}
+
+/// カスタム指標ファイルの1エントリ（JSON）
+#[derive(Debug, Deserialize)]
+struct CustomIndicatorSpec {
+    name: String,
+    expr: String,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default = "default_custom_weight")]
+    weight: f64,
+    bands: Vec<CustomScoreBand>,
+    #[serde(default)]
+    default_score: f64,
+}
+
+fn default_custom_weight() -> f64 {
+    1.0
+}
+
+/// スコア帯：値が min 以上なら score（上から順に評価し最初に一致したものを採用）
+#[derive(Debug, Clone, Deserialize)]
+struct CustomScoreBand {
+    min: f64,
+    score: f64,
+}
+
+/// 検証済みのカスタム指標（式は構文木に変換済み）
+#[derive(Debug, Clone)]
+struct CustomIndicator {
+    name: String,
+    expr: String,
+    description: Option<String>,
+    weight: f64,
+    bands: Vec<CustomScoreBand>,
+    default_score: f64,
+    ast: Expr,
+}
+
+impl CustomIndicator {
+    /// 値をスコア帯に当てはめる（-2〜+2）
+    fn score_for(&self, value: f64) -> f64 {
+        self.bands
+            .iter()
+            .find(|b| value >= b.min)
+            .map(|b| b.score)
+            .unwrap_or(self.default_score)
+    }
+}
+
+/// カスタム指標の評価結果
+#[derive(Debug, Clone)]
+struct CustomIndicatorResult {
+    name: String,
+    value: f64,
+    score: f64,
+}
+
+/// 外部プロセス・プラグインの定義（JSON）
+#[derive(Debug, Clone, Deserialize)]
+struct PluginSpec {
+    name: String,
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default = "default_plugin_timeout_ms")]
+    timeout_ms: u64,
+    #[serde(default = "default_custom_weight")]
+    weight: f64,
+    #[serde(default)]
+    params: Value, // プラグインへそのまま渡す任意パラメータ
+}
+
+fn default_plugin_timeout_ms() -> u64 {
+    5_000
+}
+
+/// プラグインの検証済み出力
+#[derive(Debug, Clone)]
+struct PluginOutput {
+    score: f64,
+    values: Vec<(String, f64)>,
+    description: Vec<String>,
+}
+
+/// プラグインの実行結果（失敗時はエラー内容を保持）
+#[derive(Debug, Clone)]
+struct PluginOutcome {
+    name: String,
+    result: Result<PluginOutput, String>,
+}
+
+/// 指標の計算に必要な本数に満たないことを表すエラー（評価ステータスの判別用）
+#[derive(Debug)]
+struct InsufficientDataError(String);
+
+impl std::fmt::Display for InsufficientDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for InsufficientDataError {}
+
+/// 拡張指標ごとの評価ステータス
+#[derive(Debug, Clone)]
+enum IndicatorStatus {
+    Ok,
+    InsufficientData(String), // 必要本数に満たない
+    Error(String),            // 計算・実行エラー
+    Skipped(String),          // 評価しなかった（足ごとの再評価でのプラグインなど。総合スコアの対象外）
+}
+
+impl IndicatorStatus {
+    fn from_error(e: &(dyn std::error::Error + 'static)) -> Self {
+        // 表示側で絵文字を付けるため、メッセージ先頭の「❌ 」は落とす
+        let message = e.to_string();
+        let reason = message.trim_start_matches("❌ ").to_string();
+        if e.downcast_ref::<InsufficientDataError>().is_some() {
+            IndicatorStatus::InsufficientData(reason)
+        } else {
+            IndicatorStatus::Error(reason)
+        }
+    }
+
+    fn is_ok(&self) -> bool {
+        matches!(self, IndicatorStatus::Ok)
+    }
+
+    /// ログ用のステータス名
+    fn label(&self) -> &'static str {
+        match self {
+            IndicatorStatus::Ok => "ok",
+            IndicatorStatus::InsufficientData(_) => "insufficient_data",
+            IndicatorStatus::Error(_) => "error",
+            IndicatorStatus::Skipped(_) => "skipped",
+        }
+    }
+
+    fn reason(&self) -> Option<&str> {
+        match self {
+            IndicatorStatus::Ok => None,
+            IndicatorStatus::InsufficientData(reason)
+            | IndicatorStatus::Error(reason)
+            | IndicatorStatus::Skipped(reason) => Some(reason),
+        }
+    }
+}
+
+/// カスタム指標式の構文木
+#[derive(Debug, Clone)]
+enum Expr {
+    Num(f64),
+    Field(PriceField),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Call(ExprFunc, Vec<Expr>, usize), // 関数・系列引数・期間
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PriceField {
+    Open,
+    High,
+    Low,
+    Close,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExprFunc {
+    Sma,
+    Ema,
+    Rsi,
+    Highest,
+    Lowest,
+    Stdev,
+    Roc,
+    Prev,
+    Abs,
+    Min,
+    Max,
+}
+
+impl Expr {
+    /// 最新足の値を算出するのに必要な足の本数（これに満たない間は期間不足で NaN）
+    fn required_bars(&self) -> usize {
+        match self {
+            Expr::Num(_) | Expr::Field(_) => 1,
+            Expr::Neg(inner) | Expr::Not(inner) => inner.required_bars(),
+            Expr::Binary(_, lhs, rhs) => lhs.required_bars().max(rhs.required_bars()),
+            Expr::Call(func, args, period) => {
+                let inner = args.iter().map(Expr::required_bars).max().unwrap_or(1);
+                match func {
+                    ExprFunc::Sma | ExprFunc::Ema | ExprFunc::Highest | ExprFunc::Lowest | ExprFunc::Stdev => {
+                        inner + period - 1
+                    }
+                    ExprFunc::Rsi | ExprFunc::Roc | ExprFunc::Prev => inner + period,
+                    ExprFunc::Abs | ExprFunc::Min | ExprFunc::Max => inner,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ExprToken {
+    Num(f64),
+    Ident(String),
+    Op(BinOp),
+    Not,
+    LParen,
+    RParen,
+    Comma,
+}
+
+/// ローソク足パターンの検出結果（どの足で何が出たか）
+#[derive(Debug, Clone)]
+struct CandlePattern {
+    name: &'static str, // 例: "明けの明星", "窓開け（上放れ）"
+    date: String,       // パターンが完成した足の日付
+    score: f64,         // パターン単体のスコア（-2〜+2）
+}
+
+/// スイング高値/安値（ピボット）
+#[derive(Debug, Clone)]
+struct SwingPivot {
+    index: usize,  // data 内の位置
+    date: String,  // ピボット足の日付
+    price: f64,    // 高値ピボットなら high、安値ピボットなら low
+    is_high: bool, // true: スイング高値 / false: スイング安値
+}
+
+/// ピボットをクラスタ化したサポート/レジスタンス水準
+#[derive(Debug, Clone)]
+struct PriceLevel {
+    price: f64,        // 水準（クラスタ内ピボットの平均）
+    low: f64,          // クラスタ内ピボットの最安値（許容幅の起点）
+    high: f64,         // クラスタ内ピボットの最高値
+    touches: usize,    // タッチ回数（クラスタ内ピボット数）
+    last_date: String, // 最後にタッチした日付
+}
+
+/// テクニカル指標の分析結果を保持する構造体
+struct AnalysisResult {
+    indicator_name: String,   // 例: "基本テクニカル分析", "EMA", "SMA"
+    description: Vec<String>, // 表示用の複数行テキスト（\n区切りでOK）
+    score: Option<f64>,       // 元のスコア（-2〜+2、算出できなかった指標は None）
+}
+// ==== 追加：最終スコアのスナップショット（唯一の真実） ====
+struct FinalScoreSnapshot {
+    total_score: f64,  // Σ(各スコア×重み)
+    total_weight: f64, // 2×Σ(有効指標の重み)  ※各指標レンジ[-2,2]
+    score_ratio: f64,  // total_score / total_weight（-1..+1）
+    coverage: f64,     // スコアを算出できた指標の重み ÷ 有効指標の重み（0..1）
+    missing: Vec<String>, // スコアを算出できなかった指標（ログ名）
+    missing_policy: MissingScorePolicy,
+    categories: Vec<CategoryScore>, // カテゴリ別の内訳（基本解析を先頭に、有効なカテゴリのみ）
+    preset: Option<String>,         // 重み・閾値の出どころ（戦略プリセット名）
+    stance: Stance,                 // 判定に使ったスタンス（ログに記録し、後から判定を採点する）
+    contributions: Vec<ScoreContribution>, // 指標ごとの寄与（理由コード付き）
+}
+
+/// 指標ごとの寄与と理由コード（機械可読：下流ツールでのフィルタ用）
+struct ScoreContribution {
+    indicator: String,         // ログ名（"Basic" / "EMA" / "Custom:xxx" など）
+    score: Option<f64>,        // 元のスコア（欠損は None）
+    weight: f64,               // 適用した重み
+    contribution: f64,         // score×weight（欠損は 0）
+    reason_codes: Vec<String>, // 例: "RSI_OVERSOLD", "EMA_GOLDEN_CROSS", "BB_SQUEEZE"
+}
+
+/// カテゴリ別のサブスコア（重み付き合計と比率）
+struct CategoryScore {
+    key: &'static str,   // ログ用キー（"basic" / "trend" など）
+    label: &'static str, // 表示名
+    score: f64,          // Σ(スコア×重み)
+    weight: f64,         // 2×Σ(重み)（欠損の扱いは総合スコアと同じ）
+    ratio: Option<f64>,  // score / weight（-1..+1、全指標が欠損なら None）
+}
+
+/// 分析結果を保持する構造体（全出力・ログ・LLMプロンプト共通）
+#[derive(Debug)]
+struct TechnicalDataEntry {
+    ticker: String,                 // ティッカー記号（例: AAPL, MSFT, 7203.T）
+    name: String,                   // 企業名（例: NVIDIA Corp、ソフトバンク）
+    date: String,                   // データ日付（例: 2025-05-09）
+    datetime: Option<String>,       // データ日時（例: 2025-05-09T15:30:00Z）
+    timestamp: Option<i64>,         // データタイムスタンプ（UNIX時間）
+    timezone: String,               // IANA TZ (exchangeTimezoneName)
+    currency: Option<String>,       // 取引通貨（meta.currency、未取得なら None）
+    close: f64,                     // 終値
+    previous_close: f64,            // 前日終値
+    price_diff: f64,                // 前日比（差額）
+    price_diff_percent: f64,        // 前日比（%）
+    macd: f64,                      // MACD値
+    signal: f64,                    // MACDシグナル値
+    prev_macd: f64,                 // 前MACD値（前日比計算用）
+    prev_signal: f64,               // 前MACDシグナル値（前日比計算用）
+    macd_crosses: Vec<CrossEvent>,  // MACD/シグナルのクロス履歴
+    rsi: f64,                       // RSI値
+    ema_short: f64,                 // EMA短期（例: 5日）
+    ema_long: f64,                  // EMA長期（例: 20日）
+    ema_crosses: Vec<CrossEvent>,   // EMA短期/長期のクロス履歴
+    sma_short: f64,                 // SMA短期
+    sma_long: f64,                  // SMA長期
+    sma_crosses: Vec<CrossEvent>,   // SMA短期/長期のクロス履歴
+    roc: Option<f64>,               // ROC（変化率）※拡張指標
+    adx: Option<f64>,               // ADX（トレンド強度）※拡張指標
+    stochastics_k: Option<f64>,     // ストキャスティクス %K
+    stochastics_d: Option<f64>,     // ストキャスティクス %D
+    stoch_crosses: Vec<CrossEvent>, // %K/%D のクロス履歴
+    bb_upper: f64,                  // ボリンジャーバンド上限
+    bb_lower: f64,                  // ボリンジャーバンド下限
+    bb_percent_b: f64,              // %Bインジケータ
+    bb_bandwidth: f64,              // Bandwidth(%) を 0–100 の実数で保持
+    fibo_23_6: Option<f64>,         // フィボナッチ 23.6%
+    fibo_38_2: Option<f64>,         // フィボナッチ 38.2%
+    fibo_50_0: Option<f64>,         // フィボナッチ 50.0%
+    fibo_61_8: Option<f64>,         // フィボナッチ 61.8%
+    fibo_78_6: Option<f64>,         // フィボナッチ 78.6%
+    fibo_ext_127_2: Option<f64>,    // フィボナッチ・エクステンション 127.2%
+    fibo_ext_161_8: Option<f64>,    // フィボナッチ・エクステンション 161.8%
+    fib_swing: Option<FibonacciSwing>, // フィボナッチのアンカー（直近の有意なスイング）
+    fib_depth: Option<f64>,         // スイングに対する押し/戻りの深さ（0..1、範囲外あり）
+    vwap: Option<f64>,              // Vwap
+    tenkan_sen: Option<f64>,        // 一目均衡表転換線
+    kijun_sen: Option<f64>,         // 一目均衡表基準線
+    ichimoku_crosses: Vec<CrossEvent>, // 転換線/基準線のクロス履歴
+    ema_score: Option<f64>,         // EMAによるスコア
+    sma_score: Option<f64>,         // SMAによるスコア
+    roc_score: Option<f64>,         // ROCによるスコア
+    adx_score: Option<f64>,         // ADXによるスコア
+    stochastics_score: Option<f64>, // ストキャスティクスによるスコア
+    bollinger_score: Option<f64>,   // ボリンジャーバンドによるスコア
+    fibonacci_score: Option<f64>,   // フィボナッチによるスコア
+    vwap_score: Option<f64>,        // Vwapによるスコア
+    ichimoku_score: Option<f64>,    // 一目均衡表スコア
+    candle_patterns: Vec<CandlePattern>, // 直近で検出したローソク足パターン
+    candlestick_score: Option<f64>, // ローソク足パターンによるスコア
+    sr_levels: Vec<PriceLevel>,     // サポート/レジスタンス水準（価格昇順）
+    nearest_support: Option<PriceLevel>, // 終値直下のサポート
+    nearest_resistance: Option<PriceLevel>, // 終値直上のレジスタンス
+    support_resistance_score: Option<f64>, // サポート/レジスタンスによるスコア
+    relative_strength: Option<RelativeStrengthStats>, // ベンチマーク比較の結果
+    relative_strength_score: Option<f64>, // 相対力によるスコア
+    custom_results: Vec<CustomIndicatorResult>, // カスタム指標の評価結果
+    plugin_outcomes: Vec<PluginOutcome>, // プラグインの実行結果
+    indicator_statuses: Vec<(String, IndicatorStatus)>, // 拡張指標ごとの評価ステータス（ログ名, 状態）。基本スコアは立ち上がり前のみ "Basic" で記録
+    reason_codes: Vec<(ExtensionIndicator, Vec<String>)>, // 拡張指標ごとの理由コード（スコア判定時に記録）
+    signal_score: f64,              // 基本シグナルスコア（MACD/RSIによる）
+    signal_reason_codes: Vec<String>, // 基本シグナルの理由コード（一致したルールから記録）
+    risk_stats: Option<RiskStats>,  // リスク統計（ボラティリティ・ドローダウン等）
+    market_regime: Option<MarketRegime>, // 相場局面（--regime-weighting 時のみ）
+    score_history: Option<ScoreHistory>, // 過去ログとの比較材料（ログがある場合のみ）
+    score_smoothing: Option<ScoreSmoothing>, // 平滑化・ヒステリシス用の直近スコア比率（指定時のみ）
+}
+
+/// TechnicalDataEntry を安全に制御するラッパー構造体   
+struct TechnicalDataGuard {
+    entry: TechnicalDataEntry, // 内部保持（書き込みは許可された関数のみ）
+}
+
+impl TechnicalDataGuard {
+    /// 新しいガード付き構造体を初期化
+    fn new(ticker: String, date: String) -> Self {
+        TechnicalDataGuard {
+            entry: TechnicalDataEntry {
+                ticker,
+                name: String::new(),
+                date,
+                datetime: None,
+                timestamp: None,
+                timezone: "UTC".to_string(), // 追加（未取得時の既定）
+                currency: None,
+                close: 0.0,
+                previous_close: 0.0,
+                price_diff: 0.0,
+                price_diff_percent: 0.0,
+                macd: 0.0,
+                signal: 0.0,
+                prev_macd: 0.0,
+                prev_signal: 0.0,
+                macd_crosses: Vec::new(),
+                rsi: 0.0,
+                ema_short: 0.0,
+                ema_long: 0.0,
+                ema_crosses: Vec::new(),
+                sma_short: 0.0,
+                sma_long: 0.0,
+                sma_crosses: Vec::new(),
+                bb_upper: 0.0,
+                bb_lower: 0.0,
+                bb_percent_b: 0.0, // ← 新規追加
+                bb_bandwidth: 0.0, // ← 新規追加
+                roc: None,
+                adx: None,
+                stochastics_k: None,
+                stochastics_d: None,
+                stoch_crosses: Vec::new(),
+                fibo_23_6: None,
+                fibo_38_2: None,
+                fibo_50_0: None,
+                fibo_61_8: None,
+                fibo_78_6: None,
+                fibo_ext_127_2: None,
+                fibo_ext_161_8: None,
+                fib_swing: None,
+                fib_depth: None,
+                vwap: None,
+                tenkan_sen: None,
+                kijun_sen: None,
+                ichimoku_crosses: Vec::new(),
+                ema_score: None,
+                sma_score: None,
+                adx_score: None,
+                roc_score: None,
+                stochastics_score: None,
+                bollinger_score: None,
+                fibonacci_score: None,
+                vwap_score: None,
+                ichimoku_score: None,
+                candle_patterns: Vec::new(),
+                candlestick_score: None,
+                sr_levels: Vec::new(),
+                nearest_support: None,
+                nearest_resistance: None,
+                support_resistance_score: None,
+                relative_strength: None,
+                relative_strength_score: None,
+                custom_results: Vec::new(),
+                plugin_outcomes: Vec::new(),
+                indicator_statuses: Vec::new(),
+                reason_codes: Vec::new(),
+                signal_score: 0.0,
+                signal_reason_codes: Vec::new(),
+                risk_stats: None,
+                market_regime: None,
+                score_history: None,
+                score_smoothing: None,
+            },
+        }
+    }
+    fn set_name(&mut self, value: &str) {
+        self.entry.name = value.to_string();
+    }
+    fn set_datetime(&mut self, value: &str) {
+        self.entry.datetime = Some(value.to_string());
+    }
+    fn set_timestamp(&mut self, value: i64) {
+        self.entry.timestamp = Some(value);
+    }
+    fn set_timezone(&mut self, value: &str) {
+        self.entry.timezone = value.to_string();
+    }
+    fn set_currency(&mut self, value: &str) {
+        self.entry.currency = Some(value.to_string());
+    }
+    fn set_close(&mut self, value: f64) {
+        self.entry.close = value;
+    }
+    fn set_previous_close(&mut self, value: f64) {
+        self.entry.previous_close = value;
+    }
+    fn set_price_diff(&mut self, value: f64) {
+        self.entry.price_diff = value;
     }
     fn set_price_diff_percent(&mut self, value: f64) {
         self.entry.price_diff_percent = value;
     }
-    fn set_rsi(&mut self, value: f64) {
-        self.entry.rsi = value;
+    fn set_rsi(&mut self, value: f64) {
+        self.entry.rsi = value;
+    }
+    fn set_macd(&mut self, value: f64) {
+        self.entry.macd = value;
+    }
+    fn set_signal(&mut self, value: f64) {
+        self.entry.signal = value;
+    }
+    fn set_prev_macd(&mut self, value: f64) {
+        self.entry.prev_macd = value;
+    }
+    fn set_prev_signal(&mut self, value: f64) {
+        self.entry.prev_signal = value;
+    }
+    fn set_macd_crosses(&mut self, value: Vec<CrossEvent>) {
+        self.entry.macd_crosses = value;
+    }
+    fn set_signal_score(&mut self, value: f64) {
+        self.entry.signal_score = value;
+    }
+    fn set_signal_reason_codes(&mut self, codes: Vec<String>) {
+        self.entry.signal_reason_codes = codes;
+    }
+    fn set_reason_codes(&mut self, indicator: ExtensionIndicator, codes: Vec<String>) {
+        self.entry.reason_codes.retain(|(ext, _)| *ext != indicator);
+        self.entry.reason_codes.push((indicator, codes));
+    }
+    fn set_risk_stats(&mut self, value: Option<RiskStats>) {
+        self.entry.risk_stats = value;
+    }
+    fn set_market_regime(&mut self, value: MarketRegime) {
+        self.entry.market_regime = Some(value);
+    }
+    fn set_score_history(&mut self, value: ScoreHistory) {
+        self.entry.score_history = Some(value);
+    }
+    fn set_score_smoothing(&mut self, value: ScoreSmoothing) {
+        self.entry.score_smoothing = Some(value);
+    }
+    fn set_ema_short(&mut self, value: f64) {
+        self.entry.ema_short = value;
+    }
+    fn set_ema_long(&mut self, value: f64) {
+        self.entry.ema_long = value;
+    }
+    fn set_ema_crosses(&mut self, value: Vec<CrossEvent>) {
+        self.entry.ema_crosses = value;
+    }
+    fn set_ema_score(&mut self, value: f64) {
+        self.entry.ema_score = Some(value);
+    }
+    fn set_sma_short(&mut self, value: f64) {
+        self.entry.sma_short = value;
+    }
+    fn set_sma_long(&mut self, value: f64) {
+        self.entry.sma_long = value;
+    }
+    fn set_sma_crosses(&mut self, value: Vec<CrossEvent>) {
+        self.entry.sma_crosses = value;
+    }
+    fn set_sma_score(&mut self, value: f64) {
+        self.entry.sma_score = Some(value);
+    }
+    fn set_adx(&mut self, value: f64) {
+        self.entry.adx = Some(value);
+    }
+    fn set_adx_score(&mut self, value: f64) {
+        self.entry.adx_score = Some(value);
+    }
+    fn set_roc(&mut self, value: f64) {
+        self.entry.roc = Some(value);
+    }
+    fn set_roc_score(&mut self, value: f64) {
+        self.entry.roc_score = Some(value);
+    }
+    fn set_stochastics_k(&mut self, value: f64) {
+        self.entry.stochastics_k = Some(value);
+    }
+    fn set_stochastics_d(&mut self, value: f64) {
+        self.entry.stochastics_d = Some(value);
+    }
+    fn set_stoch_crosses(&mut self, value: Vec<CrossEvent>) {
+        self.entry.stoch_crosses = value;
+    }
+    fn set_stochastics_score(&mut self, value: f64) {
+        self.entry.stochastics_score = Some(value);
+    }
+    fn set_bb_upper(&mut self, value: f64) {
+        self.entry.bb_upper = value;
+    }
+    fn set_bb_lower(&mut self, value: f64) {
+        self.entry.bb_lower = value;
+    }
+    fn set_bb_percent_b(&mut self, value: f64) {
+        self.entry.bb_percent_b = value;
+    }
+    fn set_bb_bandwidth(&mut self, value: f64) {
+        self.entry.bb_bandwidth = value;
+    }
+    fn set_bollinger_score(&mut self, value: f64) {
+        self.entry.bollinger_score = Some(value);
+    }
+    fn set_fibo_38_2(&mut self, value: f64) {
+        self.entry.fibo_38_2 = Some(value);
+    }
+    fn set_fibo_50_0(&mut self, value: f64) {
+        self.entry.fibo_50_0 = Some(value);
+    }
+    fn set_fibo_61_8(&mut self, value: f64) {
+        self.entry.fibo_61_8 = Some(value);
+    }
+    fn set_fibo_23_6(&mut self, value: f64) {
+        self.entry.fibo_23_6 = Some(value);
+    }
+    fn set_fibo_78_6(&mut self, value: f64) {
+        self.entry.fibo_78_6 = Some(value);
+    }
+    fn set_fibo_ext_127_2(&mut self, value: f64) {
+        self.entry.fibo_ext_127_2 = Some(value);
+    }
+    fn set_fibo_ext_161_8(&mut self, value: f64) {
+        self.entry.fibo_ext_161_8 = Some(value);
+    }
+    fn set_fib_swing(&mut self, value: &FibonacciSwing) {
+        self.entry.fib_swing = Some(value.clone());
+    }
+    fn set_fib_depth(&mut self, value: f64) {
+        self.entry.fib_depth = Some(value);
+    }
+    fn set_fibonacci_score(&mut self, value: f64) {
+        self.entry.fibonacci_score = Some(value);
+    }
+    fn set_vwap(&mut self, value: f64) {
+        self.entry.vwap = Some(value);
+    }
+    fn set_vwap_score(&mut self, value: f64) {
+        self.entry.vwap_score = Some(value);
+    }
+    fn set_tenkan_sen(&mut self, value: f64) {
+        self.entry.tenkan_sen = Some(value);
+    }
+    fn set_kijun_sen(&mut self, value: f64) {
+        self.entry.kijun_sen = Some(value);
+    }
+    fn set_ichimoku_crosses(&mut self, value: Vec<CrossEvent>) {
+        self.entry.ichimoku_crosses = value;
+    }
+    fn set_ichimoku_score(&mut self, value: f64) {
+        self.entry.ichimoku_score = Some(value);
+    }
+    fn set_candle_patterns(&mut self, value: Vec<CandlePattern>) {
+        self.entry.candle_patterns = value;
+    }
+    fn set_candlestick_score(&mut self, value: f64) {
+        self.entry.candlestick_score = Some(value);
+    }
+    fn set_sr_levels(&mut self, value: Vec<PriceLevel>) {
+        self.entry.sr_levels = value;
+    }
+    fn set_nearest_support(&mut self, value: Option<PriceLevel>) {
+        self.entry.nearest_support = value;
+    }
+    fn set_nearest_resistance(&mut self, value: Option<PriceLevel>) {
+        self.entry.nearest_resistance = value;
+    }
+    fn set_support_resistance_score(&mut self, value: f64) {
+        self.entry.support_resistance_score = Some(value);
+    }
+    fn set_relative_strength(&mut self, value: RelativeStrengthStats) {
+        self.entry.relative_strength = Some(value);
+    }
+    fn set_relative_strength_score(&mut self, value: f64) {
+        self.entry.relative_strength_score = Some(value);
+    }
+    fn set_custom_result(&mut self, value: CustomIndicatorResult) {
+        self.entry.custom_results.retain(|r| r.name != value.name);
+        self.entry.custom_results.push(value);
+    }
+    fn set_plugin_outcome(&mut self, value: PluginOutcome) {
+        self.entry.plugin_outcomes.retain(|r| r.name != value.name);
+        self.entry.plugin_outcomes.push(value);
+    }
+    fn set_indicator_status(&mut self, name: String, status: IndicatorStatus) {
+        self.entry.indicator_statuses.retain(|(n, _)| *n != name);
+        self.entry.indicator_statuses.push((name, status));
+    }
+    /// get関数
+    fn get_name(&self) -> &str {
+        &self.entry.name
+    }
+    fn get_datetime(&self) -> Option<&str> {
+        self.entry.datetime.as_deref()
+    }
+    fn get_timestamp(&self) -> Option<i64> {
+        self.entry.timestamp
+    }
+    fn get_timezone(&self) -> &str {
+        &self.entry.timezone
+    }
+    fn get_currency(&self) -> Option<&str> {
+        self.entry.currency.as_deref()
+    }
+    fn get_ticker(&self) -> &str {
+        &self.entry.ticker
+    }
+    fn get_date(&self) -> &str {
+        &self.entry.date
+    }
+    fn get_close(&self) -> f64 {
+        self.entry.close
+    }
+    fn get_previous_close(&self) -> f64 {
+        self.entry.previous_close
+    }
+    fn get_price_diff(&self) -> f64 {
+        self.entry.price_diff
+    }
+    fn get_price_diff_percent(&self) -> f64 {
+        self.entry.price_diff_percent
+    }
+    fn get_rsi(&self) -> f64 {
+        self.entry.rsi
+    }
+    fn get_macd(&self) -> f64 {
+        self.entry.macd
+    }
+    fn get_signal(&self) -> f64 {
+        self.entry.signal
+    }
+    fn get_prev_macd(&self) -> f64 {
+        self.entry.prev_macd
+    }
+    fn get_prev_signal(&self) -> f64 {
+        self.entry.prev_signal
+    }
+    fn get_macd_crosses(&self) -> &[CrossEvent] {
+        &self.entry.macd_crosses
+    }
+    fn get_signal_score(&self) -> f64 {
+        self.entry.signal_score
+    }
+    fn get_signal_reason_codes(&self) -> &[String] {
+        &self.entry.signal_reason_codes
+    }
+    fn get_reason_codes(&self, indicator: &ExtensionIndicator) -> &[String] {
+        self.entry
+            .reason_codes
+            .iter()
+            .find(|(ext, _)| ext == indicator)
+            .map_or(&[], |(_, codes)| codes.as_slice())
+    }
+    fn get_risk_stats(&self) -> Option<&RiskStats> {
+        self.entry.risk_stats.as_ref()
+    }
+    fn get_market_regime(&self) -> Option<&MarketRegime> {
+        self.entry.market_regime.as_ref()
+    }
+    fn get_score_history(&self) -> Option<&ScoreHistory> {
+        self.entry.score_history.as_ref()
+    }
+    fn get_score_smoothing(&self) -> Option<&ScoreSmoothing> {
+        self.entry.score_smoothing.as_ref()
+    }
+    fn get_ema_short(&self) -> f64 {
+        self.entry.ema_short
+    }
+    fn get_ema_long(&self) -> f64 {
+        self.entry.ema_long
+    }
+    fn get_ema_crosses(&self) -> &[CrossEvent] {
+        &self.entry.ema_crosses
+    }
+    fn get_ema_score(&self) -> Option<f64> {
+        self.entry.ema_score
+    }
+    fn get_sma_short(&self) -> f64 {
+        self.entry.sma_short
+    }
+    fn get_sma_long(&self) -> f64 {
+        self.entry.sma_long
+    }
+    fn get_sma_crosses(&self) -> &[CrossEvent] {
+        &self.entry.sma_crosses
+    }
+    fn get_sma_score(&self) -> Option<f64> {
+        self.entry.sma_score
+    }
+    fn get_adx(&self) -> Option<f64> {
+        self.entry.adx
+    }
+    fn get_adx_score(&self) -> Option<f64> {
+        self.entry.adx_score
+    }
+    fn get_roc(&self) -> Option<f64> {
+        self.entry.roc
+    }
+    fn get_roc_score(&self) -> Option<f64> {
+        self.entry.roc_score
+    }
+    fn get_stochastics_k(&self) -> Option<f64> {
+        self.entry.stochastics_k
+    }
+    fn get_stochastics_d(&self) -> Option<f64> {
+        self.entry.stochastics_d
+    }
+    fn get_stoch_crosses(&self) -> &[CrossEvent] {
+        &self.entry.stoch_crosses
+    }
+    fn get_stochastics_score(&self) -> Option<f64> {
+        self.entry.stochastics_score
+    }
+    fn get_bb_upper(&self) -> f64 {
+        self.entry.bb_upper
+    }
+    fn get_bb_lower(&self) -> f64 {
+        self.entry.bb_lower
+    }
+    fn get_bb_percent_b(&self) -> f64 {
+        self.entry.bb_percent_b
+    }
+    fn get_bb_bandwidth(&self) -> f64 {
+        self.entry.bb_bandwidth
+    }
+    fn get_bollinger_score(&self) -> Option<f64> {
+        self.entry.bollinger_score
+    }
+    fn get_fibo_38_2(&self) -> Option<f64> {
+        self.entry.fibo_38_2
+    }
+    fn get_fibo_50_0(&self) -> Option<f64> {
+        self.entry.fibo_50_0
+    }
+    fn get_fibo_61_8(&self) -> Option<f64> {
+        self.entry.fibo_61_8
+    }
+    fn get_fibo_23_6(&self) -> Option<f64> {
+        self.entry.fibo_23_6
+    }
+    fn get_fibo_78_6(&self) -> Option<f64> {
+        self.entry.fibo_78_6
+    }
+    fn get_fibo_ext_127_2(&self) -> Option<f64> {
+        self.entry.fibo_ext_127_2
+    }
+    fn get_fibo_ext_161_8(&self) -> Option<f64> {
+        self.entry.fibo_ext_161_8
+    }
+    fn get_fib_swing(&self) -> Option<&FibonacciSwing> {
+        self.entry.fib_swing.as_ref()
+    }
+    fn get_fib_depth(&self) -> Option<f64> {
+        self.entry.fib_depth
+    }
+    fn get_fibonacci_score(&self) -> Option<f64> {
+        self.entry.fibonacci_score
+    }
+    ///フィボナッチリトレースメントライン（38.2%、50%、61.8%）と 終値（close）を比較する必要がある
+    fn get_fib_close(&self) -> f64 {
+        self.entry.close
+    }
+    fn get_vwap(&self) -> Option<f64> {
+        self.entry.vwap
+    }
+    fn get_vwap_score(&self) -> Option<f64> {
+        self.entry.vwap_score
+    }
+    fn get_tenkan_sen(&self) -> Option<f64> {
+        self.entry.tenkan_sen
+    }
+    fn get_kijun_sen(&self) -> Option<f64> {
+        self.entry.kijun_sen
+    }
+    fn get_ichimoku_crosses(&self) -> &[CrossEvent] {
+        &self.entry.ichimoku_crosses
+    }
+    fn get_ichimoku_score(&self) -> Option<f64> {
+        self.entry.ichimoku_score
+    }
+    fn get_candle_patterns(&self) -> &[CandlePattern] {
+        &self.entry.candle_patterns
+    }
+    fn get_candlestick_score(&self) -> Option<f64> {
+        self.entry.candlestick_score
+    }
+    fn get_sr_levels(&self) -> &[PriceLevel] {
+        &self.entry.sr_levels
+    }
+    fn get_nearest_support(&self) -> Option<&PriceLevel> {
+        self.entry.nearest_support.as_ref()
+    }
+    fn get_nearest_resistance(&self) -> Option<&PriceLevel> {
+        self.entry.nearest_resistance.as_ref()
+    }
+    fn get_support_resistance_score(&self) -> Option<f64> {
+        self.entry.support_resistance_score
+    }
+    fn get_relative_strength(&self) -> Option<&RelativeStrengthStats> {
+        self.entry.relative_strength.as_ref()
+    }
+    fn get_relative_strength_score(&self) -> Option<f64> {
+        self.entry.relative_strength_score
+    }
+    fn get_custom_result(&self, name: &str) -> Option<&CustomIndicatorResult> {
+        self.entry.custom_results.iter().find(|r| r.name == name)
+    }
+    fn get_plugin_outcome(&self, name: &str) -> Option<&PluginOutcome> {
+        self.entry.plugin_outcomes.iter().find(|r| r.name == name)
+    }
+    fn get_indicator_status(&self, name: &str) -> Option<&IndicatorStatus> {
+        self.entry
+            .indicator_statuses
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, status)| status)
+    }
+    fn get_indicator_statuses(&self) -> &[(String, IndicatorStatus)] {
+        &self.entry.indicator_statuses
+    }
+    /// プラグインのスコア（失敗・未実行は None）
+    fn get_plugin_score(&self, name: &str) -> Option<f64> {
+        self.get_plugin_outcome(name)
+            .and_then(|o| o.result.as_ref().ok())
+            .map(|out| out.score)
+    }
+}
+
+///ファイル読み込み時に通す共通チェック関数
+fn sanitize_ascii_file_lines(path: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let content = read_to_string(path)
+        .map_err(|e| format!("❌ ファイル読み込み失敗: {} ({})", path.display(), e))?;
+    let mut result = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line_number = i + 1;
+        // ✅ 1行目に限って先頭BOMを除去
+        let line = if line_number == 1 {
+            if let Some(stripped) = line.strip_prefix('\u{FEFF}') {
+                stripped
+            } else {
+                line
+            }
+        } else {
+            line
+        };
+        if line.len() > 500 {
+            return Err(format!(
+                "❌ ファイル {} の {}行目: 行が長すぎます（{}文字）",
+                path.display(),
+                line_number,
+                line.len()
+            )
+            .into());
+        }
+        if line.contains('\0') {
+            return Err(format!(
+                "❌ ファイル {} の {}行目: NULLバイトが含まれています",
+                path.display(),
+                line_number
+            )
+            .into());
+        }
+        if line
+            .chars()
+            .any(|c| c.is_control() && c != '\n' && c != '\r')
+        {
+            return Err(format!(
+                "❌ ファイル {} の {}行目: 制御文字を含んでいます",
+                path.display(),
+                line_number
+            )
+            .into());
+        }
+        if line.contains('\u{FEFF}') {
+            return Err(format!(
+                "❌ ファイル {} の {}行目: BOM（Byte Order Mark）が含まれています",
+                path.display(),
+                line_number
+            )
+            .into());
+        }
+        result.push(line.to_string());
+    }
+    Ok(result)
+}
+
+fn resolve_hardcoded_info(ticker: &str) -> Option<HardcodedInfo> {
+    match ticker {
+        "QQQ" => Some(HardcodedInfo {
+            formal_name: "Invesco QQQ Trust (NASDAQ100)",
+            // query: "QQQ OR \"NASDAQ100\"",
+        }),
+        "SPY" => Some(HardcodedInfo {
+            formal_name: "SPDR S&P 500 ETF Trust (S&P500)",
+            //query: "SPY OR \"S&P500\"",
+        }),
+        "ACWI" => Some(HardcodedInfo {
+            formal_name: "iShares MSCI ACWI ETF (オールカントリー)",
+            //query: "ACWI OR \"オールカントリー\"",
+        }),
+        "FANG+" => Some(HardcodedInfo {
+            formal_name: "NYSE FANG+ Index",
+            //query: "FANG+ OR \"FANGプラス指数\"",
+        }),
+        _ => None,
+    }
+}
+
+/// 環境変数と設定の初期化処理
+fn initialize_environment_and_config() -> BuildCfgResult {
+    // 方針：tickwise.env を読み込み一般設定は env にセットするが、
+    // セキュリティ上 API キー（OPENAI_API_KEY / BRAVE_API_KEY）はここでセットしない。
+    let env_path = Path::new("tickwise.env");
+    match sanitize_ascii_file_lines(env_path) {
+        Ok(lines) => {
+            let content = lines.join("\n");
+            for raw in content.lines() {
+                let mut line = raw.trim().to_string();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                if let Some(rest) = line.strip_prefix("export ") {
+                    line = rest.trim().to_string();
+                }
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                if let Some(idx) = line.find('=') {
+                    let key = line[..idx].trim();
+                    // セキュリティ方針：APIキーはここで永続的にセットしない（送信直前に参照）。
+                    if key.eq_ignore_ascii_case("OPENAI_API_KEY")
+                        || key.eq_ignore_ascii_case("BRAVE_API_KEY")
+                    {
+                        continue;
+                    }
+                    let raw_val = line[idx + 1..].trim();
+                    let val = if raw_val.starts_with('"') {
+                        if raw_val.len() >= 2 && raw_val.ends_with('"') {
+                            raw_val[1..raw_val.len() - 1].to_string()
+                        } else if let Some(pos) = raw_val.rfind('"') {
+                            raw_val[1..pos].to_string()
+                        } else {
+                            raw_val.trim_matches('"').to_string()
+                        }
+                    } else if let Some(pound) = raw_val.find('#') {
+                        raw_val[..pound].trim_end().to_string()
+                    } else {
+                        raw_val.to_string()
+                    };
+                    if !key.is_empty() {
+                        std::env::set_var(key, val);
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("⚠️ tickwise.env の読み込み/サニタイズに失敗しました（無視されます）: {}", e);
+        }
+    }
+
+    // 以下は既存処理（args 解析, build_config 等）
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    args.cli_explicit = matches
+        .ids()
+        .filter(|id| matches.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+        .map(|id| id.to_string())
+        .collect();
+
+    if args.show_log_header {
+        let config = build_config(&args);
+        generate_csv_header(&config);
+        std::process::exit(0);
+    }
+
+    if args.list_presets {
+        match load_presets(resolve_presets_file(&args).as_deref()) {
+            Ok(presets) => print_preset_list(&presets),
+            Err(e) => {
+                eprintln!("❌ プリセットを読み込めません: {}", e);
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
+
+    if let Some(path) = &args.validate_signal_rules {
+        if let Err(e) = validate_signal_rules_file(path) {
+            eprintln!("❌ シグナルルール {} が不正です: {}", path, e);
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+
+    // ウォッチリスト指定時は --ticker を省略できる（先頭の銘柄を代表として設定を組み立てる）
+    let mut watchlist = match &args.watchlist {
+        Some(path) => load_watchlist(Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+        None => Vec::new(),
+    };
+    // -t にカンマ区切りで複数銘柄を渡した場合もウォッチリストとして扱う
+    if let Some(list) = args.ticker.clone().filter(|t| t.contains(',')) {
+        for raw in list.split(',').filter(|t| !t.trim().is_empty()) {
+            let cleaned = sanitize_ticker(raw).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(1);
+            });
+            let ticker = normalize_ticker(&normalize_ticker_input(&cleaned));
+            if !watchlist.contains(&ticker) {
+                watchlist.push(ticker);
+            }
+        }
+        args.ticker = watchlist.first().cloned();
+    }
+    if args.ticker.is_none() {
+        args.ticker = watchlist.first().cloned();
+    }
+    // 台帳の報告・初期化は銘柄を使わない（設定を組み立てるための仮の銘柄）
+    if args.ticker.is_none() && matches!(args.paper.as_deref(), Some("report" | "reset")) {
+        args.ticker = Some("SPY".to_string());
+    }
+
+    let raw_ticker = match args.ticker {
+        Some(ref t) => t.clone(),
+        None => {
+            eprintln!("❌ --ticker は必須です");
+            std::process::exit(1);
+        }
+    };
+
+    args.ticker = Some(sanitize_ticker(&raw_ticker).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }));
+    args.ticker = Some(normalize_ticker_input(args.ticker.as_deref().unwrap_or("")));
+    args.ticker = Some(normalize_ticker(args.ticker.as_deref().unwrap_or("")));
+
+    if let Some(q) = &args.custom_news_query {
+        args.custom_news_query = Some(sanitize_news_query(q).unwrap_or_else(|err| {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }));
+    }
+    if let Some(n) = &args.openai_extra_note {
+        args.openai_extra_note = Some(sanitize_llm_note(n).unwrap_or_else(|err| {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }));
+    }
+
+    let mut config = build_config(&args);
+    config.watchlist = watchlist;
+    if let Some(s) = args.openai_api_key.as_mut() {
+        s.zeroize();
+        s.clear();
+    }
+    if let Some(s) = args.brave_api_key.as_mut() {
+        s.zeroize();
+        s.clear();
+    }
+
+
+    if config.debug_args {
+        if config.no_env_indicators {
+            eprintln!("ℹ️ --no-env-indicators: 指標関連の設定は tickwise.env から読み込みません");
+        }
+        eprintln!("Config= {}", config_debug_string(&config));
+    }
+
+
+    let ticker = config.ticker.clone();
+
+    let ticker_name_map = match &config.alias_csv {
+        Some(csv_path) => load_alias_csv(csv_path)?,
+        None => HashMap::new(),
+    };
+
+    Ok((config, ticker, ticker_name_map))
+}
+
+/// ニュース取得のコントローラ関数
+async fn news_flow_controller(
+    guard: &TechnicalDataGuard,
+    config: &Config,
+) -> Result<Vec<Article>, Box<dyn std::error::Error>> {
+    let articles = fetch_news_articles(guard, config).await;
+    display_news(guard, config, &articles);
+    Ok(articles)
+}
+
+/// ニュース記事の取得のみ（画面には出さない。一括実行では銘柄ごとに並行して呼ぶ）
+async fn fetch_news_articles(guard: &TechnicalDataGuard, config: &Config) -> Vec<Article> {
+    // 1) CLI のキーを優先
+    if !config.brave_api_key.trim().is_empty() {
+        let key_owned = Zeroizing::new(config.brave_api_key.clone());
+        let fetched = run_news_once(guard, config, Some(&*key_owned)).await.unwrap_or_default();
+        drop(key_owned);
+        return fetched;
+    }
+
+    // 2) CLI に無ければ tickwise.env を直前に堅牢に参照
+    let env_path = Path::new("tickwise.env");
+    let mut found_key: Option<String> = None;
+    if let Ok(lines) = sanitize_ascii_file_lines(env_path) {
+        for raw in lines {
+            let mut line = raw.trim().to_string();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(rest) = line.strip_prefix("export ") {
+                line = rest.trim().to_string();
+            }
+            if let Some(idx) = line.find('=') {
+                let k = line[..idx].trim();
+                if k.eq_ignore_ascii_case("BRAVE_API_KEY") {
+                    let raw_val = line[idx + 1..].trim();
+                    let v = if raw_val.starts_with('"') {
+                        if raw_val.len() >= 2 && raw_val.ends_with('"') {
+                            raw_val[1..raw_val.len() - 1].to_string()
+                        } else if let Some(pos) = raw_val.rfind('"') {
+                            raw_val[1..pos].to_string()
+                        } else {
+                            raw_val.trim_matches('"').to_string()
+                        }
+                    } else if let Some(pound) = raw_val.find('#') {
+                        raw_val[..pound].trim_end().to_string()
+                    } else {
+                        raw_val.to_string()
+                    };
+                    if !v.is_empty() {
+                        found_key = Some(v);
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    if let Some(k) = found_key {
+        let key_owned = Zeroizing::new(k);
+        let fetched = run_news_once(guard, config, Some(&*key_owned)).await.unwrap_or_default();
+        drop(key_owned);
+        fetched
+    } else {
+        Vec::new()
+    }
+}
+
+/// 取得済みのニュースを画面に出す（--show-news 時のみ）
+fn display_news(guard: &TechnicalDataGuard, config: &Config, articles: &[Article]) {
+    if !config.show_news {
+        return;
+    }
+    // CLI でキーを渡していない場合、0件はキー未設定とみなす
+    if articles.is_empty() && config.brave_api_key.trim().is_empty() {
+        println!("【注記】ニュース検索は BRAVE_API_KEY 未設定のためスキップ。");
+    } else {
+        print_lines_to_terminal(&compose_news_lines(guard, config, articles));
+    }
+}
+
+// 追加: debug 出力用（キーを露出しない、安全な表現を返す）
+fn config_debug_string(cfg: &Config) -> String {
+    let mut tmp = cfg.clone();
+    tmp.openai_api_key = if tmp.openai_api_key.trim().is_empty() {
+        "<UNSET>".to_string()
+    } else {
+        "<REDACTED_OPENAI_KEY>".to_string()
+    };
+    tmp.brave_api_key = if tmp.brave_api_key.trim().is_empty() {
+        "<UNSET>".to_string()
+    } else {
+        "<REDACTED_BRAVE_KEY>".to_string()
+    };
+    format!("{:?}", tmp)
+}
+
+/// インデックスティッカーの変換
+/// 例: "S&P500" → "SPY"
+fn normalize_ticker_input(raw: &str) -> String {
+    match raw.trim().to_uppercase().as_str() {
+        // 米国インデックス
+        "S&P500" | "SNP500" | "SP500" => "SPY".to_string(), // S&P500 ETF（最も一般的）
+        "NASDAQ100" | "ナスダック100" => "QQQ".to_string(), // NASDAQ100 ETF
+        "DOW" | "DJIA" | "ダウ平均" => "DIA".to_string(),   // ダウ平均 ETF
+
+        // 日本インデックス
+        "日経平均" | "NIKKEI225" => "1321.T".to_string(), // 日経平均連動ETF
+        "TOPIX" => "1306.T".to_string(),                  // TOPIX連動ETF
+
+        // 全世界・全米
+        "全世界" | "オールカントリー" | "ACWI" => "ACWI".to_string(), // 全世界ETF
+        "全米" | "VTI" | "トータルマーケット" => "VTI".to_string(),   // 全米市場ETF
+
+        // FANG+インデックス
+        "FANG+" | "FANGプラス" => "FNGU".to_string(),
+        _ => raw.to_string(),
+    }
+}
+/// ティッカーのサニタイズ
+fn sanitize_ticker(t: &str) -> Result<String, &'static str> {
+    let cleaned = t.trim().to_uppercase();
+    // 英数字と.Tのみを許可
+    if !cleaned
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '.' || c == 'T')
+    {
+        eprintln!("❌ 無効なティッカー形式: {t}");
+        return Err("❌ ティッカーは英数字と.Tのみ使用可能です");
+    }
+    Ok(cleaned)
+}
+/// ウォッチリストの読み込み（1行1銘柄、カンマ区切りも可。# 以降はコメント）。
+/// 各銘柄は --ticker と同じサニタイズ・正規化を行い、重複は除く
+fn load_watchlist(path: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut tickers: Vec<String> = Vec::new();
+    for (i, line) in sanitize_ascii_file_lines(path)?.iter().enumerate() {
+        let body = line.split('#').next().unwrap_or("");
+        for raw in body.split([',', ' ']).filter(|t| !t.trim().is_empty()) {
+            let cleaned = sanitize_ticker(raw).map_err(|e| {
+                format!("❌ ウォッチリスト {} の {}行目: {}", path.display(), i + 1, e.trim_start_matches("❌ "))
+            })?;
+            let ticker = normalize_ticker(&normalize_ticker_input(&cleaned));
+            if !tickers.contains(&ticker) {
+                tickers.push(ticker);
+            }
+        }
+    }
+    if tickers.is_empty() {
+        return Err(format!("❌ ウォッチリスト {} に銘柄がありません", path.display()).into());
+    }
+    Ok(tickers)
+}
+/// ニュースクエリのサニタイズ
+fn sanitize_news_query(q: &str) -> Result<String, &'static str> {
+    if q.len() > 200 {
+        return Err("❌ ニュースクエリは200文字以内で入力してください");
+    }
+    if q.contains([';', '|', '`']) {
+        return Err("❌ ニュースクエリに使用できない文字が含まれています");
+    }
+    Ok(q.trim().to_string())
+}
+/// LLMノートのサニタイズ
+fn sanitize_llm_note(note: &str) -> Result<String, &'static str> {
+    // 危険な記号のみ禁止
+    if note.contains([';', '|', '`']) {
+        return Err("❌ 使用できない記号（; | `）が含まれています");
+    }
+    let cleaned = note
+        .trim()
+        .replace('\n', " ")
+        .replace(|c: char| c.is_whitespace(), " ");
+    if cleaned.len() > 2000 {
+        return Err("❌ 入力が長すぎます（2000文字以内で入力してください）");
+    }
+    Ok(cleaned)
+}
+
+/// missing-score-policy setting
+fn parse_missing_score_policy(policy: &str) -> MissingScorePolicy {
+    match policy {
+        "neutral" => MissingScorePolicy::Neutral,
+        "fail" => MissingScorePolicy::Fail,
+        _ => MissingScorePolicy::Exclude, // 省略は exclude にフォールバック
+    }
+}
+
+/// stance setting
+fn parse_stance(stance: &str) -> Stance {
+    match stance {
+        "buyer" => Stance::Buyer,
+        "seller" => Stance::Seller,
+        _ => Stance::Holder, // 省略は holder にフォールバック
+    }
+}
+
+/// ユーザー定義プリセットのファイル：CLI > 環境変数
+fn resolve_presets_file(args: &Args) -> Option<String> {
+    args.presets_file.clone().or_else(|| {
+        if args.no_env_indicators {
+            None
+        } else {
+            env::var("PRESETS_FILE").ok().filter(|p| !p.trim().is_empty())
+        }
+    })
+}
+
+/// コンフィグの構築
+fn build_config(args: &Args) -> Config {
+    // カスタム指標ファイル：CLI > 環境変数（読み込み失敗時は警告して無効化）
+    let custom_indicators_path = args.custom_indicators.clone().or_else(|| {
+        if args.no_env_indicators {
+            None
+        } else {
+            env::var("CUSTOM_INDICATORS").ok().filter(|p| !p.trim().is_empty())
+        }
+    });
+    let custom_indicators = match custom_indicators_path {
+        Some(path) => load_custom_indicators(&path).unwrap_or_else(|e| {
+            eprintln!("⚠️ カスタム指標ファイル {} を読み込めません（無視されます）: {}", path, e);
+            Vec::new()
+        }),
+        None => Vec::new(),
+    };
+    // プラグイン定義ファイル：CLI > 環境変数（読み込み失敗時は警告して無効化）
+    let plugins_path = args.plugins.clone().or_else(|| {
+        if args.no_env_indicators {
+            None
+        } else {
+            env::var("PLUGINS").ok().filter(|p| !p.trim().is_empty())
+        }
+    });
+    let plugins = match plugins_path {
+        Some(path) => load_plugin_specs(&path).unwrap_or_else(|e| {
+            eprintln!("⚠️ プラグイン定義ファイル {} を読み込めません（無視されます）: {}", path, e);
+            Vec::new()
+        }),
+        None => Vec::new(),
+    };
+    // 基本シグナルのルールファイル：CLI > 環境変数（読み込み失敗時は警告して既定ルール）
+    let signal_rules_path = args.signal_rules.clone().or_else(|| {
+        if args.no_env_indicators {
+            None
+        } else {
+            env::var("SIGNAL_RULES").ok().filter(|p| !p.trim().is_empty())
+        }
+    });
+    let signal_rules = match signal_rules_path {
+        Some(path) => load_signal_rules(&path).unwrap_or_else(|e| {
+            eprintln!("⚠️ シグナルルール {} を読み込めません（既定ルールを使用します）: {}", path, e);
+            default_signal_rules()
+        }),
+        None => default_signal_rules(),
+    };
+
+    // ベンチマーク：CLI > 環境変数（未指定なら取引所から自動選択）
+    let configured_benchmark = args
+        .benchmark
+        .clone()
+        .or_else(|| {
+            if args.no_env_indicators {
+                None
+            } else {
+                env::var("BENCHMARK").ok()
+            }
+        })
+        .filter(|b| !b.trim().is_empty());
+
+    let mut config = Config {
+        preset: None,
+        debug_args: args.debug_args,
+        no_env_indicators: args.no_env_indicators,
+
+        // テクニカル閾値
+        buy_rsi: if args.buy_rsi == 30.0 {
+            if args.no_env_indicators {
+                30.0
+            } else {
+                env::var("BUY_RSI")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(30.0)
+            }
+        } else {
+            args.buy_rsi
+        },
+        sell_rsi: if args.sell_rsi == 70.0 {
+            if args.no_env_indicators {
+                70.0
+            } else {
+                env::var("SELL_RSI")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(70.0)
+            }
+        } else {
+            args.sell_rsi
+        },
+        macd_diff_low: if args.macd_diff_low == 0.1 {
+            if args.no_env_indicators {
+                0.1
+            } else {
+                get_macd_diff_pct_env("MACD_DIFF_LOW_PCT", "MACD_DIFF_LOW", 0.1)
+            }
+        } else {
+            args.macd_diff_low
+        },
+        macd_diff_mid: if args.macd_diff_mid == 0.5 {
+            if args.no_env_indicators {
+                0.5
+            } else {
+                get_macd_diff_pct_env("MACD_DIFF_MID_PCT", "MACD_DIFF_MID", 0.5)
+            }
+        } else {
+            args.macd_diff_mid
+        },
+        // MACDマイナス圏フラグ
+        macd_minus_ok: if args.no_env_indicators {
+            args.macd_minus_ok
+        } else {
+            args.macd_minus_ok || get_bool_env("MACD_MINUS_OK")
+        },
+        // 投資スタンス設定
+                stance: {
+            let stance_source = if args.no_env_indicators {
+                args.stance.clone()
+            } else if args.stance == "holder" {
+                std::env::var("STANCE").unwrap_or_else(|_| "holder".to_string())
+            } else {
+                args.stance.clone()
+            };
+            parse_stance(&stance_source)
+        },
+        regime_weighting: if args.no_env_indicators {
+            args.regime_weighting
+        } else {
+            args.regime_weighting || get_bool_env("REGIME_WEIGHTING")
+        },
+        missing_score_policy: {
+            let policy_source = if args.no_env_indicators || args.missing_score_policy != "exclude" {
+                args.missing_score_policy.clone()
+            } else {
+                std::env::var("MISSING_SCORE_POLICY").unwrap_or_else(|_| "exclude".to_string())
+            };
+            parse_missing_score_policy(policy_source.trim())
+        },
+        smoothing_bars: if args.no_env_indicators {
+            args.smoothing_bars
+        } else {
+            get_usize_from_args_or_env(args.smoothing_bars, "SCORE_SMOOTHING_BARS", 0)
+        },
+        // CLI で指定した値（0 を含む）は環境変数より優先
+        hysteresis: sanitize_percent(
+            match args.hysteresis {
+                Some(value) => value,
+                None if args.no_env_indicators => 0.0,
+                None => match env::var("SCORE_HYSTERESIS") {
+                    Ok(raw) => raw.trim().parse().unwrap_or_else(|_| {
+                        eprintln!(
+                            "⚠️ 環境変数 SCORE_HYSTERESIS の値が無効（f64パース失敗）: {}。0.0（無効）を使用します。",
+                            raw
+                        );
+                        0.0
+                    }),
+                    Err(_) => 0.0,
+                },
+            },
+            0.0,
+            0.2,
+            "hysteresis",
+        ),
+        backtest: args.backtest,
+        bt_range: if args.bt_range == "2y" {
+            // CLI と同じ候補以外は既定に戻す
+            env::var("BT_RANGE")
+                .ok()
+                .filter(|v| ["6mo", "1y", "2y", "5y", "10y", "max"].contains(&v.as_str()))
+                .unwrap_or_else(|| "2y".to_string())
+        } else {
+            args.bt_range.clone()
+        },
+        bt_rule: {
+            let rule = if args.bt_rule == "threshold" {
+                env::var("BT_RULE").unwrap_or_else(|_| "threshold".to_string())
+            } else {
+                args.bt_rule.clone()
+            };
+            match rule.trim() {
+                "stance" => BacktestRule::Stance,
+                _ => BacktestRule::Threshold, // 省略は threshold にフォールバック
+            }
+        },
+        bt_entry: sanitize_percent(
+            if args.bt_entry == 0.2 {
+                env::var("BT_ENTRY")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0.2)
+            } else {
+                args.bt_entry
+            },
+            -1.0,
+            1.0,
+            "bt-entry",
+        ),
+        bt_exit: sanitize_percent(
+            if args.bt_exit == -0.2 {
+                env::var("BT_EXIT")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(-0.2)
+            } else {
+                args.bt_exit
+            },
+            -1.0,
+            1.0,
+            "bt-exit",
+        ),
+        bt_fee_pct: sanitize_percent(
+            if args.bt_fee_pct == 0.1 {
+                env::var("BT_FEE_PCT")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0.1)
+            } else {
+                args.bt_fee_pct
+            },
+            0.0,
+            5.0,
+            "bt-fee-pct",
+        ),
+        bt_slippage_pct: sanitize_percent(
+            if args.bt_slippage_pct == 0.05 {
+                env::var("BT_SLIPPAGE_PCT")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0.05)
+            } else {
+                args.bt_slippage_pct
+            },
+            0.0,
+            5.0,
+            "bt-slippage-pct",
+        ),
+        optimize: args.optimize,
+        opt_method: {
+            let method = if args.opt_method == "random" {
+                env::var("OPT_METHOD").unwrap_or_else(|_| "random".to_string())
+            } else {
+                args.opt_method.clone()
+            };
+            match method.trim() {
+                "grid" => OptMethod::Grid,
+                _ => OptMethod::Random,
+            }
+        },
+        opt_trials: get_usize_from_args_or_env(args.opt_trials, "OPT_TRIALS", 100).max(1),
+        opt_folds: get_usize_from_args_or_env(args.opt_folds, "OPT_FOLDS", 3).clamp(1, 10),
+        opt_metric: {
+            let metric = if args.opt_metric == "return" {
+                env::var("OPT_METRIC").unwrap_or_else(|_| "return".to_string())
+            } else {
+                args.opt_metric.clone()
+            };
+            match metric.trim() {
+                "return-dd" => OptMetric::ReturnDrawdown,
+                _ => OptMetric::Return,
+            }
+        },
+        opt_seed: if args.opt_seed == 42 {
+            env::var("OPT_SEED")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(42)
+        } else {
+            args.opt_seed
+        },
+        opt_output: if args.opt_output == "env" {
+            match env::var("OPT_OUTPUT").as_deref() {
+                Ok("preset") => "preset".to_string(),
+                _ => "env".to_string(),
+            }
+        } else {
+            args.opt_output.clone()
+        },
+        hit_rate: args.hit_rate,
+        track_outcomes: args.track_outcomes,
+        watchlist: Vec::new(), // 初期化時に読み込む
+        watchlist_file: args.watchlist.clone(),
+        concurrency: get_usize_from_args_or_env(args.concurrency, "BATCH_CONCURRENCY", 4).clamp(1, 16),
+        paper: args.paper.as_deref().map(|action| match action {
+            "buy" => PaperAction::Buy,
+            "sell" => PaperAction::Sell,
+            "report" => PaperAction::Report,
+            "reset" => PaperAction::Reset,
+            _ => PaperAction::Auto,
+        }),
+        paper_file: args
+            .paper_file
+            .clone()
+            .or_else(|| env::var("PAPER_FILE").ok().filter(|v| !v.trim().is_empty())),
+        paper_cash: sanitize_percent(
+            if args.paper_cash == 1_000_000.0 {
+                env::var("PAPER_CASH")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(1_000_000.0)
+            } else {
+                args.paper_cash
+            },
+            1.0,
+            1e12,
+            "paper-cash",
+        ),
+        paper_size_pct: sanitize_percent(
+            if args.paper_size_pct == 20.0 {
+                env::var("PAPER_SIZE_PCT")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(20.0)
+            } else {
+                args.paper_size_pct
+            },
+            1.0,
+            100.0,
+            "paper-size-pct",
+        ),
+        paper_units: args.paper_units.filter(|&n| n > 0),
+
+
+        // Weight設定
+        weight_basic: if args.no_env_indicators {
+            args.weight_basic
+        } else {
+            get_f64_from_args_or_env(args.weight_basic, "WEIGHT_BASIC", 1.0)
+        },
+        weight_ema: if args.no_env_indicators {
+            args.weight_ema
+        } else {
+            get_f64_from_args_or_env(args.weight_ema, "WEIGHT_EMA", 1.0)
+        },
+        weight_sma: if args.no_env_indicators {
+            args.weight_sma
+        } else {
+            get_f64_from_args_or_env(args.weight_sma, "WEIGHT_SMA", 1.0)
+        },
+        weight_bollinger: if args.no_env_indicators {
+            args.weight_bollinger
+        } else {
+            get_f64_from_args_or_env(args.weight_bollinger, "WEIGHT_BOLLINGER", 1.0)
+        },
+        weight_roc: if args.no_env_indicators {
+            args.weight_roc
+        } else {
+            get_f64_from_args_or_env(args.weight_roc, "WEIGHT_ROC", 1.0)
+        },
+        weight_adx: if args.no_env_indicators {
+            args.weight_adx
+        } else {
+            get_f64_from_args_or_env(args.weight_adx, "WEIGHT_ADX", 1.0)
+        },
+        weight_stochastics: if args.no_env_indicators {
+            args.weight_stochastics
+        } else {
+            get_f64_from_args_or_env(args.weight_stochastics, "WEIGHT_STOCHASTICS", 1.0)
+        },
+        weight_fibonacci: if args.no_env_indicators {
+            args.weight_fibonacci
+        } else {
+            get_f64_from_args_or_env(args.weight_fibonacci, "WEIGHT_FIBONACCI", 1.0)
+        },
+        weight_vwap: if args.no_env_indicators {
+            args.weight_vwap
+        } else {
+            get_f64_from_args_or_env(args.weight_vwap, "WEIGHT_VWAP", 1.0)
+        },
+        weight_ichimoku: if args.no_env_indicators {
+            args.weight_ichimoku
+        } else {
+            get_f64_from_args_or_env(args.weight_ichimoku, "WEIGHT_ICHIMOKU", 1.0)
+        },
+        weight_candlestick: if args.no_env_indicators {
+            args.weight_candlestick
+        } else {
+            get_f64_from_args_or_env(args.weight_candlestick, "WEIGHT_CANDLESTICK", 1.0)
+        },
+        weight_support_resistance: if args.no_env_indicators {
+            args.weight_support_resistance
+        } else {
+            get_f64_from_args_or_env(
+                args.weight_support_resistance,
+                "WEIGHT_SUPPORT_RESISTANCE",
+                1.0,
+            )
+        },
+        weight_relative_strength: if args.no_env_indicators {
+            args.weight_relative_strength
+        } else {
+            get_f64_from_args_or_env(
+                args.weight_relative_strength,
+                "WEIGHT_RELATIVE_STRENGTH",
+                1.0,
+            )
+        },
+        // ベンチマーク：CLI > 環境変数 > 取引所から自動選択
+        benchmark: resolve_benchmark(
+            args.ticker.as_deref().unwrap_or("SPY"),
+            configured_benchmark.clone(),
+        ),
+        benchmark_auto: configured_benchmark.is_none(),
+        custom_indicators: custom_indicators.clone(),
+        signal_rules,
+        plugins: plugins.clone(),
+        // ✅ 拡張指標の選択（Vec<ExtensionIndicator> に変換）
+                enabled_extensions: {
+            let mut extensions = Vec::new();
+            if args.ema || (!args.no_env_indicators && get_bool_env("EMA")) {
+                extensions.push(ExtensionIndicator::Ema);
+            }
+            if args.sma || (!args.no_env_indicators && get_bool_env("SMA")) {
+                extensions.push(ExtensionIndicator::Sma);
+            }
+            if args.roc || (!args.no_env_indicators && get_bool_env("ROC")) {
+                extensions.push(ExtensionIndicator::Roc);
+            }
+            if args.adx || (!args.no_env_indicators && get_bool_env("ADX")) {
+                extensions.push(ExtensionIndicator::Adx);
+            }
+            if args.stochastics || (!args.no_env_indicators && get_bool_env("STOCHASTICS")) {
+                extensions.push(ExtensionIndicator::Stochastics);
+            }
+            if args.bollinger || (!args.no_env_indicators && get_bool_env("BOLLINGER")) {
+                extensions.push(ExtensionIndicator::Bollinger);
+            }
+            if args.fibonacci || (!args.no_env_indicators && get_bool_env("FIBONACCI")) {
+                extensions.push(ExtensionIndicator::Fibonacci);
+            }
+            if args.vwap || (!args.no_env_indicators && get_bool_env("VWAP")) {
+                extensions.push(ExtensionIndicator::Vwap);
+            }
+            if args.ichimoku || (!args.no_env_indicators && get_bool_env("ICHIMOKU")) {
+                extensions.push(ExtensionIndicator::Ichimoku);
+            }
+            if args.candlestick || (!args.no_env_indicators && get_bool_env("CANDLESTICK")) {
+                extensions.push(ExtensionIndicator::Candlestick);
+            }
+            if args.support_resistance
+                || (!args.no_env_indicators && get_bool_env("SUPPORT_RESISTANCE"))
+            {
+                extensions.push(ExtensionIndicator::SupportResistance);
+            }
+            if args.relative_strength
+                || (!args.no_env_indicators && get_bool_env("RELATIVE_STRENGTH"))
+            {
+                extensions.push(ExtensionIndicator::RelativeStrength);
+            }
+            extensions.extend((0..custom_indicators.len()).map(ExtensionIndicator::Custom));
+            extensions.extend((0..plugins.len()).map(ExtensionIndicator::Plugin));
+            extensions
+        },
+
+        bb_bandwidth_squeeze_pct: sanitize_percent(
+            if args.bb_bandwidth_squeeze_pct == 8.0 {
+                if args.no_env_indicators {
+                    8.0
+                } else {
+                    env::var("BB_BANDWIDTH_SQUEEZE_PCT")
+                        .ok()
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(8.0)
+                }
+            } else {
+                args.bb_bandwidth_squeeze_pct
+            },
+            0.0,
+            100.0,
+            "Bollinger bandwidth squeeze threshold (%)",
+        ),
+        // LLM設定
+        llm_provider: if args.llm_provider == "openai" {
+            std::env::var("LLM_PROVIDER").unwrap_or_else(|_| "openai".to_string())
+        } else {
+            args.llm_provider.clone()
+        },
+
+        // OpenAI設定
+        openai_model: if args.openai_model == "gpt-4.1-nano" {
+            env::var("OPENAI_MODEL").unwrap_or_else(|_| "gpt-4.1-nano".to_string())
+        } else {
+            args.openai_model.clone()
+        },
+        openai_api_key: {
+            if let Some(k) = &args.openai_api_key {
+                k.clone()
+            } else {
+                env::var("OPENAI_API_KEY").ok().unwrap_or_default()
+            }
+        },
+        brave_api_key: {
+            if let Some(k) = &args.brave_api_key {
+                k.clone()
+            } else {
+                std::env::var("BRAVE_API_KEY").ok().unwrap_or_default()
+            }
+        },
+        openai_extra_note: args
+            .openai_extra_note
+            .clone()
+            .or_else(|| env::var("OPENAI_EXTRA_NOTE").ok()),
+        // ニュース検索設定
+        no_news: args.no_news || get_bool_env("NO_NEWS"),
+        custom_news_query: args
+            .custom_news_query
+            .clone()
+            .or_else(|| env::var("CUSTOM_NEWS_QUERY").ok())
+            .filter(|s| !s.trim().is_empty()),
+
+        news_filter: args.news_filter || get_bool_env("NEWS_FILTER"),
+        show_news: args.show_news || get_bool_env("SHOW_NEWS"),
+
+        news_count: match args.news_count {
+            Some(n_count) => n_count.clamp(1, 50),
+            None => env::var("NEWS_COUNT")
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .map(|n_count| n_count.clamp(1, 50))
+                .unwrap_or(if args.news_filter || get_bool_env("NEWS_FILTER") {
+                    20
+                } else {
+                    50
+                }),
+        },
+
+        news_freshness: match args.news_freshness.clone() {
+            Some(s) => s,
+            None => env::var("NEWS_FRESHNESS").unwrap_or_else(|_| {
+                if args.news_filter || get_bool_env("NEWS_FILTER") {
+                    "pw".to_string()
+                } else {
+                    "pm".to_string()
+                }
+            }),
+        },
+
+        // ログ設定
+        save_technical_log: args.save_technical_log || get_bool_env("SAVE_TECHNICAL_LOG"),
+        log_format: if args.log_format != "csv" && args.log_format != "json" {
+            env::var("LOG_FORMAT").unwrap_or_else(|_| "csv".to_string())
+        } else {
+            args.log_format.clone()
+        },
+        log_dir: if args.log_dir == "log" {
+            env::var("LOG_DIR").unwrap_or_else(|_| "log".to_string())
+        } else {
+            args.log_dir.clone()
+        },
+        data_append: args.data_append || get_bool_env("CSV_APPEND"),
+        log_flat: args.log_flat || get_bool_env("LOG_FLAT"),
+        stdout_log: args.stdout_log,
+        series_output: args.series_output.clone(),
+        history_days: get_usize_from_args_or_env(args.history_days, "HISTORY_DAYS", 7),
+        silent: args.silent,
+
+        // プロンプト構成：数値設定
+        max_note_length: get_usize_from_args_or_env(args.max_note_length, "MAX_NOTE_LENGTH", 300),
+        max_shortterm_length: get_usize_from_args_or_env(
+            args.max_shortterm_length,
+            "MAX_SHORTTERM_LENGTH",
+            150,
+        ),
+        max_midterm_length: get_usize_from_args_or_env(
+            args.max_midterm_length,
+            "MAX_MIDTERM_LENGTH",
+            150,
+        ),
+        max_news_length: get_usize_from_args_or_env(args.max_news_length, "MAX_NEWS_LENGTH", 600),
+        max_review_length: get_usize_from_args_or_env(
+            args.max_review_length,
+            "MAX_REVIEW_LENGTH",
+            1000,
+        ),
+
+        // その他
+        ticker: args.ticker.clone().unwrap_or_else(|| "SPY".to_string()),
+
+        alias_csv: args
+            .alias_csv
+            .clone()
+            .or_else(|| env::var("ALIAS_CSV").ok()),
+        no_llm: args.no_llm || get_bool_env("NO_LLM"),
+        debug_prompt: args.debug_prompt || get_bool_env("DEBUG_PROMPT"),
+    };
+
+    // 戦略プリセット：CLI > 環境変数（未知の名前・読み込み失敗時は警告してプリセットなし）
+    let preset_name = args.preset.clone().or_else(|| {
+        if args.no_env_indicators {
+            None
+        } else {
+            env::var("PRESET").ok().filter(|p| !p.trim().is_empty())
+        }
+    });
+    if let Some(name) = preset_name {
+        match load_presets(resolve_presets_file(args).as_deref()) {
+            Ok(presets) => match presets.iter().find(|p| p.name == name.trim()) {
+                Some(preset) => apply_preset(&mut config, preset, args),
+                None => {
+                    let names: Vec<&str> = presets.iter().map(|p| p.name.as_str()).collect();
+                    eprintln!(
+                        "⚠️ プリセット {} は見つかりません（無視されます）。利用可能: {}",
+                        name,
+                        names.join(", ")
+                    );
+                }
+            },
+            Err(e) => eprintln!("⚠️ プリセットを読み込めません（無視されます）: {}", e),
+        }
+    }
+    config
+}
+
+/// MACD-Signal 乖離の閾値（終値比%）を環境変数から取得する。
+/// 旧キー（MACD_DIFF_LOW / MACD_DIFF_MID）は差の絶対値だったため単位が合わず、読まずに警告する
+fn get_macd_diff_pct_env(key: &str, legacy_key: &str, default: f64) -> f64 {
+    if env::var(legacy_key).is_ok() {
+        eprintln!(
+            "⚠️ 環境変数 {} は単位が変わったため読み込みません（MACDとSignalの差の絶対値 → 終値比%）。{} に終値比%で設定してください",
+            legacy_key, key
+        );
     }
-    fn set_macd(&mut self, value: f64) {
-        self.entry.macd = value;
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+///　環境変数からbool値（"true"/"false"）を取得し、bool型に変換する
+fn get_bool_env(key: &str) -> bool {
+    env::var(key)
+        .map(|v| v.trim().to_lowercase() == "true")
+        .unwrap_or(false)
+}
+/// CLI/env/defaultからf64値を取得し、0.5〜3.0範囲にサニタイズする
+fn get_f64_from_args_or_env(arg_val: f64, env_key: &str, default: f64) -> f64 {
+    // CLI引数がデフォルト値と異なる場合は優先
+    let value = if (arg_val - default).abs() > f64::EPSILON {
+        arg_val
+    } else {
+        match std::env::var(env_key) {
+            Ok(env_val) => match env_val.parse::<f64>() {
+                Ok(parsed) => parsed,
+                Err(_) => {
+                    eprintln!("⚠️ 環境変数 {} の値が無効（f64パース失敗）。デフォルト値({})を使用します。", env_key, default);
+                    default
+                }
+            },
+            Err(_) => default, // 環境変数未定義はデフォルト
+        }
+    };
+
+    // ✅ サニタイズチェック（ロジック内注釈で明記）
+    // - 型変換: .parse::<f64>() で文字列・桁落ち・超大数の誤入力をf64に変換（失敗時デフォルト）
+    // - NaN/inf: is_finite()で無効化
+    // - 負値: 0.0未満を無効化
+    // - 許容範囲: 0.5～3.0以外を無効化
+    if !value.is_finite() {
+        eprintln!(
+            "⚠️ 無効なweight値 (NaN/inf検出): {}。デフォルト値({})を使用します。",
+            value, default
+        );
+        return default;
     }
-    fn set_signal(&mut self, value: f64) {
-        self.entry.signal = value;
+
+    if value < 0.0 {
+        eprintln!(
+            "⚠️ 無効なweight値 (負の数): {}。デフォルト値({})を使用します。",
+            value, default
+        );
+        return default;
+    }
+
+    if !(0.5..=3.0).contains(&value) {
+        eprintln!(
+            "⚠️ 無効なweight値 (範囲外): {}。デフォルト値({})を使用します。",
+            value, default
+        );
+        return default;
+    }
+
+    value
+}
+
+/// 百分率用サニタイズ（0.0〜100.0 を許容）
+fn sanitize_percent(value: f64, min: f64, max: f64, label: &str) -> f64 {
+    if !value.is_finite() {
+        eprintln!(
+            "⚠️ Invalid {} (NaN/inf): {} -> clamped to {}",
+            label, value, min
+        );
+        return min;
+    }
+    let clamped_value = value.clamp(min, max);
+    if (clamped_value - value).abs() > f64::EPSILON {
+        eprintln!(
+            "⚠️ {} out of range: {} -> clamped to {}..={} (used {})",
+            label, value, min, max, clamped_value
+        );
     }
-    fn set_prev_macd(&mut self, value: f64) {
-        self.entry.prev_macd = value;
+    clamped_value
+}
+
+/// コマンドライン引数がデフォルト値なら環境変数から取得し、usizeで返す
+fn get_usize_from_args_or_env(arg_val: usize, env_key: &str, default: usize) -> usize {
+    if arg_val != default {
+        arg_val
+    } else {
+        get_usize_env(env_key, default)
     }
-    fn set_prev_signal(&mut self, value: f64) {
-        self.entry.prev_signal = value;
+}
+/// 環境変数からusize型の値を取得し、失敗時はデフォルト値を返す
+fn get_usize_env(key: &str, default: usize) -> usize {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+/// ティッカーの正規化　"9432.T" / "9432" -> "9432.T", "NVDA" などはそのまま
+fn normalize_ticker(raw: &str) -> String {
+    let up = raw.trim().to_ascii_uppercase();
+    if up.ends_with(".T") {
+        up
+    } else if up.ends_with(".t") {
+        up.trim_end_matches(".t").to_string() + ".T"
+    } else if up.len() == 4 && up.chars().all(|c| c.is_ascii_digit()) {
+        format!("{up}.T")
+    } else {
+        up
     }
-    fn set_signal_score(&mut self, value: f64) {
-        self.entry.signal_score = value;
+}
+
+/// JP Code 四桁Tickerの加工　"9432.T" / "9432" -> Some("9432"), "NVDA" などは None
+fn jp_code_from_ticker(t: &str) -> Option<String> {
+    let up = t.trim().to_ascii_uppercase();
+    if let Some(code) = up.strip_suffix(".T") {
+        return (code.len() == 4 && code.chars().all(|c| c.is_ascii_digit()))
+            .then(|| code.to_string());
     }
-    fn set_ema_short(&mut self, value: f64) {
-        self.entry.ema_short = value;
+    (up.len() == 4 && up.chars().all(|c| c.is_ascii_digit())).then_some(up)
+}
+
+/// 相対力の比較対象を決定する（指定があれば正規化して採用、なければ取引所から自動選択）
+/// 日本株 → TOPIX連動ETF（1306.T）、それ以外 → S&P500 ETF（SPY）
+fn resolve_benchmark(ticker: &str, configured: Option<String>) -> String {
+    let auto = if jp_code_from_ticker(ticker).is_some() {
+        "1306.T"
+    } else {
+        "SPY"
+    };
+    match configured.filter(|b| !b.trim().is_empty()) {
+        Some(raw) => match sanitize_ticker(&raw) {
+            Ok(cleaned) => normalize_ticker(&normalize_ticker_input(&cleaned)),
+            Err(_) => {
+                eprintln!("⚠️ ベンチマーク指定が無効のため {} を使用します", auto);
+                auto.to_string()
+            }
+        },
+        None => auto.to_string(),
     }
-    fn set_ema_long(&mut self, value: f64) {
-        self.entry.ema_long = value;
+}
+/// Yahoo Finance から市場データを取得する
+/// Yahoo v8/chart: use only meta.chartPreviousClose, meta.currency, indicators.quote[0].(o/h/l/c), timestamp. Do NOT use previousClose/regularMarket*/adjclose.
+async fn fetch_market_data(
+    ticker: &str,
+    range: &str,
+) -> Result<Vec<MarketData>, Box<dyn std::error::Error>> {
+    let ysym = if let Some(code) = jp_code_from_ticker(ticker) {
+        format!("{}.T", code)
+    } else {
+        ticker.trim().to_string()
+    };
+
+    let url = format!(
+        "https://query2.finance.yahoo.com/v8/finance/chart/{}?interval=1d&range={}",
+        urlencoding::encode(&ysym),
+        range
+    );
+
+    let client = Client::builder()
+        .user_agent("Mozilla/5.0 (Tickwise)")
+        .gzip(true)
+        .brotli(true)
+        .build()?;
+
+    let text = client
+        .get(&url)
+        .header("accept", "application/json")
+        .send()
+        .await?
+        .text()
+        .await?;
+
+    let json: Value = serde_json::from_str(&text)?;
+    if json.get("chart").is_none() || !json["chart"]["error"].is_null() {
+        return Err("❌ Yahoo /v8 chart からの取得に失敗しました。".into());
     }
-    fn set_ema_score(&mut self, value: f64) {
-        self.entry.ema_score = Some(value);
+
+    let result = json["chart"]["result"]
+        .as_array()
+        .ok_or("❌ chart.result 配列なし")?;
+    if result.is_empty() {
+        return Err("❌ chart.result が空です。".into());
     }
-    fn set_sma_short(&mut self, value: f64) {
-        self.entry.sma_short = value;
+
+    let r0 = &result[0];
+
+    let tz_name = r0["meta"]["exchangeTimezoneName"]
+        .as_str()
+        .unwrap_or("UTC")
+        .to_string();
+
+    // 取引所のIANAタイムゾーン名（exchangeTimezoneName）をTZDBで解決し、表示用TZを確定する
+    let tz_parsed: Result<Tz, _> = tz_name.parse();
+    let tz = match tz_parsed {
+        Ok(v) => v,
+        Err(_) => {
+            eprintln!("⚠️ exchangeTimezoneName parse failed: {} -> fallback to UTC", tz_name);
+            chrono_tz::UTC
+        }
+    };
+    
+    let currency = r0["meta"]["currency"].as_str().map(str::to_string);
+
+    let timestamps = r0["timestamp"]
+        .as_array()
+        .ok_or("❌ timestamp がありません。")?;
+    let q0 = &r0["indicators"]["quote"][0];
+    let opens = q0["open"].as_array().ok_or("❌ open がありません。")?;
+    let highs = q0["high"].as_array().ok_or("❌ high がありません。")?;
+    let lows = q0["low"].as_array().ok_or("❌ low がありません。")?;
+    let closes = q0["close"].as_array().ok_or("❌ close がありません。")?;
+
+    let n = timestamps
+        .len()
+        .min(opens.len())
+        .min(highs.len())
+        .min(lows.len())
+        .min(closes.len());
+
+    let mut out: Vec<MarketData> = Vec::with_capacity(n);
+
+    for i in 0..n {
+        let ts = match timestamps[i].as_i64() {
+            Some(v) => v,
+            None => continue,
+        };
+        
+        let (o, h, l, c) = (
+            opens[i].as_f64(),
+            highs[i].as_f64(),
+            lows[i].as_f64(),
+            closes[i].as_f64(),
+        );
+        if let (Some(o), Some(h), Some(l), Some(c)) = (o, h, l, c) {
+            // 取引所のIANAタイムゾーン名（exchangeTimezoneName）を取得し、表示に使うTZを確定する
+            let dt = tz
+                .timestamp_opt(ts, 0)
+                .single()
+                .ok_or("❌ timestamp 変換失敗")?;
+
+            let date = dt.format("%Y-%m-%d").to_string();
+            let datetime = dt.format("%Y-%m-%d %H:%M").to_string();
+
+            out.push(MarketData {
+                date,
+                datetime: Some(datetime),
+                timestamp: Some(ts),
+                timezone: Some(tz_name.clone()),
+                currency: currency.clone(),
+                open: o,
+                high: h,
+                low: l,
+                close: c,
+                name: None,
+            });
+        }
     }
-    fn set_sma_long(&mut self, value: f64) {
-        self.entry.sma_long = value;
+
+    // metaの最新値（価格/時刻）で、最後の足を上書きして「リクエスト時点の最新値」を出せるようにする
+    let rm_time = r0["meta"]["regularMarketTime"].as_i64();
+
+    // regularMarketPrice は number の場合と {raw: ...} の場合があるので両対応
+    let rm_price = r0["meta"]["regularMarketPrice"]
+        .as_f64()
+        .or_else(|| r0["meta"]["regularMarketPrice"]["raw"].as_f64());
+
+    if let (Some(rm_time), Some(rm_price)) = (rm_time, rm_price) {
+        if let Some(last) = out.last_mut() {
+            let dt = tz
+                .timestamp_opt(rm_time, 0)
+                .single()
+                .ok_or("❌ regularMarketTime 変換失敗")?;
+
+            last.date = dt.format("%Y-%m-%d").to_string();
+            last.datetime = Some(dt.format("%Y-%m-%d %H:%M").to_string());
+            last.timestamp = Some(rm_time);
+            last.timezone = Some(tz_name.clone());
+
+            // close を “最新価格” に差し替える
+            last.close = rm_price;
+        }
     }
-    fn set_sma_score(&mut self, value: f64) {
-        self.entry.sma_score = Some(value);
+
+    if out.len() < 2 {
+        return Err("❌ 時系列データが2件未満のため、テクニカル指標を構築できません。".into());
     }
-    fn set_adx(&mut self, value: f64) {
-        self.entry.adx = Some(value);
+
+    Ok(out)
+}
+
+/// エイリアスCSVの読み込み
+fn load_alias_csv(path: &str) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let lines = sanitize_ascii_file_lines(Path::new(path))?;
+    let content = lines.join("\n");
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(Cursor::new(content)); // ← 文字列からCSVとして読み込む
+
+    let mut map = HashMap::new();
+    for result in rdr.records() {
+        let record = result?;
+        let code = record
+            .get(1)
+            .ok_or("❌ CSVの2列目（証券コード）が存在しません")?
+            .trim();
+        let name = record
+            .get(2)
+            .ok_or("❌ CSVの3列目（企業名）が存在しません")?
+            .trim();
+        if !code.is_empty() && !name.is_empty() {
+            //let ticker = format!("{}.T", code);
+            map.insert(code.to_string(), name.to_string());
+        }
     }
-    fn set_adx_score(&mut self, value: f64) {
-        self.entry.adx_score = Some(value);
+
+    Ok(map)
+}
+
+/// カスタム指標ファイル（JSON配列）の読み込みと検証
+fn load_custom_indicators(path: &str) -> Result<Vec<CustomIndicator>, Box<dyn std::error::Error>> {
+    let lines = sanitize_ascii_file_lines(Path::new(path))?;
+    let specs: Vec<CustomIndicatorSpec> = serde_json::from_str(&lines.join("\n"))?;
+
+    let mut indicators: Vec<CustomIndicator> = Vec::new();
+    for spec in specs {
+        let name = spec.name.trim().to_string();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(format!("❌ カスタム指標名は英数字・_・- のみ使用できます: '{}'", name).into());
+        }
+        if indicators.iter().any(|c| c.name == name) {
+            return Err(format!("❌ カスタム指標名が重複しています: {}", name).into());
+        }
+        let ast = parse_expr(&spec.expr).map_err(|e| format!("❌ {} の式が不正です: {}", name, e))?;
+        if spec.bands.is_empty() {
+            return Err(format!("❌ {} のスコア帯（bands）が空です", name).into());
+        }
+        let scores = spec.bands.iter().map(|b| b.score).chain([spec.default_score]);
+        for score in scores {
+            if !(-2.0..=2.0).contains(&score) {
+                return Err(format!("❌ {} のスコアは -2〜+2 で指定してください: {}", name, score).into());
+            }
+        }
+        let weight = if (0.5..=3.0).contains(&spec.weight) {
+            spec.weight
+        } else {
+            eprintln!("⚠️ {} の weight が範囲外（0.5〜3.0）: {}。1.0 を使用します。", name, spec.weight);
+            1.0
+        };
+        indicators.push(CustomIndicator {
+            name,
+            expr: spec.expr,
+            description: spec.description,
+            weight,
+            bands: spec.bands,
+            default_score: spec.default_score,
+            ast,
+        });
     }
-    fn set_roc(&mut self, value: f64) {
-        self.entry.roc = Some(value);
+    Ok(indicators)
+}
+
+/// プラグイン定義ファイル（JSON配列）の読み込みと検証
+fn load_plugin_specs(path: &str) -> Result<Vec<PluginSpec>, Box<dyn std::error::Error>> {
+    let lines = sanitize_ascii_file_lines(Path::new(path))?;
+    let mut specs: Vec<PluginSpec> = serde_json::from_str(&lines.join("\n"))?;
+
+    let mut seen: Vec<String> = Vec::new();
+    for spec in specs.iter_mut() {
+        spec.name = spec.name.trim().to_string();
+        if spec.name.is_empty()
+            || !spec
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(format!("❌ プラグイン名は英数字・_・- のみ使用できます: '{}'", spec.name).into());
+        }
+        if seen.contains(&spec.name) {
+            return Err(format!("❌ プラグイン名が重複しています: {}", spec.name).into());
+        }
+        seen.push(spec.name.clone());
+        if spec.command.trim().is_empty() {
+            return Err(format!("❌ プラグイン {} の command が空です", spec.name).into());
+        }
+        if !(1..=PLUGIN_MAX_TIMEOUT_MS).contains(&spec.timeout_ms) {
+            return Err(format!(
+                "❌ プラグイン {} の timeout_ms は 1〜{} で指定してください: {}",
+                spec.name, PLUGIN_MAX_TIMEOUT_MS, spec.timeout_ms
+            )
+            .into());
+        }
+        if !(0.5..=3.0).contains(&spec.weight) {
+            eprintln!(
+                "⚠️ プラグイン {} の weight が範囲外（0.5〜3.0）: {}。1.0 を使用します。",
+                spec.name, spec.weight
+            );
+            spec.weight = 1.0;
+        }
     }
-    fn set_roc_score(&mut self, value: f64) {
-        self.entry.roc_score = Some(value);
+    Ok(specs)
+}
+
+/// 基本シグナルのルールファイル（JSON配列）の読み込みと検証
+fn load_signal_rules(path: &str) -> Result<Vec<SignalRule>, Box<dyn std::error::Error>> {
+    let lines = sanitize_ascii_file_lines(Path::new(path))?;
+    let specs: Vec<SignalRuleSpec> = serde_json::from_str(&lines.join("\n"))?;
+    if specs.is_empty() {
+        return Err("❌ シグナルルールが1件もありません".into());
     }
-    fn set_stochastics_k(&mut self, value: f64) {
-        self.entry.stochastics_k = Some(value);
+
+    let resolve = |index: usize, spec: Option<DiffThresholdSpec>| -> Result<Option<DiffThreshold>, String> {
+        match spec {
+            None => Ok(None),
+            Some(DiffThresholdSpec::Pct(v)) if v.is_finite() && v >= 0.0 => Ok(Some(DiffThreshold::Pct(v))),
+            Some(DiffThresholdSpec::Pct(v)) => {
+                Err(format!("❌ ルール{} のMACD差閾値は0以上の数値で指定してください: {}", index, v))
+            }
+            Some(DiffThresholdSpec::Named(name)) => match name.as_str() {
+                "low" => Ok(Some(DiffThreshold::Low)),
+                "mid" => Ok(Some(DiffThreshold::Mid)),
+                "extreme" => Ok(Some(DiffThreshold::Extreme)),
+                _ => Err(format!(
+                    "❌ ルール{} のMACD差閾値は数値または low / mid / extreme で指定してください: {}",
+                    index, name
+                )),
+            },
+        }
+    };
+
+    let mut rules: Vec<SignalRule> = Vec::new();
+    for (i, spec) in specs.into_iter().enumerate() {
+        let index = i + 1;
+        if !(-2.0..=2.0).contains(&spec.score) {
+            return Err(format!("❌ ルール{} のスコアは -2〜+2 で指定してください: {}", index, spec.score).into());
+        }
+        let diff_above = resolve(index, spec.diff_above)?;
+        let diff_below = resolve(index, spec.diff_below)?;
+        rules.push(SignalRule {
+            macd: spec.macd,
+            rsi: spec.rsi,
+            diff_above,
+            diff_below,
+            score: spec.score,
+            note: spec.note.unwrap_or_default(),
+        });
     }
-    fn set_stochastics_d(&mut self, value: f64) {
-        self.entry.stochastics_d = Some(value);
+    Ok(rules)
+}
+
+/// ルール一覧を評価順に表示し、到達しないルールや未一致時の扱いを警告する（--validate-signal-rules）
+fn validate_signal_rules_file(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let rules = load_signal_rules(path)?;
+    println!("✅ シグナルルール {} を読み込みました（{}件）", path, rules.len());
+    for (i, rule) in rules.iter().enumerate() {
+        println!(
+            "  {:>2}. {:<40} → {:+}  {}",
+            i + 1,
+            rule.condition_label(),
+            rule.score,
+            rule.note
+        );
     }
-    fn set_stochastics_score(&mut self, value: f64) {
-        self.entry.stochastics_score = Some(value);
+
+    // MACD差の条件を持たないルールは、条件が同じか広い後続ルールを遮る
+    let covers_macd = |a: MacdCondition, b: MacdCondition| a == MacdCondition::Any || a == b;
+    let covers_rsi = |a: RsiCondition, b: RsiCondition| a == RsiCondition::Any || a == b;
+    let mut warnings = 0;
+    for (j, later) in rules.iter().enumerate() {
+        let shadowed_by = rules[..j].iter().position(|earlier| {
+            earlier.diff_above.is_none()
+                && earlier.diff_below.is_none()
+                && covers_macd(earlier.macd, later.macd)
+                && covers_rsi(earlier.rsi, later.rsi)
+        });
+        if let Some(i) = shadowed_by {
+            println!("⚠️ ルール{} はルール{} に先に一致するため評価されません", j + 1, i + 1);
+            warnings += 1;
+        }
     }
-    fn set_bb_upper(&mut self, value: f64) {
-        self.entry.bb_upper = value;
+    let has_catch_all = rules.iter().any(|r| {
+        r.macd == MacdCondition::Any
+            && r.rsi == RsiCondition::Any
+            && r.diff_above.is_none()
+            && r.diff_below.is_none()
+    });
+    if !has_catch_all {
+        println!("ℹ️ どのルールにも一致しない場合、基本シグナルスコアは 0（中立）になります");
     }
-    fn set_bb_lower(&mut self, value: f64) {
-        self.entry.bb_lower = value;
+    println!("ℹ️ --macd-minus-ok が無効でMACD<0のとき、プラスのスコアは従来どおり0に抑制されます");
+    if warnings > 0 {
+        println!("⚠️ 警告 {} 件", warnings);
     }
-    fn set_bb_percent_b(&mut self, value: f64) {
-        self.entry.bb_percent_b = value;
+    Ok(())
+}
+
+/// プリセット定義（JSON配列）の解析と検証
+fn parse_presets(json_text: &str) -> Result<Vec<StrategyPreset>, Box<dyn std::error::Error>> {
+    let mut presets: Vec<StrategyPreset> = serde_json::from_str(json_text)?;
+    let mut seen: Vec<String> = Vec::new();
+    for preset in &mut presets {
+        let name = preset.name.clone();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(format!("❌ プリセット名は英数字・_・- のみ使用できます: '{}'", name).into());
+        }
+        if seen.contains(&name) {
+            return Err(format!("❌ プリセット名が重複しています: {}", name).into());
+        }
+        seen.push(name.clone());
+        for ext in preset.extensions.iter().flatten() {
+            ext.parse::<ExtensionIndicator>()
+                .map_err(|e| format!("❌ プリセット {} の extensions: {}", name, e.trim_start_matches("❌ ")))?;
+        }
+        // 重みのキーは大文字小文字・別名（sr / rs）を正規の名前にそろえて保持する（apply_preset は正規の名前で引く）
+        let mut weights: HashMap<String, f64> = HashMap::new();
+        for (key, weight) in &preset.weights {
+            let canonical = if key.eq_ignore_ascii_case("basic") {
+                "basic"
+            } else {
+                key.parse::<ExtensionIndicator>()
+                    .map_err(|e| format!("❌ プリセット {} の weights: {}", name, e.trim_start_matches("❌ ")))?
+                    .key()
+                    .unwrap_or_default()
+            };
+            if weights.insert(canonical.to_string(), *weight).is_some() {
+                return Err(format!("❌ プリセット {} の weights で {} の重みが重複しています", name, canonical).into());
+            }
+            if !(0.5..=3.0).contains(weight) {
+                return Err(format!(
+                    "❌ プリセット {} の weight は 0.5〜3.0 で指定してください: {}={}",
+                    name, key, weight
+                )
+                .into());
+            }
+        }
+        preset.weights = weights;
+        if let Some(stance) = &preset.stance {
+            if !["buyer", "seller", "holder"].contains(&stance.as_str()) {
+                return Err(format!("❌ プリセット {} の stance が不正です: {}", name, stance).into());
+            }
+        }
     }
-    fn set_bb_bandwidth(&mut self, value: f64) {
-        self.entry.bb_bandwidth = value;
+    Ok(presets)
+}
+
+/// 組み込みプリセットとユーザー定義プリセットを合わせた一覧（同名はユーザー定義が優先）
+fn load_presets(user_path: Option<&str>) -> Result<Vec<StrategyPreset>, Box<dyn std::error::Error>> {
+    let mut presets = parse_presets(BUILTIN_PRESETS)?;
+    if let Some(path) = user_path {
+        let lines = sanitize_ascii_file_lines(Path::new(path))?;
+        for user in parse_presets(&lines.join("\n"))? {
+            presets.retain(|p| p.name != user.name);
+            presets.push(user);
+        }
     }
-    fn set_bollinger_score(&mut self, value: f64) {
-        self.entry.bollinger_score = Some(value);
+    Ok(presets)
+}
+
+/// プリセットを Config に適用する（CLIで明示した項目はCLIを優先）
+fn apply_preset(config: &mut Config, preset: &StrategyPreset, args: &Args) {
+    let weight = |key: &str| preset.weights.get(key).copied();
+    let from_preset = |id: &str| !args.cli_explicit.iter().any(|a| a == id);
+
+    if let (Some(v), true) = (preset.buy_rsi, from_preset("buy_rsi")) {
+        config.buy_rsi = v;
     }
-    fn set_fibo_38_2(&mut self, value: f64) {
-        self.entry.fibo_38_2 = Some(value);
+    if let (Some(v), true) = (preset.sell_rsi, from_preset("sell_rsi")) {
+        config.sell_rsi = v;
     }
-    fn set_fibo_50_0(&mut self, value: f64) {
-        self.entry.fibo_50_0 = Some(value);
+    if let (Some(v), true) = (preset.macd_diff_low, from_preset("macd_diff_low")) {
+        config.macd_diff_low = v;
     }
-    fn set_fibo_61_8(&mut self, value: f64) {
-        self.entry.fibo_61_8 = Some(value);
+    if let (Some(v), true) = (preset.macd_diff_mid, from_preset("macd_diff_mid")) {
+        config.macd_diff_mid = v;
     }
-    fn set_fibonacci_score(&mut self, value: f64) {
-        self.entry.fibonacci_score = Some(value);
+    if let (Some(v), true) = (preset.macd_minus_ok, from_preset("macd_minus_ok")) {
+        config.macd_minus_ok = v;
     }
-    fn set_vwap(&mut self, value: f64) {
-        self.entry.vwap = Some(value);
+    if let (Some(v), true) = (
+        preset.bb_bandwidth_squeeze_pct,
+        from_preset("bb_bandwidth_squeeze_pct"),
+    ) {
+        config.bb_bandwidth_squeeze_pct = sanitize_percent(
+            v,
+            0.0,
+            100.0,
+            "Bollinger bandwidth squeeze threshold (%)",
+        );
     }
-    fn set_vwap_score(&mut self, value: f64) {
-        self.entry.vwap_score = Some(value);
+    if let (Some(v), true) = (preset.regime_weighting, from_preset("regime_weighting")) {
+        config.regime_weighting = v;
+    }
+    if let (Some(v), true) = (&preset.stance, from_preset("stance")) {
+        config.stance = parse_stance(v);
+    }
+
+    let weights: [(&str, &mut f64); 13] = [
+        ("basic", &mut config.weight_basic),
+        ("ema", &mut config.weight_ema),
+        ("sma", &mut config.weight_sma),
+        ("bollinger", &mut config.weight_bollinger),
+        ("roc", &mut config.weight_roc),
+        ("adx", &mut config.weight_adx),
+        ("stochastics", &mut config.weight_stochastics),
+        ("fibonacci", &mut config.weight_fibonacci),
+        ("vwap", &mut config.weight_vwap),
+        ("ichimoku", &mut config.weight_ichimoku),
+        ("candlestick", &mut config.weight_candlestick),
+        ("support_resistance", &mut config.weight_support_resistance),
+        ("relative_strength", &mut config.weight_relative_strength),
+    ];
+    for (key, field) in weights {
+        if let (Some(v), true) = (weight(key), from_preset(&format!("weight_{}", key))) {
+            *field = v;
+        }
     }
-    fn set_tenkan_sen(&mut self, value: f64) {
-        self.entry.tenkan_sen = Some(value);
+
+    // 拡張指標：プリセットの指定で環境変数の有効化を置き換え、CLIフラグは追加で有効化
+    if let Some(names) = &preset.extensions {
+        let selected: Vec<ExtensionIndicator> =
+            names.iter().filter_map(|n| n.parse().ok()).collect();
+        let cli_flags = [
+            (ExtensionIndicator::Ema, args.ema),
+            (ExtensionIndicator::Sma, args.sma),
+            (ExtensionIndicator::Roc, args.roc),
+            (ExtensionIndicator::Adx, args.adx),
+            (ExtensionIndicator::Stochastics, args.stochastics),
+            (ExtensionIndicator::Bollinger, args.bollinger),
+            (ExtensionIndicator::Fibonacci, args.fibonacci),
+            (ExtensionIndicator::Vwap, args.vwap),
+            (ExtensionIndicator::Ichimoku, args.ichimoku),
+            (ExtensionIndicator::Candlestick, args.candlestick),
+            (ExtensionIndicator::SupportResistance, args.support_resistance),
+            (ExtensionIndicator::RelativeStrength, args.relative_strength),
+        ];
+        let mut extensions: Vec<ExtensionIndicator> = cli_flags
+            .into_iter()
+            .filter(|(ext, flag)| *flag || selected.contains(ext))
+            .map(|(ext, _)| ext)
+            .collect();
+        extensions.extend(
+            config
+                .enabled_extensions
+                .iter()
+                .filter(|e| matches!(e, ExtensionIndicator::Custom(_) | ExtensionIndicator::Plugin(_)))
+                .cloned(),
+        );
+        config.enabled_extensions = extensions;
     }
-    fn set_kijun_sen(&mut self, value: f64) {
-        self.entry.kijun_sen = Some(value);
+
+    config.preset = Some(preset.name.clone());
+}
+
+/// --list-presets：利用可能なプリセットの一覧を表示
+fn print_preset_list(presets: &[StrategyPreset]) {
+    println!("📚 利用可能な戦略プリセット（--preset <NAME>）");
+    for preset in presets {
+        println!("  {:<12} {}", preset.name, preset.description);
+        if let Some(exts) = &preset.extensions {
+            println!("  {:<12} 指標: {}", "", exts.join(", "));
+        }
+        if !preset.weights.is_empty() {
+            let mut weights: Vec<String> =
+                preset.weights.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            weights.sort();
+            println!("  {:<12} 重み: {}", "", weights.join(" "));
+        }
     }
-    fn set_ichimoku_score(&mut self, value: f64) {
-        self.entry.ichimoku_score = Some(value);
+}
+
+/// 取得した全期間から一度だけ計算する指標の系列。
+/// どの系列も先読みのない逐次計算なので、先頭 end 本だけで計算した値は各系列の end-1 番目と一致する。
+/// 足ごとの再評価（時系列出力・バックテストなど）はこれを参照し、足ごとに系列を計算し直さない
+struct IndicatorSeries {
+    len: usize,
+    rsi: Vec<f64>,
+    macd: Vec<f64>,
+    macd_signal: Vec<f64>,
+    macd_crosses: Vec<CrossEvent>, // クロスは全期間で検出（bars_ago は最新足基準）
+    ema_short: Vec<f64>,
+    ema_long: Vec<f64>,
+    ema_crosses: Vec<CrossEvent>,
+    sma_short: Vec<f64>,
+    sma_long: Vec<f64>,
+    sma_crosses: Vec<CrossEvent>,
+    stoch_k: Vec<f64>,
+    stoch_d: Vec<f64>,
+    stoch_crosses: Vec<CrossEvent>,
+    bb_upper: Vec<f64>,
+    bb_lower: Vec<f64>,
+    vwap: Vec<f64>,
+    ichimoku_crosses: Vec<CrossEvent>,
+    pivots: Vec<SwingPivot>,
+    custom: Vec<Result<Vec<f64>, String>>, // config.custom_indicators と同じ並び
+    benchmark_pairs: Option<Vec<(usize, f64, f64)>>, // ベンチマークとの共通日（足の位置, 銘柄終値, ベンチマーク終値）
+}
+
+impl IndicatorSeries {
+    fn new(
+        config: &Config,
+        data: &[MarketData],
+        benchmark_data: Option<&[MarketData]>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        use ta::indicators::{ExponentialMovingAverage, SimpleMovingAverage};
+
+        let closes: Vec<f64> = data.iter().map(|d| d.close).collect();
+        let dates: Vec<String> = data.iter().map(|d| d.date.clone()).collect();
+
+        // RSI（14期間）と MACD（12/26/9）
+        let mut rsi_calc =
+            RelativeStrengthIndex::new(14).map_err(|e| format!("❌ RSI初期化失敗: {e}"))?;
+        let rsi: Vec<f64> = closes.iter().map(|&c| rsi_calc.next(c)).collect();
+        let mut macd_calc = MovingAverageConvergenceDivergence::new(12, 26, 9)
+            .map_err(|e| format!("❌ MACD初期化失敗: {e}"))?;
+        let (macd, macd_signal): (Vec<f64>, Vec<f64>) = closes
+            .iter()
+            .map(|&c| {
+                let out = macd_calc.next(c);
+                (out.macd, out.signal)
+            })
+            .unzip();
+        // MACD/シグナルのクロス履歴（長期26＋シグナル9の立ち上がり後のみ）
+        let macd_crosses = detect_crosses(&dates, &macd, &macd_signal, 26 + 9);
+
+        // EMA / SMA（5日/20日）
+        let mut ema_short_calc =
+            ExponentialMovingAverage::new(5).map_err(|e| format!("❌ EMA短期 初期化失敗: {e}"))?;
+        let mut ema_long_calc =
+            ExponentialMovingAverage::new(20).map_err(|e| format!("❌ EMA長期 初期化失敗: {e}"))?;
+        let ema_short: Vec<f64> = closes.iter().map(|&c| ema_short_calc.next(c)).collect();
+        let ema_long: Vec<f64> = closes.iter().map(|&c| ema_long_calc.next(c)).collect();
+        let ema_crosses = detect_crosses(&dates, &ema_short, &ema_long, 20);
+        let mut sma_short_calc = SimpleMovingAverage::new(5)?;
+        let mut sma_long_calc = SimpleMovingAverage::new(20)?;
+        let sma_short: Vec<f64> = closes.iter().map(|&c| sma_short_calc.next(c)).collect();
+        let sma_long: Vec<f64> = closes.iter().map(|&c| sma_long_calc.next(c)).collect();
+        let sma_crosses = detect_crosses(&dates, &sma_short, &sma_long, 20);
+
+        // ストキャスティクス %K（14期間）と %D（%K の3本平均）
+        let period = 14;
+        let stoch_k: Vec<f64> = (0..data.len())
+            .map(|i| {
+                let start = (i + 1).saturating_sub(period);
+                let high = data[start..=i].iter().map(|d| d.high).fold(f64::MIN, f64::max);
+                let low = data[start..=i].iter().map(|d| d.low).fold(f64::MAX, f64::min);
+                if high != low {
+                    ((closes[i] - low) / (high - low)) * 100.0
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let stoch_d: Vec<f64> = (0..data.len())
+            .map(|i| {
+                let start = i.saturating_sub(2);
+                stoch_k[start..=i].iter().sum::<f64>() / (i - start + 1) as f64
+            })
+            .collect();
+        let stoch_crosses = detect_crosses(&dates, &stoch_k, &stoch_d, period + 2);
+
+        // ボリンジャーバンド（20期間・2σ）と VWAP（Typical Price の14期間平均）
+        let mut bb = BollingerBands::new(20, 2.0)?;
+        let (bb_upper, bb_lower): (Vec<f64>, Vec<f64>) = closes
+            .iter()
+            .map(|&c| {
+                let bands = bb.next(c);
+                (bands.upper, bands.lower)
+            })
+            .unzip();
+        let mut vwap_calc = SimpleMovingAverage::new(14)?;
+        let vwap: Vec<f64> = data
+            .iter()
+            .map(|d| vwap_calc.next((d.high + d.low + d.close) / 3.0))
+            .collect();
+
+        // 転換線/基準線の履歴（基準線が揃う26本目以降）からクロスを検出
+        let ichimoku_crosses = if data.len() >= 26 {
+            let mid_price = |window: &[MarketData]| {
+                let high = window.iter().map(|d| d.high).fold(f64::MIN, f64::max);
+                let low = window.iter().map(|d| d.low).fold(f64::MAX, f64::min);
+                (high + low) / 2.0
+            };
+            let tenkan_series: Vec<f64> = (25..data.len())
+                .map(|i| mid_price(&data[i + 1 - 9..=i]))
+                .collect();
+            let kijun_series: Vec<f64> = (25..data.len())
+                .map(|i| mid_price(&data[i + 1 - 26..=i]))
+                .collect();
+            detect_crosses(&dates[25..], &tenkan_series, &kijun_series, 1)
+        } else {
+            Vec::new()
+        };
+
+        // 日付で突き合わせ（休場日の違いは共通日のみ採用）
+        let benchmark_pairs = benchmark_data.map(|bench| {
+            let bench_by_date: HashMap<&str, f64> =
+                bench.iter().map(|d| (d.date.as_str(), d.close)).collect();
+            data.iter()
+                .enumerate()
+                .filter_map(|(i, d)| bench_by_date.get(d.date.as_str()).map(|&b| (i, d.close, b)))
+                .filter(|&(_, s, b)| s > 0.0 && b > 0.0)
+                .collect()
+        });
+
+        Ok(IndicatorSeries {
+            len: data.len(),
+            rsi,
+            macd,
+            macd_signal,
+            macd_crosses,
+            ema_short,
+            ema_long,
+            ema_crosses,
+            sma_short,
+            sma_long,
+            sma_crosses,
+            stoch_k,
+            stoch_d,
+            stoch_crosses,
+            bb_upper,
+            bb_lower,
+            vwap,
+            ichimoku_crosses,
+            pivots: find_swing_pivots(data, SWING_PIVOT_SPAN),
+            custom: config
+                .custom_indicators
+                .iter()
+                .map(|c| eval_expr(&c.ast, data))
+                .collect(),
+            benchmark_pairs,
+        })
     }
-    /// get関数
-    fn get_name(&self) -> &str {
-        &self.entry.name
+
+    /// 全期間で検出したクロスのうち先頭 end 本までに起きたもの（bars_ago は end 本目から数え直す）
+    fn crosses_until(&self, crosses: &[CrossEvent], end: usize) -> Vec<CrossEvent> {
+        let shift = self.len - end;
+        crosses
+            .iter()
+            .filter(|c| c.bars_ago >= shift)
+            .map(|c| CrossEvent {
+                bars_ago: c.bars_ago - shift,
+                ..c.clone()
+            })
+            .collect()
     }
-    fn get_datetime(&self) -> Option<&str> {
-        self.entry.datetime.as_deref()
+
+    /// 先頭 end 本だけで確定するピボット（右側 SWING_PIVOT_SPAN 本が end 本目までに揃うもの）
+    fn pivots_until(&self, end: usize) -> Vec<SwingPivot> {
+        self.pivots
+            .iter()
+            .filter(|p| p.index + SWING_PIVOT_SPAN < end)
+            .cloned()
+            .collect()
     }
-    fn get_timestamp(&self) -> Option<i64> {
-        self.entry.timestamp
+
+    /// 先頭 end 本までのベンチマークとの共通日（ベンチマーク未取得なら None）
+    fn benchmark_pairs_until(&self, end: usize) -> Option<&[(usize, f64, f64)]> {
+        self.benchmark_pairs
+            .as_deref()
+            .map(|pairs| &pairs[..pairs.partition_point(|p| p.0 < end)])
     }
-    fn get_timezone(&self) -> &str {
-        &self.entry.timezone
+}
+
+// 基本解析・SMA が実際の値を出すまでに必要な本数（MACD は 26+9-1 本、RSI は 14 本の差分、長期SMA は 20 本）
+const MACD_MIN_BARS: usize = 35;
+const RSI_MIN_BARS: usize = 15;
+const SMA_LONG_MIN_BARS: usize = 20;
+
+// テクニカル指標（RSI/MACD）を計算し、前日比やスコアを組み立てて Guard を返す
+fn build_basic_technical_entry(
+    config: &Config,
+    series: &IndicatorSeries,
+    data: &[MarketData],
+    ticker_name_map: &HashMap<String, String>,
+    //fetched_company_name: Option<String>,
+) -> Result<TechnicalDataGuard, Box<dyn std::error::Error>> {
+    // データが2件未満では前日比の評価ができないため中断
+    if data.len() < 2 {
+        return Err("❌ 時系列データが2件未満のため、テクニカル指標を構築できません。".into());
     }
-    fn get_ticker(&self) -> &str {
-        &self.entry.ticker
+
+    let hardcoded = resolve_hardcoded_info(&config.ticker); // ← SoT（正規化後の大文字）
+
+    let latest = &data[data.len() - 1];
+    let previous = &data[data.len() - 2];
+    
+    let alias_name_opt =
+        jp_code_from_ticker(&config.ticker).and_then(|code| ticker_name_map.get(&code).cloned());
+
+    // 優先順位：エイリアス >  LLM取得名 > ティッカー文字列
+    let name = alias_name_opt // 4桁JP alias（あるなら最優先）
+        .or(latest.name.clone()) // APIから来た会社名
+        //.or(fetched_company_name) // 追加取得の会社名
+        .or_else(|| hardcoded.map(|h| h.formal_name.to_string())) // ★ハードコード名
+        .unwrap_or_else(|| config.ticker.clone());
+
+    // 株価差と前日比（%）を算出
+    let diff = latest.close - previous.close;
+    let diff_percent = if previous.close != 0.0 {
+        diff / previous.close * 100.0
+    } else {
+        0.0
+    };
+
+    // RSI（14期間）で割高・割安を評価し、MACDは前日値と当日値を系列から取る
+    let last = data.len() - 1;
+    let rsi = series.rsi[last];
+    let (macd, signal) = (series.macd[last], series.macd_signal[last]);
+    let (prev_macd, prev_signal) = (series.macd[last - 1], series.macd_signal[last - 1]);
+    let macd_crosses = series.crosses_until(&series.macd_crosses, data.len());
+
+    let (signal_score, mut signal_codes) = basic_signal_score(config, rsi, macd, signal, latest.close);
+    signal_codes.extend(cross_reason_codes("MACD", &macd_crosses, false));
+
+    // セキュアアクセス構造（TechnicalDataGuard）で構造体へ代入
+    let mut guard = TechnicalDataGuard::new(config.ticker.clone(), latest.date.clone());
+
+    guard.set_name(&name);
+    // ガードにタイムゾーンと日時情報をセット
+    if let Some(tz) = latest.timezone.as_deref() {
+        guard.set_timezone(tz);
     }
-    fn get_date(&self) -> &str {
-        &self.entry.date
+    if let Some(currency) = latest.currency.as_deref() {
+        guard.set_currency(currency);
     }
-    fn get_close(&self) -> f64 {
-        self.entry.close
+    if let Some(dt) = latest.datetime.as_deref() {
+        guard.set_datetime(dt);
     }
-    fn get_previous_close(&self) -> f64 {
-        self.entry.previous_close
+    if let Some(ts) = latest.timestamp {
+        guard.set_timestamp(ts);
     }
-    fn get_price_diff(&self) -> f64 {
-        self.entry.price_diff
+
+    guard.set_close(latest.close);
+    guard.set_previous_close(previous.close);
+    guard.set_price_diff(diff);
+    guard.set_price_diff_percent(diff_percent);
+    guard.set_rsi(rsi);
+    guard.set_macd(macd);
+    guard.set_prev_macd(prev_macd);
+    guard.set_prev_signal(prev_signal);
+    guard.set_signal(signal);
+    guard.set_macd_crosses(macd_crosses);
+    guard.set_signal_score(signal_score);
+    guard.set_signal_reason_codes(signal_codes);
+    guard.set_risk_stats(compute_risk_stats(data));
+
+    // RSI/MACD がまだ立ち上がっていない足は、基本スコアを必要本数不足として記録する（足ごとの再評価では飛ばす）
+    let min_bars = MACD_MIN_BARS.max(RSI_MIN_BARS);
+    if data.len() < min_bars {
+        guard.set_indicator_status(
+            "Basic".to_string(),
+            IndicatorStatus::InsufficientData(format!(
+                "RSI(14)/MACD(12,26,9) の評価には最低{}日分のデータが必要です",
+                min_bars
+            )),
+        );
     }
-    fn get_price_diff_percent(&self) -> f64 {
-        self.entry.price_diff_percent
+
+    Ok(guard)
+}
+
+/// RSI/MACD から基本解析のシグナルスコアと理由コードを決める（ルールを上から評価し、最初に一致したルールを採用）
+fn basic_signal_score(config: &Config, rsi: f64, macd: f64, signal: f64, close: f64) -> (f64, Vec<String>) {
+    // ここから判定（macd-minus-ok を“買い方向”に反映）
+    // MACD差は終値比（%）で評価し、価格水準の違いを吸収する
+    let macd_diff = pct_of_price((macd - signal).abs(), close);
+    let macd_up = macd > signal && (macd > 0.0 || config.macd_minus_ok);
+    let macd_down = macd < signal;
+    let rsi_low = rsi <= config.buy_rsi;
+    let rsi_high = rsi >= config.sell_rsi;
+
+    let matched = config
+        .signal_rules
+        .iter()
+        .find(|rule| rule.matches(config, macd_up, macd_down, rsi_high, rsi_low, macd_diff));
+    let mut signal_score: f64 = matched.map_or(0.0, |rule| rule.score);
+    let mut codes = matched.map(SignalRule::reason_codes).unwrap_or_default();
+
+    // ★ゲート：--macd-minus-ok が無効で MACD<0 のとき、買い方向のプラススコアを0にクランプ
+    let clamped = !config.macd_minus_ok && macd < 0.0 && signal_score > 0.0;
+    if clamped {
+        signal_score = 0.0;
     }
-    fn get_rsi(&self) -> f64 {
-        self.entry.rsi
+    // MACD<0 のため買い方向に数えなかった（上昇側のルールには一致しないので、シグナル上抜けもここで記録）
+    if clamped || (macd > signal && !macd_up) {
+        if macd > signal {
+            codes.push("MACD_ABOVE_SIGNAL".to_string());
+        }
+        codes.push("MACD_BELOW_ZERO".to_string());
     }
-    fn get_macd(&self) -> f64 {
-        self.entry.macd
+    (signal_score, codes)
+}
+
+/// 取得した時系列の終値からリスク統計を算出する（2本未満のリターンしかない場合は None）
+fn compute_risk_stats(data: &[MarketData]) -> Option<RiskStats> {
+    if data.len() < 3 {
+        return None;
     }
-    fn get_signal(&self) -> f64 {
-        self.entry.signal
+
+    // 日次リターン（%ではなく比率）と日付
+    let returns: Vec<(f64, &str)> = data
+        .windows(2)
+        .filter(|w| w[0].close > 0.0)
+        .map(|w| (w[1].close / w[0].close - 1.0, w[1].date.as_str()))
+        .collect();
+    if returns.len() < 2 {
+        return None;
     }
-    fn get_prev_macd(&self) -> f64 {
-        self.entry.prev_macd
+
+    let std_dev = |xs: &[f64]| {
+        let n = xs.len() as f64;
+        let mean = xs.iter().sum::<f64>() / n;
+        (xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt()
+    };
+
+    // 年率ヒストリカル・ボラティリティ（日次対数リターンの標準偏差×√252）
+    let log_returns: Vec<f64> = returns.iter().map(|r| (1.0 + r.0).ln()).collect();
+    let volatility_annual_pct = std_dev(&log_returns) * TRADING_DAYS_PER_YEAR.sqrt() * 100.0;
+
+    // 最大ドローダウン／現在のドローダウン
+    let mut peak = data[0].close;
+    let mut peak_date = data[0].date.clone();
+    let mut max_drawdown_pct = 0.0;
+    let mut max_drawdown_peak = peak_date.clone();
+    let mut max_drawdown_trough = peak_date.clone();
+    for d in data {
+        if d.close > peak {
+            peak = d.close;
+            peak_date = d.date.clone();
+        }
+        let dd = pct_of_price(d.close - peak, peak);
+        if dd < max_drawdown_pct {
+            max_drawdown_pct = dd;
+            max_drawdown_peak = peak_date.clone();
+            max_drawdown_trough = d.date.clone();
+        }
     }
-    fn get_prev_signal(&self) -> f64 {
-        self.entry.prev_signal
+    let current_drawdown_pct = pct_of_price(data[data.len() - 1].close - peak, peak);
+
+    // 直近 RISK_ROLLING_WINDOW 本のシャープ/ソルティノ（無リスク金利0・年率換算）
+    let window: Vec<f64> = returns[returns.len().saturating_sub(RISK_ROLLING_WINDOW)..]
+        .iter()
+        .map(|r| r.0)
+        .collect();
+    let mean = window.iter().sum::<f64>() / window.len() as f64;
+    let sd = std_dev(&window);
+    let sharpe = (sd > 0.0).then(|| mean / sd * TRADING_DAYS_PER_YEAR.sqrt());
+    let downside = (window.iter().map(|r| r.min(0.0).powi(2)).sum::<f64>()
+        / window.len() as f64)
+        .sqrt();
+    let sortino = (downside > 0.0).then(|| mean / downside * TRADING_DAYS_PER_YEAR.sqrt());
+
+    let (worst_ret, worst_date) = returns
+        .iter()
+        .fold((f64::MAX, ""), |acc, r| if r.0 < acc.0 { *r } else { acc });
+
+    Some(RiskStats {
+        samples: returns.len(),
+        volatility_annual_pct,
+        max_drawdown_pct,
+        max_drawdown_peak,
+        max_drawdown_trough,
+        current_drawdown_pct,
+        peak_date,
+        sharpe,
+        sortino,
+        rolling_window: window.len(),
+        worst_day_pct: worst_ret * 100.0,
+        worst_day_date: worst_date.to_string(),
+    })
+}
+
+/// ADX・ボリンジャーバンド幅・SMA20の傾きから相場局面を判定する（いずれかが欠ければ判定不可）
+fn detect_market_regime(
+    config: &Config,
+    adx: Option<f64>,
+    bandwidth_pct: Option<f64>,
+    slope_pct: Option<f64>,
+) -> RegimeKind {
+    let (Some(adx), Some(bandwidth_pct), Some(slope_pct)) = (adx, bandwidth_pct, slope_pct) else {
+        return RegimeKind::Unknown;
+    };
+
+    let trending = adx >= REGIME_ADX_TREND && slope_pct.abs() >= REGIME_SLOPE_TREND_PCT;
+    let ranging = (adx < REGIME_ADX_RANGE && slope_pct.abs() < REGIME_SLOPE_TREND_PCT)
+        || bandwidth_pct <= config.bb_bandwidth_squeeze_pct;
+    match (trending, ranging) {
+        (true, _) => RegimeKind::Trending,
+        (false, true) => RegimeKind::Ranging,
+        _ => RegimeKind::Transition,
     }
-    fn get_signal_score(&self) -> f64 {
-        self.entry.signal_score
+}
+
+/// 相場局面の判定材料（ADX / ボリンジャーバンド幅% / SMA20の傾き%）
+fn regime_inputs(
+    series: &IndicatorSeries,
+    data: &[MarketData],
+) -> (Option<f64>, Option<f64>, Option<f64>) {
+    let adx = (data.len() >= 15).then(|| compute_adx(data)).filter(|v| v.is_finite());
+
+    let last = data.len() - 1;
+    let bandwidth_pct = if data.len() >= 20 {
+        let (upper, lower) = (series.bb_upper[last], series.bb_lower[last]);
+        let mid = (upper + lower) * 0.5;
+        (mid != 0.0).then(|| (upper - lower) / mid * 100.0)
+    } else {
+        None
+    };
+
+    let slope_pct = if data.len() >= 20 + REGIME_SLOPE_SPAN {
+        let now = series.sma_long[last];
+        let before = series.sma_long[last - REGIME_SLOPE_SPAN];
+        (before != 0.0).then(|| pct_of_price(now - before, before) / REGIME_SLOPE_SPAN as f64)
+    } else {
+        None
+    };
+
+    (adx, bandwidth_pct, slope_pct)
+}
+
+/// 相場局面を判定してガードに記録し、局面別の倍率を掛けた実効Weightの Config を返す
+/// （--regime-weighting 無効時はそのまま複製を返す）
+fn apply_market_regime(
+    config: &Config,
+    series: &IndicatorSeries,
+    data: &[MarketData],
+    guard: &mut TechnicalDataGuard,
+) -> Config {
+    let mut effective = config.clone();
+    if !config.regime_weighting {
+        return effective;
+    }
+
+    let (adx, bandwidth_pct, slope_pct) = regime_inputs(series, data);
+    let kind = detect_market_regime(config, adx, bandwidth_pct, slope_pct);
+    let m = |ext: ExtensionIndicator| kind.weight_multiplier(ext.category());
+
+    effective.weight_ema *= m(ExtensionIndicator::Ema);
+    effective.weight_sma *= m(ExtensionIndicator::Sma);
+    effective.weight_bollinger *= m(ExtensionIndicator::Bollinger);
+    effective.weight_roc *= m(ExtensionIndicator::Roc);
+    effective.weight_adx *= m(ExtensionIndicator::Adx);
+    effective.weight_stochastics *= m(ExtensionIndicator::Stochastics);
+    effective.weight_fibonacci *= m(ExtensionIndicator::Fibonacci);
+    effective.weight_vwap *= m(ExtensionIndicator::Vwap);
+    effective.weight_ichimoku *= m(ExtensionIndicator::Ichimoku);
+    effective.weight_candlestick *= m(ExtensionIndicator::Candlestick);
+    effective.weight_support_resistance *= m(ExtensionIndicator::SupportResistance);
+    effective.weight_relative_strength *= m(ExtensionIndicator::RelativeStrength);
+    for custom in effective.custom_indicators.iter_mut() {
+        custom.weight *= kind.weight_multiplier(IndicatorCategory::Custom);
+    }
+    for plugin in effective.plugins.iter_mut() {
+        plugin.weight *= kind.weight_multiplier(IndicatorCategory::Plugin);
+    }
+
+    let effective_weights = effective
+        .enabled_extensions
+        .iter()
+        .map(|ext| (extension_log_name(&effective, ext), extension_weight(&effective, ext)))
+        .collect();
+    guard.set_market_regime(MarketRegime {
+        kind,
+        adx,
+        bandwidth_pct,
+        slope_pct,
+        effective_weights,
+    });
+    effective
+}
+
+/// 有効な拡張指標をすべて評価し、ガードに格納する
+fn evaluate_all_selected_extensions(
+    config: &Config,
+    series: &IndicatorSeries,
+    data: &[MarketData],
+    guard: &mut TechnicalDataGuard,
+    run_plugins: bool,
+) {
+    // 指標ごとに独立して評価し、失敗しても後続の指標は継続する（結果はステータスとして記録）
+    for indicator in &config.enabled_extensions {
+        // 足ごとの再評価ではプラグインを起動しない（外部プロセスを足の数だけ実行しないため）
+        if matches!(indicator, ExtensionIndicator::Plugin(_)) && !run_plugins {
+            guard.set_indicator_status(
+                extension_log_name(config, indicator),
+                IndicatorStatus::Skipped("足ごとの再評価ではプラグインを実行しません".to_string()),
+            );
+            continue;
+        }
+        let result: Result<(), Box<dyn std::error::Error>> = match indicator {
+            ExtensionIndicator::Ema => evaluate_and_store_ema(series, data, guard),
+            ExtensionIndicator::Sma => evaluate_and_store_sma(series, data, guard),
+            ExtensionIndicator::Bollinger => evaluate_and_store_bollinger(config, series, data, guard),
+            ExtensionIndicator::Roc => evaluate_and_store_roc(data, guard),
+            ExtensionIndicator::Adx => evaluate_and_store_adx(data, guard),
+            ExtensionIndicator::Stochastics => evaluate_and_store_stochastics(series, data, guard),
+            ExtensionIndicator::Fibonacci => evaluate_and_store_fibonacci(series, data, guard),
+            ExtensionIndicator::Vwap => evaluate_and_store_vwap(series, data, guard),
+            ExtensionIndicator::Ichimoku => evaluate_and_store_ichimoku(series, data, guard),
+            ExtensionIndicator::Candlestick => evaluate_and_store_candlestick(data, guard),
+            ExtensionIndicator::SupportResistance => {
+                evaluate_and_store_support_resistance(series, data, guard)
+            }
+            ExtensionIndicator::RelativeStrength => match series.benchmark_pairs_until(data.len()) {
+                Some(pairs) => {
+                    evaluate_and_store_relative_strength(pairs, &config.benchmark, guard)
+                }
+                None => Err("❌ ベンチマークの時系列データがありません".into()),
+            },
+            ExtensionIndicator::Custom(index) => evaluate_and_store_custom(
+                *index,
+                &config.custom_indicators[*index],
+                &series.custom[*index],
+                data,
+                guard,
+            ),
+            ExtensionIndicator::Plugin(index) => {
+                // プラグインは失敗内容を PluginOutcome に保持する
+                let plugin = &config.plugins[*index];
+                evaluate_and_store_plugin(*index, plugin, config, data, guard);
+                match guard.get_plugin_outcome(&plugin.name).map(|o| &o.result) {
+                    Some(Err(reason)) => Err(reason.clone().into()),
+                    _ => Ok(()),
+                }
+            }
+        };
+        let status = match result {
+            Ok(()) => IndicatorStatus::Ok,
+            Err(e) => IndicatorStatus::from_error(e.as_ref()),
+        };
+        guard.set_indicator_status(extension_log_name(config, indicator), status);
     }
-    fn get_ema_short(&self) -> f64 {
-        self.entry.ema_short
+}
+
+/// 評価に失敗した拡張指標を警告として表示する
+fn report_indicator_failures(guard: &TechnicalDataGuard) {
+    for (name, status) in guard.get_indicator_statuses() {
+        match status {
+            IndicatorStatus::Ok => {}
+            IndicatorStatus::InsufficientData(reason) => {
+                eprintln!("⚠️ {}: データ不足のため算出できません（{}）", name, reason)
+            }
+            IndicatorStatus::Error(reason) => {
+                eprintln!("❌ {}: 評価に失敗しました（{}）", name, reason)
+            }
+            IndicatorStatus::Skipped(_) => {}
+        }
     }
-    fn get_ema_long(&self) -> f64 {
-        self.entry.ema_long
+}
+
+/// 価格差を基準価格に対する%へ変換する（基準価格が0なら0%）
+fn pct_of_price(diff: f64, base: f64) -> f64 {
+    if base != 0.0 {
+        diff / base * 100.0
+    } else {
+        0.0
     }
-    fn get_ema_score(&self) -> Option<f64> {
-        self.entry.ema_score
+}
+
+/// 乖離率（%）を5段階スコアに変換する（EMA/SMA/一目/VWAP 共通の閾値）。理由コードの接尾辞も返す
+fn score_gap_pct(gap_pct: f64) -> (f64, &'static str) {
+    match gap_pct {
+        g if g > GAP_STRONG_PCT => (2.0, "STRONG_BULLISH_GAP"),
+        g if g > GAP_MILD_PCT => (1.0, "BULLISH_GAP"),
+        g if g.abs() <= GAP_MILD_PCT => (0.0, "FLAT"),
+        g if g < -GAP_STRONG_PCT => (-2.0, "STRONG_BEARISH_GAP"),
+        _ => (-1.0, "BEARISH_GAP"),
     }
-    fn get_sma_short(&self) -> f64 {
-        self.entry.sma_short
+}
+
+/// EMA（指数平滑移動平均）を計算し、ガード構造体にセキュアに格納する
+fn evaluate_and_store_ema(
+    series: &IndicatorSeries,
+    data: &[MarketData],
+    guard: &mut TechnicalDataGuard,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if data.len() < 20 {
+        return Err(InsufficientDataError("❌ EMA計算には最低20日分のデータが必要です".to_string()).into());
     }
-    fn get_sma_long(&self) -> f64 {
-        self.entry.sma_long
+
+    // 短期5日/長期20日（系列は全期間で計算済み）
+    let last = data.len() - 1;
+    let ema_short_val = series.ema_short[last];
+    let ema_long_val = series.ema_long[last];
+    let crosses = series.crosses_until(&series.ema_crosses, data.len());
+
+    guard.set_ema_short(ema_short_val);
+    guard.set_ema_long(ema_long_val);
+
+    // 📏 5段階スコア：長期EMA比の乖離率で ±2（強）±1（通常）0（同値圏）
+    let gap_pct = pct_of_price(ema_short_val - ema_long_val, ema_long_val);
+    let (ema_score, gap_code) = score_gap_pct(gap_pct);
+    let ema_score = apply_cross_freshness(ema_score, crosses.last(), true);
+    let mut codes = vec![format!("EMA_{}", gap_code)];
+    codes.extend(cross_reason_codes("EMA", &crosses, true));
+    guard.set_ema_crosses(crosses);
+    guard.set_ema_score(ema_score);
+    guard.set_reason_codes(ExtensionIndicator::Ema, codes);
+
+    Ok(())
+}
+
+/// SMA（単純移動平均）を評価して構造体に格納（セキュアアクセス）
+fn evaluate_and_store_sma(
+    series: &IndicatorSeries,
+    data: &[MarketData],
+    guard: &mut TechnicalDataGuard,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if data.len() < SMA_LONG_MIN_BARS {
+        return Err(InsufficientDataError(format!(
+            "❌ SMA計算には最低{}日分のデータが必要です",
+            SMA_LONG_MIN_BARS
+        ))
+        .into());
     }
-    fn get_sma_score(&self) -> Option<f64> {
-        self.entry.sma_score
+
+    // 5日/20日SMA（※マジックナンバーは今後config化も可、系列は全期間で計算済み）
+    let last = data.len() - 1;
+    let short = series.sma_short[last];
+    let long = series.sma_long[last];
+    let crosses = series.crosses_until(&series.sma_crosses, data.len());
+
+    guard.set_sma_short(short);
+    guard.set_sma_long(long);
+
+    // スコアロジック（長期SMA比の乖離率で判定）
+    let gap_pct = pct_of_price(short - long, long);
+    let (sma_score, gap_code) = score_gap_pct(gap_pct);
+    let sma_score = apply_cross_freshness(sma_score, crosses.last(), true);
+    let mut codes = vec![format!("SMA_{}", gap_code)];
+    codes.extend(cross_reason_codes("SMA", &crosses, true));
+
+    guard.set_sma_crosses(crosses);
+    guard.set_sma_score(sma_score);
+    guard.set_reason_codes(ExtensionIndicator::Sma, codes);
+    Ok(())
+}
+
+/// ADXを評価して構造体に格納（セキュアアクセス　ADX　taクレートで用意されていない為演算）
+fn evaluate_and_store_adx(
+    data: &[MarketData],
+    guard: &mut TechnicalDataGuard,
+) -> Result<(), Box<dyn std::error::Error>> {
+    //ADX計算に必要なデータ量（15）
+    if data.len() < 15 {
+        return Err(InsufficientDataError("❌ ADXの計算には最低14期間以上のデータが必要です".to_string()).into());
     }
-    fn get_adx(&self) -> Option<f64> {
-        self.entry.adx
+
+    let adx = compute_adx(data);
+
+    // スコア評価（20がトレンドの分岐点とされる）
+    let (adx_score, code): (f64, &str) = match adx {
+        a if a >= 50.0 => (2.0, "ADX_VERY_STRONG_TREND"),
+        a if a >= 30.0 => (1.0, "ADX_STRONG_TREND"),
+        a if a >= 20.0 => (0.0, "ADX_MODERATE_TREND"),
+        a if a >= 10.0 => (-1.0, "ADX_WEAK_TREND"),
+        _ => (-2.0, "ADX_NO_TREND"),
+    };
+
+    // ✅ セキュアに構造体へ格納
+    guard.set_adx(adx);
+    guard.set_adx_score(adx_score);
+    guard.set_reason_codes(ExtensionIndicator::Adx, vec![code.to_string()]);
+
+    Ok(())
+}
+/// ADX（14期間、直近DXの単純版）を算出する（15本以上のデータを前提とする）
+fn compute_adx(data: &[MarketData]) -> f64 {
+    let period = 14;
+    let mut trs = Vec::new();
+    let mut plus_dm = Vec::new();
+    let mut minus_dm = Vec::new();
+
+    // 使うのは直近 period 本分の値幅のみ
+    for i in data.len() - period..data.len() {
+        let today = &data[i];
+        let yesterday = &data[i - 1];
+
+        let high_diff = today.high - yesterday.high;
+        let low_diff = yesterday.low - today.low;
+
+        let tr = (today.high - today.low)
+            .max((today.high - yesterday.close).abs())
+            .max((today.low - yesterday.close).abs());
+        trs.push(tr);
+
+        plus_dm.push(if high_diff > low_diff && high_diff > 0.0 {
+            high_diff
+        } else {
+            0.0
+        });
+        minus_dm.push(if low_diff > high_diff && low_diff > 0.0 {
+            low_diff
+        } else {
+            0.0
+        });
     }
-    fn get_adx_score(&self) -> Option<f64> {
-        self.entry.adx_score
+
+    let atr = trs.iter().rev().take(period).sum::<f64>() / period as f64;
+    let p_dm = plus_dm.iter().rev().take(period).sum::<f64>();
+    let m_dm = minus_dm.iter().rev().take(period).sum::<f64>();
+
+    let p_di = 100.0 * (p_dm / atr);
+    let m_di = 100.0 * (m_dm / atr);
+    let dx = 100.0 * ((p_di - m_di).abs() / (p_di + m_di));
+
+    // ADX = 直近のDX（単純版）
+    dx
+}
+
+/// ROC（変化率）を計算して構造体に格納（スコア含む）
+fn evaluate_and_store_roc(
+    data: &[MarketData],
+    guard: &mut TechnicalDataGuard,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if data.len() < 11 {
+        return Err(InsufficientDataError("❌ ROC計算には11日分以上のデータが必要です".to_string()).into());
     }
-    fn get_roc(&self) -> Option<f64> {
-        self.entry.roc
+
+    let latest_close = data.last().unwrap().close;
+    let previous_close = data[data.len() - 11].close;
+
+    let roc = ((latest_close - previous_close) / previous_close) * 100.0;
+
+    // スコア判定
+    let (roc_score, code): (f64, &str) = match roc {
+        r if r > 10.0 => (2.0, "ROC_STRONG_UP"),     // 非常に強い上昇
+        r if r > 3.0 => (1.0, "ROC_UP"),             // 緩やかな上昇
+        r if r >= -3.0 => (0.0, "ROC_FLAT"),         // 同値圏（±3%以内）
+        r if r < -10.0 => (-2.0, "ROC_STRONG_DOWN"), // 非常に強い下落
+        _ => (-1.0, "ROC_DOWN"),                     // 緩やかな下落
+    };
+
+    guard.set_roc(roc);
+    guard.set_roc_score(roc_score);
+    guard.set_reason_codes(ExtensionIndicator::Roc, vec![code.to_string()]);
+
+    Ok(())
+}
+fn evaluate_and_store_stochastics(
+    series: &IndicatorSeries,
+    data: &[MarketData],
+    guard: &mut TechnicalDataGuard,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if data.len() < 14 {
+        return Err(InsufficientDataError("❌ ストキャスティクス計算には14日以上のデータが必要です".to_string()).into());
     }
-    fn get_roc_score(&self) -> Option<f64> {
-        self.entry.roc_score
+
+    // %K（14期間）と %D（%K の3本平均）、そのクロス（系列は全期間で計算済み）
+    let last = data.len() - 1;
+    let percent_k = series.stoch_k[last];
+    let percent_d = series.stoch_d[last];
+    let crosses = series.crosses_until(&series.stoch_crosses, data.len());
+
+    // セキュアに構造体へ格納
+    guard.set_stochastics_k(percent_k);
+    guard.set_stochastics_d(percent_d);
+
+    // スコア計算
+    let (stoch_score, code): (f64, Option<&str>) = match percent_k {
+        k if k >= 90.0 => (-2.0, Some("STOCH_OVERBOUGHT")),
+        k if k >= 80.0 => (-1.0, Some("STOCH_OVERBOUGHT")),
+        k if k <= 10.0 => (2.0, Some("STOCH_OVERSOLD")),
+        k if k <= 20.0 => (1.0, Some("STOCH_OVERSOLD")),
+        _ => (0.0, None),
+    };
+    // 水準系のため「成熟」による抑制はせず、鮮度の高いクロスのみ加味
+    let stoch_score = apply_cross_freshness(stoch_score, crosses.last(), false);
+    let mut codes: Vec<String> = code.map(str::to_string).into_iter().collect();
+    codes.extend(cross_reason_codes("STOCH", &crosses, false));
+
+    guard.set_stoch_crosses(crosses);
+    guard.set_stochastics_score(stoch_score);
+    guard.set_reason_codes(ExtensionIndicator::Stochastics, codes);
+
+    Ok(())
+}
+
+/// ボリンジャーバンドの計算と格納（%B と Bandwidth も計算）    
+fn evaluate_and_store_bollinger(
+    config: &Config,
+    series: &IndicatorSeries,
+    data: &[MarketData],
+    guard: &mut TechnicalDataGuard,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // 一般的な設定（20期間・2σ、系列は全期間で計算済み）
+    let period: usize = 20;
+    if data.len() < period {
+        return Err(InsufficientDataError("❌ BollingerBands: データ数が20未満です。".to_string()).into());
     }
-    fn get_stochastics_k(&self) -> Option<f64> {
-        self.entry.stochastics_k
+
+    // 最新バンド値
+    let last = data.len() - 1;
+    let upper: f64 = series.bb_upper[last];
+    let lower: f64 = series.bb_lower[last];
+
+    let current_price: f64 = data[last].close;
+
+    // 保存（上限・下限）
+    guard.set_bb_upper(upper);
+    guard.set_bb_lower(lower);
+
+    // 中心線（SMA）= (upper + lower)/2（2σ対称なのでこれでOK）
+    let mid: f64 = (upper + lower) * 0.5;
+
+    // %B = (Close - Lower) / (Upper - Lower)
+    let denom = upper - lower;
+    let percent_b: f64 = if denom != 0.0 {
+        (current_price - lower) / denom
+    } else {
+        0.0
+    };
+
+    // Bandwidth(%) = (Upper - Lower) / Mid * 100
+    let bandwidth_pct: f64 = if mid != 0.0 {
+        (upper - lower) / mid * 100.0
+    } else {
+        0.0
+    };
+
+    // 保存（%B と Bandwidth）
+    guard.set_bb_percent_b(percent_b);
+    guard.set_bb_bandwidth(bandwidth_pct);
+
+    // 既存の5段階スコアはそのまま踏襲
+    let (bollinger_score, code): (f64, &str) = match current_price {
+        p if p > upper * 1.02 => (-2.0, "BB_ABOVE_UPPER"), // 上限より2%以上上 → 強い売り
+        p if p > upper => (-1.0, "BB_ABOVE_UPPER"),        // 上限突破 → やや売り
+        p if p < lower * 0.98 => (2.0, "BB_BELOW_LOWER"),  // 下限より2%以上下 → 強い買い
+        p if p < lower => (1.0, "BB_BELOW_LOWER"),         // 下限割れ → やや買い
+        _ => (0.0, "BB_INSIDE_BAND"),                      // バンド内 → 中立
+    };
+    let mut codes = vec![code.to_string()];
+    if bandwidth_pct < config.bb_bandwidth_squeeze_pct {
+        codes.push("BB_SQUEEZE".to_string());
     }
-    fn get_stochastics_d(&self) -> Option<f64> {
-        self.entry.stochastics_d
+    guard.set_bollinger_score(bollinger_score);
+    guard.set_reason_codes(ExtensionIndicator::Bollinger, codes);
+
+    Ok(())
+}
+
+/// フィボナッチを直近スイングの方向に合わせて算出し、スコア化（SoT：ここで一度だけ決める）
+fn evaluate_and_store_fibonacci(
+    series: &IndicatorSeries,
+    data: &[MarketData],
+    guard: &mut TechnicalDataGuard,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if data.len() < 2 {
+        guard.set_fibonacci_score(0.0);
+        return Err(InsufficientDataError("❌ フィボナッチ計算には最低2件のデータが必要です".to_string()).into());
     }
-    fn get_stochastics_score(&self) -> Option<f64> {
-        self.entry.stochastics_score
+
+    // アンカーは「直近の有意なスイング」（トレンド方向の1本の値幅）で決める
+    let swing = find_fibonacci_swing(data, &series.pivots_until(data.len()));
+    let span = swing.high - swing.low;
+    if span <= 0.0 {
+        // 変動がないと閾値を作れない→中立
+        guard.set_fibonacci_score(0.0);
+        return Ok(());
     }
-    fn get_bb_upper(&self) -> f64 {
-        self.entry.bb_upper
+
+    // 上昇スイング：高値から押しを測る／下降スイング：安値から戻りを測る
+    let retrace = |ratio: f64| {
+        if swing.uptrend {
+            swing.high - span * ratio
+        } else {
+            swing.low + span * ratio
+        }
+    };
+    let extend = |ratio: f64| {
+        if swing.uptrend {
+            swing.low + span * ratio
+        } else {
+            swing.high - span * ratio
+        }
+    };
+
+    guard.set_fibo_23_6(retrace(0.236));
+    guard.set_fibo_38_2(retrace(0.382));
+    guard.set_fibo_50_0(retrace(0.500));
+    guard.set_fibo_61_8(retrace(0.618));
+    guard.set_fibo_78_6(retrace(0.786));
+    guard.set_fibo_ext_127_2(extend(1.272));
+    guard.set_fibo_ext_161_8(extend(1.618));
+    guard.set_fib_swing(&swing);
+
+    // ── スコア判定（唯一の真実：ここで一度だけ） ──
+    // depth = スイングに対する押し/戻りの深さ（0=起点の高値/安値、1=スイングの反対端）
+    let close = guard.get_fib_close();
+    let depth = if swing.uptrend {
+        (swing.high - close) / span
+    } else {
+        (close - swing.low) / span
+    };
+    guard.set_fib_depth(depth);
+
+    // 浅い押し/戻り＝トレンド継続、78.6%超＝スイング否定の兆し、100%超＝スイング崩れ
+    let (trend_score, depth_code): (f64, &str) = match depth {
+        d if d < 0.0 => (2.0, "FIB_EXTENSION"),         // スイングの先（エクステンション圏）→ トレンド継続
+        d if d <= 0.618 => (1.0, "FIB_SHALLOW_RETRACE"), // 23.6〜61.8% の押し/戻り → トレンド内の調整
+        d if d <= 0.786 => (0.0, "FIB_DEEP_RETRACE"),    // 深い押し/戻り → 判断保留
+        d if d <= 1.0 => (-1.0, "FIB_SWING_FAILING"),    // 78.6%超 → トレンド否定の兆し
+        _ => (-2.0, "FIB_SWING_BROKEN"),                 // スイング起点を割り込み/上抜け → スイング崩れ
+    };
+    // 下降スイングでは符号を反転（戻り売り＝マイナス、スイング崩れ＝反転上昇でプラス）
+    let score = if swing.uptrend {
+        trend_score
+    } else {
+        -trend_score
+    };
+
+    let swing_code = if swing.uptrend { "FIB_UPSWING" } else { "FIB_DOWNSWING" };
+    guard.set_fibonacci_score(score);
+    guard.set_reason_codes(
+        ExtensionIndicator::Fibonacci,
+        vec![swing_code.to_string(), depth_code.to_string()],
+    );
+    Ok(())
+}
+
+/// 終値SMAの傾きでトレンド方向を判定（データ不足時は None）
+fn detect_trend_direction(data: &[MarketData]) -> Option<bool> {
+    let period = 20;
+    let lag = 5;
+    if data.len() < period + lag {
+        return None;
     }
-    fn get_bb_lower(&self) -> f64 {
-        self.entry.bb_lower
+    let sma_at = |end: usize| {
+        data[end - period..end].iter().map(|d| d.close).sum::<f64>() / period as f64
+    };
+    let now = sma_at(data.len());
+    let before = sma_at(data.len() - lag);
+    Some(now >= before)
+}
+
+/// 直近の有意なスイングを求める。
+/// トレンド方向のピボット（上昇なら安値、下降なら高値）のうち、その後の値幅が
+/// FIB_MIN_SWING_PCT 以上ある最も新しいものを起点にし、以降の極値を終点とする。
+/// 起点はそのピボットが属するサポート/レジスタンス水準（クラスタの平均）に揃える。
+/// 見つからなければ取得期間の高安（先に出た方を起点）で代替する。
+fn find_fibonacci_swing(data: &[MarketData], pivots: &[SwingPivot]) -> FibonacciSwing {
+    let levels = cluster_price_levels(pivots, SR_CLUSTER_TOLERANCE_PCT);
+    // 何度も反応した価格帯は1本のピボットより起点として確か（単独のピボットならそのまま）
+    let anchor_price = |pivot: &SwingPivot| {
+        levels
+            .iter()
+            .find(|l| l.low <= pivot.price && pivot.price <= l.high)
+            .map(|l| l.price)
+            .unwrap_or(pivot.price)
+    };
+    // スイングの終点は前の足までで決める（最新足を含めると終値が常にスイング内に収まり、
+    // エクステンション圏＝スイング高値/安値の更新を判定できない）
+    let last = data.len() - 2;
+
+    // 区間 [from, last] の高値/安値とその位置
+    let extreme_after = |from: usize, want_high: bool| -> (f64, usize) {
+        let mut best = from;
+        for i in from..=last {
+            let better = if want_high {
+                data[i].high > data[best].high
+            } else {
+                data[i].low < data[best].low
+            };
+            if better {
+                best = i;
+            }
+        }
+        let price = if want_high { data[best].high } else { data[best].low };
+        (price, best)
+    };
+
+    // 取得期間の高安（代替用）と、その前後関係による方向
+    let (window_high, high_idx) = extreme_after(0, true);
+    let (window_low, low_idx) = extreme_after(0, false);
+    let uptrend = detect_trend_direction(data).unwrap_or(low_idx < high_idx);
+
+    for pivot in pivots.iter().rev() {
+        let anchor = anchor_price(pivot);
+        if uptrend && !pivot.is_high {
+            let (high, idx) = extreme_after(pivot.index, true);
+            let move_pct = (high - anchor) / anchor * 100.0;
+            if anchor > 0.0 && move_pct >= FIB_MIN_SWING_PCT {
+                return FibonacciSwing {
+                    high,
+                    high_date: data[idx].date.clone(),
+                    low: anchor,
+                    low_date: pivot.date.clone(),
+                    uptrend,
+                };
+            }
+        } else if !uptrend && pivot.is_high {
+            let (low, idx) = extreme_after(pivot.index, false);
+            let move_pct = (anchor - low) / anchor * 100.0;
+            if anchor > 0.0 && move_pct >= FIB_MIN_SWING_PCT {
+                return FibonacciSwing {
+                    high: anchor,
+                    high_date: pivot.date.clone(),
+                    low,
+                    low_date: data[idx].date.clone(),
+                    uptrend,
+                };
+            }
+        }
     }
-    fn get_bb_percent_b(&self) -> f64 {
-        self.entry.bb_percent_b
+
+    FibonacciSwing {
+        high: window_high,
+        high_date: data[high_idx].date.clone(),
+        low: window_low,
+        low_date: data[low_idx].date.clone(),
+        uptrend,
     }
-    fn get_bb_bandwidth(&self) -> f64 {
-        self.entry.bb_bandwidth
+}
+
+/// スイング高値/安値（ピボット）を検出する。
+/// 左右 span 本の高値（安値）より高い（安い）足をピボットとする（右側は同値を許容）。
+fn find_swing_pivots(data: &[MarketData], span: usize) -> Vec<SwingPivot> {
+    let mut pivots = Vec::new();
+    if span == 0 || data.len() < span * 2 + 1 {
+        return pivots;
+    }
+    for i in span..(data.len() - span) {
+        let left = &data[i - span..i];
+        let right = &data[i + 1..=i + span];
+        let high = data[i].high;
+        let low = data[i].low;
+        if left.iter().all(|d| d.high < high) && right.iter().all(|d| d.high <= high) {
+            pivots.push(SwingPivot {
+                index: i,
+                date: data[i].date.clone(),
+                price: high,
+                is_high: true,
+            });
+        }
+        if left.iter().all(|d| d.low > low) && right.iter().all(|d| d.low >= low) {
+            pivots.push(SwingPivot {
+                index: i,
+                date: data[i].date.clone(),
+                price: low,
+                is_high: false,
+            });
+        }
     }
-    fn get_bollinger_score(&self) -> Option<f64> {
-        self.entry.bollinger_score
+    pivots
+}
+
+/// ピボットを価格の近さ（tolerance_pct %以内）でクラスタ化し、水準とタッチ回数にまとめる。
+/// 許容幅はクラスタの起点（最安のピボット）から測る（平均から測るとピボットが連鎖して水準が流れるため）
+fn cluster_price_levels(pivots: &[SwingPivot], tolerance_pct: f64) -> Vec<PriceLevel> {
+    let mut sorted: Vec<&SwingPivot> = pivots.iter().collect();
+    sorted.sort_by(|a, b| a.price.total_cmp(&b.price));
+
+    let mut levels: Vec<PriceLevel> = Vec::new();
+    let mut sum = 0.0;
+    for pivot in sorted {
+        if let Some(level) = levels.last_mut() {
+            let gap_pct = if level.low > 0.0 {
+                (pivot.price - level.low) / level.low * 100.0
+            } else {
+                f64::MAX
+            };
+            if gap_pct <= tolerance_pct {
+                sum += pivot.price;
+                level.touches += 1;
+                level.price = sum / level.touches as f64;
+                level.high = pivot.price;
+                if pivot.date > level.last_date {
+                    level.last_date = pivot.date.clone();
+                }
+                continue;
+            }
+        }
+        sum = pivot.price;
+        levels.push(PriceLevel {
+            price: pivot.price,
+            low: pivot.price,
+            high: pivot.price,
+            touches: 1,
+            last_date: pivot.date.clone(),
+        });
     }
-    fn get_fibo_38_2(&self) -> Option<f64> {
-        self.entry.fibo_38_2
+    levels
+}
+
+/// 終値から見た水準までの距離（%）。上なら正、下なら負
+fn level_distance_pct(close: f64, level: f64) -> f64 {
+    if close != 0.0 {
+        (level - close) / close * 100.0
+    } else {
+        0.0
     }
-    fn get_fibo_50_0(&self) -> Option<f64> {
-        self.entry.fibo_50_0
+}
+
+/// スイングピボットからサポート/レジスタンス水準を求め、最寄り水準とスコアを構造体に格納する
+fn evaluate_and_store_support_resistance(
+    series: &IndicatorSeries,
+    data: &[MarketData],
+    guard: &mut TechnicalDataGuard,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let min_len = SWING_PIVOT_SPAN * 2 + 1;
+    if data.len() < min_len {
+        return Err(InsufficientDataError(format!(
+            "❌ サポート/レジスタンス判定には最低{}本のデータが必要です",
+            min_len
+        ))
+        .into());
     }
-    fn get_fibo_61_8(&self) -> Option<f64> {
-        self.entry.fibo_61_8
+
+    let pivots = series.pivots_until(data.len());
+    let levels = cluster_price_levels(&pivots, SR_CLUSTER_TOLERANCE_PCT);
+    let close = guard.get_close();
+
+    let support = levels
+        .iter()
+        .filter(|l| l.price < close)
+        .max_by(|a, b| a.price.total_cmp(&b.price))
+        .cloned();
+    let resistance = levels
+        .iter()
+        .filter(|l| l.price > close)
+        .min_by(|a, b| a.price.total_cmp(&b.price))
+        .cloned();
+
+    // スコア判定：最寄り水準への近さとタッチ回数（多いほど強い水準）で評価
+    let near = SR_NEAR_LEVEL_PCT;
+    let support_dist = support.as_ref().map(|l| level_distance_pct(close, l.price).abs());
+    let resistance_dist = resistance
+        .as_ref()
+        .map(|l| level_distance_pct(close, l.price).abs());
+    let (score, codes): (f64, &[&str]) = match (&support, &resistance) {
+        // 上値抵抗なし（全水準を上抜け）→ ブレイク継続を評価
+        (Some(_), None) => (1.0, &["SR_ABOVE_ALL_RESISTANCE"]),
+        // 下値支持なし（全水準を割り込み）→ 支えなし
+        (None, Some(_)) => (-1.0, &["SR_BELOW_ALL_SUPPORT"]),
+        (Some(s), Some(r)) => {
+            let sd = support_dist.unwrap_or(f64::MAX);
+            let rd = resistance_dist.unwrap_or(f64::MAX);
+            if sd <= near && sd < rd {
+                if s.touches >= 3 {
+                    (2.0, &["SR_NEAR_SUPPORT", "SR_STRONG_LEVEL"]) // 強いサポート直上 → 反発期待
+                } else {
+                    (1.0, &["SR_NEAR_SUPPORT"])
+                }
+            } else if rd <= near && rd < sd {
+                if r.touches >= 3 {
+                    (-2.0, &["SR_NEAR_RESISTANCE", "SR_STRONG_LEVEL"]) // 強いレジスタンス直下 → 上値が重い
+                } else {
+                    (-1.0, &["SR_NEAR_RESISTANCE"])
+                }
+            } else {
+                (0.0, &[])
+            }
+        }
+        (None, None) => (0.0, &[]),
+    };
+
+    guard.set_sr_levels(levels);
+    guard.set_nearest_support(support);
+    guard.set_nearest_resistance(resistance);
+    guard.set_support_resistance_score(score);
+    guard.set_reason_codes(
+        ExtensionIndicator::SupportResistance,
+        codes.iter().map(|c| c.to_string()).collect(),
+    );
+
+    Ok(())
+}
+
+/// VWAP（日足簡易版）を計算し、構造体にセキュアに格納
+fn evaluate_and_store_vwap(
+    series: &IndicatorSeries,
+    data: &[MarketData],
+    guard: &mut TechnicalDataGuard,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // マジックナンバー回避のため注釈（一般的に14日で使用される）
+    let period = 14;
+
+    if data.len() < period {
+        return Err(InsufficientDataError(format!(
+            "❌ VWAP計算に必要なデータが不足しています（{}日必要）",
+            period
+        ))
+        .into());
     }
-    fn get_fibonacci_score(&self) -> Option<f64> {
-        self.entry.fibonacci_score
+
+    // Typical Price = (High + Low + Close) / 3 の14日平均（系列は全期間で計算済み）
+    let vwap = series.vwap[data.len() - 1];
+
+    let close = guard.get_close(); // セキュアアクセスで終値取得
+    let (vwap_score, _) = score_gap_pct(pct_of_price(close - vwap, vwap));
+    let code = match vwap_score {
+        s if s > 0.0 => "VWAP_ABOVE",
+        s if s < 0.0 => "VWAP_BELOW",
+        _ => "VWAP_NEAR",
+    };
+
+    guard.set_vwap(vwap); // VWAP値を格納
+    guard.set_vwap_score(vwap_score); // スコアも格納
+    guard.set_reason_codes(ExtensionIndicator::Vwap, vec![code.to_string()]);
+
+    Ok(())
+}
+/// 一目均衡表の転換線・基準線を計算し、構造体に格納する関数。
+/// 現在の終値を基に転換線 > 基準線 ならスコア+1、逆は-1、同値は0とする。
+fn evaluate_and_store_ichimoku(
+    series: &IndicatorSeries,
+    data: &[MarketData],
+    guard: &mut TechnicalDataGuard,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // 一目均衡表に必要な期間の長さ（転換線: 9期間、基準線: 26期間）
+    if data.len() < 26 {
+        return Err(InsufficientDataError("❌ 一目均衡表の評価には最低26期間のデータが必要です".to_string()).into());
     }
-    ///フィボナッチリトレースメントライン（38.2%、50%、61.8%）と 終値（close）を比較する必要がある
-    fn get_fib_close(&self) -> f64 {
-        self.entry.close
+
+    // 転換線（過去9日間の高値と安値の平均）
+    let recent_9 = &data[data.len() - 9..];
+    let high_9 = recent_9.iter().map(|d| d.high).fold(f64::MIN, f64::max);
+    let low_9 = recent_9.iter().map(|d| d.low).fold(f64::MAX, f64::min);
+    let tenkan = (high_9 + low_9) / 2.0;
+
+    // 基準線（過去26日間の高値と安値の平均）
+    let recent_26 = &data[data.len() - 26..];
+    let high_26 = recent_26.iter().map(|d| d.high).fold(f64::MIN, f64::max);
+    let low_26 = recent_26.iter().map(|d| d.low).fold(f64::MAX, f64::min);
+    let kijun = (high_26 + low_26) / 2.0;
+
+    // 構造体へ格納（セキュアアクセス経由）
+    guard.set_tenkan_sen(tenkan);
+    guard.set_kijun_sen(kijun);
+
+    // 転換線/基準線のクロス（基準線が揃う26本目以降、全期間で検出済み）
+    let crosses = series.crosses_until(&series.ichimoku_crosses, data.len());
+
+    // 📏 5段階スコア：基準線比の乖離率で ±2（強）±1（通常）0（同値圏）
+    let (ichimoku_score, gap_code) = score_gap_pct(pct_of_price(tenkan - kijun, kijun));
+    let ichimoku_score = apply_cross_freshness(ichimoku_score, crosses.last(), true);
+    let mut codes = vec![format!("ICHIMOKU_{}", gap_code)];
+    codes.extend(cross_reason_codes("ICHIMOKU", &crosses, true));
+
+    guard.set_ichimoku_crosses(crosses);
+    guard.set_ichimoku_score(ichimoku_score);
+    guard.set_reason_codes(ExtensionIndicator::Ichimoku, codes);
+
+    Ok(())
+}
+
+/// ベンチマークとの相対力・ベータ・相関を計算し、構造体に格納する。
+/// 日付で突き合わせた終値（共通日のみ）から、直近 RS_LOOKBACK 本の超過リターンでスコアを付ける。
+fn evaluate_and_store_relative_strength(
+    pairs: &[(usize, f64, f64)],
+    benchmark: &str,
+    guard: &mut TechnicalDataGuard,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if pairs.len() < RS_LOOKBACK + 1 {
+        return Err(InsufficientDataError(format!(
+            "❌ 相対力の計算にはベンチマークと共通の{}日分以上のデータが必要です（共通 {} 日）",
+            RS_LOOKBACK + 1,
+            pairs.len()
+        ))
+        .into());
     }
-    fn get_vwap(&self) -> Option<f64> {
-        self.entry.vwap
+
+    let (_, last_s, last_b) = pairs[pairs.len() - 1];
+    let (_, base_s, base_b) = pairs[pairs.len() - 1 - RS_LOOKBACK];
+    let stock_return_pct = (last_s / base_s - 1.0) * 100.0;
+    let bench_return_pct = (last_b / base_b - 1.0) * 100.0;
+    let excess_return_pct = stock_return_pct - bench_return_pct;
+
+    // 日次リターンからローリングのベータ・相関（窓幅 RS_BETA_WINDOW 本）。
+    // 現在の窓と、RS_LOOKBACK 本前に終わる同じ幅の窓（データが足りる場合のみ）を比べる
+    let tail = &pairs[pairs.len().saturating_sub(RS_BETA_WINDOW + RS_LOOKBACK + 1)..];
+    let returns: Vec<(f64, f64)> = tail
+        .windows(2)
+        .map(|w| (w[1].1 / w[0].1 - 1.0, w[1].2 / w[0].2 - 1.0))
+        .collect();
+    let window = &returns[returns.len().saturating_sub(RS_BETA_WINDOW)..];
+    let (beta, correlation) = beta_and_correlation(window);
+    let beta_prev = (returns.len() >= RS_BETA_WINDOW + RS_LOOKBACK)
+        .then(|| {
+            let end = returns.len() - RS_LOOKBACK;
+            beta_and_correlation(&returns[end - RS_BETA_WINDOW..end]).0
+        })
+        .flatten();
+
+    let (score, code): (f64, &str) = match excess_return_pct {
+        e if e > RS_EXCESS_STRONG_PCT => (2.0, "RS_OUTPERFORM"),
+        e if e > RS_EXCESS_MILD_PCT => (1.0, "RS_OUTPERFORM"),
+        e if e < -RS_EXCESS_STRONG_PCT => (-2.0, "RS_UNDERPERFORM"),
+        e if e < -RS_EXCESS_MILD_PCT => (-1.0, "RS_UNDERPERFORM"),
+        _ => (0.0, "RS_INLINE"),
+    };
+
+    guard.set_relative_strength(RelativeStrengthStats {
+        benchmark: benchmark.to_string(),
+        stock_return_pct,
+        bench_return_pct,
+        excess_return_pct,
+        beta,
+        correlation,
+        samples: window.len(),
+        beta_prev,
+    });
+    guard.set_relative_strength_score(score);
+    guard.set_reason_codes(ExtensionIndicator::RelativeStrength, vec![code.to_string()]);
+
+    Ok(())
+}
+
+/// 日次リターンの組（銘柄, ベンチマーク）からベータと相関係数を求める（分散が0なら None）
+fn beta_and_correlation(window: &[(f64, f64)]) -> (Option<f64>, Option<f64>) {
+    if window.is_empty() {
+        return (None, None);
     }
-    fn get_vwap_score(&self) -> Option<f64> {
-        self.entry.vwap_score
+    let n = window.len() as f64;
+    let mean_s = window.iter().map(|r| r.0).sum::<f64>() / n;
+    let mean_b = window.iter().map(|r| r.1).sum::<f64>() / n;
+    let cov = window
+        .iter()
+        .map(|r| (r.0 - mean_s) * (r.1 - mean_b))
+        .sum::<f64>()
+        / n;
+    let var_s = window.iter().map(|r| (r.0 - mean_s).powi(2)).sum::<f64>() / n;
+    let var_b = window.iter().map(|r| (r.1 - mean_b).powi(2)).sum::<f64>() / n;
+    let beta = (var_b > 0.0).then(|| cov / var_b);
+    let correlation = (var_s > 0.0 && var_b > 0.0).then(|| cov / (var_s.sqrt() * var_b.sqrt()));
+    (beta, correlation)
+}
+
+/// ローソク足パターン（1本・2本・3本組＋窓）を直近の足で検出し、構造体に格納する。
+/// 直近 CANDLE_LOOKBACK 本で完成したパターンのスコアを合算し、-2〜+2 にクランプする。
+fn evaluate_and_store_candlestick(
+    data: &[MarketData],
+    guard: &mut TechnicalDataGuard,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // 3本組パターン＋直前トレンド判定（5本前比較）に必要な本数
+    let min_len = CANDLE_LOOKBACK + 5;
+    if data.len() < min_len {
+        return Err(InsufficientDataError(format!(
+            "❌ ローソク足パターン判定には最低{}本のデータが必要です",
+            min_len
+        ))
+        .into());
     }
-    fn get_tenkan_sen(&self) -> Option<f64> {
-        self.entry.tenkan_sen
+
+    let mut patterns: Vec<CandlePattern> = Vec::new();
+    for i in (data.len() - CANDLE_LOOKBACK)..data.len() {
+        patterns.extend(detect_candle_patterns_at(data, i));
     }
-    fn get_kijun_sen(&self) -> Option<f64> {
-        self.entry.kijun_sen
+
+    let score = patterns
+        .iter()
+        .map(|p| p.score)
+        .sum::<f64>()
+        .clamp(-2.0, 2.0);
+
+    let mut codes = Vec::new();
+    if patterns.iter().any(|p| p.score > 0.0) {
+        codes.push("CANDLE_BULLISH_PATTERN".to_string());
     }
-    fn get_ichimoku_score(&self) -> Option<f64> {
-        self.entry.ichimoku_score
+    if patterns.iter().any(|p| p.score < 0.0) {
+        codes.push("CANDLE_BEARISH_PATTERN".to_string());
     }
+
+    guard.set_candle_patterns(patterns);
+    guard.set_candlestick_score(score);
+    guard.set_reason_codes(ExtensionIndicator::Candlestick, codes);
+
+    Ok(())
 }
 
-///ファイル読み込み時に通す共通チェック関数
-fn sanitize_ascii_file_lines(path: &Path) -> Result<Vec<String>, Box<dyn Error>> {
-    let content = read_to_string(path)
-        .map_err(|e| format!("❌ ファイル読み込み失敗: {} ({})", path.display(), e))?;
-    let mut result = Vec::new();
-    for (i, line) in content.lines().enumerate() {
-        let line_number = i + 1;
-        // ✅ 1行目に限って先頭BOMを除去
-        let line = if line_number == 1 {
-            if let Some(stripped) = line.strip_prefix('\u{FEFF}') {
-                stripped
-            } else {
-                line
+/// 足 i で完成するローソク足パターンを列挙する（i >= 5 を前提）
+fn detect_candle_patterns_at(data: &[MarketData], i: usize) -> Vec<CandlePattern> {
+    let mut found: Vec<CandlePattern> = Vec::new();
+    let date = data[i].date.clone();
+    let mut push = |name: &'static str, score: f64| {
+        found.push(CandlePattern {
+            name,
+            date: date.clone(),
+            score,
+        });
+    };
+
+    let cur = &data[i];
+    let prev = &data[i - 1];
+    let prev2 = &data[i - 2];
+
+    // 直前5本の終値変化で地合いを判定（ハンマー/首吊り線などの文脈用）
+    let prior_change = data[i - 1].close - data[i - 5].close;
+    let prior_down = prior_change < 0.0;
+    let prior_up = prior_change > 0.0;
+
+    // ── 1本足：十字線 / ハンマー / 首吊り線 / 流れ星 / 逆ハンマー ──
+    let (body, range, upper, lower) = candle_shape(cur);
+    if range > 0.0 {
+        if body <= range * 0.1 {
+            push("十字線（同時線）", 0.0);
+        } else if lower >= body * 2.0 && upper <= body * 0.5 {
+            if prior_down {
+                push("ハンマー（たくり線）", 1.0);
+            } else if prior_up {
+                push("首吊り線", -1.0);
+            }
+        } else if upper >= body * 2.0 && lower <= body * 0.5 {
+            if prior_up {
+                push("流れ星（トウバ）", -1.0);
+            } else if prior_down {
+                push("逆ハンマー（トンカチ）", 1.0);
             }
+        }
+    }
+
+    // ── 2本組：包み足 ──
+    let (prev_body, _, _, _) = candle_shape(prev);
+    let cur_bull = cur.close > cur.open;
+    let cur_bear = cur.close < cur.open;
+    let prev_bull = prev.close > prev.open;
+    let prev_bear = prev.close < prev.open;
+    if prev_bear && cur_bull && cur.open <= prev.close && cur.close >= prev.open && body > prev_body
+    {
+        push("強気の包み足（陽の抱き線）", 2.0);
+    } else if prev_bull
+        && cur_bear
+        && cur.open >= prev.close
+        && cur.close <= prev.open
+        && body > prev_body
+    {
+        push("弱気の包み足（陰の抱き線）", -2.0);
+    }
+
+    // ── 3本組：明けの明星 / 宵の明星 ──
+    let (prev2_body, _, _, _) = candle_shape(prev2);
+    let prev2_mid = (prev2.open + prev2.close) / 2.0;
+    let star_small = prev2_body > 0.0 && prev_body <= prev2_body * 0.3;
+    if star_small && prev2.close < prev2.open && cur_bull && cur.close > prev2_mid {
+        push("明けの明星", 2.0);
+    } else if star_small && prev2.close > prev2.open && cur_bear && cur.close < prev2_mid {
+        push("宵の明星", -2.0);
+    }
+
+    // ── 3本組：赤三兵 / 黒三兵 ──
+    let three = [prev2, prev, cur];
+    let all_bull = three.iter().all(|d| d.close > d.open);
+    let all_bear = three.iter().all(|d| d.close < d.open);
+    let rising = prev.close > prev2.close && cur.close > prev.close;
+    let falling = prev.close < prev2.close && cur.close < prev.close;
+    // 始値が前の足の実体内にあること（寄り付きで大きく飛ばない）
+    let opens_inside = three.windows(2).all(|w| {
+        let (lo, hi) = (w[0].open.min(w[0].close), w[0].open.max(w[0].close));
+        (lo..=hi).contains(&w[1].open)
+    });
+    if all_bull && rising && opens_inside {
+        push("赤三兵", 2.0);
+    } else if all_bear && falling && opens_inside {
+        push("黒三兵", -2.0);
+    }
+
+    // ── 窓（ギャップ）：窓開け / 三空 ──
+    let gap_up = |a: &MarketData, b: &MarketData| b.low > a.high;
+    let gap_down = |a: &MarketData, b: &MarketData| b.high < a.low;
+    let prev3 = &data[i - 3];
+    if gap_up(prev3, prev2) && gap_up(prev2, prev) && gap_up(prev, cur) {
+        push("三空踏み上げ（過熱）", -2.0);
+    } else if gap_down(prev3, prev2) && gap_down(prev2, prev) && gap_down(prev, cur) {
+        push("三空叩き込み（売られすぎ）", 2.0);
+    } else if gap_up(prev, cur) {
+        push("窓開け（上放れ）", 1.0);
+    } else if gap_down(prev, cur) {
+        push("窓開け（下放れ）", -1.0);
+    }
+
+    found
+}
+
+/// ローソク足の形状（実体・値幅・上ヒゲ・下ヒゲ）を返す
+/// ※最新足は close を最新価格で上書きしているため、高安を始値/終値で補正する
+fn candle_shape(d: &MarketData) -> (f64, f64, f64, f64) {
+    let body_top = d.open.max(d.close);
+    let body_bottom = d.open.min(d.close);
+    let high = d.high.max(body_top);
+    let low = d.low.min(body_bottom);
+    (
+        body_top - body_bottom,
+        high - low,
+        high - body_top,
+        body_bottom - low,
+    )
+}
+
+/// 2本の系列（短期/長期）のクロスを履歴全体から検出する。
+/// warmup 本目以降（両系列が安定した区間）のみを対象とし、bars_ago は最新足からの本数。
+fn detect_crosses(dates: &[String], fast: &[f64], slow: &[f64], warmup: usize) -> Vec<CrossEvent> {
+    let n = dates.len().min(fast.len()).min(slow.len());
+    let mut events = Vec::new();
+    if n < 2 {
+        return events;
+    }
+    for i in warmup.max(1)..n {
+        let prev_diff = fast[i - 1] - slow[i - 1];
+        let diff = fast[i] - slow[i];
+        let kind = if prev_diff <= 0.0 && diff > 0.0 {
+            CrossKind::Golden
+        } else if prev_diff >= 0.0 && diff < 0.0 {
+            CrossKind::Dead
         } else {
-            line
+            continue;
         };
-        if line.len() > 500 {
-            return Err(format!(
-                "❌ ファイル {} の {}行目: 行が長すぎます（{}文字）",
-                path.display(),
-                line_number,
-                line.len()
-            )
-            .into());
-        }
-        if line.contains('\0') {
-            return Err(format!(
-                "❌ ファイル {} の {}行目: NULLバイトが含まれています",
-                path.display(),
-                line_number
-            )
-            .into());
-        }
-        if line
-            .chars()
-            .any(|c| c.is_control() && c != '\n' && c != '\r')
-        {
-            return Err(format!(
-                "❌ ファイル {} の {}行目: 制御文字を含んでいます",
-                path.display(),
-                line_number
-            )
-            .into());
-        }
-        if line.contains('\u{FEFF}') {
-            return Err(format!(
-                "❌ ファイル {} の {}行目: BOM（Byte Order Mark）が含まれています",
-                path.display(),
-                line_number
-            )
-            .into());
-        }
-        result.push(line.to_string());
+        events.push(CrossEvent {
+            kind,
+            date: dates[i].clone(),
+            bars_ago: n - 1 - i,
+            slope: fast[i] - fast[i - 1],
+        });
+    }
+    events
+}
+
+/// 直近クロスの鮮度でスコアを補正する。
+/// - クロスから CROSS_FRESH_BARS 本以内：クロス方向へ +1（鮮度の高いシグナル）
+/// - クロスから CROSS_STALE_BARS 本以上：同方向のスコアを ±1 に抑制（成熟トレンド）
+///   ※ cap_stale=false の指標（水準系オシレーター）は抑制しない
+fn apply_cross_freshness(score: f64, latest: Option<&CrossEvent>, cap_stale: bool) -> f64 {
+    let Some(cross) = latest else {
+        return score;
+    };
+    let direction = match cross.kind {
+        CrossKind::Golden => 1.0,
+        CrossKind::Dead => -1.0,
+    };
+    match cross_freshness(cross, cap_stale) {
+        Some(true) => (score + direction).clamp(-2.0, 2.0),
+        Some(false) if score * direction > 0.0 => score.clamp(-1.0, 1.0),
+        _ => score,
+    }
+}
+
+/// クロスの鮮度（Some(true)=鮮度が高い、Some(false)=成熟＝cap_stale 時のみ、None=どちらでもない）
+fn cross_freshness(cross: &CrossEvent, cap_stale: bool) -> Option<bool> {
+    if cross.bars_ago <= CROSS_FRESH_BARS {
+        Some(true)
+    } else if cap_stale && cross.bars_ago >= CROSS_STALE_BARS {
+        Some(false)
+    } else {
+        None
     }
-    Ok(result)
 }
 
-fn resolve_hardcoded_info(ticker: &str) -> Option<HardcodedInfo> {
-    match ticker {
-        "QQQ" => Some(HardcodedInfo {
-            formal_name: "Invesco QQQ Trust (NASDAQ100)",
-            // query: "QQQ OR \"NASDAQ100\"",
-        }),
-        "SPY" => Some(HardcodedInfo {
-            formal_name: "SPDR S&P 500 ETF Trust (S&P500)",
-            //query: "SPY OR \"S&P500\"",
-        }),
-        "ACWI" => Some(HardcodedInfo {
-            formal_name: "iShares MSCI ACWI ETF (オールカントリー)",
-            //query: "ACWI OR \"オールカントリー\"",
-        }),
-        "FANG+" => Some(HardcodedInfo {
-            formal_name: "NYSE FANG+ Index",
-            //query: "FANG+ OR \"FANGプラス指数\"",
-        }),
-        _ => None,
+/// 直近クロスの表示行（端末/LLM共通）
+fn compose_cross_lines(label: &str, crosses: &[CrossEvent]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let Some(latest) = crosses.last() else {
+        lines.push(format!("➡️ {}: 取得期間内にクロスなし", label));
+        return lines;
+    };
+    let kind_text = match latest.kind {
+        CrossKind::Golden => "ゴールデンクロス",
+        CrossKind::Dead => "デッドクロス",
+    };
+    lines.push(format!(
+        "✖️ {}: 直近{} {}（{}本前 / クロス時の傾き {:+.2}）",
+        label, kind_text, latest.date, latest.bars_ago, latest.slope
+    ));
+    if latest.bars_ago <= CROSS_FRESH_BARS {
+        lines.push(format!(
+            "🆕 クロスから{}本以内 → 鮮度の高いシグナルとしてスコアに反映",
+            CROSS_FRESH_BARS
+        ));
+    } else if latest.bars_ago >= CROSS_STALE_BARS {
+        lines.push(format!(
+            "⏳ クロスから{}本以上経過 → 成熟したトレンドとしてスコアを抑制",
+            CROSS_STALE_BARS
+        ));
     }
+    lines
 }
 
-/// 環境変数と設定の初期化処理
-fn initialize_environment_and_config() -> BuildCfgResult {
-    // 方針：tickwise.env を読み込み一般設定は env にセットするが、
-    // セキュリティ上 API キー（OPENAI_API_KEY / BRAVE_API_KEY）はここでセットしない。
-    let env_path = Path::new("tickwise.env");
-    match sanitize_ascii_file_lines(env_path) {
-        Ok(lines) => {
-            let content = lines.join("\n");
-            for raw in content.lines() {
-                let mut line = raw.trim().to_string();
-                if line.is_empty() || line.starts_with('#') {
-                    continue;
-                }
-                if let Some(rest) = line.strip_prefix("export ") {
-                    line = rest.trim().to_string();
+/// クロス履歴をログ用の1セルに整形（CSV区切りの ',' を含めない）
+fn latest_cross_log_cells(crosses: &[CrossEvent]) -> [String; 3] {
+    match crosses.last() {
+        Some(c) => [
+            match c.kind {
+                CrossKind::Golden => "golden".to_string(),
+                CrossKind::Dead => "dead".to_string(),
+            },
+            c.date.clone(),
+            c.bars_ago.to_string(),
+        ],
+        None => [String::new(), String::new(), String::new()],
+    }
+}
+
+/// クロス履歴をJSON配列に変換
+fn crosses_to_json(crosses: &[CrossEvent]) -> Value {
+    json!(crosses
+        .iter()
+        .map(|c| json!({
+            "kind": match c.kind {
+                CrossKind::Golden => "golden",
+                CrossKind::Dead => "dead",
+            },
+            "date": c.date,
+            "bars_ago": c.bars_ago,
+            "slope": c.slope,
+        }))
+        .collect::<Vec<_>>())
+}
+
+/// カスタム指標の式を字句に分割する
+fn tokenize_expr(src: &str) -> Result<Vec<ExprToken>, String> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push(ExprToken::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(ExprToken::RParen);
+                i += 1;
+            }
+            ',' => {
+                tokens.push(ExprToken::Comma);
+                i += 1;
+            }
+            '0'..='9' | '.' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
                 }
-                if line.is_empty() || line.starts_with('#') {
-                    continue;
+                let text: String = chars[start..i].iter().collect();
+                let num = text
+                    .parse::<f64>()
+                    .map_err(|_| format!("数値として解釈できません: {}", text))?;
+                tokens.push(ExprToken::Num(num));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                    i += 1;
                 }
-                if let Some(idx) = line.find('=') {
-                    let key = line[..idx].trim();
-                    // セキュリティ方針：APIキーはここで永続的にセットしない（送信直前に参照）。
-                    if key.eq_ignore_ascii_case("OPENAI_API_KEY")
-                        || key.eq_ignore_ascii_case("BRAVE_API_KEY")
-                    {
-                        continue;
-                    }
-                    let raw_val = line[idx + 1..].trim();
-                    let val = if raw_val.starts_with('"') {
-                        if raw_val.len() >= 2 && raw_val.ends_with('"') {
-                            raw_val[1..raw_val.len() - 1].to_string()
-                        } else if let Some(pos) = raw_val.rfind('"') {
-                            raw_val[1..pos].to_string()
-                        } else {
-                            raw_val.trim_matches('"').to_string()
+                tokens.push(ExprToken::Ident(
+                    chars[start..i].iter().collect::<String>().to_lowercase(),
+                ));
+            }
+            _ => {
+                let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+                let op = match two.as_str() {
+                    "<=" => BinOp::Le,
+                    ">=" => BinOp::Ge,
+                    "==" => BinOp::Eq,
+                    "!=" => BinOp::Ne,
+                    "&&" => BinOp::And,
+                    "||" => BinOp::Or,
+                    _ => match c {
+                        '+' => BinOp::Add,
+                        '-' => BinOp::Sub,
+                        '*' => BinOp::Mul,
+                        '/' => BinOp::Div,
+                        '<' => BinOp::Lt,
+                        '>' => BinOp::Gt,
+                        '!' => {
+                            tokens.push(ExprToken::Not);
+                            i += 1;
+                            continue;
                         }
-                    } else if let Some(pound) = raw_val.find('#') {
-                        raw_val[..pound].trim_end().to_string()
-                    } else {
-                        raw_val.to_string()
-                    };
-                    if !key.is_empty() {
-                        std::env::set_var(key, val);
-                    }
-                }
+                        _ => return Err(format!("使用できない文字です: '{}'", c)),
+                    },
+                };
+                i += if matches!(
+                    op,
+                    BinOp::Le | BinOp::Ge | BinOp::Eq | BinOp::Ne | BinOp::And | BinOp::Or
+                ) {
+                    2
+                } else {
+                    1
+                };
+                tokens.push(ExprToken::Op(op));
             }
         }
-        Err(e) => {
-            eprintln!("⚠️ tickwise.env の読み込み/サニタイズに失敗しました（無視されます）: {}", e);
-        }
     }
+    Ok(tokens)
+}
 
-    // 以下は既存処理（args 解析, build_config 等）
-    let mut args = Args::parse();
-
-    if args.show_log_header {
-        let config = build_config(&args);
-        generate_csv_header(&config);
-        std::process::exit(0);
+/// カスタム指標の式を構文木に変換する（優先順位: or < and < 比較 < 加減 < 乗除 < 単項）
+fn parse_expr(src: &str) -> Result<Expr, String> {
+    let tokens = tokenize_expr(src)?;
+    let mut parser = ExprParser { tokens, pos: 0 };
+    let expr = parser.parse_or()?;
+    if parser.pos != parser.tokens.len() {
+        return Err(format!("式の末尾に解釈できない要素があります（{}番目の要素）", parser.pos + 1));
     }
+    Ok(expr)
+}
 
-    let raw_ticker = match args.ticker {
-        Some(ref t) => t.clone(),
-        None => {
-            eprintln!("❌ --ticker は必須です");
-            std::process::exit(1);
-        }
-    };
-
-    args.ticker = Some(sanitize_ticker(&raw_ticker).unwrap_or_else(|err| {
-        eprintln!("{err}");
-        std::process::exit(1);
-    }));
-    args.ticker = Some(normalize_ticker_input(args.ticker.as_deref().unwrap_or("")));
-    args.ticker = Some(normalize_ticker(args.ticker.as_deref().unwrap_or("")));
+/// 再帰下降パーサ（parse_expr からのみ使用）
+struct ExprParser {
+    tokens: Vec<ExprToken>,
+    pos: usize,
+}
 
-    if let Some(q) = &args.custom_news_query {
-        args.custom_news_query = Some(sanitize_news_query(q).unwrap_or_else(|err| {
-            eprintln!("{err}");
-            std::process::exit(1);
-        }));
-    }
-    if let Some(n) = &args.openai_extra_note {
-        args.openai_extra_note = Some(sanitize_llm_note(n).unwrap_or_else(|err| {
-            eprintln!("{err}");
-            std::process::exit(1);
-        }));
+impl ExprParser {
+    fn peek(&self) -> Option<&ExprToken> {
+        self.tokens.get(self.pos)
     }
 
-    let config = build_config(&args);
-    if let Some(s) = args.openai_api_key.as_mut() {
-        s.zeroize();
-        s.clear();
-    }
-    if let Some(s) = args.brave_api_key.as_mut() {
-        s.zeroize();
-        s.clear();
+    fn next(&mut self) -> Option<ExprToken> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
     }
 
+    fn expect(&mut self, expected: ExprToken, label: &str) -> Result<(), String> {
+        match self.next() {
+            Some(ref t) if *t == expected => Ok(()),
+            _ => Err(format!("'{}' が必要です", label)),
+        }
+    }
 
-    if config.debug_args {
-        if config.no_env_indicators {
+    /// 指定した演算子群の左結合二項演算を読む
+    fn parse_binary(
+        &mut self,
+        ops: &[BinOp],
+        lower: fn(&mut Self) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        let mut lhs = lower(self)?;
+        while let Some(ExprToken::Op(op)) = self.peek() {
+            let op = *op;
+            if !ops.contains(&op) {
+                break;
+            }
+            self.pos += 1;
+            let rhs = lower(self)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
         }
-        eprintln!("Config= {}", config_debug_string(&config));
+        Ok(lhs)
     }
 
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        self.parse_binary(&[BinOp::Or], Self::parse_and)
+    }
 
-    let ticker = config.ticker.clone();
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        self.parse_binary(&[BinOp::And], Self::parse_comparison)
+    }
 
-    let ticker_name_map = match &config.alias_csv {
-        Some(csv_path) => load_alias_csv(csv_path)?,
-        None => HashMap::new(),
-    };
+    fn parse_comparison(&mut self) -> Result<Expr, String> {
+        self.parse_binary(
+            &[BinOp::Lt, BinOp::Le, BinOp::Gt, BinOp::Ge, BinOp::Eq, BinOp::Ne],
+            Self::parse_additive,
+        )
+    }
 
-    Ok((config, ticker, ticker_name_map))
-}
+    fn parse_additive(&mut self) -> Result<Expr, String> {
+        self.parse_binary(&[BinOp::Add, BinOp::Sub], Self::parse_multiplicative)
+    }
 
-/// ニュース取得のコントローラ関数
-async fn news_flow_controller(
-    guard: &TechnicalDataGuard,
-    config: &Config,
-) -> Result<Vec<Article>, Box<dyn std::error::Error>> {
-    // 1) CLI のキーを優先
-    if !config.brave_api_key.trim().is_empty() {
-        let key_owned = Zeroizing::new(config.brave_api_key.clone());
-        let fetched = run_news_once(guard, config, Some(&*key_owned)).await.unwrap_or_default();
-        drop(key_owned);
-        let lines = compose_news_lines(guard, config, &fetched);
-        if config.show_news {
-            print_lines_to_terminal(&lines);
-        }
-        return Ok(fetched);
+    fn parse_multiplicative(&mut self) -> Result<Expr, String> {
+        self.parse_binary(&[BinOp::Mul, BinOp::Div], Self::parse_unary)
     }
 
-    // 2) CLI に無ければ tickwise.env を直前に堅牢に参照
-    let env_path = Path::new("tickwise.env");
-    let mut found_key: Option<String> = None;
-    if let Ok(lines) = sanitize_ascii_file_lines(env_path) {
-        for raw in lines {
-            let mut line = raw.trim().to_string();
-            if line.is_empty() || line.starts_with('#') {
-                continue;
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(ExprToken::Op(BinOp::Sub)) => {
+                self.pos += 1;
+                Ok(Expr::Neg(Box::new(self.parse_unary()?)))
             }
-            if let Some(rest) = line.strip_prefix("export ") {
-                line = rest.trim().to_string();
+            Some(ExprToken::Not) => {
+                self.pos += 1;
+                Ok(Expr::Not(Box::new(self.parse_unary()?)))
             }
-            if let Some(idx) = line.find('=') {
-                let k = line[..idx].trim();
-                if k.eq_ignore_ascii_case("BRAVE_API_KEY") {
-                    let raw_val = line[idx + 1..].trim();
-                    let v = if raw_val.starts_with('"') {
-                        if raw_val.len() >= 2 && raw_val.ends_with('"') {
-                            raw_val[1..raw_val.len() - 1].to_string()
-                        } else if let Some(pos) = raw_val.rfind('"') {
-                            raw_val[1..pos].to_string()
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(ExprToken::Num(n)) => Ok(Expr::Num(n)),
+            Some(ExprToken::LParen) => {
+                let inner = self.parse_or()?;
+                self.expect(ExprToken::RParen, ")")?;
+                Ok(inner)
+            }
+            Some(ExprToken::Ident(name)) => {
+                if self.peek() != Some(&ExprToken::LParen) {
+                    return match name.as_str() {
+                        "open" => Ok(Expr::Field(PriceField::Open)),
+                        "high" => Ok(Expr::Field(PriceField::High)),
+                        "low" => Ok(Expr::Field(PriceField::Low)),
+                        "close" => Ok(Expr::Field(PriceField::Close)),
+                        "and" | "or" | "not" => Err(format!("'{}' ではなく && / || / ! を使用してください", name)),
+                        _ => Err(format!("未知の系列名です: {}", name)),
+                    };
+                }
+                self.pos += 1;
+                let mut args = Vec::new();
+                if self.peek() != Some(&ExprToken::RParen) {
+                    loop {
+                        args.push(self.parse_or()?);
+                        if self.peek() == Some(&ExprToken::Comma) {
+                            self.pos += 1;
                         } else {
-                            raw_val.trim_matches('"').to_string()
+                            break;
                         }
-                    } else if let Some(pound) = raw_val.find('#') {
-                        raw_val[..pound].trim_end().to_string()
-                    } else {
-                        raw_val.to_string()
-                    };
-                    if !v.is_empty() {
-                        found_key = Some(v);
                     }
-                    break;
                 }
+                self.expect(ExprToken::RParen, ")")?;
+                build_expr_call(&name, args)
             }
+            Some(_) => Err("式の途中に予期しない要素があります".to_string()),
+            None => Err("式が途中で終わっています".to_string()),
         }
     }
+}
 
-    let articles: Vec<Article> = if let Some(k) = found_key {
-        let key_owned = Zeroizing::new(k);
-        let fetched = run_news_once(guard, config, Some(&*key_owned)).await.unwrap_or_default();
-        drop(key_owned);
-        fetched
-    } else {
-        Vec::new()
+/// 関数呼び出しを検証して構文木にする（期間引数は1以上の整数リテラルのみ許可）
+fn build_expr_call(name: &str, mut args: Vec<Expr>) -> Result<Expr, String> {
+    let (func, arity) = match name {
+        "sma" => (ExprFunc::Sma, 2),
+        "ema" => (ExprFunc::Ema, 2),
+        "rsi" => (ExprFunc::Rsi, 2),
+        "highest" => (ExprFunc::Highest, 2),
+        "lowest" => (ExprFunc::Lowest, 2),
+        "stdev" => (ExprFunc::Stdev, 2),
+        "roc" => (ExprFunc::Roc, 2),
+        "prev" => (ExprFunc::Prev, 2),
+        "abs" => (ExprFunc::Abs, 1),
+        "min" => (ExprFunc::Min, 2),
+        "max" => (ExprFunc::Max, 2),
+        _ => return Err(format!("未知の関数です: {}", name)),
+    };
+    if args.len() != arity {
+        return Err(format!(
+            "{}() の引数は{}個です（指定: {}個）",
+            name,
+            arity,
+            args.len()
+        ));
+    }
+    if matches!(func, ExprFunc::Abs | ExprFunc::Min | ExprFunc::Max) {
+        return Ok(Expr::Call(func, args, 0));
+    }
+    let period = match args.pop() {
+        Some(Expr::Num(n)) if n >= 1.0 && n.fract() == 0.0 => n as usize,
+        _ => return Err(format!("{}() の期間は1以上の整数で指定してください", name)),
     };
+    Ok(Expr::Call(func, args, period))
+}
 
-    let lines = compose_news_lines(guard, config, &articles);
-    if config.show_news {
-        if articles.is_empty() {
-            println!("【注記】ニュース検索は BRAVE_API_KEY 未設定のためスキップ。");
-        } else {
-            print_lines_to_terminal(&lines);
+/// 構文木を時系列全体で評価する（各足の値。算出できない足は NaN）
+fn eval_expr(expr: &Expr, data: &[MarketData]) -> Result<Vec<f64>, String> {
+    use ta::indicators::{ExponentialMovingAverage, Maximum, Minimum, SimpleMovingAverage, StandardDeviation};
+
+    let len = data.len();
+    let truthy = |v: f64| v != 0.0;
+    let values = match expr {
+        Expr::Num(n) => vec![*n; len],
+        Expr::Field(field) => data
+            .iter()
+            .map(|d| match field {
+                PriceField::Open => d.open,
+                PriceField::High => d.high,
+                PriceField::Low => d.low,
+                PriceField::Close => d.close,
+            })
+            .collect(),
+        Expr::Neg(inner) => eval_expr(inner, data)?.into_iter().map(|v| -v).collect(),
+        Expr::Not(inner) => eval_expr(inner, data)?
+            .into_iter()
+            .map(|v| if v.is_nan() { v } else { f64::from(u8::from(!truthy(v))) })
+            .collect(),
+        Expr::Binary(op, lhs, rhs) => {
+            let l = eval_expr(lhs, data)?;
+            let r = eval_expr(rhs, data)?;
+            l.into_iter()
+                .zip(r)
+                .map(|(a, b)| {
+                    if a.is_nan() || b.is_nan() {
+                        return f64::NAN;
+                    }
+                    let flag = |cond: bool| f64::from(u8::from(cond));
+                    match op {
+                        BinOp::Add => a + b,
+                        BinOp::Sub => a - b,
+                        BinOp::Mul => a * b,
+                        BinOp::Div if b == 0.0 => f64::NAN,
+                        BinOp::Div => a / b,
+                        BinOp::Lt => flag(a < b),
+                        BinOp::Le => flag(a <= b),
+                        BinOp::Gt => flag(a > b),
+                        BinOp::Ge => flag(a >= b),
+                        BinOp::Eq => flag((a - b).abs() < f64::EPSILON),
+                        BinOp::Ne => flag((a - b).abs() >= f64::EPSILON),
+                        BinOp::And => flag(truthy(a) && truthy(b)),
+                        BinOp::Or => flag(truthy(a) || truthy(b)),
+                    }
+                })
+                .collect()
+        }
+        Expr::Call(func, args, period) => {
+            let period = *period;
+            let x = eval_expr(&args[0], data)?;
+            let ta_err = |e: ta::errors::TaError| format!("期間 {} が不正です: {}", period, e);
+            match func {
+                ExprFunc::Sma => rolling_ta(SimpleMovingAverage::new(period).map_err(ta_err)?, &x, period),
+                ExprFunc::Ema => rolling_ta(ExponentialMovingAverage::new(period).map_err(ta_err)?, &x, period),
+                ExprFunc::Rsi => rolling_ta(RelativeStrengthIndex::new(period).map_err(ta_err)?, &x, period + 1),
+                ExprFunc::Highest => rolling_ta(Maximum::new(period).map_err(ta_err)?, &x, period),
+                ExprFunc::Lowest => rolling_ta(Minimum::new(period).map_err(ta_err)?, &x, period),
+                ExprFunc::Stdev => rolling_ta(StandardDeviation::new(period).map_err(ta_err)?, &x, period),
+                ExprFunc::Prev => (0..len)
+                    .map(|i| if i >= period { x[i - period] } else { f64::NAN })
+                    .collect(),
+                ExprFunc::Roc => (0..len)
+                    .map(|i| {
+                        if i >= period && x[i - period] != 0.0 {
+                            (x[i] / x[i - period] - 1.0) * 100.0
+                        } else {
+                            f64::NAN
+                        }
+                    })
+                    .collect(),
+                ExprFunc::Abs => x.into_iter().map(f64::abs).collect(),
+                ExprFunc::Min | ExprFunc::Max => {
+                    let y = eval_expr(&args[1], data)?;
+                    x.into_iter()
+                        .zip(y)
+                        .map(|(a, b)| {
+                            if a.is_nan() || b.is_nan() {
+                                f64::NAN
+                            } else if *func == ExprFunc::Min {
+                                a.min(b)
+                            } else {
+                                a.max(b)
+                            }
+                        })
+                        .collect()
+                }
+            }
+        }
+    };
+    Ok(values)
+}
+
+/// ta の逐次インジケータを系列に適用する（NaN の足は読み飛ばし、warmup 本に満たない足は NaN）
+fn rolling_ta<I: Next<f64, Output = f64>>(mut indicator: I, xs: &[f64], warmup: usize) -> Vec<f64> {
+    let mut out = vec![f64::NAN; xs.len()];
+    let mut fed = 0;
+    for (i, &x) in xs.iter().enumerate() {
+        if x.is_nan() {
+            continue;
+        }
+        let v = indicator.next(x);
+        fed += 1;
+        if fed >= warmup {
+            out[i] = v;
         }
     }
-    Ok(articles)
+    out
+}
+
+/// カスタム指標の式の系列（全期間で計算済み）から最新足の値を取り、スコア帯によるスコアを構造体に格納する
+fn evaluate_and_store_custom(
+    index: usize,
+    indicator: &CustomIndicator,
+    series: &Result<Vec<f64>, String>,
+    data: &[MarketData],
+    guard: &mut TechnicalDataGuard,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let series = series
+        .as_ref()
+        .map_err(|e| format!("❌ カスタム指標 {} の評価に失敗しました: {}", indicator.name, e))?;
+    let required = indicator.ast.required_bars();
+    if data.len() < required {
+        return Err(InsufficientDataError(format!(
+            "❌ カスタム指標 {} の計算には{}本以上のデータが必要です",
+            indicator.name, required
+        ))
+        .into());
+    }
+    let value = series[data.len() - 1];
+    if !value.is_finite() {
+        return Err(format!(
+            "❌ カスタム指標 {} の値を算出できません（ゼロ除算など）",
+            indicator.name
+        )
+        .into());
+    }
+
+    let score = indicator.score_for(value);
+    guard.set_custom_result(CustomIndicatorResult {
+        name: indicator.name.clone(),
+        value,
+        score,
+    });
+    guard.set_reason_codes(
+        ExtensionIndicator::Custom(index),
+        vec![direction_reason_code("CUSTOM", score)],
+    );
+
+    Ok(())
+}
+
+/// プラグインを実行し、結果（またはエラー内容）を構造体に格納する。
+/// 失敗しても他の指標の評価は止めず、エラーは表示・ログ・プロンプトに明示される。
+fn evaluate_and_store_plugin(
+    index: usize,
+    spec: &PluginSpec,
+    config: &Config,
+    data: &[MarketData],
+    guard: &mut TechnicalDataGuard,
+) {
+    let outcome = run_plugin(spec, config, data);
+    match &outcome {
+        Ok(output) => guard.set_reason_codes(
+            ExtensionIndicator::Plugin(index),
+            vec![direction_reason_code("PLUGIN", output.score)],
+        ),
+        Err(e) => eprintln!("⚠️ プラグイン {} の実行に失敗しました: {}", spec.name, e),
+    }
+    guard.set_plugin_outcome(PluginOutcome {
+        name: spec.name.clone(),
+        result: outcome,
+    });
 }
 
-// 追加: debug 出力用（キーを露出しない、安全な表現を返す）
-fn config_debug_string(cfg: &Config) -> String {
-    let mut tmp = cfg.clone();
-    tmp.openai_api_key = if tmp.openai_api_key.trim().is_empty() {
-        "<UNSET>".to_string()
-    } else {
-        "<REDACTED_OPENAI_KEY>".to_string()
-    };
-    tmp.brave_api_key = if tmp.brave_api_key.trim().is_empty() {
-        "<UNSET>".to_string()
-    } else {
-        "<REDACTED_BRAVE_KEY>".to_string()
+/// スコアの向きによる理由コード（カスタム指標・プラグイン）
+fn direction_reason_code(prefix: &str, score: f64) -> String {
+    let direction = match score {
+        s if s > 0.0 => "BULLISH",
+        s if s < 0.0 => "BEARISH",
+        _ => "NEUTRAL",
     };
-    format!("{:?}", tmp)
+    format!("{}_{}", prefix, direction)
 }
 
-/// インデックスティッカーの変換
-/// 例: "S&P500" → "SPY"
-fn normalize_ticker_input(raw: &str) -> String {
-    match raw.trim().to_uppercase().as_str() {
-        // 米国インデックス
-        "S&P500" | "SNP500" | "SP500" => "SPY".to_string(), // S&P500 ETF（最も一般的）
-        "NASDAQ100" | "ナスダック100" => "QQQ".to_string(), // NASDAQ100 ETF
-        "DOW" | "DJIA" | "ダウ平均" => "DIA".to_string(),   // ダウ平均 ETF
-
-        // 日本インデックス
-        "日経平均" | "NIKKEI225" => "1321.T".to_string(), // 日経平均連動ETF
-        "TOPIX" => "1306.T".to_string(),                  // TOPIX連動ETF
-
-        // 全世界・全米
-        "全世界" | "オールカントリー" | "ACWI" => "ACWI".to_string(), // 全世界ETF
-        "全米" | "VTI" | "トータルマーケット" => "VTI".to_string(),   // 全米市場ETF
+/// プラグインに時系列と設定を stdin で渡し、stdout の JSON を検証して返す（timeout_ms 超過で強制終了）
+fn run_plugin(spec: &PluginSpec, config: &Config, data: &[MarketData]) -> Result<PluginOutput, String> {
+    use std::io::Read;
+    use std::process::{Command, Stdio};
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    let input = json!({
+        "version": PLUGIN_PROTOCOL_VERSION,
+        "ticker": config.ticker,
+        "bars": data
+            .iter()
+            .map(|d| json!({
+                "date": d.date,
+                "open": d.open,
+                "high": d.high,
+                "low": d.low,
+                "close": d.close,
+            }))
+            .collect::<Vec<_>>(),
+        "config": {
+            "stance": stance_caption(&config.stance),
+            "buy_rsi": config.buy_rsi,
+            "sell_rsi": config.sell_rsi,
+            "params": spec.params,
+        },
+    })
+    .to_string();
+
+    let mut child = Command::new(&spec.command)
+        .args(&spec.args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("起動できません（{}）: {}", spec.command, e))?;
+
+    // パイプ詰まりを避けるため、書き込み・読み出しは別スレッドで行う。
+    // 読み出しの結果はチャネルで受け取り、出力を閉じない孫プロセスがいても期限を過ぎて待たない
+    let mut stdin = child.stdin.take().ok_or("stdin を開けません")?;
+    std::thread::spawn(move || {
+        let _ = stdin.write_all(input.as_bytes());
+    });
+    // 上限を超えたかを判別するため、上限 + 1 バイトまで読む
+    let stdout = child.stdout.take().ok_or("stdout を開けません")?;
+    let (out_tx, out_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stdout.take(PLUGIN_MAX_OUTPUT_BYTES as u64 + 1).read_to_end(&mut buf);
+        let _ = out_tx.send(buf);
+    });
+    let stderr = child.stderr.take().ok_or("stderr を開けません")?;
+    let (err_tx, err_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stderr.take(PLUGIN_MAX_OUTPUT_BYTES as u64).read_to_end(&mut buf);
+        let _ = err_tx.send(String::from_utf8_lossy(&buf).into_owned());
+    });
+    let too_large = || format!("出力が大きすぎます（上限 {} バイト）", PLUGIN_MAX_OUTPUT_BYTES);
 
-        // FANG+インデックス
-        "FANG+" | "FANGプラス" => "FNGU".to_string(),
-        _ => raw.to_string(),
+    let deadline = Instant::now() + Duration::from_millis(spec.timeout_ms);
+    let mut stdout_bytes: Option<Vec<u8>> = None;
+    let status = loop {
+        if stdout_bytes.is_none() {
+            stdout_bytes = out_rx.try_recv().ok();
+        }
+        // 上限を超えて書き続けるプラグインはパイプ詰まりで止まるので、タイムアウトを待たずに打ち切る
+        if stdout_bytes.as_ref().is_some_and(|buf| buf.len() > PLUGIN_MAX_OUTPUT_BYTES) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(too_large());
+        }
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("タイムアウト（{}ms）", spec.timeout_ms));
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(10)),
+            Err(e) => return Err(format!("終了待ちに失敗しました: {}", e)),
+        }
+    };
+    let remaining = || deadline.saturating_duration_since(Instant::now());
+    let stdout_bytes = match stdout_bytes {
+        Some(buf) => buf,
+        None => out_rx.recv_timeout(remaining()).map_err(|_| {
+            format!("タイムアウト（{}ms、終了後も出力が閉じられません）", spec.timeout_ms)
+        })?,
+    };
+    if stdout_bytes.len() > PLUGIN_MAX_OUTPUT_BYTES {
+        return Err(too_large());
     }
-}
-/// ティッカーのサニタイズ
-fn sanitize_ticker(t: &str) -> Result<String, &'static str> {
-    let cleaned = t.trim().to_uppercase();
-    // 英数字と.Tのみを許可
-    if !cleaned
-        .chars()
-        .all(|c| c.is_alphanumeric() || c == '.' || c == 'T')
-    {
-        eprintln!("❌ 無効なティッカー形式: {t}");
-        return Err("❌ ティッカーは英数字と.Tのみ使用可能です");
+    let stderr_text = err_rx.recv_timeout(remaining()).unwrap_or_default();
+
+    if !status.success() {
+        let detail: String = stderr_text.trim().chars().take(200).collect();
+        return Err(format!(
+            "異常終了（{}）{}",
+            status,
+            if detail.is_empty() { String::new() } else { format!(": {}", detail) }
+        ));
     }
-    Ok(cleaned)
+
+    let text = String::from_utf8(stdout_bytes).map_err(|_| "出力が UTF-8 ではありません")?;
+    let value: Value =
+        serde_json::from_str(text.trim()).map_err(|e| format!("出力が JSON ではありません: {}", e))?;
+    validate_plugin_output(&value)
 }
-/// ニュースクエリのサニタイズ
-fn sanitize_news_query(q: &str) -> Result<String, &'static str> {
-    if q.len() > 200 {
-        return Err("❌ ニュースクエリは200文字以内で入力してください");
+
+/// プラグイン出力のスキーマ検証
+/// 必須: score（-2〜+2 の数値）／任意: values（数値のオブジェクト）・description（文字列配列）
+fn validate_plugin_output(value: &Value) -> Result<PluginOutput, String> {
+    let obj = value.as_object().ok_or("出力はオブジェクトである必要があります")?;
+
+    let score = obj
+        .get("score")
+        .and_then(Value::as_f64)
+        .ok_or("score（数値）がありません")?;
+    if !(-2.0..=2.0).contains(&score) {
+        return Err(format!("score は -2〜+2 で返してください: {}", score));
+    }
+
+    let mut values: Vec<(String, f64)> = Vec::new();
+    match obj.get("values") {
+        None | Some(Value::Null) => {}
+        Some(Value::Object(map)) => {
+            for (key, v) in map {
+                // キーはCSVログの1セルにそのまま書くため、名前と同じく英数字・_・- のみ許可する
+                if key.is_empty()
+                    || !key
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                {
+                    return Err(format!("values のキーは英数字・_・- のみ使用できます: '{}'", key));
+                }
+                let num = v
+                    .as_f64()
+                    .ok_or_else(|| format!("values.{} が数値ではありません", key))?;
+                values.push((key.clone(), num));
+            }
+        }
+        Some(_) => return Err("values はオブジェクトである必要があります".to_string()),
     }
-    if q.contains([';', '|', '`']) {
-        return Err("❌ ニュースクエリに使用できない文字が含まれています");
+
+    let mut description: Vec<String> = Vec::new();
+    match obj.get("description") {
+        None | Some(Value::Null) => {}
+        Some(Value::Array(items)) => {
+            for item in items.iter().take(PLUGIN_MAX_DESCRIPTION_LINES) {
+                let line = item.as_str().ok_or("description は文字列の配列である必要があります")?;
+                description.push(line.chars().take(200).collect());
+            }
+        }
+        Some(_) => return Err("description は文字列の配列である必要があります".to_string()),
     }
-    Ok(q.trim().to_string())
+
+    Ok(PluginOutput {
+        score,
+        values,
+        description,
+    })
 }
-/// LLMノートのサニタイズ
-fn sanitize_llm_note(note: &str) -> Result<String, &'static str> {
-    // 危険な記号のみ禁止
-    if note.contains([';', '|', '`']) {
-        return Err("❌ 使用できない記号（; | `）が含まれています");
-    }
-    let cleaned = note
-        .trim()
-        .replace('\n', " ")
-        .replace(|c: char| c.is_whitespace(), " ");
-    if cleaned.len() > 2000 {
-        return Err("❌ 入力が長すぎます（2000文字以内で入力してください）");
-    }
-    Ok(cleaned)
+
+/// プラグインの数値出力をログ用の1セルに整形（キーは validate_plugin_output で英数字・_・- に限っているため、CSV区切りの ',' を含まない）
+fn format_plugin_values_for_log(values: &[(String, f64)]) -> String {
+    values
+        .iter()
+        .map(|(k, v)| format!("{}={:.4}", k, v))
+        .collect::<Vec<_>>()
+        .join(" | ")
 }
 
-/// stance setting
-fn parse_stance(stance: &str) -> Stance {
-    match stance {
-        "buyer" => Stance::Buyer,
-        "seller" => Stance::Seller,
-        _ => Stance::Holder, // 省略は holder にフォールバック
+///出力先セレクター
+fn select_output_target(
+    config: &Config,
+    guard: &TechnicalDataGuard,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let results = collect_log_results(config, guard);
+
+    if config.save_technical_log {
+        save_technical_log(config, guard, &results)?;
+    }
+
+    if !config.silent {
+        technical_render_to_terminal(config, guard);
     }
+
+    Ok(())
 }
 
-/// コンフィグの構築
-fn build_config(args: &Args) -> Config {
-    
-    Config {
-        debug_args: args.debug_args,
-        no_env_indicators: args.no_env_indicators,
+/// ログ出力用に、有効な拡張指標のスコアを出力順に並べる（CSV行の列順と対応）
+fn collect_log_results(config: &Config, guard: &TechnicalDataGuard) -> Vec<AnalysisResult> {
+    let mut results: Vec<AnalysisResult> = Vec::new();
 
-        // テクニカル閾値
-        buy_rsi: if args.buy_rsi == 30.0 {
-            if args.no_env_indicators {
-                30.0
-            } else {
-                env::var("BUY_RSI")
-                    .ok()
-                    .and_then(|v| v.parse().ok())
-                    .unwrap_or(30.0)
-            }
-        } else {
-            args.buy_rsi
-        },
-        sell_rsi: if args.sell_rsi == 70.0 {
-            if args.no_env_indicators {
-                70.0
-            } else {
-                env::var("SELL_RSI")
-                    .ok()
-                    .and_then(|v| v.parse().ok())
-                    .unwrap_or(70.0)
+    for indicator in &config.enabled_extensions {
+        match indicator {
+            ExtensionIndicator::Ema => {
+                let ema_score_value = guard.get_ema_score();
+                results.push(AnalysisResult {
+                    indicator_name: "EMA".to_string(),
+                    description: Vec::new(),
+                    score: ema_score_value,
+                });
             }
-        } else {
-            args.sell_rsi
-        },
-        macd_diff_low: if args.macd_diff_low == 2.0 {
-            if args.no_env_indicators {
-                2.0
-            } else {
-                env::var("MACD_DIFF_LOW")
-                    .ok()
-                    .and_then(|v| v.parse().ok())
-                    .unwrap_or(2.0)
+            ExtensionIndicator::Sma => {
+                let sma_score_value = guard.get_sma_score();
+                results.push(AnalysisResult {
+                    indicator_name: "SMA".to_string(),
+                    description: Vec::new(),
+                    score: sma_score_value,
+                });
             }
-        } else {
-            args.macd_diff_low
-        },
-        macd_diff_mid: if args.macd_diff_mid == 10.0 {
-            if args.no_env_indicators {
-                10.0
-            } else {
-                env::var("MACD_DIFF_MID")
-                    .ok()
-                    .and_then(|v| v.parse().ok())
-                    .unwrap_or(10.0)
+            ExtensionIndicator::Roc => {
+                let roc_score_value = guard.get_roc_score();
+                results.push(AnalysisResult {
+                    indicator_name: "ROC".to_string(),
+                    description: Vec::new(),
+                    score: roc_score_value,
+                });
             }
-        } else {
-            args.macd_diff_mid
-        },
-        // MACDマイナス圏フラグ
-        macd_minus_ok: if args.no_env_indicators {
-            args.macd_minus_ok
-        } else {
-            args.macd_minus_ok || get_bool_env("MACD_MINUS_OK")
-        },
-        // 投資スタンス設定
-                stance: {
-            let stance_source = if args.no_env_indicators {
-                args.stance.clone()
-            } else if args.stance == "holder" {
-                std::env::var("STANCE").unwrap_or_else(|_| "holder".to_string())
-            } else {
-                args.stance.clone()
-            };
-            parse_stance(&stance_source)
-        },
-
-
-        // Weight設定
-        weight_basic: if args.no_env_indicators {
-            args.weight_basic
-        } else {
-            get_f64_from_args_or_env(args.weight_basic, "WEIGHT_BASIC", 1.0)
-        },
-        weight_ema: if args.no_env_indicators {
-            args.weight_ema
-        } else {
-            get_f64_from_args_or_env(args.weight_ema, "WEIGHT_EMA", 1.0)
-        },
-        weight_sma: if args.no_env_indicators {
-            args.weight_sma
-        } else {
-            get_f64_from_args_or_env(args.weight_sma, "WEIGHT_SMA", 1.0)
-        },
-        weight_bollinger: if args.no_env_indicators {
-            args.weight_bollinger
-        } else {
-            get_f64_from_args_or_env(args.weight_bollinger, "WEIGHT_BOLLINGER", 1.0)
-        },
-        weight_roc: if args.no_env_indicators {
-            args.weight_roc
-        } else {
-            get_f64_from_args_or_env(args.weight_roc, "WEIGHT_ROC", 1.0)
-        },
-        weight_adx: if args.no_env_indicators {
-            args.weight_adx
-        } else {
-            get_f64_from_args_or_env(args.weight_adx, "WEIGHT_ADX", 1.0)
-        },
-        weight_stochastics: if args.no_env_indicators {
-            args.weight_stochastics
-        } else {
-            get_f64_from_args_or_env(args.weight_stochastics, "WEIGHT_STOCHASTICS", 1.0)
-        },
-        weight_fibonacci: if args.no_env_indicators {
-            args.weight_fibonacci
-        } else {
-            get_f64_from_args_or_env(args.weight_fibonacci, "WEIGHT_FIBONACCI", 1.0)
-        },
-        weight_vwap: if args.no_env_indicators {
-            args.weight_vwap
-        } else {
-            get_f64_from_args_or_env(args.weight_vwap, "WEIGHT_VWAP", 1.0)
-        },
-        weight_ichimoku: if args.no_env_indicators {
-            args.weight_ichimoku
-        } else {
-            get_f64_from_args_or_env(args.weight_ichimoku, "WEIGHT_ICHIMOKU", 1.0)
-        },
-        // ✅ 拡張指標の選択（Vec<ExtensionIndicator> に変換）
-                enabled_extensions: {
-            let mut extensions = Vec::new();
-            if args.ema || (!args.no_env_indicators && get_bool_env("EMA")) {
-                extensions.push(ExtensionIndicator::Ema);
+            ExtensionIndicator::Adx => {
+                let adx_score_value = guard.get_adx_score();
+                results.push(AnalysisResult {
+                    indicator_name: "ADX".to_string(),
+                    description: Vec::new(),
+                    score: adx_score_value,
+                });
             }
-            if args.sma || (!args.no_env_indicators && get_bool_env("SMA")) {
-                extensions.push(ExtensionIndicator::Sma);
+            ExtensionIndicator::Stochastics => {
+                let stoch_score_value = guard.get_stochastics_score();
+                results.push(AnalysisResult {
+                    indicator_name: "Stochastics".to_string(),
+                    description: Vec::new(),
+                    score: stoch_score_value,
+                });
             }
-            if args.roc || (!args.no_env_indicators && get_bool_env("ROC")) {
-                extensions.push(ExtensionIndicator::Roc);
+            ExtensionIndicator::Bollinger => {
+                let bollinger_score_value = guard.get_bollinger_score();
+                results.push(AnalysisResult {
+                    indicator_name: "Bollinger".to_string(),
+                    description: Vec::new(),
+                    score: bollinger_score_value,
+                });
             }
-            if args.adx || (!args.no_env_indicators && get_bool_env("ADX")) {
-                extensions.push(ExtensionIndicator::Adx);
+            ExtensionIndicator::Fibonacci => {
+                let fibonacci_score_value = guard.get_fibonacci_score();
+                results.push(AnalysisResult {
+                    indicator_name: "Fibonacci".to_string(),
+                    description: Vec::new(),
+                    score: fibonacci_score_value,
+                });
             }
-            if args.stochastics || (!args.no_env_indicators && get_bool_env("STOCHASTICS")) {
-                extensions.push(ExtensionIndicator::Stochastics);
+            ExtensionIndicator::Vwap => {
+                let vwap_score_value = guard.get_vwap_score();
+                results.push(AnalysisResult {
+                    indicator_name: "VWAP".to_string(),
+                    description: Vec::new(),
+                    score: vwap_score_value,
+                });
             }
-            if args.bollinger || (!args.no_env_indicators && get_bool_env("BOLLINGER")) {
-                extensions.push(ExtensionIndicator::Bollinger);
+            ExtensionIndicator::Ichimoku => {
+                let ichimoku_score_value = guard.get_ichimoku_score();
+                results.push(AnalysisResult {
+                    indicator_name: "Ichimoku".to_string(),
+                    description: Vec::new(),
+                    score: ichimoku_score_value,
+                });
             }
-            if args.fibonacci || (!args.no_env_indicators && get_bool_env("FIBONACCI")) {
-                extensions.push(ExtensionIndicator::Fibonacci);
+            ExtensionIndicator::Candlestick => {
+                let candlestick_score_value = guard.get_candlestick_score();
+                results.push(AnalysisResult {
+                    indicator_name: "Candlestick".to_string(),
+                    description: Vec::new(),
+                    score: candlestick_score_value,
+                });
             }
-            if args.vwap || (!args.no_env_indicators && get_bool_env("VWAP")) {
-                extensions.push(ExtensionIndicator::Vwap);
+            ExtensionIndicator::SupportResistance => {
+                let sr_score_value = guard.get_support_resistance_score();
+                results.push(AnalysisResult {
+                    indicator_name: "SupportResistance".to_string(),
+                    description: Vec::new(),
+                    score: sr_score_value,
+                });
             }
-            if args.ichimoku || (!args.no_env_indicators && get_bool_env("ICHIMOKU")) {
-                extensions.push(ExtensionIndicator::Ichimoku);
+            ExtensionIndicator::RelativeStrength => {
+                let rs_score_value = guard.get_relative_strength_score();
+                results.push(AnalysisResult {
+                    indicator_name: "RelativeStrength".to_string(),
+                    description: Vec::new(),
+                    score: rs_score_value,
+                });
             }
-            extensions
-        },
+            ExtensionIndicator::Custom(index) => {
+                let name = &config.custom_indicators[*index].name;
+                let custom_score_value = guard.get_custom_result(name).map(|r| r.score);
+                results.push(AnalysisResult {
+                    indicator_name: format!("Custom:{}", name),
+                    description: Vec::new(),
+                    score: custom_score_value,
+                });
+            }
+            ExtensionIndicator::Plugin(index) => {
+                let name = &config.plugins[*index].name;
+                let plugin_score_value = guard.get_plugin_score(name);
+                results.push(AnalysisResult {
+                    indicator_name: format!("Plugin:{}", name),
+                    description: Vec::new(),
+                    score: plugin_score_value,
+                });
+            }
+        }
+    }
 
-        bb_bandwidth_squeeze_pct: sanitize_percent(
-            if args.bb_bandwidth_squeeze_pct == 8.0 {
-                if args.no_env_indicators {
-                    8.0
-                } else {
-                    env::var("BB_BANDWIDTH_SQUEEZE_PCT")
-                        .ok()
-                        .and_then(|v| v.parse().ok())
-                        .unwrap_or(8.0)
-                }
-            } else {
-                args.bb_bandwidth_squeeze_pct
-            },
-            0.0,
-            100.0,
-            "Bollinger bandwidth squeeze threshold (%)",
-        ),
-        // LLM設定
-        llm_provider: if args.llm_provider == "openai" {
-            std::env::var("LLM_PROVIDER").unwrap_or_else(|_| "openai".to_string())
-        } else {
-            args.llm_provider.clone()
-        },
+    results
+}
 
-        // OpenAI設定
-        openai_model: if args.openai_model == "gpt-4.1-nano" {
-            env::var("OPENAI_MODEL").unwrap_or_else(|_| "gpt-4.1-nano".to_string())
-        } else {
-            args.openai_model.clone()
-        },
-        openai_api_key: {
-            if let Some(k) = &args.openai_api_key {
-                k.clone()
-            } else {
-                env::var("OPENAI_API_KEY").ok().unwrap_or_default()
+/// テクニカル分析結果を画面に出力（セキュアアクセスにより構造体を直接参照せず、TechnicalDataGuard経由で取得）
+fn technical_render_to_terminal(config: &Config, guard: &TechnicalDataGuard) {
+    // ① メイン情報
+    display_main_info(config, guard);
+
+    // ①' リスク統計
+    print_lines_to_terminal(&compose_risk_lines(guard));
+
+    // ② 基本テクニカル分析
+    let basic_result = render_basic(config, guard);
+    display_analysis_result(&basic_result);
+
+    // ③ 拡張テクニカル分析
+    let extension_results = render_extension(config, guard);
+    for extension_result in extension_results {
+        match extension_result {
+            ExtensionResult::Header(ref s) => println!("{}", s),
+            ExtensionResult::Analysis(ref result) => display_analysis_result(result),
+        }
+    }
+
+    // ④ 総合スコア表示（相場局面による重み補正があれば先に表示）
+    print_lines_to_terminal(&compose_regime_lines(guard));
+    render_final_score(config, guard);
+}
+
+/// 拡張テクニカル指標のレンダリング処理（仕様厳守）
+enum ExtensionResult {
+    Header(String),
+    Analysis(AnalysisResult),
+}
+/// 拡張テクニカル指標のレンダリング処理（仕様厳守）
+fn render_extension(config: &Config, guard: &TechnicalDataGuard) -> Vec<ExtensionResult> {
+    let mut extension_results = Vec::new();
+
+    let mut categorized_map: HashMap<IndicatorCategory, Vec<ExtensionIndicator>> = HashMap::new();
+    for indicator in &config.enabled_extensions {
+        let category = indicator.category();
+        categorized_map
+            .entry(category)
+            .or_default()
+            .push(indicator.clone());
+    }
+
+    for category in [
+        IndicatorCategory::Trend,
+        IndicatorCategory::Oscillator,
+        IndicatorCategory::Volatility,
+        IndicatorCategory::SupportResist,
+        IndicatorCategory::Candlestick,
+        IndicatorCategory::Custom,
+        IndicatorCategory::Plugin,
+    ] {
+        if let Some(indicators) = categorized_map.get(&category) {
+            match category {
+                IndicatorCategory::Trend => extension_results
+                    .push(ExtensionResult::Header("---トレンド系指標---".to_string())),
+                IndicatorCategory::Oscillator => extension_results.push(ExtensionResult::Header(
+                    "---オシレーター系指標---".to_string(),
+                )),
+                IndicatorCategory::Volatility => extension_results.push(ExtensionResult::Header(
+                    "---ボラティリティ系指標---".to_string(),
+                )),
+                IndicatorCategory::SupportResist => extension_results.push(
+                    ExtensionResult::Header("---補助トレンド系指標---".to_string()),
+                ),
+                IndicatorCategory::Candlestick => extension_results.push(
+                    ExtensionResult::Header("---ローソク足パターン---".to_string()),
+                ),
+                IndicatorCategory::Custom => extension_results
+                    .push(ExtensionResult::Header("---カスタム指標---".to_string())),
+                IndicatorCategory::Plugin => extension_results
+                    .push(ExtensionResult::Header("---プラグイン指標---".to_string())),
             }
-        },
-        brave_api_key: {
-            if let Some(k) = &args.brave_api_key {
-                k.clone()
-            } else {
-                std::env::var("BRAVE_API_KEY").ok().unwrap_or_default()
+
+            for indicator in indicators {
+                // 評価に失敗した指標は値を出さずにステータスを表示（プラグインは専用表示で失敗理由を出す）
+                let failed = guard
+                    .get_indicator_status(&extension_log_name(config, indicator))
+                    .filter(|status| !status.is_ok());
+                if let (Some(status), false) =
+                    (failed, matches!(indicator, ExtensionIndicator::Plugin(_)))
+                {
+                    extension_results.push(ExtensionResult::Analysis(render_indicator_failure(
+                        config, indicator, status,
+                    )));
+                    continue;
+                }
+                let rendered = match indicator {
+                    ExtensionIndicator::Ema => render_ema(config, guard),
+                    ExtensionIndicator::Sma => render_sma(config, guard),
+                    ExtensionIndicator::Bollinger => render_bollinger(config, guard),
+                    ExtensionIndicator::Roc => render_roc(config, guard),
+                    ExtensionIndicator::Adx => render_adx(config, guard),
+                    ExtensionIndicator::Stochastics => render_stochastics(config, guard),
+                    ExtensionIndicator::Fibonacci => render_fibonacci(config, guard),
+                    ExtensionIndicator::Vwap => render_vwap(config, guard),
+                    ExtensionIndicator::Ichimoku => render_ichimoku(config, guard),
+                    ExtensionIndicator::Candlestick => render_candlestick(config, guard),
+                    ExtensionIndicator::SupportResistance => {
+                        render_support_resistance(config, guard)
+                    }
+                    ExtensionIndicator::RelativeStrength => render_relative_strength(config, guard),
+                    ExtensionIndicator::Custom(index) => render_custom(config, guard, *index),
+                    ExtensionIndicator::Plugin(index) => render_plugin(config, guard, *index),
+                };
+                extension_results.push(ExtensionResult::Analysis(rendered));
             }
-        },
-        openai_extra_note: args
-            .openai_extra_note
-            .clone()
-            .or_else(|| env::var("OPENAI_EXTRA_NOTE").ok()),
-        // ニュース検索設定
-        no_news: args.no_news || get_bool_env("NO_NEWS"),
-        custom_news_query: args
-            .custom_news_query
-            .clone()
-            .or_else(|| env::var("CUSTOM_NEWS_QUERY").ok())
-            .filter(|s| !s.trim().is_empty()),
+        }
+    }
+    extension_results
+}
 
-        news_filter: args.news_filter || get_bool_env("NEWS_FILTER"),
-        show_news: args.show_news || get_bool_env("SHOW_NEWS"),
+/// 評価に失敗した拡張指標の表示（データ不足 / エラー）
+fn render_indicator_failure(
+    config: &Config,
+    indicator: &ExtensionIndicator,
+    status: &IndicatorStatus,
+) -> AnalysisResult {
+    let name = extension_log_name(config, indicator);
+    let mut description_lines = vec![format!("📊 【{}】", name)];
+    match status {
+        IndicatorStatus::InsufficientData(reason) => {
+            description_lines.push(format!("⚠️ データ不足のため算出できません: {}", reason));
+        }
+        IndicatorStatus::Error(reason) => {
+            description_lines.push(format!("❌ 評価に失敗しました: {}", reason));
+        }
+        IndicatorStatus::Skipped(reason) => {
+            description_lines.push(format!("⏭️ 評価を省略しました: {}", reason));
+        }
+        IndicatorStatus::Ok => {}
+    }
+    description_lines.push(format!(
+        "📝 スコア: 欠損（総合スコアでは{}、Weight({:.1})）",
+        config.missing_score_policy,
+        extension_weight(config, indicator)
+    ));
 
-        news_count: match args.news_count {
-            Some(n_count) => n_count.clamp(1, 50),
-            None => env::var("NEWS_COUNT")
-                .ok()
-                .and_then(|v| v.parse::<usize>().ok())
-                .map(|n_count| n_count.clamp(1, 50))
-                .unwrap_or(if args.news_filter || get_bool_env("NEWS_FILTER") {
-                    20
-                } else {
-                    50
-                }),
-        },
+    AnalysisResult {
+        indicator_name: name,
+        description: description_lines,
+        score: None,
+    }
+}
 
-        news_freshness: match args.news_freshness.clone() {
-            Some(s) => s,
-            None => env::var("NEWS_FRESHNESS").unwrap_or_else(|_| {
-                if args.news_filter || get_bool_env("NEWS_FILTER") {
-                    "pw".to_string()
-                } else {
-                    "pm".to_string()
+/// 総合スコアの集計値
+struct ScoreTotals {
+    total_score: f64,  // 加重合計（欠損スコアは0点）
+    total_weight: f64, // 重み合計×2.0（欠損スコアの重みはポリシーに従う）
+    score_ratio: f64,
+    coverage: f64, // 有効な重みのうちスコアを算出できた割合
+}
+
+/// 基本スコアと拡張指標の (重み, スコア) から総合スコアを集計する（スナップショットと最適化で共通）。
+/// parts は評価を省略した指標を含めない。スコアが None の指標は欠損として扱う
+fn aggregate_scores(
+    policy: MissingScorePolicy,
+    signal_score: f64,
+    weight_basic: f64,
+    parts: &[(f64, Option<f64>)],
+) -> ScoreTotals {
+    let mut total_score = signal_score * weight_basic;
+    let mut enabled_weights = weight_basic;
+    let mut scored_weights = weight_basic;
+    for (weight, score) in parts {
+        total_score += score.unwrap_or(0.0) * weight;
+        enabled_weights += weight;
+        if score.is_some() {
+            scored_weights += weight;
+        }
+    }
+    let sum_weights = match policy {
+        MissingScorePolicy::Neutral => enabled_weights,
+        MissingScorePolicy::Exclude | MissingScorePolicy::Fail => scored_weights,
+    };
+    let total_weight = 2.0 * sum_weights;
+    ScoreTotals {
+        total_score,
+        total_weight,
+        score_ratio: if total_weight != 0.0 { total_score / total_weight } else { 0.0 },
+        coverage: if enabled_weights != 0.0 { scored_weights / enabled_weights } else { 1.0 },
+    }
+}
+
+/// 評価した（省略していない）拡張指標の (重み, スコア)
+fn extension_score_parts(config: &Config, guard: &TechnicalDataGuard) -> Vec<(f64, Option<f64>)> {
+    config
+        .enabled_extensions
+        .iter()
+        .filter(|ext| !extension_skipped(config, guard, ext))
+        .map(|ext| (extension_weight(config, ext), extension_score(config, guard, ext)))
+        .collect()
+}
+
+// 最終スコアのスナップショットを一度だけ算出（SoT）
+fn calculate_final_score_snapshot(
+    config: &Config,
+    guard: &TechnicalDataGuard,
+) -> FinalScoreSnapshot {
+    let totals = aggregate_scores(
+        config.missing_score_policy,
+        guard.get_signal_score(),
+        config.weight_basic,
+        &extension_score_parts(config, guard),
+    );
+    let missing: Vec<String> = config
+        .enabled_extensions
+        .iter()
+        .filter(|ext| {
+            !extension_skipped(config, guard, ext) && extension_score(config, guard, ext).is_none()
+        })
+        .map(|ext| extension_log_name(config, ext))
+        .collect();
+
+    // カテゴリ別の内訳（基本解析 + 有効な拡張指標のカテゴリ）
+    let basic_weight = 2.0 * config.weight_basic;
+    let mut categories = vec![CategoryScore {
+        key: "basic",
+        label: "基本",
+        score: guard.get_signal_score() * config.weight_basic,
+        weight: basic_weight,
+        ratio: (basic_weight != 0.0)
+            .then(|| guard.get_signal_score() * config.weight_basic / basic_weight),
+    }];
+    for category in IndicatorCategory::ALL {
+        let members: Vec<&ExtensionIndicator> = config
+            .enabled_extensions
+            .iter()
+            .filter(|ext| ext.category() == category && !extension_skipped(config, guard, ext))
+            .collect();
+        if members.is_empty() {
+            continue;
+        }
+        let mut score = 0.0;
+        let mut weights = 0.0;
+        for ext in members {
+            let weight = extension_weight(config, ext);
+            match extension_score(config, guard, ext) {
+                Some(value) => {
+                    score += value * weight;
+                    weights += weight;
                 }
-            }),
-        },
+                None if config.missing_score_policy == MissingScorePolicy::Neutral => {
+                    weights += weight;
+                }
+                None => {}
+            }
+        }
+        let weight = 2.0 * weights;
+        categories.push(CategoryScore {
+            key: category.key(),
+            label: category.label(),
+            score,
+            weight,
+            ratio: (weight != 0.0).then(|| score / weight),
+        });
+    }
 
-        // ログ設定
-        save_technical_log: args.save_technical_log || get_bool_env("SAVE_TECHNICAL_LOG"),
-        log_format: if args.log_format != "csv" && args.log_format != "json" {
-            env::var("LOG_FORMAT").unwrap_or_else(|_| "csv".to_string())
-        } else {
-            args.log_format.clone()
-        },
-        log_dir: if args.log_dir == "log" {
-            env::var("LOG_DIR").unwrap_or_else(|_| "log".to_string())
-        } else {
-            args.log_dir.clone()
-        },
-        data_append: args.data_append || get_bool_env("CSV_APPEND"),
-        log_flat: args.log_flat || get_bool_env("LOG_FLAT"),
-        stdout_log: args.stdout_log,
-        silent: args.silent,
+    FinalScoreSnapshot {
+        total_score: totals.total_score,
+        total_weight: totals.total_weight,
+        score_ratio: totals.score_ratio,
+        coverage: totals.coverage,
+        missing,
+        missing_policy: config.missing_score_policy,
+        categories,
+        preset: config.preset.clone(),
+        stance: config.stance,
+        contributions: collect_score_contributions(config, guard),
+    }
+}
 
-        // プロンプト構成：数値設定
-        max_note_length: get_usize_from_args_or_env(args.max_note_length, "MAX_NOTE_LENGTH", 300),
-        max_shortterm_length: get_usize_from_args_or_env(
-            args.max_shortterm_length,
-            "MAX_SHORTTERM_LENGTH",
-            150,
-        ),
-        max_midterm_length: get_usize_from_args_or_env(
-            args.max_midterm_length,
-            "MAX_MIDTERM_LENGTH",
-            150,
-        ),
-        max_news_length: get_usize_from_args_or_env(args.max_news_length, "MAX_NEWS_LENGTH", 600),
-        max_review_length: get_usize_from_args_or_env(
-            args.max_review_length,
-            "MAX_REVIEW_LENGTH",
-            1000,
-        ),
+/// missing-score-policy=fail のとき、欠損スコアがあればエラーにする
+fn check_missing_scores(config: &Config, guard: &TechnicalDataGuard) -> Result<(), String> {
+    if config.missing_score_policy != MissingScorePolicy::Fail {
+        return Ok(());
+    }
+    let missing: Vec<String> = config
+        .enabled_extensions
+        .iter()
+        .filter(|ext| {
+            !extension_skipped(config, guard, ext) && extension_score(config, guard, ext).is_none()
+        })
+        .map(|ext| extension_log_name(config, ext))
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "❌ スコアを算出できなかった指標があります（--missing-score-policy=fail）: {}",
+            missing.join(", ")
+        ))
+    }
+}
 
-        // その他
-        ticker: args.ticker.clone().unwrap_or_else(|| "SPY".to_string()),
+/// 評価を省略した拡張指標か（総合スコアの分母にも欠損にも数えない）
+fn extension_skipped(config: &Config, guard: &TechnicalDataGuard, ext: &ExtensionIndicator) -> bool {
+    matches!(
+        guard.get_indicator_status(&extension_log_name(config, ext)),
+        Some(IndicatorStatus::Skipped(_))
+    )
+}
 
-        alias_csv: args
-            .alias_csv
-            .clone()
-            .or_else(|| env::var("ALIAS_CSV").ok()),
-        no_llm: args.no_llm || get_bool_env("NO_LLM"),
-        debug_prompt: args.debug_prompt || get_bool_env("DEBUG_PROMPT"),
+/// 拡張指標のログ名（CSV/JSON の indicator_name と同じ英語表記）
+fn extension_log_name(config: &Config, ext: &ExtensionIndicator) -> String {
+    match ext {
+        ExtensionIndicator::Ema => "EMA".to_string(),
+        ExtensionIndicator::Sma => "SMA".to_string(),
+        ExtensionIndicator::Roc => "ROC".to_string(),
+        ExtensionIndicator::Adx => "ADX".to_string(),
+        ExtensionIndicator::Stochastics => "Stochastics".to_string(),
+        ExtensionIndicator::Bollinger => "Bollinger".to_string(),
+        ExtensionIndicator::Fibonacci => "Fibonacci".to_string(),
+        ExtensionIndicator::Vwap => "VWAP".to_string(),
+        ExtensionIndicator::Ichimoku => "Ichimoku".to_string(),
+        ExtensionIndicator::Candlestick => "Candlestick".to_string(),
+        ExtensionIndicator::SupportResistance => "SupportResistance".to_string(),
+        ExtensionIndicator::RelativeStrength => "RelativeStrength".to_string(),
+        ExtensionIndicator::Custom(index) => format!("Custom:{}", config.custom_indicators[*index].name),
+        ExtensionIndicator::Plugin(index) => format!("Plugin:{}", config.plugins[*index].name),
     }
 }
 
-///　環境変数からbool値（"true"/"false"）を取得し、bool型に変換する
-fn get_bool_env(key: &str) -> bool {
-    env::var(key)
-        .map(|v| v.trim().to_lowercase() == "true")
-        .unwrap_or(false)
+/// 拡張指標の重み
+fn extension_weight(config: &Config, ext: &ExtensionIndicator) -> f64 {
+    match ext {
+        ExtensionIndicator::Ema => config.weight_ema,
+        ExtensionIndicator::Sma => config.weight_sma,
+        ExtensionIndicator::Roc => config.weight_roc,
+        ExtensionIndicator::Adx => config.weight_adx,
+        ExtensionIndicator::Stochastics => config.weight_stochastics,
+        ExtensionIndicator::Bollinger => config.weight_bollinger,
+        ExtensionIndicator::Fibonacci => config.weight_fibonacci,
+        ExtensionIndicator::Vwap => config.weight_vwap,
+        ExtensionIndicator::Ichimoku => config.weight_ichimoku,
+        ExtensionIndicator::Candlestick => config.weight_candlestick,
+        ExtensionIndicator::SupportResistance => config.weight_support_resistance,
+        ExtensionIndicator::RelativeStrength => config.weight_relative_strength,
+        ExtensionIndicator::Custom(index) => config.custom_indicators[*index].weight,
+        ExtensionIndicator::Plugin(index) => config.plugins[*index].weight,
+    }
 }
-/// CLI/env/defaultからf64値を取得し、0.5〜3.0範囲にサニタイズする
-fn get_f64_from_args_or_env(arg_val: f64, env_key: &str, default: f64) -> f64 {
-    // CLI引数がデフォルト値と異なる場合は優先
-    let value = if (arg_val - default).abs() > f64::EPSILON {
-        arg_val
-    } else {
-        match std::env::var(env_key) {
-            Ok(env_val) => match env_val.parse::<f64>() {
-                Ok(parsed) => parsed,
-                Err(_) => {
-                    eprintln!("⚠️ 環境変数 {} の値が無効（f64パース失敗）。デフォルト値({})を使用します。", env_key, default);
-                    default
-                }
-            },
-            Err(_) => default, // 環境変数未定義はデフォルト
-        }
-    };
 
-    // ✅ サニタイズチェック（ロジック内注釈で明記）
-    // - 型変換: .parse::<f64>() で文字列・桁落ち・超大数の誤入力をf64に変換（失敗時デフォルト）
-    // - NaN/inf: is_finite()で無効化
-    // - 負値: 0.0未満を無効化
-    // - 許容範囲: 0.5～3.0以外を無効化
-    if !value.is_finite() {
-        eprintln!(
-            "⚠️ 無効なweight値 (NaN/inf検出): {}。デフォルト値({})を使用します。",
-            value, default
-        );
-        return default;
+/// 拡張指標のスコア（算出できなかった場合・評価に失敗した場合は None）
+fn extension_score(
+    config: &Config,
+    guard: &TechnicalDataGuard,
+    ext: &ExtensionIndicator,
+) -> Option<f64> {
+    let failed = guard
+        .get_indicator_status(&extension_log_name(config, ext))
+        .is_some_and(|status| !status.is_ok());
+    if failed {
+        return None;
+    }
+    match ext {
+        ExtensionIndicator::Ema => guard.get_ema_score(),
+        ExtensionIndicator::Sma => guard.get_sma_score(),
+        ExtensionIndicator::Roc => guard.get_roc_score(),
+        ExtensionIndicator::Adx => guard.get_adx_score(),
+        ExtensionIndicator::Stochastics => guard.get_stochastics_score(),
+        ExtensionIndicator::Bollinger => guard.get_bollinger_score(),
+        ExtensionIndicator::Fibonacci => guard.get_fibonacci_score(),
+        ExtensionIndicator::Vwap => guard.get_vwap_score(),
+        ExtensionIndicator::Ichimoku => guard.get_ichimoku_score(),
+        ExtensionIndicator::Candlestick => guard.get_candlestick_score(),
+        ExtensionIndicator::SupportResistance => guard.get_support_resistance_score(),
+        ExtensionIndicator::RelativeStrength => guard.get_relative_strength_score(),
+        ExtensionIndicator::Custom(index) => guard
+            .get_custom_result(&config.custom_indicators[*index].name)
+            .map(|r| r.score),
+        ExtensionIndicator::Plugin(index) => guard.get_plugin_score(&config.plugins[*index].name),
     }
+}
 
-    if value < 0.0 {
-        eprintln!(
-            "⚠️ 無効なweight値 (負の数): {}。デフォルト値({})を使用します。",
-            value, default
-        );
-        return default;
+// 上位寄与として表示する指標数
+const TOP_CONTRIBUTORS: usize = 5;
+
+/// 指標ごとの寄与（スコア×重み）を理由コード付きで列挙（基本解析を先頭に、有効な拡張指標の順）
+fn collect_score_contributions(config: &Config, guard: &TechnicalDataGuard) -> Vec<ScoreContribution> {
+    let mut contributions = vec![ScoreContribution {
+        indicator: "Basic".to_string(),
+        score: Some(guard.get_signal_score()),
+        weight: config.weight_basic,
+        contribution: guard.get_signal_score() * config.weight_basic,
+        reason_codes: guard.get_signal_reason_codes().to_vec(),
+    }];
+    for ext in config
+        .enabled_extensions
+        .iter()
+        .filter(|ext| !extension_skipped(config, guard, ext))
+    {
+        let weight = extension_weight(config, ext);
+        let score = extension_score(config, guard, ext);
+        let reason_codes = match score {
+            Some(_) => guard.get_reason_codes(ext).to_vec(),
+            None => vec!["SCORE_MISSING".to_string()],
+        };
+        contributions.push(ScoreContribution {
+            indicator: extension_log_name(config, ext),
+            score,
+            weight,
+            contribution: score.unwrap_or(0.0) * weight,
+            reason_codes,
+        });
     }
+    contributions
+}
 
-    if !(0.5..=3.0).contains(&value) {
-        eprintln!(
-            "⚠️ 無効なweight値 (範囲外): {}。デフォルト値({})を使用します。",
-            value, default
-        );
-        return default;
+/// 直近クロスの理由コード（鮮度の高いクロス／成熟による抑制）
+fn cross_reason_codes(prefix: &str, crosses: &[CrossEvent], cap_stale: bool) -> Vec<String> {
+    let Some(cross) = crosses.last() else {
+        return Vec::new();
+    };
+    let kind = match cross.kind {
+        CrossKind::Golden => "GOLDEN_CROSS",
+        CrossKind::Dead => "DEAD_CROSS",
+    };
+    match cross_freshness(cross, cap_stale) {
+        Some(true) => vec![format!("{}_{}", prefix, kind)],
+        Some(false) => vec![format!("{}_TREND_MATURE", prefix)],
+        None => Vec::new(),
     }
+}
 
-    value
+/// 総合スコアを表示（計算結果とグラフを含む）
+fn render_final_score(config: &Config, guard: &TechnicalDataGuard) {
+    let snap = calculate_final_score_snapshot(config, guard);
+    let lines =
+        compose_final_score_lines_stance(&snap, &config.stance, /*include_gauge=*/ true);
+    for line in lines {
+        println!("{}", line);
+    }
+    print_lines_to_terminal(&compose_score_smoothing_lines(guard, &snap, &config.stance));
+    print_lines_to_terminal(&compose_score_history_lines(guard, &snap));
 }
 
-/// 百分率用サニタイズ（0.0〜100.0 を許容）
-fn sanitize_percent(value: f64, min: f64, max: f64, label: &str) -> f64 {
-    if !value.is_finite() {
-        eprintln!(
-            "⚠️ Invalid {} (NaN/inf): {} -> clamped to {}",
-            label, value, min
+/// 銘柄名・日付・価格情報を表示（セキュアアクセスによりTechnicalDataEntryを直接参照せず、TechnicalDataGuard経由で取得）
+fn display_main_info(config: &Config, guard: &TechnicalDataGuard) {
+    // ← ここでの固定見出し（MACDマイナス許容）は削除
+
+    if (config.buy_rsi - 30.0).abs() > f64::EPSILON {
+        println!(
+            "{}",
+            format!(
+                "🔧 --buy-rsi={:.2} を指定 → RSIが{:.2}以下で買い圏とみなされます",
+                config.buy_rsi, config.buy_rsi
+            )
+            .red()
         );
-        return min;
     }
-    let clamped_value = value.clamp(min, max);
-    if (clamped_value - value).abs() > f64::EPSILON {
-        eprintln!(
-            "⚠️ {} out of range: {} -> clamped to {}..={} (used {})",
-            label, value, min, max, clamped_value
+    if (config.sell_rsi - 70.0).abs() > f64::EPSILON {
+        println!(
+            "{}",
+            format!(
+                "🔧 --sell-rsi={:.2} を指定 → RSIが{:.2}以上で売り圏とみなされます",
+                config.sell_rsi, config.sell_rsi
+            )
+            .red()
         );
     }
-    clamped_value
-}
+    if (config.macd_diff_low - 0.1).abs() > f64::EPSILON {
+        println!(
+            "{}",
+            format!(
+                "🔧 --macd-diff-low={:.2} を指定 → MACD差が終値比{:.2}%未満ならスコアを中立に補正します",
+                config.macd_diff_low, config.macd_diff_low
+            )
+            .red()
+        );
+    }
+    if (config.macd_diff_mid - 0.5).abs() > f64::EPSILON {
+        println!(
+            "{}",
+            format!(
+                "🔧 --macd-diff-mid={:.2} を指定 → MACD差が終値比{:.2}%以上でスコアを強化します",
+                config.macd_diff_mid, config.macd_diff_mid
+            )
+            .red()
+        );
+    }
+    
+    if let Some(preset) = &config.preset {
+        println!("{}", format!("🎛️ 戦略プリセット: {}（個別に指定したオプションが優先されます）", preset).red());
+    }
 
-/// コマンドライン引数がデフォルト値なら環境変数から取得し、usizeで返す
-fn get_usize_from_args_or_env(arg_val: usize, env_key: &str, default: usize) -> usize {
-    if arg_val != default {
-        arg_val
+    println!("\n📊 銘柄: {}（{}）", guard.get_name(), guard.get_ticker());
+    
+    let date = guard.get_date();
+    let time = guard
+        .get_datetime()
+        .and_then(|s| s.split_whitespace().nth(1))
+        .unwrap_or("--:--");
+
+    println!("📅 日時: {} {} {}", date, time, guard.get_timezone());
+    
+    //println!("📅 日時: {} {} JST", date_jst, time_jst);
+    println!("💰 現在値　: {:.2}", guard.get_close());
+    println!("💰 前日終値: {:.2}", guard.get_previous_close());
+
+    let diff = guard.get_price_diff();
+    let percent = guard.get_price_diff_percent();
+    let diff_str = if diff > 0.0 {
+        format!("{:+.2} ({:+.2}%)", diff, percent).green()
+    } else if diff < 0.0 {
+        format!("{:+.2} ({:+.2}%)", diff, percent).red()
     } else {
-        get_usize_env(env_key, default)
-    }
-}
-/// 環境変数からusize型の値を取得し、失敗時はデフォルト値を返す
-fn get_usize_env(key: &str, default: usize) -> usize {
-    env::var(key)
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(default)
+        format!("{:+.2} ({:+.2}%)", diff, percent).normal()
+    };
+    println!("📊 前日比: {}", diff_str);
+
+    // ← ここで動的ラベルを差し込む
+    let macd_minus_label = if config.macd_minus_ok {
+        if guard.get_macd() < 0.0 && guard.get_macd() > guard.get_signal() {
+            "※『MACDマイナス許容』設定: 有効（今回“適用対象”）"
+        } else {
+            "※『MACDマイナス許容』設定: 有効（今回“未適用”）"
+        }
+    } else {
+        "※『MACDマイナス許容』設定: 無効"
+    };
+    println!("{}", macd_minus_label);
+    println!(); // 空行
 }
-/// ティッカーの正規化　"9432.T" / "9432" -> "9432.T", "NVDA" などはそのまま
-fn normalize_ticker(raw: &str) -> String {
-    let up = raw.trim().to_ascii_uppercase();
-    if up.ends_with(".T") {
-        up
-    } else if up.ends_with(".t") {
-        up.trim_end_matches(".t").to_string() + ".T"
-    } else if up.len() == 4 && up.chars().all(|c| c.is_ascii_digit()) {
-        format!("{up}.T")
+
+/// 相場局面と実効Weightの行を組み立てる（画面・LLMプロンプト共通、--regime-weighting 時のみ）
+fn compose_regime_lines(guard: &TechnicalDataGuard) -> Vec<String> {
+    let mut lines = Vec::new();
+    let Some(regime) = guard.get_market_regime() else {
+        return lines;
+    };
+    let fmt = |v: Option<f64>, suffix: &str| v.map(|x| format!("{:.2}{}", x, suffix)).unwrap_or("-".into());
+
+    lines.push(format!("🧭 【相場局面】{}", regime.kind.label()));
+    lines.push(format!(
+        "判定材料: ADX {} / ボリンジャーバンド幅 {} / SMA20の傾き {}",
+        fmt(regime.adx, ""),
+        fmt(regime.bandwidth_pct, "%"),
+        regime.slope_pct.map(|x| format!("{:+.2}%/日", x)).unwrap_or("-".into())
+    ));
+    let multipliers: Vec<String> = IndicatorCategory::ALL
+        .iter()
+        .map(|c| (c, regime.kind.weight_multiplier(*c)))
+        .filter(|(_, m)| (*m - 1.0).abs() > f64::EPSILON)
+        .map(|(c, m)| format!("{}×{}", c.label(), m))
+        .collect();
+    if multipliers.is_empty() {
+        lines.push("⚖️ 重み補正: なし".to_string());
     } else {
-        up
+        lines.push(format!("⚖️ 重み補正: {}", multipliers.join(" / ")));
+    }
+    if !regime.effective_weights.is_empty() {
+        let weights: Vec<String> = regime
+            .effective_weights
+            .iter()
+            .map(|(name, w)| format!("{} {:.2}", name, w))
+            .collect();
+        lines.push(format!("⚖️ 実効Weight: {}", weights.join(" / ")));
     }
+    lines.push(String::new());
+    lines
 }
 
-/// JP Code 四桁Tickerの加工　"9432.T" / "9432" -> Some("9432"), "NVDA" などは None
-fn jp_code_from_ticker(t: &str) -> Option<String> {
-    let up = t.trim().to_ascii_uppercase();
-    if let Some(code) = up.strip_suffix(".T") {
-        return (code.len() == 4 && code.chars().all(|c| c.is_ascii_digit()))
-            .then(|| code.to_string());
+/// スコア比率の系列を EMA で平滑化する（期間1以下ならそのまま）
+fn smooth_ratios(ratios: &[f64], bars: usize) -> Vec<f64> {
+    if bars <= 1 {
+        return ratios.to_vec();
     }
-    (up.len() == 4 && up.chars().all(|c| c.is_ascii_digit())).then_some(up)
+    let alpha = 2.0 / (bars as f64 + 1.0);
+    let mut ema: Option<f64> = None;
+    ratios
+        .iter()
+        .map(|&r| {
+            let value = ema.map_or(r, |prev| prev + alpha * (r - prev));
+            ema = Some(value);
+            value
+        })
+        .collect()
 }
-/// Yahoo Finance から市場データを取得する
-/// Yahoo v8/chart: use only meta.chartPreviousClose, meta.currency, indicators.quote[0].(o/h/l/c), timestamp. Do NOT use previousClose/regularMarket*/adjclose.
-async fn fetch_market_data(
-    ticker: &str,
-) -> Result<Vec<MarketData>, Box<dyn std::error::Error>> {
-    let ysym = if let Some(code) = jp_code_from_ticker(ticker) {
-        format!("{}.T", code)
-    } else {
-        ticker.trim().to_string()
-    };
-
-    let url = format!(
-        "https://query2.finance.yahoo.com/v8/finance/chart/{}?interval=1d&range=3mo",
-        urlencoding::encode(&ysym)
-    );
 
-    let client = Client::builder()
-        .user_agent("Mozilla/5.0 (Tickwise)")
-        .gzip(true)
-        .brotli(true)
-        .build()?;
+/// ヒステリシス付きで系列を順に判定し、最後の判定を返す。
+/// 判定が変わるのは、新しい値を直前の判定側へ margin だけ戻しても判定が変わるときのみ
+fn judge_with_hysteresis(ratios: &[f64], margin: f64, judge: impl Fn(f64) -> String) -> Option<String> {
+    let mut held: Option<f64> = None;
+    for &r in ratios {
+        held = match held {
+            Some(prev) if judge(r) != judge(prev) => {
+                let pulled = r - margin * (r - prev).signum();
+                if judge(pulled) != judge(prev) {
+                    Some(r)
+                } else {
+                    Some(prev)
+                }
+            }
+            _ => Some(r),
+        };
+    }
+    held.map(judge)
+}
 
-    let text = client
-        .get(&url)
-        .header("accept", "application/json")
-        .send()
-        .await?
-        .text()
-        .await?;
+/// 平滑化・ヒステリシス適用後の (スコア比率, 判定)。今回の比率を系列の末尾に加えて評価する
+fn smoothed_judgement(smoothing: &ScoreSmoothing, stance: &Stance, current_ratio: f64) -> (f64, String) {
+    let mut ratios = smoothing.past_ratios.clone();
+    ratios.push(current_ratio);
+    let smoothed = smooth_ratios(&ratios, smoothing.bars);
+    let last = *smoothed.last().unwrap_or(&current_ratio);
+    let judgement = judge_with_hysteresis(&smoothed, smoothing.hysteresis, |r| {
+        stance_judgement(stance, r)
+    })
+    .unwrap_or_else(|| stance_judgement(stance, last));
+    (last, judgement)
+}
 
-    let json: Value = serde_json::from_str(&text)?;
-    if json.get("chart").is_none() || !json["chart"]["error"].is_null() {
-        return Err("❌ Yahoo /v8 chart からの取得に失敗しました。".into());
+/// 今回採用する (スコア比率, 判定)。平滑化を指定していれば平滑化・ヒステリシス後、なければ生の値
+fn effective_judgement(guard: &TechnicalDataGuard, stance: &Stance, score_ratio: f64) -> (f64, String) {
+    match guard.get_score_smoothing() {
+        Some(smoothing) => smoothed_judgement(smoothing, stance, score_ratio),
+        None => (score_ratio, stance_judgement(stance, score_ratio)),
     }
+}
 
-    let result = json["chart"]["result"]
-        .as_array()
-        .ok_or("❌ chart.result 配列なし")?;
-    if result.is_empty() {
-        return Err("❌ chart.result が空です。".into());
+/// 生の判定と平滑化後の判定を並べる行（画面・LLMプロンプト共通、指定時のみ）
+fn compose_score_smoothing_lines(
+    guard: &TechnicalDataGuard,
+    snap: &FinalScoreSnapshot,
+    stance: &Stance,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    let Some(smoothing) = guard.get_score_smoothing() else {
+        return lines;
+    };
+    let (smoothed, judgement) = smoothed_judgement(smoothing, stance, snap.score_ratio);
+    let raw = stance_judgement(stance, snap.score_ratio);
+
+    let mut method = Vec::new();
+    if smoothing.bars > 1 {
+        method.push(format!("EMA{}", smoothing.bars));
+    }
+    if smoothing.hysteresis > 0.0 {
+        method.push(format!("ヒステリシス ±{:.2}", smoothing.hysteresis));
+    }
+    lines.push(format!(
+        "🪄 【判定の平滑化】直近{}本のスコア比率（{}）",
+        smoothing.past_ratios.len() + 1,
+        method.join(" ／ ")
+    ));
+    lines.push(format!("  生の判定:       {}（スコア比率 {:+.2}）", raw, snap.score_ratio));
+    lines.push(format!("  平滑化後の判定: {}（スコア比率 {:+.2}）", judgement, smoothed));
+    if judgement != raw {
+        lines.push("ℹ️ 直近の一時的な変動を抑えた判定のため、生の判定と異なります".to_string());
+    }
+    if smoothing.plugins_skipped {
+        lines.push("ℹ️ 過去の足はプラグイン指標を実行せず、プラグインを除いたスコア比率で評価しています".to_string());
     }
+    lines.push(String::new());
+    lines
+}
 
-    let r0 = &result[0];
+/// 過去ログとの比較行（前回の実行と N日前の実行。画面・LLMプロンプト共通、過去ログがある場合のみ）
+fn compose_score_history_lines(guard: &TechnicalDataGuard, snap: &FinalScoreSnapshot) -> Vec<String> {
+    let mut lines = Vec::new();
+    let Some(history) = guard.get_score_history() else {
+        return lines;
+    };
 
-    let tz_name = r0["meta"]["exchangeTimezoneName"]
-        .as_str()
-        .unwrap_or("UTC")
-        .to_string();
+    lines.push("🕘 【過去ログとの比較】".to_string());
+    match &history.previous {
+        Some(previous) => lines.extend(compose_history_comparison_lines("前回", previous, snap)),
+        None => lines.push("前回: 読み取れる過去ログがありません".to_string()),
+    }
+    match &history.days_ago {
+        Some(past) => lines.extend(compose_history_comparison_lines(
+            &format!("{}日前", history.days),
+            past,
+            snap,
+        )),
+        None => lines.push(format!("{}日前: 該当する過去ログがありません", history.days)),
+    }
+    if history.skipped_rows > 0 {
+        lines.push(format!(
+            "⚠️ 列構成が一致しない・読み取れない過去ログ {} 行は比較対象外",
+            history.skipped_rows
+        ));
+    }
+    lines.push(String::new());
+    lines
+}
 
-    // 取引所のIANAタイムゾーン名（exchangeTimezoneName）をTZDBで解決し、表示用TZを確定する
-    let tz_parsed: Result<Tz, _> = tz_name.parse();
-    let tz = match tz_parsed {
-        Ok(v) => v,
-        Err(_) => {
-            eprintln!("⚠️ exchangeTimezoneName parse failed: {} -> fallback to UTC", tz_name);
-            chrono_tz::UTC
+/// 過去の1回分と今回の比較（スコア比率・総合スコア・指標ごとのスコア変化）
+fn compose_history_comparison_lines(
+    label: &str,
+    past: &ScoreHistoryRecord,
+    snap: &FinalScoreSnapshot,
+) -> Vec<String> {
+    // 表示桁未満の差（ログの丸め誤差）は変化なしとみなす
+    let direction = |delta: f64, tolerance: f64| {
+        if delta > tolerance {
+            "改善"
+        } else if delta < -tolerance {
+            "悪化"
+        } else {
+            "変化なし"
         }
     };
-    
-    let timestamps = r0["timestamp"]
-        .as_array()
-        .ok_or("❌ timestamp がありません。")?;
-    let q0 = &r0["indicators"]["quote"][0];
-    let highs = q0["high"].as_array().ok_or("❌ high がありません。")?;
-    let lows = q0["low"].as_array().ok_or("❌ low がありません。")?;
-    let closes = q0["close"].as_array().ok_or("❌ close がありません。")?;
+    let mut lines = Vec::new();
+    // 重みや有効指標が変わると総合スコアは比べにくいため、比率があれば比率で方向を判定する
+    match past.score_ratio {
+        Some(ratio) => lines.push(format!(
+            "{}（{}）比: スコア比率 {:+.2} → {:+.2}（{}）／総合スコア {:+.1} → {:+.1}",
+            label,
+            past.date,
+            ratio,
+            snap.score_ratio,
+            direction(snap.score_ratio - ratio, 0.005),
+            past.final_score,
+            snap.total_score
+        )),
+        None => lines.push(format!(
+            "{}（{}）比: 総合スコア {:+.1} → {:+.1}（{}）",
+            label,
+            past.date,
+            past.final_score,
+            snap.total_score,
+            direction(snap.total_score - past.final_score, 0.05)
+        )),
+    }
+
+    // 指標ごとの変化（双方で算出できた指標のみ比較）
+    let current: Vec<(&str, f64)> = snap
+        .contributions
+        .iter()
+        .filter_map(|c| c.score.map(|s| (c.indicator.as_str(), s)))
+        .collect();
+    let changes: Vec<String> = current
+        .iter()
+        .filter_map(|(name, score)| {
+            let (_, before) = past.indicator_scores.iter().find(|(n, _)| n == name)?;
+            ((before - score).abs() > f64::EPSILON).then(|| format!("{} {:+}→{:+}", name, before, score))
+        })
+        .collect();
+    if changes.is_empty() {
+        lines.push("  指標スコアの変化: なし".to_string());
+    } else {
+        lines.push(format!("  指標スコアの変化: {}", changes.join(" / ")));
+    }
+    let added: Vec<&str> = current
+        .iter()
+        .map(|(name, _)| *name)
+        .filter(|name| !past.indicator_scores.iter().any(|(n, _)| n == name))
+        .collect();
+    let removed: Vec<&str> = past
+        .indicator_scores
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .filter(|name| !current.iter().any(|(n, _)| n == name))
+        .collect();
+    if !added.is_empty() || !removed.is_empty() {
+        lines.push(format!(
+            "  比較対象外: 今回のみ [{}] ／ {}のみ [{}]",
+            added.join(", "),
+            label,
+            removed.join(", ")
+        ));
+    }
+    lines
+}
 
-    let n = timestamps
-        .len()
-        .min(highs.len())
-        .min(lows.len())
-        .min(closes.len());
+/// リスク統計セクションの行を組み立てる（画面・LLMプロンプト共通）
+fn compose_risk_lines(guard: &TechnicalDataGuard) -> Vec<String> {
+    let mut lines = Vec::new();
+    let Some(risk) = guard.get_risk_stats() else {
+        return lines;
+    };
+    let fmt_ratio = |v: Option<f64>| v.map(|x| format!("{:.2}", x)).unwrap_or("-".into());
 
-    let mut out: Vec<MarketData> = Vec::with_capacity(n);
+    lines.push(format!("📉 【リスク統計（日次リターン{}本）】", risk.samples));
+    lines.push(format!(
+        "📈 ヒストリカル・ボラティリティ（年率）: {:.2}%",
+        risk.volatility_annual_pct
+    ));
+    lines.push(format!(
+        "📉 最大ドローダウン: {:.2}%（{} → {}）",
+        risk.max_drawdown_pct, risk.max_drawdown_peak, risk.max_drawdown_trough
+    ));
+    lines.push(format!(
+        "📍 現在のドローダウン: {:.2}%（期間内高値 {} 比）",
+        risk.current_drawdown_pct, risk.peak_date
+    ));
+    lines.push(format!(
+        "⚖️ シャープレシオ: {} / ソルティノレシオ: {}（直近{}本・年率換算・無リスク金利0）",
+        fmt_ratio(risk.sharpe),
+        fmt_ratio(risk.sortino),
+        risk.rolling_window
+    ));
+    lines.push(format!(
+        "⚠️ 最悪の日次変動: {:+.2}%（{}）",
+        risk.worst_day_pct, risk.worst_day_date
+    ));
+    lines.push(String::new());
+    lines
+}
 
-    for i in 0..n {
-        let ts = match timestamps[i].as_i64() {
-            Some(v) => v,
-            None => continue,
-        };
-        
-        let (h, l, c) = (highs[i].as_f64(), lows[i].as_f64(), closes[i].as_f64());
-        if let (Some(h), Some(l), Some(c)) = (h, l, c) {
-            // 取引所のIANAタイムゾーン名（exchangeTimezoneName）を取得し、表示に使うTZを確定する
-            let dt = tz
-                .timestamp_opt(ts, 0)
-                .single()
-                .ok_or("❌ timestamp 変換失敗")?;
+/// 基本テクニカル分析（MACD + RSI）を行い、AnalysisResultに格納する
+fn render_basic(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
+    let rsi = guard.get_rsi();
+    let macd = guard.get_macd();
+    let signal = guard.get_signal();
+    let prev_macd = guard.get_prev_macd();
+    let prev_signal = guard.get_prev_signal();
+    let weight = config.weight_basic;
 
-            let date = dt.format("%Y-%m-%d").to_string();
-            let datetime = dt.format("%Y-%m-%d %H:%M").to_string();
+    let score = guard.get_signal_score();
+    let adjusted_score = score * weight;
 
-            out.push(MarketData {
-                date,
-                datetime: Some(datetime),
-                timestamp: Some(ts),
-                timezone: Some(tz_name.clone()),
-                high: h,
-                low: l,
-                close: c,
-                name: None,
-            });
-        }
-    }
+    let mut description_lines: Vec<String> = Vec::new();
 
-    // metaの最新値（価格/時刻）で、最後の足を上書きして「リクエスト時点の最新値」を出せるようにする
-    let rm_time = r0["meta"]["regularMarketTime"].as_i64();
+    // ★ ここから追加：MACDとRSIの数値を明示的に表示
+    description_lines.push("基本テクニカル分析（MACDとRSIによる評価）".to_string());
+    description_lines.push(format!("📈 MACD: {:.4} / Signal: {:.4}", macd, signal));
+    description_lines.push(format!("📊 RSI: {:.2}", rsi));
+    // ★ ここまで追加
 
-    // regularMarketPrice は number の場合と {raw: ...} の場合があるので両対応
-    let rm_price = r0["meta"]["regularMarketPrice"]
-        .as_f64()
-        .or_else(|| r0["meta"]["regularMarketPrice"]["raw"].as_f64());
+    // ① トレンド継続 or 転換
+    if prev_macd < prev_signal && macd > signal {
+        description_lines.push("⚠️ MACDがゴールデンクロス → 上昇トレンド転換の可能性".to_string());
+    } else if macd > signal {
+        description_lines.push(
+            "⚠️ MACDがSignalを上回る状態が継続 → 上昇トレンドが維持されている可能性".to_string(),
+        );
+    } else if prev_macd > prev_signal && macd < signal {
+        description_lines.push("⚠️ MACDがデッドクロス → 下落トレンド転換の可能性".to_string());
+    } else if macd < signal {
+        description_lines
+            .push("⚠️ MACDがSignalを下回る状態が継続 → 弱含みトレンドが継続中".to_string());
+    }
+    description_lines.extend(compose_cross_lines("MACD", guard.get_macd_crosses()));
 
-    if let (Some(rm_time), Some(rm_price)) = (rm_time, rm_price) {
-        if let Some(last) = out.last_mut() {
-            let dt = tz
-                .timestamp_opt(rm_time, 0)
-                .single()
-                .ok_or("❌ regularMarketTime 変換失敗")?;
+    // ② 乖離の大きさ（終値比%で判定）
+    let macd_diff = macd - signal;
+    let macd_diff_pct = pct_of_price(macd_diff, guard.get_close());
+    if macd_diff_pct >= MACD_DIFF_WIDE_PCT {
+        description_lines.push(format!(
+            "⚠️ MACDがSignalより大幅に上回っています（+{:.2} / 終値比 {:+.2}%）→ 過熱感がある可能性があります",
+            macd_diff, macd_diff_pct
+        ));
+    } else if macd_diff_pct <= -MACD_DIFF_WIDE_PCT {
+        description_lines.push(format!(
+            "⚠️ MACDがSignalより大幅に下回っています（{:.2} / 終値比 {:+.2}%）→ 割安感がある可能性があります",
+            macd_diff, macd_diff_pct
+        ));
+    }
 
-            last.date = dt.format("%Y-%m-%d").to_string();
-            last.datetime = Some(dt.format("%Y-%m-%d %H:%M").to_string());
-            last.timestamp = Some(rm_time);
-            last.timezone = Some(tz_name.clone());
+    // ③ RSIの極端な値
+    if rsi <= 5.0 {
+        description_lines.push("⚠️ RSIが 0% に近い極端な売られすぎ → 反発に警戒".to_string());
+    } else if rsi >= 95.0 {
+        description_lines.push("⚠️ RSIが 100% に近い極端な買われすぎ → 反転下落に注意".to_string());
+    }
 
-            // close を “最新価格” に差し替える
-            last.close = rm_price;
+    // ④ スコア評価の要約
+    match score {
+        2.0 => {
+            if rsi < 30.0 {
+                description_lines.push(
+                    "🟢 [基本スコア:+2] RSIが極端に割安 → 強い買いシグナル → スコア+2加点"
+                        .to_string(),
+                );
+            } else {
+                description_lines
+                    .push("🟢 [基本スコア:+2] MACDが強い上昇トレンド → スコア+2加点".to_string());
+            }
+        }
+        1.0 => {
+            if rsi < 40.0 {
+                description_lines.push(
+                    "🟢 [基本スコア:+1] RSIが割安圏 → 買いシグナル → スコア+1加点".to_string(),
+                );
+            } else {
+                description_lines
+                    .push("🟢 [基本スコア:+1] MACDが上昇傾向 → スコア+1加点".to_string());
+            }
+        }
+        0.0 => {
+            description_lines
+                .push("⚪️ [基本スコア:0] RSI・MACDともに中立 → スコアなし".to_string());
+        }
+        -1.0 => {
+            if rsi > 60.0 {
+                description_lines.push(
+                    "🔴 [基本スコア:-1] RSIが割高圏 → 売りシグナル → スコア-1減点".to_string(),
+                );
+            } else {
+                description_lines
+                    .push("🔴 [基本スコア:-1] MACDが下降傾向 → スコア-1減点".to_string());
+            }
+        }
+        -2.0 => {
+            if rsi > 70.0 {
+                description_lines.push(
+                    "🔴 [基本スコア:-2] RSIが極端に割高 → 強い売りシグナル → スコア-2減点"
+                        .to_string(),
+                );
+            } else {
+                description_lines
+                    .push("🔴 [基本スコア:-2] MACDが強い下降トレンド → スコア-2減点".to_string());
+            }
+        }
+        _ => {
+            description_lines.push("💡 [基本スコア:?] 不明または不正なスコア条件".to_string());
         }
     }
 
-    if out.len() < 2 {
-        return Err("❌ 時系列データが2件未満のため、テクニカル指標を構築できません。".into());
-    }
+    // ⑤ スコア調整情報
+    description_lines.push(format!(
+        "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
+        adjusted_score, score, weight
+    ));
 
-    Ok(out)
+    // 結果構造体にまとめる
+    AnalysisResult {
+        indicator_name: "基本テクニカル分析".to_string(),
+        description: description_lines,
+        score: Some(score),
+    }
 }
-
-/// エイリアスCSVの読み込み
-fn load_alias_csv(path: &str) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
-    let lines = sanitize_ascii_file_lines(Path::new(path))?;
-    let content = lines.join("\n");
-    let mut rdr = ReaderBuilder::new()
-        .has_headers(true)
-        .from_reader(Cursor::new(content)); // ← 文字列からCSVとして読み込む
-
-    let mut map = HashMap::new();
-    for result in rdr.records() {
-        let record = result?;
-        let code = record
-            .get(1)
-            .ok_or("❌ CSVの2列目（証券コード）が存在しません")?
-            .trim();
-        let name = record
-            .get(2)
-            .ok_or("❌ CSVの3列目（企業名）が存在しません")?
-            .trim();
-        if !code.is_empty() && !name.is_empty() {
-            //let ticker = format!("{}.T", code);
-            map.insert(code.to_string(), name.to_string());
-        }
+/// AnalysisResultを受け取り、内容を画面に出力する関数
+fn display_analysis_result(result: &AnalysisResult) {
+    for line in &result.description {
+        println!("{}", line);
     }
-
-    Ok(map)
+    println!(); // 改行
 }
 
-// テクニカル指標（RSI/MACD）を計算し、前日比やスコアを組み立てて Guard を返す
-fn build_basic_technical_entry(
-    config: &Config,
-    data: &[MarketData],
-    ticker_name_map: &HashMap<String, String>,
-    //fetched_company_name: Option<String>,
-) -> Result<TechnicalDataGuard, Box<dyn std::error::Error>> {
-    // データが2件未満では前日比の評価ができないため中断
-    if data.len() < 2 {
-        return Err("❌ 時系列データが2件未満のため、テクニカル指標を構築できません。".into());
+/// スコア分類
+fn classify_score(score_ratio: f64) -> &'static str {
+    match score_ratio {
+        r if r >= 0.8 => "🟢 強い買い",
+        r if r >= 0.6 => "🟢 買い優勢",
+        r if r >= 0.4 => "🟢 買い傾向あり",
+        r if r >= 0.2 => "🟡 やや買い寄り",
+        r if r >= 0.0 => "🟡 買い気配",
+        r if r >= -0.2 => "⚪️ 様子見（中立）",
+        r if r >= -0.4 => "🟠 売り気配",
+        r if r >= -0.6 => "🟠 やや売り寄り",
+        r if r >= -0.8 => "🔴 売り傾向あり",
+        _ => "🔴 強い売り",
     }
+}
 
-    let hardcoded = resolve_hardcoded_info(&config.ticker); // ← SoT（正規化後の大文字）
-
-    let latest = &data[data.len() - 1];
-    let previous = &data[data.len() - 2];
-    
-    let alias_name_opt =
-        jp_code_from_ticker(&config.ticker).and_then(|code| ticker_name_map.get(&code).cloned());
-
-    // 優先順位：エイリアス >  LLM取得名 > ティッカー文字列
-    let name = alias_name_opt // 4桁JP alias（あるなら最優先）
-        .or(latest.name.clone()) // APIから来た会社名
-        //.or(fetched_company_name) // 追加取得の会社名
-        .or_else(|| hardcoded.map(|h| h.formal_name.to_string())) // ★ハードコード名
-        .unwrap_or_else(|| config.ticker.clone());
-
-    // 株価差と前日比（%）を算出
-    let diff = latest.close - previous.close;
-    let diff_percent = if previous.close != 0.0 {
-        diff / previous.close * 100.0
+/// Buyer/Seller の判定（色＋語彙）。percent はスタンス側の割合（Buyer＝買い％、Seller＝売り％）
+fn stance_action(stance: &Stance, percent: u8) -> (&'static str, &'static str) {
+    let buyer = matches!(stance, Stance::Buyer);
+    // 61–89 を「買う/売る」、40–60 を厳密に中立
+    if percent >= 90 {
+        ("🟢", if buyer { "積極的に買う" } else { "積極的に売る" })
+    } else if percent >= 61 {
+        ("🟡", if buyer { "買う" } else { "売る" })
+    } else if (40..=60).contains(&percent) {
+        ("⚪️", "中立")
+    } else if percent >= 20 {
+        ("🟠", if buyer { "買いを推奨しない" } else { "売りを推奨しない" })
     } else {
-        0.0
-    };
-
-    // 終値をベクトル化（MACD, RSI計算用）
-    let closes: Vec<f64> = data.iter().map(|d| d.close).collect();
-
-    // RSI（14期間）で割高・割安を評価
-    let mut rsi_calc =
-        RelativeStrengthIndex::new(14).map_err(|e| format!("❌ RSI初期化失敗: {e}"))?;
-    let rsi = closes
-        .iter()
-        .cloned()
-        .map(|close_value| rsi_calc.next(close_value))
-        .last()
-        .ok_or("❌ RSIの計算に失敗しました")?;
+        ("🔴", if buyer { "買わない" } else { "売らない" })
+    }
+}
 
-    // ===== MACDは“1回だけ”計算して、前日値と当日値を同時取得（無駄排除）=====
-    let mut macd_calc = MovingAverageConvergenceDivergence::new(12, 26, 9)
-        .map_err(|e| format!("❌ MACD初期化失敗: {e}"))?;
+/// スコア比率からスタンス別の判定（色＋語彙）を作る（Buyer/Seller の％は総合ゲージと同じ換算）
+fn stance_judgement(stance: &Stance, ratio: f64) -> String {
+    let buyer_percent = (((1.0 + ratio) / 2.0).clamp(0.0, 1.0) * 100.0).round() as u8;
+    let percent = match stance {
+        Stance::Holder => return classify_score(ratio).to_string(),
+        Stance::Buyer => buyer_percent,
+        Stance::Seller => 100u8.saturating_sub(buyer_percent),
+    };
+    let (mark, text) = stance_action(stance, percent);
+    format!("{} {}", mark, text)
+}
 
-    let mut prev_macd: f64 = 0.0;
-    let mut prev_signal: f64 = 0.0;
-    let mut macd: f64 = 0.0;
-    let mut signal: f64 = 0.0;
+/// 総合スコア行（スタンス別）。ゲージ有無は引数で切替
+/// 端末/LLM共通：総合スコアの表示行を生成（スタンス別、ゲージ有無切替）
+/// ★ 0除算ガードはこの関数内だけで完結
+fn compose_final_score_lines_stance(
+    snap: &FinalScoreSnapshot,
+    stance: &Stance,
+    include_gauge: bool,
+) -> Vec<String> {
+    let mut lines = Vec::new();
 
-    for (index, close_value) in closes.iter().cloned().enumerate() {
-        let out = macd_calc.next(close_value);
-        if index == closes.len() - 2 {
-            prev_macd = out.macd;
-            prev_signal = out.signal;
-        }
-        if index == closes.len() - 1 {
-            macd = out.macd;
-            signal = out.signal;
+    // 1) 総合スコア見出し（例: -12.0/-22.0-22.0）
+    lines.push(format!(
+        "🧮 総合スコア: {s:.1} ({w:.1}〜-{w:.1})の範囲",
+        s = snap.total_score,
+        w = if snap.total_weight.is_finite() {
+            snap.total_weight.abs()
+        } else {
+            0.0
         }
+    ));
+    lines.push(format!(
+        "トータルスコア（スタンス：{}）",
+        stance_caption(stance)
+    ));
+    if snap.missing.is_empty() {
+        lines.push(format!("🧭 算出カバレッジ: {:.0}%（全指標のスコアを算出）", snap.coverage * 100.0));
+    } else {
+        lines.push(format!(
+            "🧭 算出カバレッジ: {:.0}%（⚠️ 欠損: {}／扱い: {}）",
+            snap.coverage * 100.0,
+            snap.missing.join(", "),
+            snap.missing_policy
+        ));
     }
 
-    // ここから判定（macd-minus-ok を“買い方向”に反映）
-    let macd_diff = (macd - signal).abs();
-    let macd_up = macd > signal && (macd > 0.0 || config.macd_minus_ok);
-    let macd_down = macd < signal;
-    let rsi_low = rsi <= config.buy_rsi;
-    let rsi_high = rsi >= config.sell_rsi;
-
-    // シグナルスコア（既存ロジックを踏襲）
-    let mut signal_score: f64 = match (macd_up, macd_down, rsi_high, rsi_low) {
-        // RSIが過熱でMACDが上昇 → 売り（異常なMACD乖離）
-        (true, _, true, _) if macd_diff > 100.0 => -2.0,
-
-        // RSIが過熱でMACDが上昇 → 売り
-        (true, _, true, _) => -1.0,
-
-        // RSIが過熱かつMACDが下降 → 売り
-        (_, true, true, _) => -1.0,
-
-        // RSIが割安かつMACDが上昇 → 買い（強いシグナル）
-        (true, _, _, true) => 2.0,
-
-        // RSIが割安でもMACDが下降 → 買い（やや反発期待）
-        (_, true, _, true) => 1.0,
-
-        // RSI中立かつMACD上昇 → MACD乖離小 → 様子見（+1）
-        (true, _, false, false) if macd_diff < config.macd_diff_low => 1.0,
-
-        // RSI中立かつMACD上昇 → MACD乖離中 → 買い（+2）
-        (true, _, false, false) if macd_diff < config.macd_diff_mid => 2.0,
-
-        // RSI中立かつMACD上昇 → MACD乖離大 → 買い（+2）
-        (true, _, false, false) => 2.0,
-
-        // RSI中立かつMACD下降 → MACD乖離小 → 様子見（0）
-        (_, true, false, false) if macd_diff < config.macd_diff_low => 0.0,
-
-        // RSI中立かつMACD下降 → 弱含み（-1）
-        (_, true, false, false) => -1.0,
-
-        // それ以外は中立
-        _ => 0.0,
+    // ★ ここでのみ 0除算を防ぐ（UI層に限定）
+    let weight_abs = if snap.total_weight.is_finite() {
+        snap.total_weight.abs()
+    } else {
+        0.0
     };
+    let has_weight = weight_abs > f64::EPSILON;
 
-    // ★ゲート：--macd-minus-ok が無効で MACD<0 のとき、買い方向のプラススコアを0にクランプ
-    if !config.macd_minus_ok && macd < 0.0 && signal_score > 0.0 {
-        signal_score = 0.0;
-    }
-
-    // セキュアアクセス構造（TechnicalDataGuard）で構造体へ代入
-    let mut guard = TechnicalDataGuard::new(config.ticker.clone(), latest.date.clone());
-
-    guard.set_name(&name);
-    // ガードにタイムゾーンと日時情報をセット
-    if let Some(tz) = latest.timezone.as_deref() {
-        guard.set_timezone(tz);
-    }
-    if let Some(dt) = latest.datetime.as_deref() {
-        guard.set_datetime(dt);
-    }
-    if let Some(ts) = latest.timestamp {
-        guard.set_timestamp(ts);
-    }
+    match stance {
+        // Buyer / Seller：単極ゲージ（右→左に塗る）。％は 0..=100、丸め＝四捨五入で統一。
+        Stance::Buyer | Stance::Seller => {
+            // 基準は Buyer％。Seller％は 100 - Buyer％ で相補（表示・判定とも統一）
+            let buyer_percent: u8 = if !has_weight {
+                50
+            } else {
+                let p = ((weight_abs + snap.total_score).clamp(0.0, 2.0 * weight_abs)
+                    / (2.0 * weight_abs))
+                    * 100.0;
+                p.round().clamp(0.0, 100.0) as u8
+            };
+            let seller_percent: u8 = 100u8.saturating_sub(buyer_percent);
 
-    guard.set_close(latest.close);
-    guard.set_previous_close(previous.close);
-    guard.set_price_diff(diff);
-    guard.set_price_diff_percent(diff_percent);
-    guard.set_rsi(rsi);
-    guard.set_macd(macd);
-    guard.set_prev_macd(prev_macd);
-    guard.set_prev_signal(prev_signal);
-    guard.set_signal(signal);
-    guard.set_signal_score(signal_score);
+            // 5段階のラベル＋色（🟢🟡⚪️🟠🔴）をスタンス別に割当
+            let percent = if matches!(stance, Stance::Seller) {
+                seller_percent
+            } else {
+                buyer_percent
+            };
+            let (mark, action_text) = stance_action(stance, percent);
+            // 判定行（％はスタンスごとの percent を表示）
+            lines.push(format!(
+                "→ 判定: {mark} {action} {pct}%",
+                action = action_text,
+                pct = percent
+            ));
 
-    Ok(guard)
-}
+            if include_gauge {
+                // グラフの横に「100％/0％」を付与（仕様固定文言）
+                let gauge = match stance {
+                    Stance::Buyer => {
+                        render_unipolar_gauge_rtl(percent, "買い 100％", "0％ 買わない", 25)
+                    }
+                    Stance::Seller => {
+                        render_unipolar_gauge_rtl(percent, "売り 100％", "0％ 売らない", 25)
+                    }
+                    _ => String::new(),
+                };
 
-fn evaluate_all_selected_extensions(
-    config: &Config,
-    data: &[MarketData],
-    guard: &mut TechnicalDataGuard,
-) -> Result<(), Box<dyn std::error::Error>> {
-    for indicator in &config.enabled_extensions {
-        match indicator {
-            ExtensionIndicator::Ema => {
-                evaluate_and_store_ema(data, guard)?;
-            }
-            ExtensionIndicator::Sma => {
-                evaluate_and_store_sma(data, guard)?;
-            }
-            ExtensionIndicator::Bollinger => {
-                evaluate_and_store_bollinger(data, guard)?;
-            }
-            ExtensionIndicator::Roc => {
-                evaluate_and_store_roc(data, guard)?;
-            }
-            ExtensionIndicator::Adx => {
-                evaluate_and_store_adx(data, guard)?;
-            }
-            ExtensionIndicator::Stochastics => {
-                evaluate_and_store_stochastics(data, guard)?;
-            }
-            ExtensionIndicator::Fibonacci => {
-                evaluate_and_store_fibonacci(data, guard)?;
-            }
-            ExtensionIndicator::Vwap => {
-                evaluate_and_store_vwap(data, guard)?;
+                if !gauge.is_empty() {
+                    // 判定の“色ボール”とゲージ色を一致させる（'█' のみ着色）
+                    // ※ 既存の colored::* を使用（新規関数なし／副作用なし）
+                    let colored_g = match mark {
+                        "🟢" => gauge.replace("█", &"█".green().to_string()),
+                        "🟡" => gauge.replace("█", &"█".yellow().to_string()),
+                        "⚪️" => gauge.replace("█", &"█".white().to_string()),
+                        "🟠" => gauge.replace("█", &"█".truecolor(255, 165, 0).to_string()),
+                        "🔴" => gauge.replace("█", &"█".red().to_string()),
+                        _ => gauge,
+                    };
+                    lines.push(colored_g);
+                }
             }
-            ExtensionIndicator::Ichimoku => {
-                evaluate_and_store_ichimoku(data, guard)?;
-            } //   _ => {}
         }
-    }
-
-    Ok(())
-}
 
-/// EMA（指数平滑移動平均）を計算し、ガード構造体にセキュアに格納する
-fn evaluate_and_store_ema(
-    data: &[MarketData],
-    guard: &mut TechnicalDataGuard,
-) -> Result<(), Box<dyn std::error::Error>> {
-    use ta::indicators::ExponentialMovingAverage;
+        // Holder：両極ゲージ。符号付き％は score_ratio*100（重みゼロ時は0固定）
+        // Holder：判定の色（🟢🟡⚪️🟠🔴）とバー色を完全一致させる。
+        // 0除算時は score_ratio=0% 固定（中央'|'のみ、無塗り）。
+        Stance::Holder => {
+            let holder_pct: i32 = if has_weight {
+                (snap.score_ratio * 100.0).round() as i32
+            } else {
+                0
+            };
+            // 判定テキスト（色付き絵文字＋語彙）は仕様の分類関数を使用
+            let action_text = classify_score(snap.score_ratio);
+            // 表記は「→ 判定: <action>  スコア比率 ±X%」のまま（スペース2つを保持）
+            lines.push(format!(
+                "→ 判定: {action} スコア比率 {:+}%",
+                holder_pct,
+                action = action_text
+            ));
 
-    if data.len() < 20 {
-        return Err("❌ EMA計算には最低20日分のデータが必要です".into());
+            if include_gauge {
+                let g = render_bipolar_gauge_lr(snap.score_ratio, 51);
+                lines.push(g);
+            }
+        }
     }
 
-    let closes: Vec<f64> = data.iter().map(|d| d.close).collect();
+    lines.extend(compose_category_breakdown_lines(snap, include_gauge));
+    lines.extend(compose_top_contributor_lines(snap));
 
-    let mut ema_short =
-        ExponentialMovingAverage::new(5).map_err(|e| format!("❌ EMA短期 初期化失敗: {e}"))?;
-    let mut ema_long =
-        ExponentialMovingAverage::new(20).map_err(|e| format!("❌ EMA長期 初期化失敗: {e}"))?;
+    lines.push(String::new());
+    lines
+}
 
-    let mut ema_short_val = 0.0;
-    let mut ema_long_val = 0.0;
+// カテゴリ間の対立とみなすスコア比率（一方が +40% 以上、他方が -40% 以下）
+const CATEGORY_CONFLICT_RATIO: f64 = 0.4;
 
-    for close in closes.iter().cloned() {
-        ema_short_val = ema_short.next(close);
-        ema_long_val = ema_long.next(close);
+/// カテゴリ別の内訳行（比率と小型ゲージ）。方向が対立するカテゴリがあれば併記する
+fn compose_category_breakdown_lines(snap: &FinalScoreSnapshot, include_gauge: bool) -> Vec<String> {
+    let mut lines = Vec::new();
+    if snap.categories.len() < 2 {
+        return lines;
     }
 
-    guard.set_ema_short(ema_short_val);
-    guard.set_ema_long(ema_long_val);
-
-    let diff = ema_short_val - ema_long_val;
-
-    // 📏 5段階スコア：±2（強）±1（通常）0（同値圏 ±0.3）
-    let ema_score: f64 = match diff {
-        d if d > 2.0 => 2.0,        // 短期が大幅に上 → 強い上昇トレンド
-        d if d > 0.5 => 1.0,        // やや上昇トレンド
-        d if d.abs() <= 0.5 => 0.0, // 同値圏 → トレンドなし
-        d if d < -2.0 => -2.0,      // 短期が大幅に下 → 強い下降トレンド
-        _ => -1.0,                  // やや下降トレンド
-    };
-    guard.set_ema_score(ema_score);
+    lines.push("📂 カテゴリ別内訳（スコア比率）".to_string());
+    for cat in &snap.categories {
+        // 全角を含むため表示幅で揃える（全角=2）
+        let width: usize = cat.label.chars().map(|c| if c.is_ascii() { 1 } else { 2 }).sum();
+        let pad = " ".repeat(16usize.saturating_sub(width));
+        let line = match cat.ratio {
+            Some(ratio) if include_gauge => {
+                // 小型ゲージ（左=買い、右=売り。総合ゲージと同じ向き）
+                let half = 10;
+                let blocks = ((ratio.abs() * half as f64).round() as usize).min(half);
+                let (buy, sell) = if ratio > 0.0 { (blocks, 0) } else { (0, blocks) };
+                format!(
+                    "  {}{} {:>+5.0}% [{}{}|{}{}]  ({:.1}/{:.1})",
+                    cat.label,
+                    pad,
+                    ratio * 100.0,
+                    ".".repeat(half - buy),
+                    "█".repeat(buy),
+                    "█".repeat(sell),
+                    ".".repeat(half - sell),
+                    cat.score,
+                    cat.weight
+                )
+            }
+            Some(ratio) => format!(
+                "  {}{} {:>+5.0}%  ({:.1}/{:.1})",
+                cat.label,
+                pad,
+                ratio * 100.0,
+                cat.score,
+                cat.weight
+            ),
+            None => format!("  {}{}    --  （全指標が欠損）", cat.label, pad),
+        };
+        lines.push(line);
+    }
 
-    Ok(())
+    let bullish: Vec<String> = snap
+        .categories
+        .iter()
+        .filter(|c| c.ratio.is_some_and(|r| r >= CATEGORY_CONFLICT_RATIO))
+        .map(|c| format!("{}({:+.0}%)", c.label, c.ratio.unwrap_or_default() * 100.0))
+        .collect();
+    let bearish: Vec<String> = snap
+        .categories
+        .iter()
+        .filter(|c| c.ratio.is_some_and(|r| r <= -CATEGORY_CONFLICT_RATIO))
+        .map(|c| format!("{}({:+.0}%)", c.label, c.ratio.unwrap_or_default() * 100.0))
+        .collect();
+    if !bullish.is_empty() && !bearish.is_empty() {
+        lines.push(format!(
+            "⚠️ カテゴリ間で方向が対立: 買い方向 {} ↔ 売り方向 {}",
+            bullish.join(", "),
+            bearish.join(", ")
+        ));
+    }
+    lines
 }
 
-/// SMA（単純移動平均）を評価して構造体に格納（セキュアアクセス）
-fn evaluate_and_store_sma(
-    data: &[MarketData],
-    guard: &mut TechnicalDataGuard,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // 5日/20日SMA（※マジックナンバーは今後config化も可）
-    use ta::indicators::SimpleMovingAverage;
-    use ta::Next;
-
-    let closes: Vec<f64> = data.iter().map(|d| d.close).collect();
+/// 総合スコアへの寄与が大きい指標（|スコア×重み| の上位）と理由コード
+fn compose_top_contributor_lines(snap: &FinalScoreSnapshot) -> Vec<String> {
+    let mut ranked: Vec<&ScoreContribution> = snap
+        .contributions
+        .iter()
+        .filter(|c| c.contribution.abs() > f64::EPSILON)
+        .collect();
+    if ranked.is_empty() {
+        return Vec::new();
+    }
+    ranked.sort_by(|a, b| b.contribution.abs().total_cmp(&a.contribution.abs()));
 
-    let mut sma_short = SimpleMovingAverage::new(5)?; // 短期SMA（5日）
-    let mut sma_long = SimpleMovingAverage::new(20)?; // 長期SMA（20日）
+    let mut lines = vec![format!("🏷️ 寄与の大きい指標（上位{}）", TOP_CONTRIBUTORS.min(ranked.len()))];
+    for c in ranked.into_iter().take(TOP_CONTRIBUTORS) {
+        lines.push(format!(
+            "  {:<20} {:>+6.1}（{:+.1}×{:.1}）  {}",
+            c.indicator,
+            c.contribution,
+            c.score.unwrap_or(0.0),
+            c.weight,
+            c.reason_codes.join(", ")
+        ));
+    }
+    lines
+}
 
-    let short = closes
-        .iter()
-        .cloned()
-        .map(|c| sma_short.next(c))
-        .last()
-        .unwrap_or(0.0);
-    let long = closes
-        .iter()
-        .cloned()
-        .map(|c| sma_long.next(c))
-        .last()
-        .unwrap_or(0.0);
+/// EMA の表示（文言ロジックだけ修正：同値圏なら DC/GC を出さない）
+fn render_ema(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
+    let weight = config.weight_ema;
+    let mut description_lines: Vec<String> = Vec::new();
 
-    guard.set_sma_short(short);
-    guard.set_sma_long(long);
+    description_lines.push("📊 【EMA（指数平滑移動平均）】".to_string());
 
-    // スコアロジック
-    let diff = short - long;
+    let short = guard.get_ema_short();
+    let long = guard.get_ema_long();
+    description_lines.push(format!("短期EMA: {:.2} / 長期EMA: {:.2}", short, long));
 
-    let sma_score: f64 = match diff {
-        d if d > 2.0 => 2.0,                   // 強いゴールデンクロス
-        d if d > 0.5 => 1.0,                   // 緩やかな上昇
-        d if (-0.5..=0.5).contains(&d) => 0.0, // 同値圏（絶対値0.5以下）
-        d if d < -2.0 => -2.0,                 // 強いデッドクロス
-        _ => -1.0,                             // 緩やかな下降
+    // 文言はスコアと同じ乖離の帯（score_gap_pct）で決める
+    let gap_pct = pct_of_price(short - long, long);
+    let status = match score_gap_pct(gap_pct).0 {
+        s if s > 0.0 => "🟢 ゴールデンクロス進行中（短期EMAが長期EMAを上回る）".to_string(),
+        s if s < 0.0 => "📉 デッドクロス進行中（短期EMAが長期EMAを下回る）".to_string(),
+        _ => format!("➡️ EMAが同値圏（乖離±{}%以内）→ スコア変動なし", GAP_MILD_PCT),
     };
+    description_lines.push(status);
+    description_lines.push(format!("📏 乖離率（長期EMA比）: {:+.2}%", gap_pct));
+    description_lines.extend(compose_cross_lines("EMA", guard.get_ema_crosses()));
 
-    guard.set_sma_score(sma_score);
-    Ok(())
-}
-
-/// ADXを評価して構造体に格納（セキュアアクセス　ADX　taクレートで用意されていない為演算）
-fn evaluate_and_store_adx(
-    data: &[MarketData],
-    guard: &mut TechnicalDataGuard,
-) -> Result<(), Box<dyn std::error::Error>> {
-    //ADX計算に必要なデータ量（15）
-    if data.len() < 15 {
-        return Err("❌ ADXの計算には最低14期間以上のデータが必要です".into());
-    }
+    // ★ スコアはSoT原則で Guard に格納済みの値のみを参照（再計算しない）
+    let base_score = guard.get_ema_score().unwrap_or(0.0).round() as i32;
+    let adjusted = base_score as f64 * weight;
 
-    let mut trs = Vec::new();
-    let mut plus_dm = Vec::new();
-    let mut minus_dm = Vec::new();
+    description_lines.push(format!(
+        "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
+        adjusted, base_score, weight
+    ));
 
-    for i in 1..data.len() {
-        let today = &data[i];
-        let yesterday = &data[i - 1];
+    AnalysisResult {
+        indicator_name: "EMA".to_string(),
+        description: description_lines,
+        score: Some(base_score as f64),
+    }
+}
 
-        let high_diff = today.high - yesterday.high;
-        let low_diff = yesterday.low - today.low;
+/// SMA（単純移動平均）の表示（セキュアアクセス：TechnicalDataGuard経由）
+fn render_sma(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
+    let short = guard.get_sma_short();
+    let long = guard.get_sma_long();
+    let weight = config.weight_sma;
 
-        let tr = (today.high - today.low)
-            .max((today.high - yesterday.close).abs())
-            .max((today.low - yesterday.close).abs());
-        trs.push(tr);
+    let mut description_lines: Vec<String> = Vec::new();
+    description_lines.push("📊 【SMA（単純移動平均）】".to_string());
+    description_lines.push(format!("短期SMA: {:.2} / 長期SMA: {:.2}", short, long));
 
-        plus_dm.push(if high_diff > low_diff && high_diff > 0.0 {
-            high_diff
-        } else {
-            0.0
-        });
-        minus_dm.push(if low_diff > high_diff && low_diff > 0.0 {
-            low_diff
-        } else {
-            0.0
-        });
+    // クロス判定出力（スコアと同じ乖離の帯で判定）
+    let gap_pct = pct_of_price(short - long, long);
+    match score_gap_pct(gap_pct).0 {
+        s if s > 0.0 => {
+            description_lines.push("📈 ゴールデンクロス発生中（短期SMAが長期SMAを上回る）".to_string())
+        }
+        s if s < 0.0 => {
+            description_lines.push("📉 デッドクロス発生中（短期SMAが長期SMAを下回る）".to_string())
+        }
+        _ => description_lines.push(format!("➖ SMAが同値圏（乖離±{}%以内）：クロス傾向なし", GAP_MILD_PCT)),
     }
+    description_lines.push(format!("📏 乖離率（長期SMA比）: {:+.2}%", gap_pct));
+    description_lines.extend(compose_cross_lines("SMA", guard.get_sma_crosses()));
 
-    let period = 14;
-
-    let atr = trs.iter().rev().take(period).sum::<f64>() / period as f64;
-    let p_dm = plus_dm.iter().rev().take(period).sum::<f64>();
-    let m_dm = minus_dm.iter().rev().take(period).sum::<f64>();
+    // スコア取得と調整出力
+    match guard.get_sma_score().map(|v| v as i32) {
+        Some(base_score) => {
+            let adjusted_score = base_score as f64 * weight;
+            description_lines.push(rank_sma_score(Some(base_score)).to_string());
+            description_lines.push(format!(
+                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
+                adjusted_score, base_score, weight
+            ));
 
-    let p_di = 100.0 * (p_dm / atr);
-    let m_di = 100.0 * (m_dm / atr);
-    let dx = 100.0 * ((p_di - m_di).abs() / (p_di + m_di));
+            AnalysisResult {
+                indicator_name: "SMA".to_string(),
+                description: description_lines,
+                score: Some(base_score as f64),
+            }
+        }
+        None => {
+            description_lines.push("⚠️ SMAスコア情報なし".to_string());
+            AnalysisResult {
+                indicator_name: "SMA".to_string(),
+                description: description_lines,
+                score: None,
+            }
+        }
+    }
+}
 
-    // ADX = 直近のDX（単純版）
-    let adx = dx;
+fn rank_sma_score(sma_score: Option<i32>) -> &'static str {
+    match sma_score {
+        Some(2) => "🟢 短期SMAが長期より大幅に上 → 強い上昇トレンド → スコア+2加点",
+        Some(1) => "🟢 短期SMAが長期よりやや上 → 上昇トレンド → スコア+1加点",
+        Some(0) => "➡️ SMAが同値圏 → スコア変動なし",
+        Some(-1) => "🔴 短期SMAが長期よりやや下 → 下降トレンド → スコア-1減点",
+        Some(-2) => "🔴 短期SMAが長期より大幅に下 → 強い下降トレンド → スコア-2減点",
+        _ => "⚠️ SMAスコア情報なし",
+    }
+}
+/// ADXの表示（セキュアアクセス: TechnicalDataGuard経由）
+/// ADXスコアのランク評価（スコア → ラベル文字列）
+fn rank_adx_score(adx_score: Option<i32>) -> &'static str {
+    match adx_score {
+        Some(2) => "🟢 ADXが非常に強い（40以上）→ 強いトレンド継続 → スコア+2加点",
+        Some(1) => "🟢 ADXがやや強い（25以上40未満）→ トレンド発生 → スコア+1加点",
+        Some(0) => "➡️ ADXが中立（20以上25未満）→ 様子見",
+        Some(-1) => "🔴 ADXがやや弱い（15以上20未満）→ トレンド弱まる → スコア-1減点",
+        Some(-2) => "🔴 ADXが非常に弱い（15未満）→ トレンド消失 → スコア-2減点",
+        _ => "⚠️ ADXスコア不明",
+    }
+}
+/// ADX（平均方向性指数）の表示（セキュアアクセス：TechnicalDataGuard経由）
+fn render_adx(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
+    let mut description_lines: Vec<String> = Vec::new();
+    description_lines.push("📊 【ADX（平均方向性指数）】".to_string());
 
-    // スコア評価（20がトレンドの分岐点とされる）
-    let adx_score: f64 = match adx {
-        a if a >= 50.0 => 2.0,
-        a if a >= 30.0 => 1.0,
-        a if a >= 20.0 => 0.0,
-        a if a >= 10.0 => -1.0,
-        _ => -2.0,
-    };
+    match guard.get_adx() {
+        Some(adx) => {
+            description_lines.push(format!("現在のADX: {:.2}", adx));
 
-    // ✅ セキュアに構造体へ格納
-    guard.set_adx(adx);
-    guard.set_adx_score(adx_score);
+            // 極端な値の警告
+            if adx >= 50.0 {
+                description_lines.push(format!(
+                    "⚠️ ADXが50以上（{:.2}）→ 非常に強いトレンド → 反転リスクに警戒",
+                    adx
+                ));
+            } else if adx <= 10.0 {
+                description_lines.push(format!(
+                    "⚠️ ADXが10以下（{:.2}）→ トレンド不在（レンジ相場） → 仕掛け注意",
+                    adx
+                ));
+            }
 
-    Ok(())
-}
-/// ROC（変化率）を計算して構造体に格納（スコア含む）
-fn evaluate_and_store_roc(
-    data: &[MarketData],
-    guard: &mut TechnicalDataGuard,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if data.len() < 11 {
-        return Err("❌ ROC計算には11日分以上のデータが必要です".into());
+            match guard.get_adx_score().map(|v| v as i32) {
+                Some(base_score) => {
+                    let adjusted_score = base_score as f64 * config.weight_adx;
+                    description_lines.push(rank_adx_score(Some(base_score)).to_string());
+                    description_lines.push(format!(
+                        "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
+                        adjusted_score, base_score, config.weight_adx
+                    ));
+                    AnalysisResult {
+                        indicator_name: "ADX".to_string(),
+                        description: description_lines,
+                        score: Some(base_score as f64),
+                    }
+                }
+                None => {
+                    description_lines.push("⚠️ ADXスコア情報なし".to_string());
+                    AnalysisResult {
+                        indicator_name: "ADX".to_string(),
+                        description: description_lines,
+                        score: None,
+                    }
+                }
+            }
+        }
+        None => {
+            description_lines.push("⚠️ ADXデータなし".to_string());
+            AnalysisResult {
+                indicator_name: "ADX".to_string(),
+                description: description_lines,
+                score: None,
+            }
+        }
     }
+}
 
-    let latest_close = data.last().unwrap().close;
-    let previous_close = data[data.len() - 11].close;
+/// ROC（変化率）の表示（セキュアアクセス：TechnicalDataGuard経由）
+fn rank_roc_score(roc_score: Option<i32>) -> &'static str {
+    match roc_score {
+        Some(2) => "🟢 ROCが大幅上昇 → 強い上昇トレンド → スコア+2加点",
+        Some(1) => "🟢 ROCが上昇傾向 → スコア+1加点",
+        Some(0) => "➡️ ROCが安定圏（±3%）→ スコア変動なし",
+        Some(-1) => "🔴 ROCがやや下降 → スコア-1減点",
+        Some(-2) => "🔴 ROCが大幅下降 → 強い下降トレンド → スコア-2減点",
+        _ => "⚠️ ROCスコア情報なし",
+    }
+}
 
-    let roc = ((latest_close - previous_close) / previous_close) * 100.0;
+fn render_roc(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
+    let mut description_lines: Vec<String> = Vec::new();
+    description_lines.push("📊  【ROC（変化率）】".to_string());
 
-    // スコア判定
-    let roc_score: f64 = match roc {
-        r if r > 10.0 => 2.0,   // 非常に強い上昇
-        r if r > 3.0 => 1.0,    // 緩やかな上昇
-        r if r >= -3.0 => 0.0,  // 同値圏（±3%以内）
-        r if r < -10.0 => -2.0, // 非常に強い下落
-        _ => -1.0,              // 緩やかな下落
-    };
+    match guard.get_roc() {
+        Some(roc) => {
+            description_lines.push(format!("10日間のROC: {:.2}%", roc));
 
-    guard.set_roc(roc);
-    guard.set_roc_score(roc_score);
+            // 極端なROCに対する警告
+            if roc >= 15.0 {
+                description_lines.push(format!(
+                    "⚠️ ROCが+15%以上（{:.2}%）→ 短期的な過熱上昇、反落に警戒",
+                    roc
+                ));
+            } else if roc <= -15.0 {
+                description_lines.push(format!(
+                    "⚠️ ROCが-15%以下（{:.2}%）→ パニック売りの可能性、反発に備えた注視を",
+                    roc
+                ));
+            }
 
-    Ok(())
+            match guard.get_roc_score().map(|v| v as i32) {
+                Some(base_score) => {
+                    let adjusted_score = base_score as f64 * config.weight_roc;
+                    description_lines.push(rank_roc_score(Some(base_score)).to_string());
+                    description_lines.push(format!(
+                        "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
+                        adjusted_score, base_score, config.weight_roc
+                    ));
+                    AnalysisResult {
+                        indicator_name: "ROC".to_string(),
+                        description: description_lines,
+                        score: Some(base_score as f64),
+                    }
+                }
+                None => {
+                    description_lines.push("⚠️ ROCスコア情報なし".to_string());
+                    AnalysisResult {
+                        indicator_name: "ROC".to_string(),
+                        description: description_lines,
+                        score: None,
+                    }
+                }
+            }
+        }
+        None => {
+            description_lines.push("⚠️ ROCデータが不足しています".to_string());
+            AnalysisResult {
+                indicator_name: "ROC".to_string(),
+                description: description_lines,
+                score: None,
+            }
+        }
+    }
 }
-fn evaluate_and_store_stochastics(
-    data: &[MarketData],
-    guard: &mut TechnicalDataGuard,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if data.len() < 14 {
-        return Err("❌ ストキャスティクス計算には14日以上のデータが必要です".into());
+
+/// ストキャスティクスの表示（セキュアアクセス：TechnicalDataGuard経由）
+/// ストキャスティクススコアの判定文字列
+fn rank_stochastics_score(stochastics_score: Option<i32>) -> &'static str {
+    match stochastics_score {
+        Some(2) => "🟢 %Kが10%以下 → 強い売られすぎと判断 → 買いシグナル → スコア+2加点",
+        Some(1) => "🟢 %Kが20%以下 → 売られすぎと判断 → 買いシグナル → スコア+1加点",
+        Some(0) => "➡️ %Kが中立圏（20〜80%） → シグナルなし → スコア変動なし",
+        Some(-1) => "🔴 %Kが80%以上 → 買われすぎと判断 → 売りシグナル → スコア-1減点",
+        Some(-2) => "🔴 %Kが90%以上 → 強い買われすぎと判断 → 売りシグナル → スコア-2減点",
+        _ => "⚠️ ストキャスティクススコア不明",
     }
+}
+fn render_stochastics(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
+    let mut description_lines: Vec<String> = Vec::new();
+    description_lines.push("📊 【ストキャスティクス】".to_string());
 
-    let period = 14; // ストキャスティクス %K の計算に使用する期間
-    let mut highest_highs = Vec::new();
-    let mut lowest_lows = Vec::new();
-    let mut closes = Vec::new();
+    let k_opt = guard.get_stochastics_k();
+    let d_opt = guard.get_stochastics_d();
 
-    for i in 0..data.len() {
-        closes.push(data[i].close);
-        let start = (i + 1).saturating_sub(period);
-        let high = data[start..=i]
-            .iter()
-            .map(|d| d.high)
-            .fold(f64::MIN, f64::max);
-        let low = data[start..=i]
-            .iter()
-            .map(|d| d.low)
-            .fold(f64::MAX, f64::min);
-        highest_highs.push(high);
-        lowest_lows.push(low);
+    match (k_opt, d_opt) {
+        (Some(k), Some(d)) => {
+            description_lines.push(format!("現在の%K: {:.2}% / 現在の%D: {:.2}%", k, d));
+            if k == 0.0 && d == 0.0 {
+                description_lines.push("⚠️ %Kおよび%Dが0.00%に張り付き → 極端な売られすぎ水準 → リバウンドの可能性あり（注目シグナル）".to_string());
+            }
+        }
+        (Some(_), None) => {
+            description_lines.push("⚠️ %Dデータが不足しています".to_string());
+        }
+        (None, _) => {
+            description_lines.push("⚠️ %Kデータが不足しています".to_string());
+        }
     }
+    description_lines.extend(compose_cross_lines("%K/%D", guard.get_stoch_crosses()));
 
-    let last = data.len() - 1;
-    let high = highest_highs[last];
-    let low = lowest_lows[last];
-    let close = closes[last];
+    match guard.get_stochastics_score().map(|v| v as i32) {
+        Some(base_score) => {
+            let adjusted_score = base_score as f64 * config.weight_stochastics;
+            description_lines.push(rank_stochastics_score(Some(base_score)).to_string());
+            description_lines.push(format!(
+                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
+                adjusted_score, base_score, config.weight_stochastics
+            ));
+            AnalysisResult {
+                indicator_name: "ストキャスティクス".to_string(),
+                description: description_lines,
+                score: Some(base_score as f64),
+            }
+        }
+        None => {
+            description_lines.push("⚠️ ストキャスティクススコア情報なし".to_string());
+            AnalysisResult {
+                indicator_name: "ストキャスティクス".to_string(),
+                description: description_lines,
+                score: None,
+            }
+        }
+    }
+}
 
-    let percent_k = if high != low {
-        ((close - low) / (high - low)) * 100.0
-    } else {
-        0.0
-    };
+/// ボリンジャーバンドの表示（セキュアアクセス：TechnicalDataGuard経由）
+fn render_bollinger(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
+    let upper: f64 = guard.get_bb_upper();
+    let lower: f64 = guard.get_bb_lower();
+    let percent_b: f64 = guard.get_bb_percent_b();
+    let bandwidth_pct: f64 = guard.get_bb_bandwidth();
+    let weight: f64 = config.weight_bollinger;
 
-    let mut percent_ds = Vec::new();
-    for i in (last + 1 - 3)..=last {
-        let high = highest_highs[i];
-        let low = lowest_lows[i];
-        let close = closes[i];
-        let k = if high != low {
-            ((close - low) / (high - low)) * 100.0
-        } else {
-            0.0
-        };
-        percent_ds.push(k);
+    let mut description_lines: Vec<String> = Vec::new();
+    description_lines.push("📊 【ボリンジャーバンド】".to_string());
+    description_lines.push(format!("上限 {:.2} / 下限 {:.2}", upper, lower));
+
+    if (upper - lower).abs() < f64::EPSILON {
+        description_lines
+            .push("⚠️ バンド幅が0に近いため、%b/帯幅の解釈に注意（計算不安定）".to_string());
     }
 
-    let percent_d = percent_ds.iter().copied().sum::<f64>() / percent_ds.len() as f64;
+    description_lines.push(format!(
+        "%b indicator: {:.2} / 帯幅(Bandwidth): {:.1}%",
+        percent_b, bandwidth_pct
+    ));
+    // ✅ 帯幅のスクイーズ判定
+    let bw = guard.get_bb_bandwidth(); // ← これを使う
+    let th = config.bb_bandwidth_squeeze_pct; // 設定のしきい値(%)
 
-    // セキュアに構造体へ格納
-    guard.set_stochastics_k(percent_k);
-    guard.set_stochastics_d(percent_d);
+    if bw <= th {
+        description_lines.push(format!(
+            "⚠️ スクイーズ進行中（帯幅が設定閾値 {:.1}% 以下）",
+            th
+        ));
+    } else {
+        description_lines.push(format!(
+            "ℹ️ 帯幅は設定閾値 {:.1}% を上回り、スクイーズ未発生",
+            th
+        ));
+    }
 
-    // スコア計算
-    let stoch_score: f64 = match percent_k {
-        k if k >= 90.0 => -2.0,
-        k if k >= 80.0 => -1.0,
-        k if k <= 10.0 => 2.0,
-        k if k <= 20.0 => 1.0,
-        _ => 0.0,
+    // ✅ 位置情報は“%b”だけで1行に統一（重複/矛盾を排除）
+    let state_line = if percent_b > 1.0 {
+        format!(
+            "⚠️ 上限ブレイク（%b {:.2}）→ 伸び一巡後の反動に注意",
+            percent_b
+        )
+    } else if percent_b < 0.0 {
+        format!(
+            "⚠️ 下限ブレイク（%b {:.2}）→ リバウンド/続落の分岐に注意",
+            percent_b
+        )
+    } else {
+        "➡️ 現在価格がバンド内 → 中立".to_string()
     };
+    description_lines.push(state_line);
 
-    guard.set_stochastics_score(stoch_score);
+    // ✅ スコアは数値出力のみ（説明は重複させない）
+    //    ただし“バンド内”かつ ±評価が付いた時だけ、短いヒントを追加
+    match guard.get_bollinger_score().map(|v| v as i32) {
+        Some(base) => {
+            let adjusted = base as f64 * weight;
 
-    Ok(())
-}
+            if (0.0..=1.0).contains(&percent_b) && base != 0 {
+                let hint = if base > 0 {
+                    "🟢 バンド内で下側優位（反発寄り）"
+                } else {
+                    "🔴 バンド内で上側優位（過熱寄り）"
+                };
+                description_lines.push(format!(
+                    "{} → スコア{:+}{}",
+                    hint,
+                    base,
+                    if base > 0 { "加点" } else { "減点" }
+                ));
+            }
 
-/// ボリンジャーバンドの計算と格納（%B と Bandwidth も計算）    
-fn evaluate_and_store_bollinger(
-    data: &[MarketData],
-    guard: &mut TechnicalDataGuard,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let closes: Vec<f64> = data.iter().map(|d| d.close).collect();
+            description_lines.push(format!(
+                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
+                adjusted, base, weight
+            ));
 
-    // 一般的な設定
-    let period: usize = 20;
-    if closes.len() < period {
-        return Err("❌ BollingerBands: データ数が20未満です。".into());
+            AnalysisResult {
+                indicator_name: "ボリンジャーバンド".to_string(),
+                description: description_lines,
+                score: Some(base as f64),
+            }
+        }
+        None => {
+            description_lines.push("⚠️ ボリンジャーバンドスコア情報なし".to_string());
+            AnalysisResult {
+                indicator_name: "ボリンジャーバンド".to_string(),
+                description: description_lines,
+                score: None,
+            }
+        }
     }
-    let stddev_multiplier: f64 = 2.0;
+}
 
-    // インジケータ（既存のものを流用）
-    let mut bb = BollingerBands::new(period, stddev_multiplier)?;
+/// フィボナッチリトレースメントの表示（セキュアアクセス：TechnicalDataGuard経由）
+fn render_fibonacci(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
+    let weight = config.weight_fibonacci;
+    let mut description_lines: Vec<String> = Vec::new();
+    description_lines.push("📊 【フィボナッチリトレースメント】".to_string());
+    description_lines.push("💡 直近の有意なスイングに対する押し目/戻り目と到達目標".to_string());
 
-    // 最新バンド値
-    let mut upper: f64 = 0.0;
-    let mut lower: f64 = 0.0;
+    if let (Some(swing), Some(level_38_2), Some(level_50), Some(level_61_8)) = (
+        guard.get_fib_swing(),
+        guard.get_fibo_38_2(),
+        guard.get_fibo_50_0(),
+        guard.get_fibo_61_8(),
+    ) {
+        if swing.uptrend {
+            description_lines.push(format!(
+                "📈 上昇スイング: 安値 {:.2}（{}）→ 高値 {:.2}（{}）",
+                swing.low, swing.low_date, swing.high, swing.high_date
+            ));
+        } else {
+            description_lines.push(format!(
+                "📉 下降スイング: 高値 {:.2}（{}）→ 安値 {:.2}（{}）",
+                swing.high, swing.high_date, swing.low, swing.low_date
+            ));
+        }
+        description_lines.push(format!(
+            "{}: 23.6%: {} / 38.2%: {:.2} / 50.0%: {:.2} / 61.8%: {:.2} / 78.6%: {}",
+            if swing.uptrend { "押し目" } else { "戻り目" },
+            opt_f64(guard.get_fibo_23_6()),
+            level_38_2,
+            level_50,
+            level_61_8,
+            opt_f64(guard.get_fibo_78_6())
+        ));
+        description_lines.push(format!(
+            "🎯 エクステンション（{}）: 127.2%: {} / 161.8%: {}",
+            if swing.uptrend { "上値目標" } else { "下値目標" },
+            opt_f64(guard.get_fibo_ext_127_2()),
+            opt_f64(guard.get_fibo_ext_161_8())
+        ));
+        if let Some(depth) = guard.get_fib_depth() {
+            description_lines.push(format!(
+                "現在値の位置: スイングの{:.1}%まで{}",
+                depth * 100.0,
+                if swing.uptrend { "押し" } else { "戻し" }
+            ));
+        }
 
-    for &price in &closes {
-        let bands = bb.next(price);
-        upper = bands.upper;
-        lower = bands.lower;
+        // base_score は Guard に保存済み（-2,-1,0,1,2）
+        let base_score = guard.get_fibonacci_score().map(|v| v.round() as i32);
+        let band_line = match (swing.uptrend, base_score) {
+            (true, Some(2)) => "🟢 高値更新（エクステンション圏）→ 上昇継続 → スコア+2".to_string(),
+            (true, Some(1)) => "🟢 61.8%以内の押し → 上昇トレンド内の調整 → スコア+1".to_string(),
+            (true, Some(0)) => "➡️ 61.8〜78.6%の深押し → 判断保留（0）".to_string(),
+            (true, Some(-1)) => "🔴 78.6%超の押し → 上昇スイング否定の兆し → スコア-1".to_string(),
+            (true, Some(-2)) => "🔴 スイング安値割れ → 上昇スイング崩れ → スコア-2".to_string(),
+            (false, Some(-2)) => "🔴 安値更新（エクステンション圏）→ 下落継続 → スコア-2".to_string(),
+            (false, Some(-1)) => "🔴 61.8%以内の戻り → 下降トレンド内の戻り → スコア-1".to_string(),
+            (false, Some(0)) => "➡️ 61.8〜78.6%の深い戻り → 判断保留（0）".to_string(),
+            (false, Some(1)) => "🟢 78.6%超の戻り → 下降スイング否定の兆し → スコア+1".to_string(),
+            (false, Some(2)) => "🟢 スイング高値超え → 下降スイング崩れ → スコア+2".to_string(),
+            (_, Some(other)) => format!("⚠️ 想定外スコア({}) → 中立扱い（0）", other), // フォールバック
+            (_, None) => "⚠️ フィボナッチスコア情報なし".to_string(),
+        };
+        description_lines.push(band_line);
+    } else {
+        description_lines.push("⚠️ フィボナッチデータが不足しています".to_string());
     }
 
-    let current_price: f64 = *closes.last().unwrap_or(&0.0);
+    match guard.get_fibonacci_score().map(|v| v as i32) {
+        Some(base_score) => {
+            let adjusted_score = base_score as f64 * weight;
 
-    // 保存（上限・下限）
-    guard.set_bb_upper(upper);
-    guard.set_bb_lower(lower);
+            description_lines.push(format!(
+                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
+                adjusted_score, base_score, weight
+            ));
+            AnalysisResult {
+                indicator_name: "フィボナッチ".to_string(),
+                description: description_lines,
+                score: Some(base_score as f64),
+            }
+        }
+        None => {
+            description_lines.push("⚠️ フィボナッチスコア情報なし".to_string());
+            AnalysisResult {
+                indicator_name: "フィボナッチ".to_string(),
+                description: description_lines,
+                score: None,
+            }
+        }
+    }
+}
 
-    // 中心線（SMA）= (upper + lower)/2（2σ対称なのでこれでOK）
-    let mid: f64 = (upper + lower) * 0.5;
+/// VWAP（日足簡易版）の表示（セキュアアクセス：TechnicalDataGuard経由）
+fn rank_vwap_score(vwap_score: Option<i32>) -> &'static str {
+    match vwap_score {
+        Some(2) => "🟢 VWAPが現在価格より大幅に下 → 強い買いシグナル → スコア+2加点",
+        Some(1) => "🟢 VWAPが現在価格よりやや下 → 買いシグナル → スコア+1加点",
+        Some(0) => "➡️ VWAPと同水準（乖離±0.5%以内）→ スコア変動なし",
+        Some(-1) => "🔴 VWAPが現在価格よりやや上 → 売りシグナル → スコア-1減点",
+        Some(-2) => "🔴 VWAPが現在価格より大幅に上 → 強い売りシグナル → スコア-2減点",
+        _ => "⚠️ VWAPスコア不明",
+    }
+}
 
-    // %B = (Close - Lower) / (Upper - Lower)
-    let denom = upper - lower;
-    let percent_b: f64 = if denom != 0.0 {
-        (current_price - lower) / denom
-    } else {
-        0.0
-    };
+/// VWAP（日足簡易版）の表示（セキュアアクセス：TechnicalDataGuard経由）
+fn render_vwap(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
+    let weight = config.weight_vwap;
+    let mut description_lines: Vec<String> = Vec::new();
+    description_lines.push("📊 【VWAP（日足簡易版）】".to_string());
+    description_lines.push("⚠️ VWAPは高値・安値・終値をもとに算出した簡易版です。正式な出来高加重平均価格とは異なる場合があります。".to_string());
 
-    // Bandwidth(%) = (Upper - Lower) / Mid * 100
-    let bandwidth_pct: f64 = if mid != 0.0 {
-        (upper - lower) / mid * 100.0
+    if let Some(vwap_value) = guard.get_vwap() {
+        description_lines.push(format!("VWAP値: {:.2}", vwap_value));
+        description_lines.push(format!(
+            "📏 乖離率（VWAP比）: {:+.2}%",
+            pct_of_price(guard.get_close() - vwap_value, vwap_value)
+        ));
     } else {
-        0.0
-    };
-
-    // 保存（%B と Bandwidth）
-    guard.set_bb_percent_b(percent_b);
-    guard.set_bb_bandwidth(bandwidth_pct);
-
-    // 既存の5段階スコアはそのまま踏襲
-    let bollinger_score: f64 = match current_price {
-        p if p > upper * 1.02 => -2.0, // 上限より2%以上上 → 強い売り
-        p if p > upper => -1.0,        // 上限突破 → やや売り
-        p if p < lower * 0.98 => 2.0,  // 下限より2%以上下 → 強い買い
-        p if p < lower => 1.0,         // 下限割れ → やや買い
-        _ => 0.0,                      // バンド内 → 中立
-    };
-    guard.set_bollinger_score(bollinger_score);
-
-    Ok(())
-}
-
-/// フィボナッチを算出し、仕様に沿ってスコア化（SoT：ここで一度だけ決める）
-fn evaluate_and_store_fibonacci(
-    data: &[MarketData],
-    guard: &mut TechnicalDataGuard,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if data.len() < 2 {
-        guard.set_fibonacci_score(0.0);
-        return Err("❌ フィボナッチ計算には最低2件のデータが必要です".into());
+        description_lines.push("⚠️ VWAPデータが不足しています".to_string());
     }
 
-    let highs: Vec<f64> = data.iter().map(|d| d.high).collect();
-    let lows: Vec<f64> = data.iter().map(|d| d.low).collect();
-    let high = highs.iter().cloned().fold(f64::MIN, f64::max);
-    let low = lows.iter().cloned().fold(f64::MAX, f64::min);
-    let span = high - low;
-    if span <= 0.0 {
-        // 変動がないと閾値を作れない→中立
-        guard.set_fibonacci_score(0.0);
-        return Ok(());
+    match guard.get_vwap_score().map(|v| v as i32) {
+        Some(base_score) => {
+            let adjusted_score = base_score as f64 * weight;
+            description_lines.push(rank_vwap_score(Some(base_score)).to_string());
+            description_lines.push(format!(
+                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
+                adjusted_score, base_score, weight
+            ));
+            AnalysisResult {
+                indicator_name: "VWAP".to_string(),
+                description: description_lines,
+                score: Some(base_score as f64),
+            }
+        }
+        None => {
+            description_lines.push("⚠️ VWAPスコア情報なし".to_string());
+            AnalysisResult {
+                indicator_name: "VWAP".to_string(),
+                description: description_lines,
+                score: None,
+            }
+        }
     }
+}
 
-    let f38 = high - span * 0.382;
-    let f50 = high - span * 0.500;
-    let f62 = high - span * 0.618;
-
-    guard.set_fibo_38_2(f38);
-    guard.set_fibo_50_0(f50);
-    guard.set_fibo_61_8(f62);
+/// 一目均衡表の転換線・基準線・スコアを表示（セキュアアクセス：TechnicalDataGuard経由）
+fn rank_ichimoku_score(ichimoku_score: Option<i32>) -> &'static str {
+    match ichimoku_score {
+        Some(2) => "🟢 転換線が基準線より大幅に上 → 強い買い圧力 → スコア+2加点",
+        Some(1) => "🟢 転換線が基準線よりやや上 → 買い優勢 → スコア+1加点",
+        Some(0) => "➡️ 転換線と基準線が同値圏 → トレンドなし → スコア変動なし",
+        Some(-1) => "🔴 転換線が基準線よりやや下 → 売り優勢 → スコア-1減点",
+        Some(-2) => "🔴 転換線が基準線より大幅に下 → 強い売り圧力 → スコア-2減点",
+        _ => "⚠️ 一目均衡表スコア不明",
+    }
+}
+/// 一目均衡表の転換線・基準線・スコアを表示（セキュアアクセス：TechnicalDataGuard経由）
+fn render_ichimoku(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
+    let weight = config.weight_ichimoku;
+    let mut description_lines: Vec<String> = Vec::new();
+    description_lines.push("📊 【一目均衡表】".to_string());
 
-    // ── スコア判定（唯一の真実：ここで一度だけ） ──
-    // ポジ/ネガの帯をガチッと固定。0 は「50% 近傍のみ」。
-    let close = guard.get_fib_close();
-    let eps = 0.50; // 50%近傍の許容幅
-
-    let score = if (close - f50).abs() <= eps {
-        0.0 // 50%±eps → 0
-    } else if close > f38 {
-        2.0 // 38.2%より上 → +2
-    } else if close > f50 + eps && close < f38 {
-        1.0 // 50%超〜38.2%未満 → +1
-    } else if close < f62 {
-        -2.0 // 61.8%より下 → -2
-    } else if close < f50 - eps && close > f62 {
-        -1.0 // 61.8%超〜50%未満 → -1
-    } else {
-        // ここに来るのは 50%±eps の外側ギリ/端バッファなど
-        // 境界丸めのブレを避けるため 0 に落とさない
-        if close > f50 {
-            1.0
+    if let (Some(tenkan), Some(kijun)) = (guard.get_tenkan_sen(), guard.get_kijun_sen()) {
+        description_lines.push(format!("転換線: {:.2} / 基準線: {:.2}", tenkan, kijun));
+        if tenkan > kijun {
+            description_lines
+                .push("📈 ゴールデンクロス発生中（転換線が基準線を上回る）".to_string());
+        } else if tenkan < kijun {
+            description_lines.push("📉 デッドクロス発生中（転換線が基準線を下回る）".to_string());
         } else {
-            -1.0
+            description_lines.push("➡️ 転換線と基準線が交差中（横ばい）".to_string());
         }
-    };
-
-    guard.set_fibonacci_score(score);
-    Ok(())
-}
-
-/// VWAP（日足簡易版）を計算し、構造体にセキュアに格納
-fn evaluate_and_store_vwap(
-    data: &[MarketData],
-    guard: &mut TechnicalDataGuard,
-) -> Result<(), Box<dyn std::error::Error>> {
-    use ta::indicators::SimpleMovingAverage;
-    use ta::Next;
-
-    // マジックナンバー回避のため注釈（一般的に14日で使用される）
-    let period = 14;
-
-    if data.len() < period {
-        return Err(format!(
-            "❌ VWAP計算に必要なデータが不足しています（{}日必要）",
-            period
-        )
-        .into());
+        description_lines.extend(compose_cross_lines(
+            "転換線/基準線",
+            guard.get_ichimoku_crosses(),
+        ));
+        if kijun != 0.0 {
+            let gap_ratio = ((tenkan - kijun) / kijun).abs() * 100.0;
+            if gap_ratio < 1.0 {
+                description_lines.push(format!(
+                    "💡 クロス直後の接近状態（乖離 {:.2}%）→ トレンド確定には弱い傾向",
+                    gap_ratio
+                ));
+            } else if gap_ratio > 5.0 {
+                description_lines.push(format!(
+                    "💡 クロス乖離が大きい（乖離 {:.2}%）→ 強いトレンドの可能性",
+                    gap_ratio
+                ));
+            }
+        }
+    } else {
+        description_lines.push("⚠️ 一目均衡表データが不足しています".to_string());
     }
 
-    // Typical Price = (High + Low + Close) / 3
-    let typical_prices: Vec<f64> = data
-        .iter()
-        .map(|d| (d.high + d.low + d.close) / 3.0)
-        .collect();
-
-    let mut sma = SimpleMovingAverage::new(period)?;
-    let vwap = typical_prices
-        .iter()
-        .cloned()
-        .map(|p| sma.next(p))
-        .last()
-        .unwrap_or(0.0);
-
-    let close = guard.get_close(); // セキュアアクセスで終値取得
-    let diff = close - vwap;
-    let vwap_score: f64 = match diff {
-        d if d >= 4.0 => 2.0,
-        d if d >= 1.0 => 1.0,
-        d if d <= -4.0 => -2.0,
-        d if d <= -1.0 => -1.0,
-        _ => 0.0,
-    };
-
-    guard.set_vwap(vwap); // VWAP値を格納
-    guard.set_vwap_score(vwap_score); // スコアも格納
-
-    Ok(())
-}
-/// 一目均衡表の転換線・基準線を計算し、構造体に格納する関数。
-/// 現在の終値を基に転換線 > 基準線 ならスコア+1、逆は-1、同値は0とする。
-fn evaluate_and_store_ichimoku(
-    data: &[MarketData],
-    guard: &mut TechnicalDataGuard,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // 一目均衡表に必要な期間の長さ（転換線: 9期間、基準線: 26期間）
-    if data.len() < 26 {
-        return Err("❌ 一目均衡表の評価には最低26期間のデータが必要です".into());
+    match guard.get_ichimoku_score().map(|v: f64| v as i32) {
+        Some(base_score) => {
+            let adjusted_score = base_score as f64 * weight;
+            description_lines.push(rank_ichimoku_score(Some(base_score)).to_string());
+            description_lines.push(format!(
+                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
+                adjusted_score, base_score, weight
+            ));
+            AnalysisResult {
+                indicator_name: "一目均衡表".to_string(),
+                description: description_lines,
+                score: Some(base_score as f64),
+            }
+        }
+        None => {
+            description_lines.push("⚠️ 一目均衡表スコア情報なし".to_string());
+            AnalysisResult {
+                indicator_name: "一目均衡表".to_string(),
+                description: description_lines,
+                score: None,
+            }
+        }
     }
+}
 
-    // 転換線（過去9日間の高値と安値の平均）
-    let recent_9 = &data[data.len() - 9..];
-    let high_9 = recent_9.iter().map(|d| d.high).fold(f64::MIN, f64::max);
-    let low_9 = recent_9.iter().map(|d| d.low).fold(f64::MAX, f64::min);
-    let tenkan = (high_9 + low_9) / 2.0;
+/// ローソク足パターンの表示（セキュアアクセス：TechnicalDataGuard経由）
+fn render_candlestick(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
+    let weight = config.weight_candlestick;
+    let mut description_lines: Vec<String> = Vec::new();
+    description_lines.push("📊 【ローソク足パターン】".to_string());
 
-    // 基準線（過去26日間の高値と安値の平均）
-    let recent_26 = &data[data.len() - 26..];
-    let high_26 = recent_26.iter().map(|d| d.high).fold(f64::MIN, f64::max);
-    let low_26 = recent_26.iter().map(|d| d.low).fold(f64::MAX, f64::min);
-    let kijun = (high_26 + low_26) / 2.0;
+    let patterns = guard.get_candle_patterns();
+    if patterns.is_empty() {
+        description_lines.push(format!(
+            "➡️ 直近{}本で該当するパターンなし",
+            CANDLE_LOOKBACK
+        ));
+    } else {
+        description_lines.push(format!("直近{}本で検出したパターン:", CANDLE_LOOKBACK));
+        for pattern in patterns {
+            let mark = if pattern.score > 0.0 {
+                "🟢"
+            } else if pattern.score < 0.0 {
+                "🔴"
+            } else {
+                "➡️"
+            };
+            description_lines.push(format!(
+                "{} {}（{}）→ {:+}",
+                mark, pattern.name, pattern.date, pattern.score
+            ));
+        }
+    }
 
-    // 構造体へ格納（セキュアアクセス経由）
-    guard.set_tenkan_sen(tenkan);
-    guard.set_kijun_sen(kijun);
+    match guard.get_candlestick_score().map(|v| v as i32) {
+        Some(base_score) => {
+            let adjusted_score = base_score as f64 * weight;
+            description_lines.push(format!(
+                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
+                adjusted_score, base_score, weight
+            ));
+            AnalysisResult {
+                indicator_name: "ローソク足パターン".to_string(),
+                description: description_lines,
+                score: Some(base_score as f64),
+            }
+        }
+        None => {
+            description_lines.push("⚠️ ローソク足パターンスコア情報なし".to_string());
+            AnalysisResult {
+                indicator_name: "ローソク足パターン".to_string(),
+                description: description_lines,
+                score: None,
+            }
+        }
+    }
+}
 
-    let diff = tenkan - kijun;
+/// サポート/レジスタンスの表示（セキュアアクセス：TechnicalDataGuard経由）
+fn render_support_resistance(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
+    let weight = config.weight_support_resistance;
+    let close = guard.get_close();
+    let mut description_lines: Vec<String> = Vec::new();
+    description_lines.push("📊 【サポート/レジスタンス（スイング）】".to_string());
+    description_lines.push(format!(
+        "💡 スイング高値/安値（前後{}本）を±{:.1}%でクラスタ化した価格帯",
+        SWING_PIVOT_SPAN, SR_CLUSTER_TOLERANCE_PCT
+    ));
 
-    // 📏 5段階スコア：±2（強）±1（通常）0（同値圏 ±0.5）
-    let ichimoku_score: f64 = match diff {
-        d if d > 2.0 => 2.0,        // 強いゴールデンクロス
-        d if d > 0.5 => 1.0,        // ややゴールデンクロス
-        d if d.abs() <= 0.5 => 0.0, // 同値圏
-        d if d < -2.0 => -2.0,      // 強いデッドクロス
-        _ => -1.0,                  // ややデッドクロス
+    match guard.get_nearest_resistance() {
+        Some(r) => description_lines.push(format!(
+            "🔺 直近レジスタンス: {:.2}（{:+.2}% / タッチ{}回 / 最終 {}）",
+            r.price,
+            level_distance_pct(close, r.price),
+            r.touches,
+            r.last_date
+        )),
+        None => description_lines.push("🔺 上値の抵抗帯なし（全水準を上抜け）".to_string()),
+    }
+    match guard.get_nearest_support() {
+        Some(s) => description_lines.push(format!(
+            "🔻 直近サポート: {:.2}（{:+.2}% / タッチ{}回 / 最終 {}）",
+            s.price,
+            level_distance_pct(close, s.price),
+            s.touches,
+            s.last_date
+        )),
+        None => description_lines.push("🔻 下値の支持帯なし（全水準を割り込み）".to_string()),
+    }
+
+    let rank_line = match guard.get_support_resistance_score().map(|v| v as i32) {
+        Some(2) => "🟢 強いサポート（3回以上）の直上 → 反発期待 → スコア+2加点",
+        Some(1) => "🟢 サポート近辺 or 上値抵抗なし → スコア+1加点",
+        Some(0) => "➡️ サポートとレジスタンスの中間 → スコア変動なし",
+        Some(-1) => "🔴 レジスタンス近辺 or 下値支持なし → スコア-1減点",
+        Some(-2) => "🔴 強いレジスタンス（3回以上）の直下 → 上値が重い → スコア-2減点",
+        _ => "⚠️ サポート/レジスタンススコア不明",
     };
+    description_lines.push(rank_line.to_string());
 
-    guard.set_ichimoku_score(ichimoku_score);
-
-    Ok(())
+    match guard.get_support_resistance_score().map(|v| v as i32) {
+        Some(base_score) => {
+            let adjusted_score = base_score as f64 * weight;
+            description_lines.push(format!(
+                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
+                adjusted_score, base_score, weight
+            ));
+            AnalysisResult {
+                indicator_name: "サポート/レジスタンス".to_string(),
+                description: description_lines,
+                score: Some(base_score as f64),
+            }
+        }
+        None => {
+            description_lines.push("⚠️ サポート/レジスタンススコア情報なし".to_string());
+            AnalysisResult {
+                indicator_name: "サポート/レジスタンス".to_string(),
+                description: description_lines,
+                score: None,
+            }
+        }
+    }
 }
 
-///出力先セレクター
-fn select_output_target(
-    config: &Config,
-    guard: &TechnicalDataGuard,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut results: Vec<AnalysisResult> = Vec::new();
+/// 相対力（対ベンチマーク）の表示（セキュアアクセス：TechnicalDataGuard経由）
+fn render_relative_strength(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
+    let weight = config.weight_relative_strength;
+    let mut description_lines: Vec<String> = Vec::new();
+    description_lines.push(format!("📊 【相対力（対 {}）】", config.benchmark));
 
-    for indicator in &config.enabled_extensions {
-        match indicator {
-            ExtensionIndicator::Ema => {
-                let ema_score_value = guard.get_ema_score().unwrap_or(0.0);
-                results.push(AnalysisResult {
-                    indicator_name: "EMA".to_string(),
-                    description: Vec::new(),
-                    score: ema_score_value,
-                });
-            }
-            ExtensionIndicator::Sma => {
-                let sma_score_value = guard.get_sma_score().unwrap_or(0.0);
-                results.push(AnalysisResult {
-                    indicator_name: "SMA".to_string(),
-                    description: Vec::new(),
-                    score: sma_score_value,
-                });
-            }
-            ExtensionIndicator::Roc => {
-                let roc_score_value = guard.get_roc_score().unwrap_or(0.0);
-                results.push(AnalysisResult {
-                    indicator_name: "ROC".to_string(),
-                    description: Vec::new(),
-                    score: roc_score_value,
-                });
-            }
-            ExtensionIndicator::Adx => {
-                let adx_score_value = guard.get_adx_score().unwrap_or(0.0);
-                results.push(AnalysisResult {
-                    indicator_name: "ADX".to_string(),
-                    description: Vec::new(),
-                    score: adx_score_value,
-                });
-            }
-            ExtensionIndicator::Stochastics => {
-                let stoch_score_value = guard.get_stochastics_score().unwrap_or(0.0);
-                results.push(AnalysisResult {
-                    indicator_name: "Stochastics".to_string(),
-                    description: Vec::new(),
-                    score: stoch_score_value,
-                });
+    match guard.get_relative_strength() {
+        Some(rs) => {
+            description_lines.push(format!(
+                "📈 直近{}本の騰落率: 銘柄 {:+.2}% / ベンチマーク {:+.2}% → 超過リターン {:+.2}%",
+                RS_LOOKBACK, rs.stock_return_pct, rs.bench_return_pct, rs.excess_return_pct
+            ));
+            let fmt_opt = |v: Option<f64>| v.map(|x| format!("{:.2}", x)).unwrap_or("-".into());
+            description_lines.push(format!(
+                "📐 ベータ: {} / 相関係数: {}（直近の日次リターン{}本）",
+                fmt_opt(rs.beta),
+                fmt_opt(rs.correlation),
+                rs.samples
+            ));
+            if let (Some(beta), Some(prev)) = (rs.beta, rs.beta_prev) {
+                description_lines.push(format!(
+                    "📐 ベータの推移: {}本前 {:.2} → 現在 {:.2}（{:+.2}）",
+                    RS_LOOKBACK,
+                    prev,
+                    beta,
+                    beta - prev
+                ));
             }
-            ExtensionIndicator::Bollinger => {
-                let bollinger_score_value = guard.get_bollinger_score().unwrap_or(0.0);
-                results.push(AnalysisResult {
-                    indicator_name: "Bollinger".to_string(),
-                    description: Vec::new(),
-                    score: bollinger_score_value,
-                });
+            if let Some(beta) = rs.beta {
+                if beta >= 1.2 {
+                    description_lines
+                        .push("💡 ベータ高め → 市場より値動きが大きい（地合いの影響を増幅）".to_string());
+                } else if beta <= 0.8 {
+                    description_lines
+                        .push("💡 ベータ低め → 市場より値動きが小さい（ディフェンシブ寄り）".to_string());
+                }
             }
-            ExtensionIndicator::Fibonacci => {
-                let fibonacci_score_value = guard.get_fibonacci_score().unwrap_or(0.0);
-                results.push(AnalysisResult {
-                    indicator_name: "Fibonacci".to_string(),
-                    description: Vec::new(),
-                    score: fibonacci_score_value,
-                });
+            if let Some(corr) = rs.correlation {
+                if corr < 0.3 {
+                    description_lines
+                        .push("💡 市場との連動性が低い → 個別要因（材料・需給）が主導".to_string());
+                }
             }
-            ExtensionIndicator::Vwap => {
-                let vwap_score_value = guard.get_vwap_score().unwrap_or(0.0);
-                results.push(AnalysisResult {
-                    indicator_name: "VWAP".to_string(),
-                    description: Vec::new(),
-                    score: vwap_score_value,
-                });
+        }
+        None => description_lines.push("⚠️ ベンチマーク比較データが不足しています".to_string()),
+    }
+
+    let rank_line = match guard.get_relative_strength_score().map(|v| v as i32) {
+        Some(2) => "🟢 市場を大きくアウトパフォーム → スコア+2加点",
+        Some(1) => "🟢 市場をアウトパフォーム → スコア+1加点",
+        Some(0) => "➡️ 市場並みの推移 → スコア変動なし",
+        Some(-1) => "🔴 市場をアンダーパフォーム → スコア-1減点",
+        Some(-2) => "🔴 市場を大きくアンダーパフォーム → スコア-2減点",
+        _ => "⚠️ 相対力スコア不明",
+    };
+    description_lines.push(rank_line.to_string());
+
+    match guard.get_relative_strength_score().map(|v| v as i32) {
+        Some(base_score) => {
+            let adjusted_score = base_score as f64 * weight;
+            description_lines.push(format!(
+                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
+                adjusted_score, base_score, weight
+            ));
+            AnalysisResult {
+                indicator_name: "相対力".to_string(),
+                description: description_lines,
+                score: Some(base_score as f64),
             }
-            ExtensionIndicator::Ichimoku => {
-                let ichimoku_score_value = guard.get_ichimoku_score().unwrap_or(0.0);
-                results.push(AnalysisResult {
-                    indicator_name: "Ichimoku".to_string(),
-                    description: Vec::new(),
-                    score: ichimoku_score_value,
-                });
+        }
+        None => {
+            description_lines.push("⚠️ 相対力スコア情報なし".to_string());
+            AnalysisResult {
+                indicator_name: "相対力".to_string(),
+                description: description_lines,
+                score: None,
             }
         }
     }
+}
 
-    if config.save_technical_log {
-        save_technical_log(config, guard, &results)?;
+/// カスタム指標（ユーザー定義式）の表示（セキュアアクセス：TechnicalDataGuard経由）
+fn render_custom(config: &Config, guard: &TechnicalDataGuard, index: usize) -> AnalysisResult {
+    let custom = &config.custom_indicators[index];
+    let mut description_lines: Vec<String> = Vec::new();
+    description_lines.push(format!("📊 【カスタム指標: {}】", custom.name));
+    description_lines.push(format!("🧮 式: {}", custom.expr));
+    if let Some(description) = &custom.description {
+        description_lines.push(format!("💡 {}", description));
     }
-
-    if !config.silent {
-        technical_render_to_terminal(config, guard);
+    let bands = custom
+        .bands
+        .iter()
+        .map(|b| format!("{}以上→{:+}", b.min, b.score))
+        .chain([format!("それ以外→{:+}", custom.default_score)])
+        .collect::<Vec<_>>()
+        .join(" / ");
+    description_lines.push(format!("🎯 スコア帯: {}", bands));
+
+    match guard.get_custom_result(&custom.name) {
+        Some(result) => {
+            // スコア帯は小数も指定できるため、総合スコアと同じ値をそのまま表示・記録する
+            let base_score = result.score;
+            let adjusted_score = base_score * custom.weight;
+            description_lines.push(format!("📏 最新値: {:.4} → スコア{:+}", result.value, base_score));
+            description_lines.push(format!(
+                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
+                adjusted_score, base_score, custom.weight
+            ));
+            AnalysisResult {
+                indicator_name: custom.name.clone(),
+                description: description_lines,
+                score: Some(base_score),
+            }
+        }
+        None => {
+            description_lines.push("⚠️ カスタム指標の値を算出できませんでした".to_string());
+            AnalysisResult {
+                indicator_name: custom.name.clone(),
+                description: description_lines,
+                score: None,
+            }
+        }
     }
-
-    Ok(())
 }
 
-/// テクニカル分析結果を画面に出力（セキュアアクセスにより構造体を直接参照せず、TechnicalDataGuard経由で取得）
-fn technical_render_to_terminal(config: &Config, guard: &TechnicalDataGuard) {
-    // ① メイン情報
-    display_main_info(config, guard);
-
-    // ② 基本テクニカル分析
-    let basic_result = render_basic(config, guard);
-    display_analysis_result(&basic_result);
+/// プラグイン指標の表示（セキュアアクセス：TechnicalDataGuard経由）
+fn render_plugin(config: &Config, guard: &TechnicalDataGuard, index: usize) -> AnalysisResult {
+    let plugin = &config.plugins[index];
+    let mut description_lines: Vec<String> = Vec::new();
+    description_lines.push(format!("📊 【プラグイン: {}】", plugin.name));
 
-    // ③ 拡張テクニカル分析
-    let extension_results = render_extension(config, guard);
-    for extension_result in extension_results {
-        match extension_result {
-            ExtensionResult::Header(ref s) => println!("{}", s),
-            ExtensionResult::Analysis(ref result) => display_analysis_result(result),
+    match guard.get_plugin_outcome(&plugin.name).map(|o| &o.result) {
+        Some(Ok(output)) => {
+            for (key, value) in &output.values {
+                description_lines.push(format!("📏 {}: {:.4}", key, value));
+            }
+            description_lines.extend(output.description.iter().map(|l| format!("💡 {}", l)));
+            // 総合スコア・カスタム指標と同じく、プラグインが返した値をそのまま使う
+            let base_score = output.score;
+            let adjusted_score = base_score * plugin.weight;
+            description_lines.push(format!(
+                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
+                adjusted_score, base_score, plugin.weight
+            ));
+            AnalysisResult {
+                indicator_name: plugin.name.clone(),
+                description: description_lines,
+                score: Some(base_score),
+            }
+        }
+        Some(Err(e)) => {
+            description_lines.push(format!("❌ プラグインの実行に失敗: {}（スコアは0扱い）", e));
+            AnalysisResult {
+                indicator_name: plugin.name.clone(),
+                description: description_lines,
+                score: None,
+            }
+        }
+        None => {
+            description_lines.push("⚠️ プラグインの結果がありません".to_string());
+            AnalysisResult {
+                indicator_name: plugin.name.clone(),
+                description: description_lines,
+                score: None,
+            }
         }
     }
+}
 
-    // ④ 総合スコア表示
-    render_final_score(config, guard);
+/// ローソク足パターン一覧をログ用の1セルに整形（CSV区切りの ',' を含めない）
+fn format_candle_patterns_for_log(patterns: &[CandlePattern]) -> String {
+    patterns
+        .iter()
+        .map(|p| format!("{}@{}({:+})", p.name, p.date, p.score))
+        .collect::<Vec<_>>()
+        .join(" | ")
 }
 
-/// 拡張テクニカル指標のレンダリング処理（仕様厳守）
-enum ExtensionResult {
-    Header(String),
-    Analysis(AnalysisResult),
+/// 単極ゲージ（Seller/Buyerの見た目長さ差を解消）。例: 「Buyer [.....█████] Seller」
+fn render_unipolar_gauge_rtl(
+    percent: u8,
+    left_label: &str,
+    right_label: &str,
+    width: usize,
+) -> String {
+    // 充填は四捨五入で統一（Seller/Buyerの見た目長さ差を解消）
+    let w = width.max(10);
+    let filled = (((percent as usize) * w) + 50) / 100; // round(percent * w / 100)
+    let empty = w - filled;
+
+    // filled + empty = width を常に満たす
+    let bar = format!("[{}{}]", ".".repeat(empty), "█".repeat(filled));
+    format!(
+        "{left} {bar} {right}",
+        left = left_label,
+        right = right_label
+    )
 }
-/// 拡張テクニカル指標のレンダリング処理（仕様厳守）
-fn render_extension(config: &Config, guard: &TechnicalDataGuard) -> Vec<ExtensionResult> {
-    let mut extension_results = Vec::new();
 
-    let mut categorized_map: HashMap<IndicatorCategory, Vec<ExtensionIndicator>> = HashMap::new();
-    for indicator in &config.enabled_extensions {
-        let category = indicator.category();
-        categorized_map
-            .entry(category)
-            .or_default()
-            .push(indicator.clone());
-    }
+/// 両極ゲージ（中央'|'、正は左へ・負は右へ塗る）。例: 「買い [█████|..........] 売り」
+/// 幅は呼び出し側で 50 指定（ここでは安全側で最小12を確保）。中央'|'を配置。
+#[allow(clippy::needless_range_loop)]
+fn render_bipolar_gauge_lr(score_ratio: f64, width: usize) -> String {
+    // 幅は呼び出し側で 50 指定（ここでは安全側で最小12を確保）。中央'|'を配置。
+    let w = width.max(12);
+    let mid = w / 2;
 
-    for category in [
-        IndicatorCategory::Trend,
-        IndicatorCategory::Oscillator,
-        IndicatorCategory::Volatility,
-        IndicatorCategory::SupportResist,
-    ] {
-        if let Some(indicators) = categorized_map.get(&category) {
-            match category {
-                IndicatorCategory::Trend => extension_results
-                    .push(ExtensionResult::Header("---トレンド系指標---".to_string())),
-                IndicatorCategory::Oscillator => extension_results.push(ExtensionResult::Header(
-                    "---オシレーター系指標---".to_string(),
-                )),
-                IndicatorCategory::Volatility => extension_results.push(ExtensionResult::Header(
-                    "---ボラティリティ系指標---".to_string(),
-                )),
-                IndicatorCategory::SupportResist => extension_results.push(
-                    ExtensionResult::Header("---補助トレンド系指標---".to_string()),
-                ),
-            }
+    // |score_ratio| を中央半幅にスケールし、丸めて段階化。上限は mid。
+    let blocks = ((score_ratio.abs() * mid as f64).round() as usize).min(mid);
 
-            for indicator in indicators {
-                let rendered = match indicator {
-                    ExtensionIndicator::Ema => render_ema(config, guard),
-                    ExtensionIndicator::Sma => render_sma(config, guard),
-                    ExtensionIndicator::Bollinger => render_bollinger(config, guard),
-                    ExtensionIndicator::Roc => render_roc(config, guard),
-                    ExtensionIndicator::Adx => render_adx(config, guard),
-                    ExtensionIndicator::Stochastics => render_stochastics(config, guard),
-                    ExtensionIndicator::Fibonacci => render_fibonacci(config, guard),
-                    ExtensionIndicator::Vwap => render_vwap(config, guard),
-                    ExtensionIndicator::Ichimoku => render_ichimoku(config, guard),
-                };
-                extension_results.push(ExtensionResult::Analysis(rendered));
+    // ベースは '.' で埋め、中央に '|'。filled + empty = w を維持。
+    let mut v = vec!['.'; w];
+    if mid < w {
+        v[mid] = '|';
+    }
+
+    // 向きは仕様固定：左=買い、右=売り。
+    // 正（買い）は左側（midの左）へ、負（売り）は右側（midの右）へ塗る。
+    if score_ratio > 0.0 {
+        for i in (mid.saturating_sub(blocks))..mid {
+            v[i] = '█';
+        }
+    } else if score_ratio < 0.0 {
+        for i in (mid + 1)..(mid + 1 + blocks) {
+            if i < w {
+                v[i] = '█';
             }
         }
     }
-    extension_results
-}
 
-// 最終スコアのスナップショットを一度だけ算出（SoT）
-fn calculate_final_score_snapshot(
-    config: &Config,
-    guard: &TechnicalDataGuard,
-) -> FinalScoreSnapshot {
-    // 分子：既存ロジック
-    let total_score = calculate_final_score(config, guard);
+    // バー色は判定色と一致させる（'█' のみ着色）。色名は get_color_for_score に準拠。
+    let color = get_color_for_score(score_ratio);
+    let bar: String = v
+        .into_iter()
+        .map(|c| {
+            if c == '█' {
+                match color {
+                    "green" => c.to_string().green().to_string(),
+                    "yellow" => c.to_string().yellow().to_string(),
+                    "orange" => c.to_string().truecolor(255, 165, 0).to_string(),
+                    "red" => c.to_string().red().to_string(),
+                    "white" => c.to_string().white().to_string(), // 中立近傍
+                    _ => c.to_string(),
+                }
+            } else {
+                c.to_string()
+            }
+        })
+        .collect();
 
-    // 分母：有効な指標の重み合計×2.0（欠損スコアでもenabledなら含める）
-    let mut sum_weights = config.weight_basic;
-    for ext in &config.enabled_extensions {
-        sum_weights += match ext {
-            ExtensionIndicator::Ema => config.weight_ema,
-            ExtensionIndicator::Sma => config.weight_sma,
-            ExtensionIndicator::Roc => config.weight_roc,
-            ExtensionIndicator::Adx => config.weight_adx,
-            ExtensionIndicator::Stochastics => config.weight_stochastics,
-            ExtensionIndicator::Bollinger => config.weight_bollinger,
-            ExtensionIndicator::Fibonacci => config.weight_fibonacci,
-            ExtensionIndicator::Vwap => config.weight_vwap,
-            ExtensionIndicator::Ichimoku => config.weight_ichimoku,
-        };
-    }
-    let total_weight = 2.0 * sum_weights;
-    let score_ratio = if total_weight != 0.0 {
-        total_score / total_weight
-    } else {
-        0.0
-    };
+    // 文言は仕様固定：「買い+100％[バー] -100% 売り」
+    format!("買い+100％[{bar}] -100% 売り")
+}
 
-    FinalScoreSnapshot {
-        total_score,
-        total_weight,
-        score_ratio,
+fn stance_caption(s: &Stance) -> &'static str {
+    match s {
+        Stance::Buyer => "Buyer",
+        Stance::Holder => "Holder",
+        Stance::Seller => "Seller",
     }
 }
-
-/// 総合スコアを表示（計算結果とグラフを含む）
-fn render_final_score(config: &Config, guard: &TechnicalDataGuard) {
-    let snap = calculate_final_score_snapshot(config, guard);
-    let lines =
-        compose_final_score_lines_stance(&snap, &config.stance, /*include_gauge=*/ true);
-    for line in lines {
-        println!("{}", line);
+///グラフ色分け
+fn get_color_for_score(score_ratio: f64) -> &'static str {
+    match score_ratio {
+        r if r >= 0.8 => "green", // 強い買い
+        r if r >= 0.6 => "green", // やや買い
+        r if r >= 0.4 => "green",
+        r if r >= 0.2 => "yellow",
+        r if r >= 0.0 => "yellow",
+        r if r >= -0.2 => "white",
+        r if r >= -0.4 => "orange",
+        r if r >= -0.6 => "orange",
+        r if r >= -0.8 => "red",
+        _ => "red",
     }
 }
+///CSVヘッダー出力
+fn generate_csv_header(config: &Config) {
+    println!("{}", build_csv_header(config)); // ✅ 出力ここで完結
+}
 
-/// 最終スコア（基本＋拡張指標の加重合計）を計算
-fn calculate_final_score(config: &Config, guard: &TechnicalDataGuard) -> f64 {
-    let mut total_score = guard.get_signal_score() * config.weight_basic;
+/// 現在の設定に対応するCSVヘッダー行を組み立てる
+fn build_csv_header(config: &Config) -> String {
+    // カスタム指標は名前から列名を作る（custom_<name>_value / custom_<name>_score）
+    let custom_columns: Vec<[String; 2]> = config
+        .custom_indicators
+        .iter()
+        .map(|c| [format!("custom_{}_value", c.name), format!("custom_{}_score", c.name)])
+        .collect();
+    let plugin_columns: Vec<[String; 3]> = config
+        .plugins
+        .iter()
+        .map(|p| {
+            [
+                format!("plugin_{}_status", p.name),
+                format!("plugin_{}_values", p.name),
+                format!("plugin_{}_score", p.name),
+            ]
+        })
+        .collect();
+    let mut headers = vec![
+        "ticker",
+        "date",
+        "datetime", 
+        "timezone",
+        "close",
+        "prev_close",
+        "diff",
+        "diff_pct",
+        "macd",
+        "signal",
+        "macd_cross",
+        "macd_cross_date",
+        "macd_cross_bars_ago",
+        "rsi",
+        "score",
+    ];
 
-    for ext in &config.enabled_extensions {
-        let weighted_score = match ext {
-            ExtensionIndicator::Ema => guard.get_ema_score().unwrap_or(0.0) * config.weight_ema,
-            ExtensionIndicator::Sma => guard.get_sma_score().unwrap_or(0.0) * config.weight_sma,
-            ExtensionIndicator::Roc => guard.get_roc_score().unwrap_or(0.0) * config.weight_roc,
-            ExtensionIndicator::Adx => guard.get_adx_score().unwrap_or(0.0) * config.weight_adx,
+    for indicator in &config.enabled_extensions {
+        match indicator {
+            ExtensionIndicator::Ema => {
+                headers.extend_from_slice(&[
+                    "ema_short",
+                    "ema_long",
+                    "ema_cross",
+                    "ema_cross_date",
+                    "ema_cross_bars_ago",
+                    "ema_score",
+                ]);
+            }
+            ExtensionIndicator::Sma => {
+                headers.extend_from_slice(&[
+                    "sma_short",
+                    "sma_long",
+                    "sma_cross",
+                    "sma_cross_date",
+                    "sma_cross_bars_ago",
+                    "sma_score",
+                ]);
+            }
+            ExtensionIndicator::Roc => {
+                headers.extend_from_slice(&["roc", "roc_score"]);
+            }
+            ExtensionIndicator::Adx => {
+                headers.extend_from_slice(&["adx", "adx_score"]);
+            }
             ExtensionIndicator::Stochastics => {
-                guard.get_stochastics_score().unwrap_or(0.0) * config.weight_stochastics
+                headers.extend_from_slice(&[
+                    "stoch_k",
+                    "stoch_d",
+                    "stoch_cross",
+                    "stoch_cross_date",
+                    "stoch_cross_bars_ago",
+                    "stoch_score",
+                ]);
             }
             ExtensionIndicator::Bollinger => {
-                guard.get_bollinger_score().unwrap_or(0.0) * config.weight_bollinger
+                headers.extend_from_slice(&[
+                    "bb_upper",
+                    "bb_lower",
+                    "percent_b",
+                    "bandwidth_%",
+                    "bb_score",
+                ]);
             }
             ExtensionIndicator::Fibonacci => {
-                guard.get_fibonacci_score().unwrap_or(0.0) * config.weight_fibonacci
+                headers.extend_from_slice(&[
+                    "fibo_dir",
+                    "fibo_swing_high",
+                    "fibo_swing_low",
+                    "fibo_23_6",
+                    "fibo_38_2",
+                    "fibo_50_0",
+                    "fibo_61_8",
+                    "fibo_78_6",
+                    "fibo_ext_127_2",
+                    "fibo_ext_161_8",
+                    "fibo_score",
+                ]);
+            }
+            ExtensionIndicator::Vwap => {
+                headers.extend_from_slice(&["vwap", "vwap_score"]);
             }
-            ExtensionIndicator::Vwap => guard.get_vwap_score().unwrap_or(0.0) * config.weight_vwap,
             ExtensionIndicator::Ichimoku => {
-                guard.get_ichimoku_score().unwrap_or(0.0) * config.weight_ichimoku
+                headers.extend_from_slice(&[
+                    "tenkan",
+                    "kijun",
+                    "ichimoku_cross",
+                    "ichimoku_cross_date",
+                    "ichimoku_cross_bars_ago",
+                    "ichimoku_score",
+                ]);
             }
-        };
-        total_score += weighted_score;
+            ExtensionIndicator::Candlestick => {
+                headers.extend_from_slice(&["candle_patterns", "candle_score"]);
+            }
+            ExtensionIndicator::SupportResistance => {
+                headers.extend_from_slice(&[
+                    "sr_support",
+                    "sr_support_dist_%",
+                    "sr_support_touches",
+                    "sr_resistance",
+                    "sr_resistance_dist_%",
+                    "sr_resistance_touches",
+                    "sr_score",
+                ]);
+            }
+            ExtensionIndicator::RelativeStrength => {
+                headers.extend_from_slice(&[
+                    "rs_benchmark",
+                    "rs_stock_return_%",
+                    "rs_bench_return_%",
+                    "rs_excess_return_%",
+                    "rs_beta",
+                    "rs_correlation",
+                    "rs_beta_change",
+                    "rs_score",
+                ]);
+            }
+            ExtensionIndicator::Custom(index) => {
+                headers.extend(custom_columns[*index].iter().map(String::as_str));
+            }
+            ExtensionIndicator::Plugin(index) => {
+                headers.extend(plugin_columns[*index].iter().map(String::as_str));
+            }
+        }
     }
 
-    total_score
+    headers.extend([
+        "final_score",
+        "score_ratio",
+        "score_coverage",
+        "missing_indicators",
+        "indicator_status",
+        "preset",
+        "stance",
+        "judgement",
+        "direction",
+    ]);
+    // カテゴリ別の内訳（基本 + 有効な拡張指標のカテゴリ）
+    let category_columns: Vec<String> = std::iter::once("basic")
+        .chain(
+            IndicatorCategory::ALL
+                .iter()
+                .filter(|c| config.enabled_extensions.iter().any(|e| e.category() == **c))
+                .map(|c| c.key()),
+        )
+        .flat_map(|key| [format!("cat_{}_score", key), format!("cat_{}_ratio", key)])
+        .collect();
+    headers.extend(category_columns.iter().map(String::as_str));
+    if config.regime_weighting {
+        headers.extend([
+            "regime",
+            "regime_adx",
+            "regime_bandwidth_%",
+            "regime_slope_%",
+            "effective_weights",
+        ]);
+    }
+    headers.join(",")
 }
 
-/// 銘柄名・日付・価格情報を表示（セキュアアクセスによりTechnicalDataEntryを直接参照せず、TechnicalDataGuard経由で取得）
-fn display_main_info(config: &Config, guard: &TechnicalDataGuard) {
-    // ← ここでの固定見出し（MACDマイナス許容）は削除
-
-    if (config.buy_rsi - 30.0).abs() > f64::EPSILON {
-        println!(
-            "{}",
-            format!(
-                "🔧 --buy-rsi={:.2} を指定 → RSIが{:.2}以下で買い圏とみなされます",
-                config.buy_rsi, config.buy_rsi
-            )
-            .red()
-        );
-    }
-    if (config.sell_rsi - 70.0).abs() > f64::EPSILON {
-        println!(
-            "{}",
-            format!(
-                "🔧 --sell-rsi={:.2} を指定 → RSIが{:.2}以上で売り圏とみなされます",
-                config.sell_rsi, config.sell_rsi
-            )
-            .red()
-        );
-    }
-    if (config.macd_diff_low - 2.0).abs() > f64::EPSILON {
-        println!(
-            "{}",
-            format!(
-                "🔧 --macd-diff-low={:.2} を指定 → MACD差が{:.2}未満ならスコアを中立に補正します",
-                config.macd_diff_low, config.macd_diff_low
-            )
-            .red()
-        );
-    }
-    if (config.macd_diff_mid - 10.0).abs() > f64::EPSILON {
-        println!(
-            "{}",
-            format!(
-                "🔧 --macd-diff-mid={:.2} を指定 → MACD差が{:.2}以上でスコアを強化します",
-                config.macd_diff_mid, config.macd_diff_mid
-            )
-            .red()
-        );
-    }
-    
-    println!("\n📊 銘柄: {}（{}）", guard.get_name(), guard.get_ticker());
-    
-    let date = guard.get_date();
-    let time = guard
-        .get_datetime()
-        .and_then(|s| s.split_whitespace().nth(1))
-        .unwrap_or("--:--");
-
-    println!("📅 日時: {} {} {}", date, time, guard.get_timezone());
-    
-    //println!("📅 日時: {} {} JST", date_jst, time_jst);
-    println!("💰 現在値　: {:.2}", guard.get_close());
-    println!("💰 前日終値: {:.2}", guard.get_previous_close());
+// ヒステリシスのみ指定時に遡る足の数
+const SMOOTHING_MIN_LOOKBACK: usize = 20;
 
-    let diff = guard.get_price_diff();
-    let percent = guard.get_price_diff_percent();
-    let diff_str = if diff > 0.0 {
-        format!("{:+.2} ({:+.2}%)", diff, percent).green()
-    } else if diff < 0.0 {
-        format!("{:+.2} ({:+.2}%)", diff, percent).red()
-    } else {
-        format!("{:+.2} ({:+.2}%)", diff, percent).normal()
-    };
-    println!("📊 前日比: {}", diff_str);
+/// 直近の足（今回の足を除く）のスコア比率を足ごとに評価して集める（--smoothing-bars / --hysteresis 指定時のみ）。
+/// EMA の立ち上がりを考慮し、期間の3倍（最低 SMOOTHING_MIN_LOOKBACK 本）遡る。
+/// 指標の系列は今回の分析で計算したものを使い回し、プラグインは足ごとには実行しない
+fn compute_score_smoothing(
+    config: &Config,
+    series: &IndicatorSeries,
+    data: &[MarketData],
+    ticker_name_map: &HashMap<String, String>,
+) -> Option<ScoreSmoothing> {
+    if config.smoothing_bars == 0 && config.hysteresis <= 0.0 {
+        return None;
+    }
+    let lookback = (config.smoothing_bars * 3).max(SMOOTHING_MIN_LOOKBACK);
+    let first = data.len().saturating_sub(lookback).max(2);
+    let past_ratios: Vec<f64> = (first..data.len())
+        .filter_map(|end| {
+            // 必要本数に満たない足は飛ばす
+            let (guard, bar_config) =
+                evaluate_bar(config, series, &data[..end], ticker_name_map).ok()?;
+            Some(calculate_final_score_snapshot(&bar_config, &guard).score_ratio)
+        })
+        .collect();
 
-    // ← ここで動的ラベルを差し込む
-    let macd_minus_label = if config.macd_minus_ok {
-        if guard.get_macd() < 0.0 && guard.get_macd() > guard.get_signal() {
-            "※『MACDマイナス許容』設定: 有効（今回“適用対象”）"
-        } else {
-            "※『MACDマイナス許容』設定: 有効（今回“未適用”）"
-        }
-    } else {
-        "※『MACDマイナス許容』設定: 無効"
-    };
-    println!("{}", macd_minus_label);
-    println!(); // 空行
+    Some(ScoreSmoothing {
+        past_ratios,
+        bars: config.smoothing_bars,
+        hysteresis: config.hysteresis,
+        plugins_skipped: !config.plugins.is_empty(),
+    })
 }
 
-/// 基本テクニカル分析（MACD + RSI）を行い、AnalysisResultに格納する
-fn render_basic(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
-    let rsi = guard.get_rsi();
-    let macd = guard.get_macd();
-    let signal = guard.get_signal();
-    let prev_macd = guard.get_prev_macd();
-    let prev_signal = guard.get_prev_signal();
-    let weight = config.weight_basic;
-
-    let score = guard.get_signal_score();
-    let adjusted_score = score * weight;
-
-    let mut description_lines: Vec<String> = Vec::new();
-
-    // ★ ここから追加：MACDとRSIの数値を明示的に表示
-    description_lines.push("基本テクニカル分析（MACDとRSIによる評価）".to_string());
-    description_lines.push(format!("📈 MACD: {:.4} / Signal: {:.4}", macd, signal));
-    description_lines.push(format!("📊 RSI: {:.2}", rsi));
-    // ★ ここまで追加
-
-    // ① トレンド継続 or 転換
-    if prev_macd < prev_signal && macd > signal {
-        description_lines.push("⚠️ MACDがゴールデンクロス → 上昇トレンド転換の可能性".to_string());
-    } else if macd > signal {
-        description_lines.push(
-            "⚠️ MACDがSignalを上回る状態が継続 → 上昇トレンドが維持されている可能性".to_string(),
-        );
-    } else if prev_macd > prev_signal && macd < signal {
-        description_lines.push("⚠️ MACDがデッドクロス → 下落トレンド転換の可能性".to_string());
-    } else if macd < signal {
-        description_lines
-            .push("⚠️ MACDがSignalを下回る状態が継続 → 弱含みトレンドが継続中".to_string());
+/// 1本の足を、その足までのデータだけで評価する（先読みなし）。相場局面も足ごとに判定し直す。
+/// 指標の系列は全期間で計算済みのものを先頭 window.len() 本分だけ参照する。
+/// プラグインは実行せず skipped として総合スコアから外す（外部プロセスを足の数だけ起動しないため）。
+/// 必要本数に満たない指標（立ち上がり前の基本スコアを含む）がある足はエラー（エラーの指標はステータス付きで評価済みとして返す）
+fn evaluate_bar(
+    config: &Config,
+    series: &IndicatorSeries,
+    window: &[MarketData],
+    ticker_name_map: &HashMap<String, String>,
+) -> Result<(TechnicalDataGuard, Config), Box<dyn std::error::Error>> {
+    let mut guard = build_basic_technical_entry(config, series, window, ticker_name_map)?;
+    let bar_config = apply_market_regime(config, series, window, &mut guard);
+    evaluate_all_selected_extensions(&bar_config, series, window, &mut guard, false);
+    let warming_up = guard
+        .get_indicator_statuses()
+        .iter()
+        .find(|(_, status)| matches!(status, IndicatorStatus::InsufficientData(_)));
+    if let Some((_, status)) = warming_up {
+        return Err(status.reason().unwrap_or_default().into());
     }
+    check_missing_scores(&bar_config, &guard)?;
+    Ok((guard, bar_config))
+}
 
-    // ② 乖離の大きさ
-    let macd_diff = macd - signal;
-    if macd_diff >= 5.0 {
-        description_lines.push(format!(
-            "⚠️ MACDがSignalより大幅に上回っています（+{:.2}）→ 過熱感がある可能性があります",
-            macd_diff
-        ));
-    } else if macd_diff <= -5.0 {
-        description_lines.push(format!(
-            "⚠️ MACDがSignalより大幅に下回っています（{:.2}）→ 割安感がある可能性があります",
-            macd_diff
-        ));
-    }
+/// 全期間を足ごとに評価し、バックテスト用の系列を作る（各足はその足までのデータだけで評価、先読みなし）。
+/// 戻り値の usize は必要本数に満たず評価しなかった足の数
+fn collect_backtest_bars(
+    config: &Config,
+    data: &[MarketData],
+    benchmark_data: Option<&[MarketData]>,
+    ticker_name_map: &HashMap<String, String>,
+) -> Result<(Vec<BacktestBar>, usize), Box<dyn std::error::Error>> {
+    let (bars, skipped) = replay_bars(config, data, benchmark_data, ticker_name_map, |_, _| ())?;
+    Ok((bars.into_iter().map(|(bar, _)| bar).collect(), skipped))
+}
 
-    // ③ RSIの極端な値
-    if rsi <= 5.0 {
-        description_lines.push("⚠️ RSIが 0% に近い極端な売られすぎ → 反発に警戒".to_string());
-    } else if rsi >= 95.0 {
-        description_lines.push("⚠️ RSIが 100% に近い極端な買われすぎ → 反転下落に注意".to_string());
+/// 全期間を足ごとに評価し、評価できた足ごとに extract で評価結果から必要な値を取り出す
+/// （collect_backtest_bars と最適化の共通部分。戻り値の usize は評価しなかった足の数）
+fn replay_bars<T>(
+    config: &Config,
+    data: &[MarketData],
+    benchmark_data: Option<&[MarketData]>,
+    ticker_name_map: &HashMap<String, String>,
+    mut extract: impl FnMut(&Config, &TechnicalDataGuard) -> T,
+) -> ReplayResult<T> {
+    let series = IndicatorSeries::new(config, data, benchmark_data)?;
+    let mut bars = Vec::new();
+    let mut skipped = 0;
+    for end in 2..=data.len() {
+        let last = &data[end - 1];
+        match evaluate_bar(config, &series, &data[..end], ticker_name_map) {
+            Ok((guard, bar_config)) => bars.push((
+                BacktestBar {
+                    date: last.date.clone(),
+                    open: last.open,
+                    close: last.close,
+                    score_ratio: calculate_final_score_snapshot(&bar_config, &guard).score_ratio,
+                },
+                extract(&bar_config, &guard),
+            )),
+            Err(_) => skipped += 1,
+        }
     }
+    Ok((bars, skipped))
+}
 
-    // ④ スコア評価の要約
-    match score {
-        2.0 => {
-            if rsi < 30.0 {
-                description_lines.push(
-                    "🟢 [基本スコア:+2] RSIが極端に割安 → 強い買いシグナル → スコア+2加点"
-                        .to_string(),
-                );
-            } else {
-                description_lines
-                    .push("🟢 [基本スコア:+2] MACDが強い上昇トレンド → スコア+2加点".to_string());
-            }
-        }
-        1.0 => {
-            if rsi < 40.0 {
-                description_lines.push(
-                    "🟢 [基本スコア:+1] RSIが割安圏 → 買いシグナル → スコア+1加点".to_string(),
-                );
-            } else {
-                description_lines
-                    .push("🟢 [基本スコア:+1] MACDが上昇傾向 → スコア+1加点".to_string());
-            }
-        }
-        0.0 => {
-            description_lines
-                .push("⚪️ [基本スコア:0] RSI・MACDともに中立 → スコアなし".to_string());
-        }
-        -1.0 => {
-            if rsi > 60.0 {
-                description_lines.push(
-                    "🔴 [基本スコア:-1] RSIが割高圏 → 売りシグナル → スコア-1減点".to_string(),
-                );
-            } else {
-                description_lines
-                    .push("🔴 [基本スコア:-1] MACDが下降傾向 → スコア-1減点".to_string());
-            }
-        }
-        -2.0 => {
-            if rsi > 70.0 {
-                description_lines.push(
-                    "🔴 [基本スコア:-2] RSIが極端に割高 → 強い売りシグナル → スコア-2減点"
-                        .to_string(),
-                );
+/// 足の終値時点のシグナル（Some(true)=買い、Some(false)=手仕舞い、None=何もしない）
+fn backtest_signal(config: &Config, score_ratio: f64) -> Option<bool> {
+    match config.bt_rule {
+        BacktestRule::Threshold => {
+            if score_ratio >= config.bt_entry {
+                Some(true)
+            } else if score_ratio <= config.bt_exit {
+                Some(false)
             } else {
-                description_lines
-                    .push("🔴 [基本スコア:-2] MACDが強い下降トレンド → スコア-2減点".to_string());
+                None
             }
         }
-        _ => {
-            description_lines.push("💡 [基本スコア:?] 不明または不正なスコア条件".to_string());
-        }
+        BacktestRule::Stance => stance_direction(&config.stance, score_ratio),
     }
+}
 
-    // ⑤ スコア調整情報
-    description_lines.push(format!(
-        "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
-        adjusted_score, score, weight
-    ));
+/// スタンス別の判定が見込む値動きの向き（Some(true)=上昇、Some(false)=下落、None=中立）。
+/// 判定の色で決める：Buyer/Holder は買い側の色が上昇、Seller は「売る」側の色が下落
+fn stance_direction(stance: &Stance, score_ratio: f64) -> Option<bool> {
+    judgement_direction(stance, &stance_judgement(stance, score_ratio))
+}
 
-    // 結果構造体にまとめる
-    AnalysisResult {
-        indicator_name: "基本テクニカル分析".to_string(),
-        description: description_lines,
-        score,
+/// 判定の向きのログ表記（up / down / neutral）
+fn direction_key(direction: Option<bool>) -> &'static str {
+    match direction {
+        Some(true) => "up",
+        Some(false) => "down",
+        None => "neutral",
     }
 }
-/// AnalysisResultを受け取り、内容を画面に出力する関数
-fn display_analysis_result(result: &AnalysisResult) {
-    for line in &result.description {
-        println!("{}", line);
+
+/// ログの向き表記を読む（不明な値は None）
+fn parse_direction_key(value: &str) -> Option<Option<bool>> {
+    match value {
+        "up" => Some(Some(true)),
+        "down" => Some(Some(false)),
+        "neutral" => Some(None),
+        _ => None,
     }
-    println!(); // 改行
 }
 
-/// スコア分類
-fn classify_score(score_ratio: f64) -> &'static str {
-    match score_ratio {
-        r if r >= 0.8 => "🟢 強い買い",
-        r if r >= 0.6 => "🟢 買い優勢",
-        r if r >= 0.4 => "🟢 買い傾向あり",
-        r if r >= 0.2 => "🟡 やや買い寄り",
-        r if r >= 0.0 => "🟡 買い気配",
-        r if r >= -0.2 => "⚪️ 様子見（中立）",
-        r if r >= -0.4 => "🟠 売り気配",
-        r if r >= -0.6 => "🟠 やや売り寄り",
-        r if r >= -0.8 => "🔴 売り傾向あり",
-        _ => "🔴 強い売り",
+/// 判定文言（平滑化・ヒステリシス後のものを含む）が見込む値動きの向き
+fn judgement_direction(stance: &Stance, judgement: &str) -> Option<bool> {
+    let marked = |marks: &[&str]| marks.iter().any(|m| judgement.starts_with(m));
+    let (bullish, bearish) = match stance {
+        Stance::Buyer => (marked(&["🟢", "🟡"]), marked(&["🟠", "🔴"])),
+        Stance::Holder => (marked(&["🟢"]), marked(&["🟠", "🔴"])),
+        Stance::Seller => (marked(&["🟠", "🔴"]), marked(&["🟢", "🟡"])),
+    };
+    if bullish {
+        Some(true)
+    } else if bearish {
+        Some(false)
+    } else {
+        None
     }
 }
 
-/// 総合スコア行（スタンス別）。ゲージ有無は引数で切替
-/// 端末/LLM共通：総合スコアの表示行を生成（スタンス別、ゲージ有無切替）
-/// ★ 0除算ガードはこの関数内だけで完結
-fn compose_final_score_lines_stance(
-    snap: &FinalScoreSnapshot,
-    stance: &Stance,
-    include_gauge: bool,
-) -> Vec<String> {
+/// 系列を買い持ちのみで売買シミュレーションする。シグナルは足の終値で判定し、翌足の始値で約定（先読みなし）。
+/// 資産は 1.0 から始め、手数料・スリッページは片道ごとに差し引く
+fn simulate_backtest(config: &Config, bars: &[BacktestBar]) -> BacktestReport {
+    let fee = config.bt_fee_pct / 100.0;
+    let slippage = config.bt_slippage_pct / 100.0;
+    // 平滑化を指定していれば平滑化後のスコア比率で判定する（EMA は過去の足のみを使う）
+    let ratios: Vec<f64> = smooth_ratios(
+        &bars.iter().map(|b| b.score_ratio).collect::<Vec<f64>>(),
+        config.smoothing_bars,
+    );
+
+    let mut cash = 1.0;
+    let mut units = 0.0;
+    let mut open_trade: Option<(usize, f64, f64)> = None; // (エントリーの足, 約定値, 投入資金)
+    let mut pending: Option<bool> = None;
+    let mut peak = 1.0_f64;
+    let mut max_drawdown_pct = 0.0_f64;
+    let mut bars_in_position = 0;
+    let mut trades = Vec::new();
+    let mut curve = Vec::with_capacity(bars.len());
+
+    for (i, bar) in bars.iter().enumerate() {
+        // 前の足のシグナルを当足の始値で約定
+        let mut action = "";
+        match (pending.take(), open_trade) {
+            (Some(true), None) => {
+                let price = bar.open * (1.0 + slippage);
+                units = cash * (1.0 - fee) / price;
+                open_trade = Some((i, price, cash));
+                cash = 0.0;
+                action = "buy";
+            }
+            (Some(false), Some((entry_index, entry_price, invested))) => {
+                let price = bar.open * (1.0 - slippage);
+                cash = units * price * (1.0 - fee);
+                units = 0.0;
+                trades.push(BacktestTrade {
+                    entry_date: bars[entry_index].date.clone(),
+                    entry_price,
+                    exit_date: bar.date.clone(),
+                    exit_price: price,
+                    return_pct: (cash / invested - 1.0) * 100.0,
+                    bars_held: i - entry_index,
+                    open_at_end: false,
+                });
+                open_trade = None;
+                action = "sell";
+            }
+            _ => {}
+        }
+
+        let equity = cash + units * bar.close;
+        peak = peak.max(equity);
+        let drawdown_pct = (equity / peak - 1.0) * 100.0;
+        max_drawdown_pct = max_drawdown_pct.min(drawdown_pct);
+        if open_trade.is_some() {
+            bars_in_position += 1;
+        }
+
+        // 当足の終値でシグナル判定（約定は次の足）
+        pending = match backtest_signal(config, ratios[i]) {
+            Some(true) if open_trade.is_none() => Some(true),
+            Some(false) if open_trade.is_some() => Some(false),
+            _ => None,
+        };
+        curve.push(EquityPoint {
+            date: bar.date.clone(),
+            close: bar.close,
+            score_ratio: ratios[i],
+            action,
+            in_position: open_trade.is_some(),
+            equity,
+            drawdown_pct,
+        });
+    }
+
+    // 期末に保有中のトレードは最終終値で手仕舞った場合の損益を参考として記録する
+    if let (Some((entry_index, entry_price, invested)), Some(last)) = (open_trade, bars.last()) {
+        let price = last.close * (1.0 - slippage);
+        trades.push(BacktestTrade {
+            entry_date: bars[entry_index].date.clone(),
+            entry_price,
+            exit_date: last.date.clone(),
+            exit_price: price,
+            return_pct: (units * price * (1.0 - fee) / invested - 1.0) * 100.0,
+            bars_held: bars.len() - 1 - entry_index,
+            open_at_end: true,
+        });
+    }
+
+    let final_equity = curve.last().map_or(1.0, |p| p.equity);
+    let years = match (bars.first(), bars.last()) {
+        (Some(first), Some(last)) => match (parse_log_date(&first.date), parse_log_date(&last.date)) {
+            (Some(a), Some(b)) => (b - a).num_days() as f64 / 365.25,
+            _ => 0.0,
+        },
+        _ => 0.0,
+    };
+    let buy_hold_return_pct = match (bars.first(), bars.last()) {
+        (Some(first), Some(last)) if first.close > 0.0 => (last.close / first.close - 1.0) * 100.0,
+        _ => 0.0,
+    };
+
+    BacktestReport {
+        trades,
+        curve,
+        total_return_pct: (final_equity - 1.0) * 100.0,
+        cagr_pct: (years > 0.0 && final_equity > 0.0)
+            .then(|| (final_equity.powf(1.0 / years) - 1.0) * 100.0),
+        max_drawdown_pct,
+        buy_hold_return_pct,
+        exposure_pct: if bars.is_empty() {
+            0.0
+        } else {
+            bars_in_position as f64 / bars.len() as f64 * 100.0
+        },
+    }
+}
+
+// 端末に一覧表示する直近トレード数
+const BACKTEST_RECENT_TRADES: usize = 10;
+
+/// バックテスト結果の表示行
+fn compose_backtest_lines(config: &Config, report: &BacktestReport, skipped: usize) -> Vec<String> {
     let mut lines = Vec::new();
+    let (Some(first), Some(last)) = (report.curve.first(), report.curve.last()) else {
+        return lines;
+    };
+    lines.push(format!(
+        "📈 【バックテスト】{} {}〜{}（評価 {}本／先頭 {}本は指標の準備期間）",
+        config.ticker,
+        first.date,
+        last.date,
+        report.curve.len(),
+        skipped
+    ));
+    let rule = match config.bt_rule {
+        BacktestRule::Threshold => format!(
+            "{}（買い ≥ {:+.2} ／ 手仕舞い ≤ {:+.2}）",
+            config.bt_rule, config.bt_entry, config.bt_exit
+        ),
+        BacktestRule::Stance => format!("{}（スタンス：{}）", config.bt_rule, stance_caption(&config.stance)),
+    };
+    lines.push(format!(
+        "ルール: {}  手数料 {:.2}%・スリッページ {:.2}%（片道）",
+        rule, config.bt_fee_pct, config.bt_slippage_pct
+    ));
+    if config.smoothing_bars > 1 {
+        lines.push(format!("平滑化: スコア比率の EMA{} で判定", config.smoothing_bars));
+    }
+    lines.push("約定: シグナルが出た足の翌足始値（先読みなし）".to_string());
 
-    // 1) 総合スコア見出し（例: -12.0/-22.0-22.0）
+    let closed: Vec<&BacktestTrade> = report.trades.iter().filter(|t| !t.open_at_end).collect();
+    let wins = closed.iter().filter(|t| t.return_pct > 0.0).count();
+    if closed.is_empty() {
+        lines.push("トレード数: 0（期間内に手仕舞いまで完了したトレードなし）".to_string());
+    } else {
+        let avg_return = closed.iter().map(|t| t.return_pct).sum::<f64>() / closed.len() as f64;
+        let avg_bars = closed.iter().map(|t| t.bars_held).sum::<usize>() as f64 / closed.len() as f64;
+        lines.push(format!(
+            "トレード数: {}（勝ち {} ／ 負け {}、勝率 {:.1}%）  平均損益 {:+.2}%／回  平均保有 {:.1}本",
+            closed.len(),
+            wins,
+            closed.len() - wins,
+            wins as f64 / closed.len() as f64 * 100.0,
+            avg_return,
+            avg_bars
+        ));
+    }
     lines.push(format!(
-        "🧮 総合スコア: {s:.1} ({w:.1}〜-{w:.1})の範囲",
-        s = snap.total_score,
-        w = if snap.total_weight.is_finite() {
-            snap.total_weight.abs()
-        } else {
-            0.0
-        }
+        "総リターン: {:+.2}%  CAGR: {}  最大ドローダウン: {:.2}%",
+        report.total_return_pct,
+        report.cagr_pct.map(|v| format!("{:+.2}%", v)).unwrap_or("-".into()),
+        report.max_drawdown_pct
     ));
     lines.push(format!(
-        "トータルスコア（スタンス：{}）",
-        stance_caption(stance)
+        "比較（バイ&ホールド）: {:+.2}%  保有率: {:.0}%",
+        report.buy_hold_return_pct, report.exposure_pct
     ));
 
-    // ★ ここでのみ 0除算を防ぐ（UI層に限定）
-    let weight_abs = if snap.total_weight.is_finite() {
-        snap.total_weight.abs()
+    if !report.trades.is_empty() {
+        lines.push(format!(
+            "🧾 直近のトレード（最大{}件）",
+            BACKTEST_RECENT_TRADES
+        ));
+        let start = report.trades.len().saturating_sub(BACKTEST_RECENT_TRADES);
+        for trade in &report.trades[start..] {
+            lines.push(format!(
+                "  {} {:>10.2} → {} {:>10.2}  {:>+7.2}%  {:>3}本{}",
+                trade.entry_date,
+                trade.entry_price,
+                trade.exit_date,
+                trade.exit_price,
+                trade.return_pct,
+                trade.bars_held,
+                if trade.open_at_end { "（保有中・期末終値で評価）" } else { "" }
+            ));
+        }
+    }
+    lines.push(String::new());
+    lines
+}
+
+/// 資産曲線を CSV で出力する（<log_dir>/<ticker>/<ticker>_backtest_equity.csv、--stdout-log 時は標準出力）
+fn write_backtest_equity(config: &Config, report: &BacktestReport) -> Result<(), Box<dyn std::error::Error>> {
+    let mut lines: Vec<String> = Vec::with_capacity(report.curve.len() + 1);
+    lines.push("date,close,score_ratio,action,position,equity,drawdown_%".to_string());
+    for point in &report.curve {
+        lines.push(format!(
+            "{},{:.2},{:.4},{},{},{:.6},{:.2}",
+            point.date,
+            point.close,
+            point.score_ratio,
+            point.action,
+            u8::from(point.in_position),
+            point.equity,
+            point.drawdown_pct
+        ));
+    }
+
+    if config.stdout_log {
+        print_lines_to_terminal(&lines);
+        return Ok(());
+    }
+
+    let base_dir = Path::new(&config.log_dir);
+    let dir_path = if config.log_flat {
+        base_dir.to_path_buf()
     } else {
-        0.0
+        base_dir.join(&config.ticker)
     };
-    let has_weight = weight_abs > f64::EPSILON;
+    create_dir_all(&dir_path)?;
+    let file_path = dir_path.join(format!("{}_backtest_equity.csv", config.ticker));
+    let mut writer = BufWriter::new(std::fs::File::create(&file_path)?);
+    for line in &lines {
+        writeln!(writer, "{}", line)?;
+    }
+    if !config.silent {
+        println!("💾 資産曲線: {}（{}本）", file_path.display(), report.curve.len());
+    }
+    Ok(())
+}
 
-    match stance {
-        // Buyer / Seller：単極ゲージ（右→左に塗る）。％は 0..=100、丸め＝四捨五入で統一。
-        Stance::Buyer | Stance::Seller => {
-            // 基準は Buyer％。Seller％は 100 - Buyer％ で相補（表示・判定とも統一）
-            let buyer_percent: u8 = if !has_weight {
-                50
-            } else {
-                let p = ((weight_abs + snap.total_score).clamp(0.0, 2.0 * weight_abs)
-                    / (2.0 * weight_abs))
-                    * 100.0;
-                p.round().clamp(0.0, 100.0) as u8
-            };
-            let seller_percent: u8 = 100u8.saturating_sub(buyer_percent);
+/// バックテストモード：足ごとに再評価して売買をシミュレーションし、結果と資産曲線を出力する
+fn run_backtest(
+    config: &Config,
+    data: &[MarketData],
+    benchmark_data: Option<&[MarketData]>,
+    ticker_name_map: &HashMap<String, String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (bars, skipped) = collect_backtest_bars(config, data, benchmark_data, ticker_name_map)?;
+    if bars.len() < 2 {
+        return Err(format!(
+            "❌ バックテストできる足がありません（取得 {}本、うち準備期間 {}本）。--bt-range を長くしてください",
+            data.len(),
+            skipped
+        )
+        .into());
+    }
+    let report = simulate_backtest(config, &bars);
+    if !config.silent {
+        print_lines_to_terminal(&compose_backtest_lines(config, &report, skipped));
+    }
+    write_backtest_equity(config, &report)
+}
 
-            // 5段階のラベル＋色（🟢🟡⚪️🟠🔴）をスタンス別に割当
-            let (percent, mark, action_text) = match *stance {
-                Stance::Buyer => {
-                    let p = buyer_percent;
-                    let (m, t) = if p >= 90 {
-                        ("🟢", "積極的に買う")
-                    } else if p >= 61 {
-                        ("🟡", "買う")
-                    }
-                    // 61–89 を「買う」
-                    else if (40..=60).contains(&p) {
-                        ("⚪️", "中立")
-                    }
-                    // 40–60 を厳密に中立
-                    else if p >= 20 {
-                        ("🟠", "買いを推奨しない")
-                    } else {
-                        ("🔴", "買わない")
-                    };
-                    (p, m, t)
-                }
-                Stance::Seller => {
-                    let p = seller_percent;
-                    let (m, t) = if p >= 90 {
-                        ("🟢", "積極的に売る")
-                    } else if p >= 61 {
-                        ("🟡", "売る")
-                    }
-                    // 61–89 を「売る」
-                    else if (40..=60).contains(&p) {
-                        ("⚪️", "中立")
-                    }
-                    // 40–60 を厳密に中立
-                    else if p >= 20 {
-                        ("🟠", "売りを推奨しない")
-                    } else {
-                        ("🔴", "売らない")
-                    };
-                    (p, m, t)
-                }
-                _ => unreachable!("Holder 分岐は外側で処理済み"),
-            };
-            // 判定行（％はスタンスごとの percent を表示）
-            lines.push(format!(
-                "→ 判定: {mark} {action} {pct}%",
-                action = action_text,
-                pct = percent
-            ));
+// 最適化の探索範囲（最小, 最大, 刻み）。重みは Weight の許容範囲と同じ
+const OPT_WEIGHT_RANGE: (f64, f64, f64) = (0.5, 3.0, 0.1);
+const OPT_BUY_RSI_RANGE: (f64, f64, f64) = (20.0, 40.0, 1.0);
+const OPT_SELL_RSI_RANGE: (f64, f64, f64) = (60.0, 80.0, 1.0);
+const OPT_BB_SQUEEZE_RANGE: (f64, f64, f64) = (4.0, 12.0, 0.5);
+// グリッドサーチの代表値
+const OPT_GRID_WEIGHTS: [f64; 3] = [0.5, 1.5, 3.0];
+const OPT_GRID_BUY_RSI: [f64; 3] = [25.0, 30.0, 35.0];
+const OPT_GRID_SELL_RSI: [f64; 3] = [65.0, 70.0, 75.0];
+const OPT_GRID_BB_SQUEEZE: [f64; 3] = [6.0, 8.0, 10.0];
+// グリッドの組み合わせ数の上限（超える場合はランダムサーチを案内）
+const OPT_GRID_MAX: usize = 3000;
+// ウォークフォワードの1区間に必要な最小本数
+const OPT_MIN_SEGMENT_BARS: usize = 20;
+
+/// 最適化で探索する1組のパラメータ
+#[derive(Debug, Clone)]
+struct OptCandidate {
+    weights: Vec<(&'static str, f64)>,     // "basic" または拡張指標名 → 重み（有効な指標のみ）
+    buy_rsi: f64,
+    sell_rsi: f64,
+    bb_bandwidth_squeeze_pct: Option<f64>, // ボリンジャー有効時のみ探索
+}
 
-            if include_gauge {
-                // グラフの横に「100％/0％」を付与（仕様固定文言）
-                let gauge = match stance {
-                    Stance::Buyer => {
-                        render_unipolar_gauge_rtl(percent, "買い 100％", "0％ 買わない", 25)
-                    }
-                    Stance::Seller => {
-                        render_unipolar_gauge_rtl(percent, "売り 100％", "0％ 売らない", 25)
-                    }
-                    _ => String::new(),
-                };
+/// 最適化用に一度だけ評価した足の材料（候補ごとに重み・閾値を差し替えて採点し直す）
+#[derive(Debug, Clone)]
+struct OptBar {
+    rsi: f64,
+    macd: f64,
+    signal: f64,
+    close: f64,
+    regime_inputs: Option<(Option<f64>, Option<f64>, Option<f64>)>, // --regime-weighting 時の ADX / 帯幅% / 傾き%
+    scores: Vec<(usize, Option<f64>)>, // 評価した拡張指標（enabled_extensions の添字）とスコア
+}
 
-                if !gauge.is_empty() {
-                    // 判定の“色ボール”とゲージ色を一致させる（'█' のみ着色）
-                    // ※ 既存の colored::* を使用（新規関数なし／副作用なし）
-                    let colored_g = match mark {
-                        "🟢" => gauge.replace("█", &"█".green().to_string()),
-                        "🟡" => gauge.replace("█", &"█".yellow().to_string()),
-                        "⚪️" => gauge.replace("█", &"█".white().to_string()),
-                        "🟠" => gauge.replace("█", &"█".truecolor(255, 165, 0).to_string()),
-                        "🔴" => gauge.replace("█", &"█".red().to_string()),
-                        _ => gauge,
-                    };
-                    lines.push(colored_g);
-                }
-            }
-        }
+/// 候補の採点で使い回す、閾値ごとの基本スコアと相場局面
+#[derive(Default)]
+struct OptScoreCache {
+    signal_scores: HashMap<(u64, u64), Vec<f64>>,     // (buy_rsi, sell_rsi) → 足ごとの基本スコア
+    regime_kinds: HashMap<u64, Vec<RegimeKind>>, // bb_bandwidth_squeeze_pct → 足ごとの局面
+}
 
-        // Holder：両極ゲージ。符号付き％は score_ratio*100（重みゼロ時は0固定）
-        // Holder：判定の色（🟢🟡⚪️🟠🔴）とバー色を完全一致させる。
-        // 0除算時は score_ratio=0% 固定（中央'|'のみ、無塗り）。
-        Stance::Holder => {
-            let holder_pct: i32 = if has_weight {
-                (snap.score_ratio * 100.0).round() as i32
-            } else {
-                0
-            };
-            // 判定テキスト（色付き絵文字＋語彙）は仕様の分類関数を使用
-            let action_text = classify_score(snap.score_ratio);
-            // 表記は「→ 判定: <action>  スコア比率 ±X%」のまま（スペース2つを保持）
-            lines.push(format!(
-                "→ 判定: {action} スコア比率 {:+}%",
-                holder_pct,
-                action = action_text
-            ));
+/// ウォークフォワードの1区間（学習期間で最良の候補を直後の検証期間で評価）
+#[derive(Debug, Clone)]
+struct OptFold {
+    train: std::ops::Range<usize>,
+    test: std::ops::Range<usize>,
+    winner: usize,      // 学習期間で最良だった候補の添字（0 は現在の設定）
+    train_score: f64,   // 勝者の学習期間の評価値
+    winner_test: BacktestReport,   // 勝者の検証期間の成績
+    baseline_test: BacktestReport, // 現在の設定の検証期間の成績
+}
 
-            if include_gauge {
-                let g = render_bipolar_gauge_lr(snap.score_ratio, 51);
-                lines.push(g);
-            }
-        }
+/// 検証期間をつないだ通算成績（アウトオブサンプル）
+#[derive(Debug, Clone, Copy)]
+struct OosSummary {
+    total_return_pct: f64,
+    max_drawdown_pct: f64,
+    trades: usize, // 検証期間の終わりに保有中だったトレードも含む
+    wins: usize,
+}
+
+/// 再現可能な乱数（SplitMix64）。同じシードなら同じ候補を生成する
+struct OptRng(u64);
+
+impl OptRng {
+    fn next_f64(&mut self) -> f64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
     }
 
-    lines.push(String::new());
-    lines
+    /// 範囲内から刻みに乗る値を一様に選ぶ
+    fn pick(&mut self, (min, max, step): (f64, f64, f64)) -> f64 {
+        let steps = ((max - min) / step).round() as usize;
+        let index = ((self.next_f64() * (steps + 1) as f64) as usize).min(steps);
+        ((min + index as f64 * step) * 100.0).round() / 100.0
+    }
 }
 
-/// EMA の表示（文言ロジックだけ修正：同値圏なら DC/GC を出さない）
-fn render_ema(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
-    let weight = config.weight_ema;
-    let mut description_lines: Vec<String> = Vec::new();
+/// 重みのキー（"basic" または拡張指標名）に対応する Config のフィールド
+fn weight_field_mut<'a>(config: &'a mut Config, key: &str) -> Option<&'a mut f64> {
+    match key {
+        "basic" => Some(&mut config.weight_basic),
+        "ema" => Some(&mut config.weight_ema),
+        "sma" => Some(&mut config.weight_sma),
+        "bollinger" => Some(&mut config.weight_bollinger),
+        "roc" => Some(&mut config.weight_roc),
+        "adx" => Some(&mut config.weight_adx),
+        "stochastics" => Some(&mut config.weight_stochastics),
+        "fibonacci" => Some(&mut config.weight_fibonacci),
+        "vwap" => Some(&mut config.weight_vwap),
+        "ichimoku" => Some(&mut config.weight_ichimoku),
+        "candlestick" => Some(&mut config.weight_candlestick),
+        "support_resistance" => Some(&mut config.weight_support_resistance),
+        "relative_strength" => Some(&mut config.weight_relative_strength),
+        _ => None,
+    }
+}
 
-    description_lines.push("📊 【EMA（指数平滑移動平均）】".to_string());
+/// 探索対象の重みのキー（基本スコアと有効な組み込み拡張指標。カスタム指標・プラグインは対象外）
+fn optimizable_weight_keys(config: &Config) -> Vec<&'static str> {
+    std::iter::once("basic")
+        .chain(config.enabled_extensions.iter().filter_map(|ext| ext.key()))
+        .collect()
+}
 
-    let short = guard.get_ema_short();
-    let long = guard.get_ema_long();
-    description_lines.push(format!("短期EMA: {:.2} / 長期EMA: {:.2}", short, long));
+/// 候補のパラメータを適用した設定
+fn apply_opt_candidate(config: &Config, candidate: &OptCandidate) -> Config {
+    let mut cfg = config.clone();
+    for (key, value) in &candidate.weights {
+        if let Some(field) = weight_field_mut(&mut cfg, key) {
+            *field = *value;
+        }
+    }
+    cfg.buy_rsi = candidate.buy_rsi;
+    cfg.sell_rsi = candidate.sell_rsi;
+    if let Some(v) = candidate.bb_bandwidth_squeeze_pct {
+        cfg.bb_bandwidth_squeeze_pct = v;
+    }
+    cfg
+}
 
-    let diff = short - long;
-    let status = match diff {
-        d if d > EMA_EQ_EPS => "🟢 ゴールデンクロス進行中（短期EMAが長期EMAを上回る）",
-        d if d < -EMA_EQ_EPS => "📉 デッドクロス進行中（短期EMAが長期EMAを下回る）",
-        _ => "➡️ EMAが同値圏（差が±0.01未満）→ スコア変動なし",
+/// 探索候補の一覧（先頭は比較用の現在の設定）
+fn generate_opt_candidates(config: &Config) -> Result<Vec<OptCandidate>, Box<dyn std::error::Error>> {
+    let keys = optimizable_weight_keys(config);
+    let use_bb = config.enabled_extensions.contains(&ExtensionIndicator::Bollinger);
+    let mut current = config.clone();
+    let baseline = OptCandidate {
+        weights: keys
+            .iter()
+            .map(|key| (*key, weight_field_mut(&mut current, key).map_or(1.0, |v| *v)))
+            .collect(),
+        buy_rsi: config.buy_rsi,
+        sell_rsi: config.sell_rsi,
+        bb_bandwidth_squeeze_pct: use_bb.then_some(config.bb_bandwidth_squeeze_pct),
     };
-    description_lines.push(status.to_string());
+    let mut candidates = vec![baseline];
+
+    match config.opt_method {
+        OptMethod::Random => {
+            let mut rng = OptRng(config.opt_seed);
+            for _ in 0..config.opt_trials {
+                candidates.push(OptCandidate {
+                    weights: keys.iter().map(|key| (*key, rng.pick(OPT_WEIGHT_RANGE))).collect(),
+                    buy_rsi: rng.pick(OPT_BUY_RSI_RANGE),
+                    sell_rsi: rng.pick(OPT_SELL_RSI_RANGE),
+                    bb_bandwidth_squeeze_pct: use_bb.then(|| rng.pick(OPT_BB_SQUEEZE_RANGE)),
+                });
+            }
+        }
+        OptMethod::Grid => {
+            let dimensions = keys.len() + 2 + usize::from(use_bb);
+            let total = 3usize.saturating_pow(dimensions as u32);
+            if total > OPT_GRID_MAX {
+                return Err(format!(
+                    "❌ グリッドの組み合わせが多すぎます（{}通り、上限 {}）。--opt-method random を使うか、有効な指標を減らしてください",
+                    total, OPT_GRID_MAX
+                )
+                .into());
+            }
+            // 各次元の代表値の全組み合わせ（添字を3進数として数える）
+            for n in 0..total {
+                let mut digits = (0..dimensions).scan(n, |rest, _| {
+                    let digit = *rest % 3;
+                    *rest /= 3;
+                    Some(digit)
+                });
+                let mut next = || digits.next().unwrap_or(1);
+                candidates.push(OptCandidate {
+                    weights: keys.iter().map(|key| (*key, OPT_GRID_WEIGHTS[next()])).collect(),
+                    buy_rsi: OPT_GRID_BUY_RSI[next()],
+                    sell_rsi: OPT_GRID_SELL_RSI[next()],
+                    bb_bandwidth_squeeze_pct: use_bb.then(|| OPT_GRID_BB_SQUEEZE[next()]),
+                });
+            }
+        }
+    }
+    Ok(candidates)
+}
 
-    // ★ スコアはSoT原則で Guard に格納済みの値のみを参照（再計算しない）
-    let base_score = guard.get_ema_score().unwrap_or(0.0).round() as i32;
-    let adjusted = base_score as f64 * weight;
+/// 学習期間の成績を比べる評価値（大きいほど良い）
+fn opt_objective(config: &Config, report: &BacktestReport) -> f64 {
+    match config.opt_metric {
+        OptMetric::Return => report.total_return_pct,
+        // ドローダウンが極端に小さい区間で値が跳ねないよう、分母は 1% を下限にする
+        OptMetric::ReturnDrawdown => report.total_return_pct / report.max_drawdown_pct.abs().max(1.0),
+    }
+}
 
-    description_lines.push(format!(
-        "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
-        adjusted, base_score, weight
-    ));
+/// ウォークフォワードの区間（学習・検証）。全体を folds+1 等分し、k 番目で学習して k+1 番目で検証する
+fn walk_forward_windows(
+    len: usize,
+    folds: usize,
+) -> Vec<(std::ops::Range<usize>, std::ops::Range<usize>)> {
+    let segment = len / (folds + 1);
+    (0..folds)
+        .map(|k| {
+            let test_end = if k + 1 == folds { len } else { (k + 2) * segment };
+            (k * segment..(k + 1) * segment, (k + 1) * segment..test_end)
+        })
+        .collect()
+}
 
-    AnalysisResult {
-        indicator_name: "EMA".to_string(),
-        description: description_lines,
-        score: base_score as f64,
+/// 基準の系列の日付・価格に、候補ごとのスコア比率を差し込む
+fn bars_with_ratios(base: &[BacktestBar], ratios: &[f64]) -> Vec<BacktestBar> {
+    base.iter()
+        .zip(ratios)
+        .map(|(bar, ratio)| BacktestBar { score_ratio: *ratio, ..bar.clone() })
+        .collect()
+}
+
+/// 検証期間の資産曲線をつないだ通算成績
+fn summarize_oos<'a>(reports: impl Iterator<Item = &'a BacktestReport>) -> OosSummary {
+    let mut summary = OosSummary { total_return_pct: 0.0, max_drawdown_pct: 0.0, trades: 0, wins: 0 };
+    let mut scale = 1.0;
+    let mut peak = 1.0_f64;
+    for report in reports {
+        for point in &report.curve {
+            let equity = scale * point.equity;
+            peak = peak.max(equity);
+            summary.max_drawdown_pct = summary.max_drawdown_pct.min((equity / peak - 1.0) * 100.0);
+        }
+        scale *= report.curve.last().map_or(1.0, |p| p.equity);
+        for trade in &report.trades {
+            summary.trades += 1;
+            summary.wins += usize::from(trade.return_pct > 0.0);
+        }
     }
+    summary.total_return_pct = (scale - 1.0) * 100.0;
+    summary
 }
 
-/// SMA（単純移動平均）の表示（セキュアアクセス：TechnicalDataGuard経由）
-fn render_sma(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
-    let short = guard.get_sma_short();
-    let long = guard.get_sma_long();
-    let weight = config.weight_sma;
+/// 候補のパラメータを1行で表す
+fn describe_opt_candidate(candidate: &OptCandidate) -> String {
+    let mut parts: Vec<String> =
+        candidate.weights.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+    parts.push(format!("buy_rsi={}", candidate.buy_rsi));
+    parts.push(format!("sell_rsi={}", candidate.sell_rsi));
+    if let Some(v) = candidate.bb_bandwidth_squeeze_pct {
+        parts.push(format!("bb_squeeze={}", v));
+    }
+    parts.join(" ")
+}
 
-    let mut description_lines: Vec<String> = Vec::new();
-    description_lines.push("📊 【SMA（単純移動平均）】".to_string());
-    description_lines.push(format!("短期SMA: {:.2} / 長期SMA: {:.2}", short, long));
+/// 最適化結果の表示行
+fn compose_optimization_lines(
+    config: &Config,
+    base: &[BacktestBar],
+    warmup: usize,
+    candidates: &[OptCandidate],
+    folds: &[OptFold],
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    let (Some(first), Some(last), Some(final_fold)) = (base.first(), base.last(), folds.last()) else {
+        return lines;
+    };
+    let period = |range: &std::ops::Range<usize>| {
+        format!("{}〜{}", base[range.start].date, base[range.end - 1].date)
+    };
+    let oos_line = |label: &str, s: &OosSummary| {
+        format!(
+            "  {}: 総リターン {:+.2}%  最大DD {:.2}%  トレード {}（勝率 {}）",
+            label,
+            s.total_return_pct,
+            s.max_drawdown_pct,
+            s.trades,
+            if s.trades == 0 { "-".to_string() } else { format!("{:.1}%", s.wins as f64 / s.trades as f64 * 100.0) }
+        )
+    };
+
+    lines.push(format!(
+        "🧮 【最適化】{} {}〜{}（{}本／先頭 {}本は指標の準備期間で対象外、ウォークフォワード {}区間）",
+        config.ticker,
+        first.date,
+        last.date,
+        base.len(),
+        warmup,
+        folds.len()
+    ));
+    lines.push(format!(
+        "探索: {} {}通り{}  学習期間の評価: {}",
+        config.opt_method,
+        candidates.len() - 1,
+        if config.opt_method == OptMethod::Random { format!("（seed {}）", config.opt_seed) } else { String::new() },
+        config.opt_metric
+    ));
+    lines.push(format!(
+        "売買ルール: {}  手数料 {:.2}%・スリッページ {:.2}%（--bt-* の設定）",
+        config.bt_rule, config.bt_fee_pct, config.bt_slippage_pct
+    ));
+    lines.push("🔁 各区間：学習期間で最良の組を、直後の検証期間（未使用のデータ）で評価".to_string());
+    for (k, fold) in folds.iter().enumerate() {
+        lines.push(format!(
+            "  #{} 学習 {}（評価 {:+.2}）→ 検証 {}: 最適 {:+.2}%（DD {:.2}%）／現在の設定 {:+.2}%",
+            k + 1,
+            period(&fold.train),
+            fold.train_score,
+            period(&fold.test),
+            fold.winner_test.total_return_pct,
+            fold.winner_test.max_drawdown_pct,
+            fold.baseline_test.total_return_pct
+        ));
+    }
+
+    let optimized = summarize_oos(folds.iter().map(|f| &f.winner_test));
+    let baseline = summarize_oos(folds.iter().map(|f| &f.baseline_test));
+    let oos_start = &base[folds[0].test.start];
+    let buy_hold = (last.close / oos_start.close - 1.0) * 100.0;
+    lines.push(format!("📊 検証期間の通算（アウトオブサンプル {}〜{}）", oos_start.date, last.date));
+    lines.push(oos_line("最適化", &optimized));
+    lines.push(oos_line("現在の設定", &baseline));
+    lines.push(format!("  バイ&ホールド: 総リターン {:+.2}%", buy_hold));
+    if optimized.total_return_pct < baseline.total_return_pct {
+        lines.push("⚠️ 検証期間では現在の設定を下回りました（学習期間への過剰適合の可能性）".to_string());
+    }
 
-    // クロス判定出力
-    if short > long {
-        description_lines.push("📈 ゴールデンクロス発生中（短期SMAが長期SMAを上回る）".to_string());
-    } else if short < long {
-        description_lines.push("📉 デッドクロス発生中（短期SMAが長期SMAを下回る）".to_string());
+    let best = &candidates[final_fold.winner];
+    lines.push(format!(
+        "🏁 推奨パラメータ（直近の学習期間 {} で最良、検証期間 {} で {:+.2}%）",
+        period(&final_fold.train),
+        period(&final_fold.test),
+        final_fold.winner_test.total_return_pct
+    ));
+    lines.push(format!("  {}", describe_opt_candidate(best)));
+    if final_fold.winner == 0 {
+        lines.push("  （現在の設定を上回る組は見つかりませんでした）".to_string());
     } else {
-        description_lines.push("➖ SMAが一致：クロス傾向なし".to_string());
+        lines.push(format!("  現在: {}", describe_opt_candidate(&candidates[0])));
     }
+    lines.push(String::new());
+    lines
+}
 
-    // スコア取得と調整出力
-    match guard.get_sma_score().map(|v| v as i32) {
-        Some(base_score) => {
-            let adjusted_score = base_score as f64 * weight;
-            description_lines.push(rank_sma_score(Some(base_score)).to_string());
-            description_lines.push(format!(
-                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
-                adjusted_score, base_score, weight
-            ));
+/// 推奨パラメータを tickwise.env の断片またはプリセット（JSON配列）として組み立てる
+fn format_optimization_result(
+    config: &Config,
+    candidate: &OptCandidate,
+    trials: usize,
+    oos: &OosSummary,
+    final_test_return_pct: f64,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let summary = format!(
+        "検証期間の通算 {:+.2}%（最大DD {:.2}%、{}トレード）、直近の検証 {:+.2}%",
+        oos.total_return_pct, oos.max_drawdown_pct, oos.trades, final_test_return_pct
+    );
+    let extensions: Vec<&str> = config.enabled_extensions.iter().filter_map(|ext| ext.key()).collect();
 
-            AnalysisResult {
-                indicator_name: "SMA".to_string(),
-                description: description_lines,
-                score: base_score as f64,
-            }
+    if config.opt_output == "preset" {
+        // --presets-file にそのまま渡せる形（プリセット名は英数字・_・- のみ）
+        let name: String = format!("opt-{}", config.ticker)
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '-' })
+            .collect();
+        let weights: serde_json::Map<String, Value> =
+            candidate.weights.iter().map(|(key, value)| (key.to_string(), json!(value))).collect();
+        let mut preset = json!({
+            "name": name,
+            "description": format!("{} の最適化結果（{}）", config.ticker, summary),
+            "extensions": extensions,
+            "weights": weights,
+            "buy_rsi": candidate.buy_rsi,
+            "sell_rsi": candidate.sell_rsi,
+            "stance": stance_caption(&config.stance).to_lowercase(),
+        });
+        if let Some(v) = candidate.bb_bandwidth_squeeze_pct {
+            preset["bb_bandwidth_squeeze_pct"] = json!(v);
         }
-        None => {
-            description_lines.push("⚠️ SMAスコア情報なし".to_string());
-            AnalysisResult {
-                indicator_name: "SMA".to_string(),
-                description: description_lines,
-                score: 0.0,
-            }
+        if config.regime_weighting {
+            preset["regime_weighting"] = json!(true);
         }
+        let text = serde_json::to_string_pretty(&json!([preset]))?;
+        return Ok(text.lines().map(str::to_string).collect());
     }
-}
 
-fn rank_sma_score(sma_score: Option<i32>) -> &'static str {
-    match sma_score {
-        Some(2) => "🟢 短期SMAが長期より大幅に上 → 強い上昇トレンド → スコア+2加点",
-        Some(1) => "🟢 短期SMAが長期よりやや上 → 上昇トレンド → スコア+1加点",
-        Some(0) => "➡️ SMAが同値圏 → スコア変動なし",
-        Some(-1) => "🔴 短期SMAが長期よりやや下 → 下降トレンド → スコア-1減点",
-        Some(-2) => "🔴 短期SMAが長期より大幅に下 → 強い下降トレンド → スコア-2減点",
-        _ => "⚠️ SMAスコア情報なし",
+    let mut lines = vec![
+        format!(
+            "# tickwise 最適化結果: {}（{} {}通り、評価 {}）",
+            config.ticker, config.opt_method, trials, config.opt_metric
+        ),
+        format!("# {}", summary),
+    ];
+    lines.extend(extensions.iter().map(|key| format!("{}=true", key.to_uppercase())));
+    lines.extend(
+        candidate
+            .weights
+            .iter()
+            .map(|(key, value)| format!("WEIGHT_{}={}", key.to_uppercase(), value)),
+    );
+    lines.push(format!("BUY_RSI={}", candidate.buy_rsi));
+    lines.push(format!("SELL_RSI={}", candidate.sell_rsi));
+    if let Some(v) = candidate.bb_bandwidth_squeeze_pct {
+        lines.push(format!("BB_BANDWIDTH_SQUEEZE_PCT={}", v));
     }
+    Ok(lines)
 }
-/// ADXの表示（セキュアアクセス: TechnicalDataGuard経由）
-/// ADXスコアのランク評価（スコア → ラベル文字列）
-fn rank_adx_score(adx_score: Option<i32>) -> &'static str {
-    match adx_score {
-        Some(2) => "🟢 ADXが非常に強い（40以上）→ 強いトレンド継続 → スコア+2加点",
-        Some(1) => "🟢 ADXがやや強い（25以上40未満）→ トレンド発生 → スコア+1加点",
-        Some(0) => "➡️ ADXが中立（20以上25未満）→ 様子見",
-        Some(-1) => "🔴 ADXがやや弱い（15以上20未満）→ トレンド弱まる → スコア-1減点",
-        Some(-2) => "🔴 ADXが非常に弱い（15未満）→ トレンド消失 → スコア-2減点",
-        _ => "⚠️ ADXスコア不明",
+
+/// 評価済みの足から最適化用の材料を取り出す
+fn opt_bar(config: &Config, guard: &TechnicalDataGuard) -> OptBar {
+    OptBar {
+        rsi: guard.get_rsi(),
+        macd: guard.get_macd(),
+        signal: guard.get_signal(),
+        close: guard.get_close(),
+        regime_inputs: guard
+            .get_market_regime()
+            .map(|regime| (regime.adx, regime.bandwidth_pct, regime.slope_pct)),
+        scores: config
+            .enabled_extensions
+            .iter()
+            .enumerate()
+            .filter(|(_, ext)| !extension_skipped(config, guard, ext))
+            .map(|(index, ext)| (index, extension_score(config, guard, ext)))
+            .collect(),
     }
 }
-/// ADX（平均方向性指数）の表示（セキュアアクセス：TechnicalDataGuard経由）
-fn render_adx(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
-    let mut description_lines: Vec<String> = Vec::new();
-    description_lines.push("📊 【ADX（平均方向性指数）】".to_string());
 
-    match guard.get_adx() {
-        Some(adx) => {
-            description_lines.push(format!("現在のADX: {:.2}", adx));
+/// 候補の設定で足ごとのスコア比率を求め直す（指標は評価し直さず、重みを掛け直すだけ）。
+/// 基本スコアは RSI 閾値、相場局面はスクイーズ閾値の組ごとに一度だけ判定する
+fn opt_candidate_ratios(cfg: &Config, bars: &[OptBar], cache: &mut OptScoreCache) -> Vec<f64> {
+    let signal_scores = cache
+        .signal_scores
+        .entry((cfg.buy_rsi.to_bits(), cfg.sell_rsi.to_bits()))
+        .or_insert_with(|| {
+            bars.iter()
+                .map(|bar| basic_signal_score(cfg, bar.rsi, bar.macd, bar.signal, bar.close).0)
+                .collect()
+        });
+    let regime_kinds = cfg.regime_weighting.then(|| {
+        &*cache
+            .regime_kinds
+            .entry(cfg.bb_bandwidth_squeeze_pct.to_bits())
+            .or_insert_with(|| {
+                bars.iter()
+                    .map(|bar| {
+                        let (adx, bandwidth_pct, slope_pct) = bar.regime_inputs.unwrap_or_default();
+                        detect_market_regime(cfg, adx, bandwidth_pct, slope_pct)
+                    })
+                    .collect()
+            })
+    });
 
-            // 極端な値の警告
-            if adx >= 50.0 {
-                description_lines.push(format!(
-                    "⚠️ ADXが50以上（{:.2}）→ 非常に強いトレンド → 反転リスクに警戒",
-                    adx
-                ));
-            } else if adx <= 10.0 {
-                description_lines.push(format!(
-                    "⚠️ ADXが10以下（{:.2}）→ トレンド不在（レンジ相場） → 仕掛け注意",
-                    adx
-                ));
+    let weights: Vec<(f64, IndicatorCategory)> = cfg
+        .enabled_extensions
+        .iter()
+        .map(|ext| (extension_weight(cfg, ext), ext.category()))
+        .collect();
+    bars.iter()
+        .enumerate()
+        .map(|(i, bar)| {
+            let parts: Vec<(f64, Option<f64>)> = bar
+                .scores
+                .iter()
+                .map(|&(index, score)| {
+                    let (weight, category) = weights[index];
+                    match regime_kinds {
+                        Some(kinds) => (weight * kinds[i].weight_multiplier(category), score),
+                        None => (weight, score),
+                    }
+                })
+                .collect();
+            aggregate_scores(cfg.missing_score_policy, signal_scores[i], cfg.weight_basic, &parts).score_ratio
+        })
+        .collect()
+}
+
+/// 最適化モード：全期間を足ごとに一度だけ評価し、候補ごとに採点し直してウォークフォワードで学習・検証し、推奨パラメータを出力する
+fn run_optimization(
+    config: &Config,
+    data: &[MarketData],
+    benchmark_data: Option<&[MarketData]>,
+    ticker_name_map: &HashMap<String, String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let candidates = generate_opt_candidates(config)?;
+
+    // 指標は現在の設定で一度だけ評価する。重み・閾値は必要本数に影響しないので、区間も全候補で共通。
+    // 指標の準備期間の足は評価済みの系列に含めず、最初の学習期間は評価できた最初の足から始める
+    let (evaluated, warmup) = replay_bars(config, data, benchmark_data, ticker_name_map, opt_bar)?;
+    let (base, opt_bars): (Vec<BacktestBar>, Vec<OptBar>) = evaluated.into_iter().unzip();
+    if base.len() < OPT_MIN_SEGMENT_BARS * (config.opt_folds + 1) {
+        return Err(format!(
+            "❌ ウォークフォワードに必要な本数が足りません（評価できる足 {}本、{}区間には {}本以上）。--bt-range を長くするか --opt-folds を減らしてください",
+            base.len(),
+            config.opt_folds,
+            OPT_MIN_SEGMENT_BARS * (config.opt_folds + 1)
+        )
+        .into());
+    }
+    let windows = walk_forward_windows(base.len(), config.opt_folds);
+
+    // 候補ごとのスコア比率と、各区間の学習期間での評価値
+    let mut ratios: Vec<Vec<f64>> = Vec::with_capacity(candidates.len());
+    let mut train_scores: Vec<Vec<f64>> = Vec::with_capacity(candidates.len());
+    let mut cache = OptScoreCache::default();
+    let progress_step = (candidates.len() / 10).max(1);
+    for (i, candidate) in candidates.iter().enumerate() {
+        let cfg = apply_opt_candidate(config, candidate);
+        let bars = bars_with_ratios(&base, &opt_candidate_ratios(&cfg, &opt_bars, &mut cache));
+        train_scores.push(
+            windows
+                .iter()
+                .map(|(train, _)| opt_objective(&cfg, &simulate_backtest(&cfg, &bars[train.clone()])))
+                .collect(),
+        );
+        ratios.push(bars.iter().map(|b| b.score_ratio).collect());
+        if !config.silent && ((i + 1) % progress_step == 0 || i + 1 == candidates.len()) {
+            eprintln!("⏳ 最適化: {}/{} 通り評価済み", i + 1, candidates.len());
+        }
+    }
+
+    // 区間ごとに学習期間の最良候補を選び、検証期間で評価（同点は現在の設定・先の候補を優先）
+    let folds: Vec<OptFold> = windows
+        .iter()
+        .enumerate()
+        .map(|(k, (train, test))| {
+            let winner = (0..candidates.len()).fold(0, |best, i| {
+                if train_scores[i][k] > train_scores[best][k] { i } else { best }
+            });
+            let test_report = |index: usize| {
+                let cfg = apply_opt_candidate(config, &candidates[index]);
+                simulate_backtest(&cfg, &bars_with_ratios(&base[test.clone()], &ratios[index][test.clone()]))
+            };
+            OptFold {
+                train: train.clone(),
+                test: test.clone(),
+                winner,
+                train_score: train_scores[winner][k],
+                winner_test: test_report(winner),
+                baseline_test: test_report(0),
             }
+        })
+        .collect();
+
+    if !config.silent {
+        print_lines_to_terminal(&compose_optimization_lines(config, &base, warmup, &candidates, &folds));
+    }
+
+    let final_fold = &folds[folds.len() - 1];
+    let lines = format_optimization_result(
+        config,
+        &candidates[final_fold.winner],
+        candidates.len() - 1,
+        &summarize_oos(folds.iter().map(|f| &f.winner_test)),
+        final_fold.winner_test.total_return_pct,
+    )?;
+    if config.stdout_log {
+        print_lines_to_terminal(&lines);
+        return Ok(());
+    }
+
+    let base_dir = Path::new(&config.log_dir);
+    let dir_path = if config.log_flat {
+        base_dir.to_path_buf()
+    } else {
+        base_dir.join(&config.ticker)
+    };
+    create_dir_all(&dir_path)?;
+    let file_name = match config.opt_output.as_str() {
+        "preset" => format!("{}_optimized_preset.json", config.ticker),
+        _ => format!("{}_optimized.env", config.ticker),
+    };
+    let file_path = dir_path.join(file_name);
+    let mut writer = BufWriter::new(std::fs::File::create(&file_path)?);
+    for line in &lines {
+        writeln!(writer, "{}", line)?;
+    }
+    if !config.silent {
+        println!("💾 最適化結果: {}", file_path.display());
+    }
+    Ok(())
+}
+
+/// 集計系モードのCSVを書き出す。保存先は <log_dir>/<ticker>/<ticker>_<suffix>.csv、
+/// ウォッチリスト時は <log_dir>/<ファイル名>_<suffix>.csv（-t A,B,… なら watchlist_<suffix>.csv、--stdout-log 時は標準出力）
+fn write_research_csv(config: &Config, suffix: &str, lines: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if config.stdout_log {
+        print_lines_to_terminal(lines);
+        return Ok(());
+    }
+    let base_dir = Path::new(&config.log_dir);
+    let file_path = if !config.watchlist.is_empty() {
+        let stem = config
+            .watchlist_file
+            .as_deref()
+            .and_then(|path| Path::new(path).file_stem())
+            .and_then(|s| s.to_str())
+            .unwrap_or("watchlist");
+        base_dir.join(format!("{}_{}.csv", stem, suffix))
+    } else if config.log_flat {
+        base_dir.join(format!("{}_{}.csv", config.ticker, suffix))
+    } else {
+        base_dir
+            .join(&config.ticker)
+            .join(format!("{}_{}.csv", config.ticker, suffix))
+    };
+    if let Some(dir_path) = file_path.parent() {
+        create_dir_all(dir_path)?;
+    }
+    let mut writer = BufWriter::new(std::fs::File::create(&file_path)?);
+    for line in lines {
+        writeln!(writer, "{}", line)?;
+    }
+    if !config.silent {
+        println!("💾 集計結果: {}（{}行）", file_path.display(), lines.len().saturating_sub(1));
+    }
+    Ok(())
+}
 
-            match guard.get_adx_score().map(|v| v as i32) {
-                Some(base_score) => {
-                    let adjusted_score = base_score as f64 * config.weight_adx;
-                    description_lines.push(rank_adx_score(Some(base_score)).to_string());
-                    description_lines.push(format!(
-                        "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
-                        adjusted_score, base_score, config.weight_adx
-                    ));
-                    AnalysisResult {
-                        indicator_name: "ADX".to_string(),
-                        description: description_lines,
-                        score: base_score as f64,
-                    }
-                }
-                None => {
-                    description_lines.push("⚠️ ADXスコア情報なし".to_string());
-                    AnalysisResult {
-                        indicator_name: "ADX".to_string(),
-                        description: description_lines,
-                        score: 0.0,
-                    }
-                }
-            }
-        }
-        None => {
-            description_lines.push("⚠️ ADXデータなし".to_string());
-            AnalysisResult {
-                indicator_name: "ADX".to_string(),
-                description: description_lines,
-                score: 0.0,
-            }
-        }
+/// 見出し用のウォッチリスト名（ファイル名、-t A,B,… なら銘柄の並び）
+fn watchlist_label(config: &Config) -> String {
+    match &config.watchlist_file {
+        Some(path) => format!("ウォッチリスト {}", path),
+        None => format!("ウォッチリスト {}", config.watchlist.join(",")),
     }
 }
 
-/// ROC（変化率）の表示（セキュアアクセス：TechnicalDataGuard経由）
-fn rank_roc_score(roc_score: Option<i32>) -> &'static str {
-    match roc_score {
-        Some(2) => "🟢 ROCが大幅上昇 → 強い上昇トレンド → スコア+2加点",
-        Some(1) => "🟢 ROCが上昇傾向 → スコア+1加点",
-        Some(0) => "➡️ ROCが安定圏（±3%）→ スコア変動なし",
-        Some(-1) => "🔴 ROCがやや下降 → スコア-1減点",
-        Some(-2) => "🔴 ROCが大幅下降 → 強い下降トレンド → スコア-2減点",
-        _ => "⚠️ ROCスコア情報なし",
+/// ウォッチリストの銘柄用の設定（ベンチマークが自動選択なら銘柄ごとに選び直す）
+fn config_for_ticker(config: &Config, ticker: &str) -> Config {
+    let mut cfg = config.clone();
+    cfg.ticker = ticker.to_string();
+    if config.benchmark_auto {
+        cfg.benchmark = resolve_benchmark(ticker, None);
     }
+    cfg
 }
 
-fn render_roc(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
-    let mut description_lines: Vec<String> = Vec::new();
-    description_lines.push("📊  【ROC（変化率）】".to_string());
+// 先読みリターンを測る期間（足数）
+const HIT_RATE_HORIZONS: [usize; 3] = [1, 5, 20];
 
-    match guard.get_roc() {
-        Some(roc) => {
-            description_lines.push(format!("10日間のROC: {:.2}%", roc));
+/// 指標スコア1段階の先読みリターン集計
+#[derive(Debug, Clone, Copy, Default)]
+struct HitRateStats {
+    bars: usize,          // このスコアだった足の数
+    samples: [usize; 3],  // 先読みリターンを測れた足の数（期間ごと。末尾の足は先のデータがない）
+    return_sum: [f64; 3], // 先読みリターンの合計（%）
+    hits: [usize; 3],     // スコアの向きにリターンが動いた数（スコア0は上昇した数）
+}
 
-            // 極端なROCに対する警告
-            if roc >= 15.0 {
-                description_lines.push(format!(
-                    "⚠️ ROCが+15%以上（{:.2}%）→ 短期的な過熱上昇、反落に警戒",
-                    roc
-                ));
-            } else if roc <= -15.0 {
-                description_lines.push(format!(
-                    "⚠️ ROCが-15%以下（{:.2}%）→ パニック売りの可能性、反発に備えた注視を",
-                    roc
-                ));
-            }
+/// 指標ごとの集計（スコア -2..+2 の5段階）
+#[derive(Debug, Clone)]
+struct IndicatorHitRate {
+    indicator: String, // ログ上の指標名（基本スコアは "Basic"）
+    levels: [HitRateStats; 5],
+}
 
-            match guard.get_roc_score().map(|v| v as i32) {
-                Some(base_score) => {
-                    let adjusted_score = base_score as f64 * config.weight_roc;
-                    description_lines.push(rank_roc_score(Some(base_score)).to_string());
-                    description_lines.push(format!(
-                        "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
-                        adjusted_score, base_score, config.weight_roc
-                    ));
-                    AnalysisResult {
-                        indicator_name: "ROC".to_string(),
-                        description: description_lines,
-                        score: base_score as f64,
-                    }
-                }
+/// 1銘柄の全期間を足ごとに評価し、指標スコアと先読みリターンを集計する。
+/// 各足のスコアはその足までのデータだけで計算し、指標の準備期間（必要本数に満たない足）は集計しない。
+/// 戻り値の usize は評価した足の数と、準備期間として除外した足の数
+fn collect_hit_rates(
+    config: &Config,
+    data: &[MarketData],
+    benchmark_data: Option<&[MarketData]>,
+    ticker_name_map: &HashMap<String, String>,
+) -> Result<(Vec<IndicatorHitRate>, usize, usize), Box<dyn std::error::Error>> {
+    let series = IndicatorSeries::new(config, data, benchmark_data)?;
+    let mut table: Vec<IndicatorHitRate> = Vec::new();
+    let mut evaluated = 0;
+    let mut warmup = 0;
+    for end in 2..=data.len() {
+        let index = end - 1;
+        let Ok((guard, bar_config)) = evaluate_bar(config, &series, &data[..end], ticker_name_map) else {
+            warmup += 1;
+            continue;
+        };
+        if data[index].close <= 0.0 {
+            continue;
+        }
+        evaluated += 1;
+
+        let forward: Vec<Option<f64>> = HIT_RATE_HORIZONS
+            .iter()
+            .map(|h| data.get(index + h).map(|f| (f.close / data[index].close - 1.0) * 100.0))
+            .collect();
+        for contribution in calculate_final_score_snapshot(&bar_config, &guard).contributions {
+            let Some(score) = contribution.score else {
+                continue;
+            };
+            let level = score.round().clamp(-2.0, 2.0) as i64;
+            let position = match table.iter().position(|r| r.indicator == contribution.indicator) {
+                Some(position) => position,
                 None => {
-                    description_lines.push("⚠️ ROCスコア情報なし".to_string());
-                    AnalysisResult {
-                        indicator_name: "ROC".to_string(),
-                        description: description_lines,
-                        score: 0.0,
-                    }
+                    table.push(IndicatorHitRate {
+                        indicator: contribution.indicator.clone(),
+                        levels: [HitRateStats::default(); 5],
+                    });
+                    table.len() - 1
+                }
+            };
+            let stats = &mut table[position].levels[(level + 2) as usize];
+            stats.bars += 1;
+            for (h, ret) in forward.iter().enumerate() {
+                if let Some(ret) = ret {
+                    stats.samples[h] += 1;
+                    stats.return_sum[h] += ret;
+                    let hit = if level < 0 { *ret < 0.0 } else { *ret > 0.0 };
+                    stats.hits[h] += usize::from(hit);
                 }
-            }
-        }
-        None => {
-            description_lines.push("⚠️ ROCデータが不足しています".to_string());
-            AnalysisResult {
-                indicator_name: "ROC".to_string(),
-                description: description_lines,
-                score: 0.0,
             }
         }
     }
+    Ok((table, evaluated, warmup))
 }
 
-/// ストキャスティクスの表示（セキュアアクセス：TechnicalDataGuard経由）
-/// ストキャスティクススコアの判定文字列
-fn rank_stochastics_score(stochastics_score: Option<i32>) -> &'static str {
-    match stochastics_score {
-        Some(2) => "🟢 %Kが10%以下 → 強い売られすぎと判断 → 買いシグナル → スコア+2加点",
-        Some(1) => "🟢 %Kが20%以下 → 売られすぎと判断 → 買いシグナル → スコア+1加点",
-        Some(0) => "➡️ %Kが中立圏（20〜80%） → シグナルなし → スコア変動なし",
-        Some(-1) => "🔴 %Kが80%以上 → 買われすぎと判断 → 売りシグナル → スコア-1減点",
-        Some(-2) => "🔴 %Kが90%以上 → 強い買われすぎと判断 → 売りシグナル → スコア-2減点",
-        _ => "⚠️ ストキャスティクススコア不明",
+/// 銘柄ごとの集計を合算する（指標の並びは最初に現れた順）
+fn merge_hit_rates(into: &mut Vec<IndicatorHitRate>, from: &[IndicatorHitRate]) {
+    for row in from {
+        match into.iter_mut().find(|r| r.indicator == row.indicator) {
+            Some(target) => {
+                for (t, s) in target.levels.iter_mut().zip(&row.levels) {
+                    t.bars += s.bars;
+                    for h in 0..HIT_RATE_HORIZONS.len() {
+                        t.samples[h] += s.samples[h];
+                        t.return_sum[h] += s.return_sum[h];
+                        t.hits[h] += s.hits[h];
+                    }
+                }
+            }
+            None => into.push(row.clone()),
+        }
     }
 }
-fn render_stochastics(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
-    let mut description_lines: Vec<String> = Vec::new();
-    description_lines.push("📊 【ストキャスティクス】".to_string());
 
-    let k_opt = guard.get_stochastics_k();
-    let d_opt = guard.get_stochastics_d();
-
-    match (k_opt, d_opt) {
-        (Some(k), Some(d)) => {
-            description_lines.push(format!("現在の%K: {:.2}% / 現在の%D: {:.2}%", k, d));
-            if k == 0.0 && d == 0.0 {
-                description_lines.push("⚠️ %Kおよび%Dが0.00%に張り付き → 極端な売られすぎ水準 → リバウンドの可能性あり（注目シグナル）".to_string());
+/// 集計表の表示行
+fn compose_hit_rate_lines(title: &str, table: &[IndicatorHitRate], evaluated: usize, warmup: usize) -> Vec<String> {
+    let mut lines = vec![
+        format!(
+            "🔬 【指標スコア別の先読みリターン】{}（評価 {}本／指標の準備期間 {}本は除外）",
+            title, evaluated, warmup
+        ),
+        format!(
+            "  {:<18} {:>5} {:>6}   {}",
+            "指標",
+            "スコア",
+            "本数",
+            HIT_RATE_HORIZONS
+                .iter()
+                .map(|h| format!("{:>6}本後 平均/的中", h))
+                .collect::<Vec<String>>()
+                .join("  ")
+        ),
+    ];
+    for row in table {
+        for (i, stats) in row.levels.iter().enumerate().rev() {
+            if stats.bars == 0 {
+                continue;
             }
-        }
-        (Some(_), None) => {
-            description_lines.push("⚠️ %Dデータが不足しています".to_string());
-        }
-        (None, _) => {
-            description_lines.push("⚠️ %Kデータが不足しています".to_string());
+            let cells: Vec<String> = (0..HIT_RATE_HORIZONS.len())
+                .map(|h| match stats.samples[h] {
+                    0 => format!("{:>9} {:>7}", "-", "-"),
+                    n => format!(
+                        "{:>+8.2}% {:>6.1}%",
+                        stats.return_sum[h] / n as f64,
+                        stats.hits[h] as f64 / n as f64 * 100.0
+                    ),
+                })
+                .collect();
+            let level = match i as i64 - 2 {
+                0 => "0".to_string(),
+                level => format!("{:+}", level),
+            };
+            lines.push(format!(
+                "  {:<18} {:>5} {:>6}   {}",
+                row.indicator,
+                level,
+                stats.bars,
+                cells.join("  ")
+            ));
         }
     }
+    lines.push("的中: スコア+は上昇、スコア−は下落した割合（スコア0は上昇した割合）。平均は終値ベースのリターン".to_string());
+    lines.push(String::new());
+    lines
+}
 
-    match guard.get_stochastics_score().map(|v| v as i32) {
-        Some(base_score) => {
-            let adjusted_score = base_score as f64 * config.weight_stochastics;
-            description_lines.push(rank_stochastics_score(Some(base_score)).to_string());
-            description_lines.push(format!(
-                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
-                adjusted_score, base_score, config.weight_stochastics
-            ));
-            AnalysisResult {
-                indicator_name: "ストキャスティクス".to_string(),
-                description: description_lines,
-                score: base_score as f64,
-            }
-        }
-        None => {
-            description_lines.push("⚠️ ストキャスティクススコア情報なし".to_string());
-            AnalysisResult {
-                indicator_name: "ストキャスティクス".to_string(),
-                description: description_lines,
-                score: 0.0,
+/// 集計表のCSV行（ヘッダーなし）
+fn hit_rate_csv_rows(label: &str, table: &[IndicatorHitRate]) -> Vec<String> {
+    let mut rows = Vec::new();
+    for row in table {
+        for (i, stats) in row.levels.iter().enumerate() {
+            if stats.bars == 0 {
+                continue;
             }
+            let cells: Vec<String> = (0..HIT_RATE_HORIZONS.len())
+                .map(|h| match stats.samples[h] {
+                    0 => ",,0".to_string(),
+                    n => format!(
+                        "{:.4},{:.2},{}",
+                        stats.return_sum[h] / n as f64,
+                        stats.hits[h] as f64 / n as f64 * 100.0,
+                        n
+                    ),
+                })
+                .collect();
+            rows.push(format!(
+                "{},{},{},{},{}",
+                label,
+                row.indicator,
+                i as i64 - 2,
+                stats.bars,
+                cells.join(",")
+            ));
         }
     }
+    rows
 }
 
-/// ボリンジャーバンドの表示（セキュアアクセス：TechnicalDataGuard経由）
-fn render_bollinger(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
-    let upper: f64 = guard.get_bb_upper();
-    let lower: f64 = guard.get_bb_lower();
-    let percent_b: f64 = guard.get_bb_percent_b();
-    let bandwidth_pct: f64 = guard.get_bb_bandwidth();
-    let weight: f64 = config.weight_bollinger;
-
-    let mut description_lines: Vec<String> = Vec::new();
-    description_lines.push("📊 【ボリンジャーバンド】".to_string());
-    description_lines.push(format!("上限 {:.2} / 下限 {:.2}", upper, lower));
-
-    if (upper - lower).abs() < f64::EPSILON {
-        description_lines
-            .push("⚠️ バンド幅が0に近いため、%b/帯幅の解釈に注意（計算不安定）".to_string());
-    }
-
-    description_lines.push(format!(
-        "%b indicator: {:.2} / 帯幅(Bandwidth): {:.1}%",
-        percent_b, bandwidth_pct
-    ));
-    // ✅ 帯幅のスクイーズ判定
-    let bw = guard.get_bb_bandwidth(); // ← これを使う
-    let th = config.bb_bandwidth_squeeze_pct; // 設定のしきい値(%)
-
-    if bw <= th {
-        description_lines.push(format!(
-            "⚠️ スクイーズ進行中（帯幅が設定閾値 {:.1}% 以下）",
-            th
-        ));
-    } else {
-        description_lines.push(format!(
-            "ℹ️ 帯幅は設定閾値 {:.1}% を上回り、スクイーズ未発生",
-            th
-        ));
-    }
-
-    // ✅ 位置情報は“%b”だけで1行に統一（重複/矛盾を排除）
-    let state_line = if percent_b > 1.0 {
-        format!(
-            "⚠️ 上限ブレイク（%b {:.2}）→ 伸び一巡後の反動に注意",
-            percent_b
-        )
-    } else if percent_b < 0.0 {
-        format!(
-            "⚠️ 下限ブレイク（%b {:.2}）→ リバウンド/続落の分岐に注意",
-            percent_b
-        )
+/// --hit-rate：1銘柄またはウォッチリストの全銘柄について、指標スコア別の先読みリターンと的中率を集計して表とCSVを出力する
+async fn run_hit_rate_report(
+    config: &Config,
+    ticker_name_map: &HashMap<String, String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let tickers: Vec<String> = if config.watchlist.is_empty() {
+        vec![config.ticker.clone()]
     } else {
-        "➡️ 現在価格がバンド内 → 中立".to_string()
+        config.watchlist.clone()
     };
-    description_lines.push(state_line);
-
-    // ✅ スコアは数値出力のみ（説明は重複させない）
-    //    ただし“バンド内”かつ ±評価が付いた時だけ、短いヒントを追加
-    match guard.get_bollinger_score().map(|v| v as i32) {
-        Some(base) => {
-            let adjusted = base as f64 * weight;
-
-            if (0.0..=1.0).contains(&percent_b) && base != 0 {
-                let hint = if base > 0 {
-                    "🟢 バンド内で下側優位（反発寄り）"
-                } else {
-                    "🔴 バンド内で上側優位（過熱寄り）"
-                };
-                description_lines.push(format!(
-                    "{} → スコア{:+}{}",
-                    hint,
-                    base,
-                    if base > 0 { "加点" } else { "減点" }
-                ));
-            }
-
-            description_lines.push(format!(
-                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
-                adjusted, base, weight
-            ));
-
-            AnalysisResult {
-                indicator_name: "ボリンジャーバンド".to_string(),
-                description: description_lines,
-                score: base as f64,
+    let mut header = vec!["ticker".to_string(), "indicator".to_string(), "score".to_string(), "bars".to_string()];
+    for h in HIT_RATE_HORIZONS {
+        header.extend([format!("ret_{}_avg_%", h), format!("hit_{}_%", h), format!("n_{}", h)]);
+    }
+    let mut csv_lines = vec![header.join(",")];
+    let mut combined: Vec<IndicatorHitRate> = Vec::new();
+    let mut evaluated = 0;
+    let mut warmup = 0;
+    let mut completed = 0;
+    // ベンチマークは銘柄間で共通のことが多いので使い回す
+    let mut benchmarks: HashMap<String, Option<Vec<MarketData>>> = HashMap::new();
+
+    for ticker in &tickers {
+        let cfg = config_for_ticker(config, ticker);
+        let mut data = match fetch_market_data(ticker, &cfg.bt_range).await {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("⚠️ {} の株価を取得できません（集計から除外）: {}", ticker, e);
+                continue;
             }
+        };
+        data.sort_by(|a, b| a.date.cmp(&b.date));
+        if cfg.enabled_extensions.contains(&ExtensionIndicator::RelativeStrength)
+            && !benchmarks.contains_key(&cfg.benchmark)
+        {
+            let fetched = match fetch_market_data(&cfg.benchmark, &cfg.bt_range).await {
+                Ok(mut bench) => {
+                    bench.sort_by(|a, b| a.date.cmp(&b.date));
+                    Some(bench)
+                }
+                Err(e) => {
+                    eprintln!("⚠️ ベンチマーク（{}）の取得に失敗しました: {}", cfg.benchmark, e);
+                    None
+                }
+            };
+            benchmarks.insert(cfg.benchmark.clone(), fetched);
         }
-        None => {
-            description_lines.push("⚠️ ボリンジャーバンドスコア情報なし".to_string());
-            AnalysisResult {
-                indicator_name: "ボリンジャーバンド".to_string(),
-                description: description_lines,
-                score: 0.0,
+        let benchmark_data = benchmarks.get(&cfg.benchmark).and_then(|b| b.as_deref());
+
+        let (table, bars, warmup_bars) = match collect_hit_rates(&cfg, &data, benchmark_data, ticker_name_map) {
+            Ok(collected) => collected,
+            Err(e) => {
+                eprintln!("⚠️ {} を集計できません: {}", ticker, e);
+                continue;
             }
+        };
+        if bars == 0 {
+            eprintln!("⚠️ {} は評価できる足がありません（集計から除外）", ticker);
+            continue;
         }
+        if !config.silent && tickers.len() > 1 {
+            println!("🔬 {}: {}本を集計", ticker, bars);
+        }
+        csv_lines.extend(hit_rate_csv_rows(ticker, &table));
+        merge_hit_rates(&mut combined, &table);
+        evaluated += bars;
+        warmup += warmup_bars;
+        completed += 1;
+    }
+    if completed == 0 {
+        return Err("❌ 集計できた銘柄がありません".into());
+    }
+
+    let title = if config.watchlist.is_empty() {
+        format!("{}（{}）", config.ticker, config.bt_range)
+    } else {
+        // ウォッチリスト全体の合算（銘柄ごとの内訳はCSV）
+        csv_lines.extend(hit_rate_csv_rows("ALL", &combined));
+        format!("{}（{}/{}銘柄、{}）", watchlist_label(config), completed, tickers.len(), config.bt_range)
+    };
+    if !config.silent {
+        print_lines_to_terminal(&compose_hit_rate_lines(&title, &combined, evaluated, warmup));
     }
+
+    write_research_csv(config, "hit_rate", &csv_lines)
 }
 
-/// フィボナッチリトレースメントの表示（セキュアアクセス：TechnicalDataGuard経由）
-fn render_fibonacci(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
-    let weight = config.weight_fibonacci;
-    let mut description_lines: Vec<String> = Vec::new();
-    description_lines.push("📊 【フィボナッチリトレースメント】".to_string());
-    description_lines.push("💡 トレンド内の押し目や戻り目を判断するための価格帯".to_string());
+// 採点する期間（営業日数）。LLM の「1週間短期目線」「1ヶ月中期目線」に合わせる
+const OUTCOME_HORIZONS: [(usize, &str); 2] = [(5, "1週間"), (20, "1か月")];
+// スコア比率の帯（下限と表示名、上から順に判定）
+const OUTCOME_SCORE_BANDS: [(f64, &str); 5] = [
+    (0.6, "+0.6〜+1.0"),
+    (0.2, "+0.2〜+0.6"),
+    (-0.2, "-0.2〜+0.2"),
+    (-0.6, "-0.6〜-0.2"),
+    (f64::NEG_INFINITY, "-1.0〜-0.6"),
+];
+// 端末に一覧表示する直近の判定数
+const OUTCOME_RECENT_CALLS: usize = 15;
 
-    if let (Some(level_38_2), Some(level_50), Some(level_61_8)) = (
-        guard.get_fibo_38_2(),
-        guard.get_fibo_50_0(),
-        guard.get_fibo_61_8(),
-    ) {
-        description_lines.push(format!(
-            "38.2%: {:.2} / 50.0%: {:.2} / 61.8%: {:.2}",
-            level_38_2, level_50, level_61_8
-        ));
+/// 過去の1回分の判定と、その後の値動き
+#[derive(Debug, Clone)]
+struct GradedCall {
+    ticker: String,
+    date: String,
+    stance: Stance,
+    stance_logged: bool,       // ログに stance が記録されていた（false は現在の --stance で判定）
+    score_ratio: f64,
+    judgement: String,         // 記録された判定（古いログは当時のスタンスとスコア比率から再現）
+    direction: Option<bool>,   // 判定が見込む向き（Some(true)=上昇、Some(false)=下落、None=中立）
+    close: f64,                // 判定日の終値（取得した価格）
+    returns: [Option<f64>; 2], // 期間ごとのリターン（%）。期間が経過していなければ None
+}
 
-        // base_score は Guard に保存済み（-2,-1,0,1,2）
-        let base_score = guard.get_fibonacci_score().map(|v| v.round() as i32);
-        let band_line = match base_score {
-            Some(2) => format!(
-                "🟢 終値が50%（{:.2}）より+2.00超 → 非常に強い上昇 → スコア+2",
-                level_50
-            ),
-            Some(1) => format!(
-                "🟢 終値が50%（{:.2}）より+0.50超 → 上昇傾向 → スコア+1",
-                level_50
-            ),
-            Some(0) => format!("➡️ 終値が50%（{:.2}）±0.50内 → 中立（0）", level_50),
-            Some(-1) => format!(
-                "🔴 終値が50%（{:.2}）より-0.50超 → 下降傾向 → スコア-1",
-                level_50
-            ),
-            Some(-2) => format!(
-                "🔴 終値が50%（{:.2}）より-2.00超 → 非常に強い下落 → スコア-2",
-                level_50
-            ),
-            Some(other) => format!("⚠️ 想定外スコア({}) → 中立扱い（0）", other), // フォールバック
-            None => "⚠️ フィボナッチスコア情報なし".to_string(),
-        };
-        description_lines.push(band_line);
-    } else {
-        description_lines.push("⚠️ フィボナッチデータが不足しています".to_string());
+impl GradedCall {
+    /// 期間 h の当否（中立の判定・期間未経過は None）
+    fn hit(&self, h: usize) -> Option<bool> {
+        let ret = self.returns[h]?;
+        self.direction.map(|up| if up { ret > 0.0 } else { ret < 0.0 })
     }
+}
 
-    match guard.get_fibonacci_score().map(|v| v as i32) {
-        Some(base_score) => {
-            let adjusted_score = base_score as f64 * weight;
+/// 判定のグループ（スタンス別・スコア帯別）ごとの集計
+#[derive(Debug, Clone, Default)]
+struct OutcomeStats {
+    label: String,
+    calls: usize,         // 判定の数（中立を含む）
+    graded: [usize; 2],   // 採点できた数（向きのある判定で、期間が経過したもの）
+    hits: [usize; 2],
+    return_sum: [f64; 2], // 期間が経過した判定のリターン合計（中立を含む）
+    returns: [usize; 2],
+}
 
-            description_lines.push(format!(
-                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
-                adjusted_score, base_score, weight
-            ));
-            AnalysisResult {
-                indicator_name: "フィボナッチ".to_string(),
-                description: description_lines,
-                score: base_score as f64,
+impl OutcomeStats {
+    fn add(&mut self, call: &GradedCall) {
+        self.calls += 1;
+        for h in 0..OUTCOME_HORIZONS.len() {
+            if let Some(ret) = call.returns[h] {
+                self.returns[h] += 1;
+                self.return_sum[h] += ret;
             }
-        }
-        None => {
-            description_lines.push("⚠️ フィボナッチスコア情報なし".to_string());
-            AnalysisResult {
-                indicator_name: "フィボナッチ".to_string(),
-                description: description_lines,
-                score: 0.0,
+            if let Some(hit) = call.hit(h) {
+                self.graded[h] += 1;
+                self.hits[h] += usize::from(hit);
             }
         }
     }
 }
 
-/// VWAP（日足簡易版）の表示（セキュアアクセス：TechnicalDataGuard経由）
-fn rank_vwap_score(vwap_score: Option<i32>) -> &'static str {
-    match vwap_score {
-        Some(2) => "🟢 VWAPが現在価格より大幅に下 → 強い買いシグナル → スコア+2加点",
-        Some(1) => "🟢 VWAPが現在価格よりやや下 → 買いシグナル → スコア+1加点",
-        Some(0) => "➡️ VWAPと同水準（±1.0以内）→ スコア変動なし",
-        Some(-1) => "🔴 VWAPが現在価格よりやや上 → 売りシグナル → スコア-1減点",
-        Some(-2) => "🔴 VWAPが現在価格より大幅に上 → 強い売りシグナル → スコア-2減点",
-        _ => "⚠️ VWAPスコア不明",
+/// 過去ログの期間をカバーする取得範囲（Yahoo の range）
+fn outcome_fetch_range(earliest: chrono::NaiveDate) -> &'static str {
+    let days = (chrono::Local::now().date_naive() - earliest).num_days();
+    match days {
+        d if d <= 80 => "3mo",
+        d if d <= 170 => "6mo",
+        d if d <= 350 => "1y",
+        d if d <= 720 => "2y",
+        d if d <= 1800 => "5y",
+        d if d <= 3600 => "10y",
+        _ => "max",
     }
 }
 
-/// VWAP（日足簡易版）の表示（セキュアアクセス：TechnicalDataGuard経由）
-fn render_vwap(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
-    let weight = config.weight_vwap;
-    let mut description_lines: Vec<String> = Vec::new();
-    description_lines.push("📊 【VWAP（日足簡易版）】".to_string());
-    description_lines.push("⚠️ VWAPは高値・安値・終値をもとに算出した簡易版です。正式な出来高加重平均価格とは異なる場合があります。".to_string());
+/// 過去ログの判定を、その後の終値で採点する（同じ日付・スタンスの実行は最後の1回だけ）。
+/// usize は score_ratio がなく採点できない古い行の数
+fn grade_logged_calls(
+    config: &Config,
+    records: &[ScoreHistoryRecord],
+    data: &[MarketData],
+) -> (Vec<GradedCall>, usize) {
+    let mut calls: Vec<GradedCall> = Vec::new();
+    let mut without_ratio = 0;
+    for record in records {
+        let Some(score_ratio) = record.score_ratio else {
+            without_ratio += 1;
+            continue;
+        };
+        let Some(date) = record.date.get(..10) else {
+            continue;
+        };
+        // 判定日の足（その日のデータがなければ直前の足）
+        let index = match data.partition_point(|d| d.date.get(..10).unwrap_or("") <= date) {
+            0 => continue,
+            n => n - 1,
+        };
+        if data[index].close <= 0.0 {
+            continue;
+        }
+        let stance = record.stance.unwrap_or(config.stance);
+        // 判定が記録されていればそれを採点し、古いログはスタンスとスコア比率から再現する
+        let (judgement, direction) = record.judgement.clone().unwrap_or_else(|| {
+            (stance_judgement(&stance, score_ratio), stance_direction(&stance, score_ratio))
+        });
+        let mut returns = [None; 2];
+        for (h, (bars, _)) in OUTCOME_HORIZONS.iter().enumerate() {
+            returns[h] = data
+                .get(index + bars)
+                .map(|f| (f.close / data[index].close - 1.0) * 100.0);
+        }
+        calls.retain(|c| !(c.date == date && c.stance == stance));
+        calls.push(GradedCall {
+            ticker: config.ticker.clone(),
+            date: date.to_string(),
+            stance,
+            stance_logged: record.stance.is_some(),
+            score_ratio,
+            judgement,
+            direction,
+            close: data[index].close,
+            returns,
+        });
+    }
+    (calls, without_ratio)
+}
 
-    if let Some(vwap_value) = guard.get_vwap() {
-        description_lines.push(format!("VWAP値: {:.2}", vwap_value));
-    } else {
-        description_lines.push("⚠️ VWAPデータが不足しています".to_string());
+/// 集計表の行（グループごと）
+fn compose_outcome_stats_lines(title: &str, groups: &[OutcomeStats]) -> Vec<String> {
+    let mut lines = vec![format!(
+        "{}  {}",
+        title,
+        OUTCOME_HORIZONS
+            .iter()
+            .map(|(_, label)| format!("{}後: 的中率（採点数）・平均", label))
+            .collect::<Vec<String>>()
+            .join(" ／ ")
+    )];
+    for group in groups.iter().filter(|g| g.calls > 0) {
+        let cells: Vec<String> = (0..OUTCOME_HORIZONS.len())
+            .map(|h| {
+                let rate = match group.graded[h] {
+                    0 => "   -  ".to_string(),
+                    n => format!("{:>5.1}%", group.hits[h] as f64 / n as f64 * 100.0),
+                };
+                let avg = match group.returns[h] {
+                    0 => "    -   ".to_string(),
+                    n => format!("{:>+7.2}%", group.return_sum[h] / n as f64),
+                };
+                format!("{}（{:>3}） {}", rate, group.graded[h], avg)
+            })
+            .collect();
+        lines.push(format!("  {:<12} {:>4}件  {}", group.label, group.calls, cells.join(" ／ ")));
     }
+    lines
+}
 
-    match guard.get_vwap_score().map(|v| v as i32) {
-        Some(base_score) => {
-            let adjusted_score = base_score as f64 * weight;
-            description_lines.push(rank_vwap_score(Some(base_score)).to_string());
-            description_lines.push(format!(
-                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
-                adjusted_score, base_score, weight
-            ));
-            AnalysisResult {
-                indicator_name: "VWAP".to_string(),
-                description: description_lines,
-                score: base_score as f64,
+/// 採点結果の表示行
+fn compose_outcome_lines(title: &str, calls: &[GradedCall], without_ratio: usize) -> Vec<String> {
+    let mut lines = vec![format!("📝 【過去の判定の答え合わせ】{}（{}件）", title, calls.len())];
+    let mut by_stance: Vec<OutcomeStats> = [Stance::Buyer, Stance::Holder, Stance::Seller]
+        .iter()
+        .map(|s| OutcomeStats { label: stance_caption(s).to_string(), ..Default::default() })
+        .collect();
+    let mut by_band: Vec<OutcomeStats> = OUTCOME_SCORE_BANDS
+        .iter()
+        .map(|(_, label)| OutcomeStats { label: label.to_string(), ..Default::default() })
+        .collect();
+    for call in calls {
+        let stance_index = match call.stance {
+            Stance::Buyer => 0,
+            Stance::Holder => 1,
+            Stance::Seller => 2,
+        };
+        by_stance[stance_index].add(call);
+        let band = OUTCOME_SCORE_BANDS
+            .iter()
+            .position(|(lower, _)| call.score_ratio >= *lower)
+            .unwrap_or(OUTCOME_SCORE_BANDS.len() - 1);
+        by_band[band].add(call);
+    }
+    lines.extend(compose_outcome_stats_lines("🎯 スタンス別", &by_stance));
+    lines.extend(compose_outcome_stats_lines("📶 スコア比率の帯別", &by_band));
+
+    lines.push(format!("🧾 直近の判定（最大{}件）", OUTCOME_RECENT_CALLS));
+    let start = calls.len().saturating_sub(OUTCOME_RECENT_CALLS);
+    for call in &calls[start..] {
+        let cells: Vec<String> = (0..OUTCOME_HORIZONS.len())
+            .map(|h| match (call.returns[h], call.hit(h)) {
+                (None, _) => format!("{}後 未到来", OUTCOME_HORIZONS[h].1),
+                (Some(ret), hit) => format!(
+                    "{}後 {:+.2}%{}",
+                    OUTCOME_HORIZONS[h].1,
+                    ret,
+                    match hit {
+                        Some(true) => " ✅",
+                        Some(false) => " ❌",
+                        None => "",
+                    }
+                ),
+            })
+            .collect();
+        lines.push(format!(
+            "  {} {} {}{} {:+.2} {}  {}",
+            call.date,
+            call.ticker,
+            stance_caption(&call.stance),
+            if call.stance_logged { "" } else { "*" },
+            call.score_ratio,
+            call.judgement,
+            cells.join("  ")
+        ));
+    }
+    lines.push(
+        "的中: 上昇を見込む判定は上昇、下落を見込む判定は下落で的中（中立の判定は採点しない）。平均はその後の終値ベースのリターン"
+            .to_string(),
+    );
+    if calls.iter().any(|c| !c.stance_logged) {
+        lines.push("* stance が記録されていない古いログは、現在の --stance で判定を再現しています".to_string());
+    }
+    if without_ratio > 0 {
+        lines.push(format!("ℹ️ score_ratio が記録されていない古いログ {}件は採点できません", without_ratio));
+    }
+    lines.push(String::new());
+    lines
+}
+
+/// --track-outcomes：過去ログの判定を、その後の1週間・1か月のリターンで採点し、スタンス別・スコア帯別に集計する
+async fn run_outcome_tracking(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let tickers: Vec<String> = if config.watchlist.is_empty() {
+        vec![config.ticker.clone()]
+    } else {
+        config.watchlist.clone()
+    };
+    let mut calls: Vec<GradedCall> = Vec::new();
+    let mut without_ratio = 0;
+    for ticker in &tickers {
+        let cfg = config_for_ticker(config, ticker);
+        let (records, _) = read_logged_runs(&cfg, ticker);
+        let Some(earliest) = records.iter().find_map(|r| parse_log_date(&r.date)) else {
+            if config.watchlist.is_empty() {
+                return Err(format!(
+                    "❌ {} の過去ログがありません（--save-technical-log で記録した {} を読みます）",
+                    ticker, cfg.log_dir
+                )
+                .into());
             }
-        }
-        None => {
-            description_lines.push("⚠️ VWAPスコア情報なし".to_string());
-            AnalysisResult {
-                indicator_name: "VWAP".to_string(),
-                description: description_lines,
-                score: 0.0,
+            continue;
+        };
+        let mut data = match fetch_market_data(ticker, outcome_fetch_range(earliest)).await {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("⚠️ {} の株価を取得できません（採点から除外）: {}", ticker, e);
+                continue;
             }
+        };
+        data.sort_by(|a, b| a.date.cmp(&b.date));
+        let (graded, skipped) = grade_logged_calls(&cfg, &records, &data);
+        calls.extend(graded);
+        without_ratio += skipped;
+    }
+    if calls.is_empty() {
+        return Err("❌ 採点できる過去の判定がありません".into());
+    }
+    calls.sort_by(|a, b| a.date.cmp(&b.date));
+
+    let title = if config.watchlist.is_empty() {
+        config.ticker.clone()
+    } else {
+        watchlist_label(config)
+    };
+    if !config.silent {
+        print_lines_to_terminal(&compose_outcome_lines(&title, &calls, without_ratio));
+    }
+
+    let mut header = vec!["ticker", "date", "stance", "score_ratio", "judgement", "direction", "close"]
+        .into_iter()
+        .map(str::to_string)
+        .collect::<Vec<String>>();
+    for (bars, _) in OUTCOME_HORIZONS {
+        header.extend([format!("ret_{}_%", bars), format!("hit_{}", bars)]);
+    }
+    let mut lines = vec![header.join(",")];
+    for call in &calls {
+        let mut cells = vec![
+            call.ticker.clone(),
+            call.date.clone(),
+            stance_caption(&call.stance).to_lowercase(),
+            format!("{:.4}", call.score_ratio),
+            call.judgement.clone(),
+            direction_key(call.direction).to_string(),
+            format!("{:.2}", call.close),
+        ];
+        for h in 0..OUTCOME_HORIZONS.len() {
+            cells.push(call.returns[h].map(|r| format!("{:.2}", r)).unwrap_or_default());
+            cells.push(call.hit(h).map(|hit| u8::from(hit).to_string()).unwrap_or_default());
         }
+        lines.push(cells.join(","));
     }
+    write_research_csv(config, "outcomes", &lines)
 }
 
-/// 一目均衡表の転換線・基準線・スコアを表示（セキュアアクセス：TechnicalDataGuard経由）
-fn rank_ichimoku_score(ichimoku_score: Option<i32>) -> &'static str {
-    match ichimoku_score {
-        Some(2) => "🟢 転換線が基準線より大幅に上 → 強い買い圧力 → スコア+2加点",
-        Some(1) => "🟢 転換線が基準線よりやや上 → 買い優勢 → スコア+1加点",
-        Some(0) => "➡️ 転換線と基準線が同値圏 → トレンドなし → スコア変動なし",
-        Some(-1) => "🔴 転換線が基準線よりやや下 → 売り優勢 → スコア-1減点",
-        Some(-2) => "🔴 転換線が基準線より大幅に下 → 強い売り圧力 → スコア-2減点",
-        _ => "⚠️ 一目均衡表スコア不明",
-    }
+// 台帳の報告に表示する直近の約定数
+const PAPER_RECENT_TRADES: usize = 10;
+
+/// ペーパートレード台帳（JSON ファイルに保存。1つの台帳は1つの通貨で、通貨の異なる銘柄は買わない）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PaperLedger {
+    initial_cash: f64,
+    cash: f64,
+    created: String, // 台帳を作成した日時
+    #[serde(default)]
+    currency: Option<String>, // 台帳の通貨（最初に買った銘柄の通貨で決まる）
+    positions: Vec<PaperPosition>,
+    trades: Vec<PaperTrade>,
 }
-/// 一目均衡表の転換線・基準線・スコアを表示（セキュアアクセス：TechnicalDataGuard経由）
-fn render_ichimoku(config: &Config, guard: &TechnicalDataGuard) -> AnalysisResult {
-    let weight = config.weight_ichimoku;
-    let mut description_lines: Vec<String> = Vec::new();
-    description_lines.push("📊 【一目均衡表】".to_string());
 
-    if let (Some(tenkan), Some(kijun)) = (guard.get_tenkan_sen(), guard.get_kijun_sen()) {
-        description_lines.push(format!("転換線: {:.2} / 基準線: {:.2}", tenkan, kijun));
-        if tenkan > kijun {
-            description_lines
-                .push("📈 ゴールデンクロス発生中（転換線が基準線を上回る）".to_string());
-        } else if tenkan < kijun {
-            description_lines.push("📉 デッドクロス発生中（転換線が基準線を下回る）".to_string());
-        } else {
-            description_lines.push("➡️ 転換線と基準線が交差中（横ばい）".to_string());
+/// 保有中の建玉（銘柄ごとに1つ。買い増しは取得単価を平均する）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PaperPosition {
+    ticker: String,
+    units: u64,
+    cost: f64,      // 手数料込みの1株あたり取得単価
+    opened: String, // 最初に建てた足の日付
+    #[serde(default)]
+    currency: Option<String>, // 銘柄の通貨（通貨を記録する前の台帳は None）
+}
+
+/// 約定の記録
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PaperTrade {
+    date: String,
+    ticker: String,
+    side: String, // "buy"|"sell"
+    units: u64,
+    price: f64,                // スリッページ込みの約定値
+    fee: f64,
+    realized_pnl: Option<f64>, // 売りのみ（手数料込み）
+    reason: String,            // 売買のきっかけ（判定とスコア比率、または「手動」）
+}
+
+impl PaperLedger {
+    fn new(initial_cash: f64) -> Self {
+        PaperLedger {
+            initial_cash,
+            cash: initial_cash,
+            created: chrono::Local::now().format("%Y-%m-%d %H:%M").to_string(),
+            currency: None,
+            positions: Vec::new(),
+            trades: Vec::new(),
         }
-        if kijun != 0.0 {
-            let gap_ratio = ((tenkan - kijun) / kijun).abs() * 100.0;
-            if gap_ratio < 1.0 {
-                description_lines.push(format!(
-                    "💡 クロス直後の接近状態（乖離 {:.2}%）→ トレンド確定には弱い傾向",
-                    gap_ratio
-                ));
-            } else if gap_ratio > 5.0 {
-                description_lines.push(format!(
-                    "💡 クロス乖離が大きい（乖離 {:.2}%）→ 強いトレンドの可能性",
-                    gap_ratio
+    }
+
+    fn position(&self, ticker: &str) -> Option<&PaperPosition> {
+        self.positions.iter().find(|p| p.ticker == ticker)
+    }
+
+    fn realized_pnl(&self) -> f64 {
+        self.trades.iter().filter_map(|t| t.realized_pnl).fold(0.0, |acc, pnl| acc + pnl)
+    }
+
+    /// 今回の足の終値にスリッページを乗せて買う。株数の指定がなければ初期資金の paper_size_pct% を上限に買える分だけ。
+    /// 台帳と通貨の異なる銘柄は買わない（通貨が取得できなかった銘柄は照合せずに買う）
+    fn buy(
+        &mut self,
+        config: &Config,
+        guard: &TechnicalDataGuard,
+        units: Option<u64>,
+        reason: &str,
+    ) -> Result<String, String> {
+        let (ticker, date, close, currency) =
+            (guard.get_ticker(), guard.get_date(), guard.get_close(), guard.get_currency());
+        if let (Some(ledger), Some(currency)) = (self.currency.as_deref(), currency) {
+            if ledger != currency {
+                return Err(format!(
+                    "{} の通貨（{}）が台帳の通貨（{}）と異なるため買えません（--paper-file で台帳を分けてください）",
+                    ticker, currency, ledger
                 ));
             }
         }
-    } else {
-        description_lines.push("⚠️ 一目均衡表データが不足しています".to_string());
+        let fee_rate = config.bt_fee_pct / 100.0;
+        let price = close * (1.0 + config.bt_slippage_pct / 100.0);
+        if !(price.is_finite() && price > 0.0) {
+            return Err(format!("{} の終値が不正です（{}）", ticker, close));
+        }
+        let units = units.unwrap_or_else(|| {
+            let budget = (self.initial_cash * config.paper_size_pct / 100.0).min(self.cash);
+            (budget / (price * (1.0 + fee_rate))).floor().max(0.0) as u64
+        });
+        let amount = price * units as f64;
+        let fee = amount * fee_rate;
+        if units == 0 || amount + fee > self.cash + 1e-9 {
+            return Err(format!(
+                "資金不足のため買えません（{}株 @{:.2} に {:.2} 必要、現金 {:.2}）",
+                units.max(1),
+                price,
+                price * units.max(1) as f64 * (1.0 + fee_rate),
+                self.cash
+            ));
+        }
+        self.cash -= amount + fee;
+        match self.positions.iter_mut().find(|p| p.ticker == ticker) {
+            Some(p) => {
+                let total = p.units + units;
+                p.cost = (p.cost * p.units as f64 + amount + fee) / total as f64;
+                p.units = total;
+            }
+            None => self.positions.push(PaperPosition {
+                ticker: ticker.to_string(),
+                units,
+                cost: (amount + fee) / units as f64,
+                opened: date.to_string(),
+                currency: currency.map(str::to_string),
+            }),
+        }
+        if self.currency.is_none() {
+            self.currency = currency.map(str::to_string);
+        }
+        self.trades.push(PaperTrade {
+            date: date.to_string(),
+            ticker: ticker.to_string(),
+            side: "buy".to_string(),
+            units,
+            price,
+            fee,
+            realized_pnl: None,
+            reason: reason.to_string(),
+        });
+        Ok(format!("{} 買い {}株 @{:.2}（手数料 {:.2}）", ticker, units, price, fee))
+    }
+
+    /// 今回の足の終値からスリッページを引いて売る。株数の指定がなければ全株
+    fn sell(
+        &mut self,
+        config: &Config,
+        guard: &TechnicalDataGuard,
+        units: Option<u64>,
+        reason: &str,
+    ) -> Result<String, String> {
+        let (ticker, date, close) = (guard.get_ticker(), guard.get_date(), guard.get_close());
+        let Some(idx) = self.positions.iter().position(|p| p.ticker == ticker) else {
+            return Err(format!("{} の建玉がありません", ticker));
+        };
+        let price = close * (1.0 - config.bt_slippage_pct / 100.0);
+        if !(price.is_finite() && price > 0.0) {
+            return Err(format!("{} の終値が不正です（{}）", ticker, close));
+        }
+        let position = &mut self.positions[idx];
+        let units = units.unwrap_or(position.units).min(position.units);
+        let amount = price * units as f64;
+        let fee = amount * config.bt_fee_pct / 100.0;
+        let pnl = amount - fee - position.cost * units as f64;
+        position.units -= units;
+        if position.units == 0 {
+            self.positions.remove(idx);
+        }
+        self.cash += amount - fee;
+        self.trades.push(PaperTrade {
+            date: date.to_string(),
+            ticker: ticker.to_string(),
+            side: "sell".to_string(),
+            units,
+            price,
+            fee,
+            realized_pnl: Some(pnl),
+            reason: reason.to_string(),
+        });
+        Ok(format!(
+            "{} 売り {}株 @{:.2}（手数料 {:.2}）実現損益 {:+.2}",
+            ticker, units, price, fee, pnl
+        ))
+    }
+}
+
+/// 台帳ファイルのパス（--paper-file / PAPER_FILE、未指定なら <log_dir>/paper_ledger.json）
+fn paper_ledger_path(config: &Config) -> PathBuf {
+    match &config.paper_file {
+        Some(path) => PathBuf::from(path),
+        None => Path::new(&config.log_dir).join("paper_ledger.json"),
     }
+}
 
-    match guard.get_ichimoku_score().map(|v: f64| v as i32) {
-        Some(base_score) => {
-            let adjusted_score = base_score as f64 * weight;
-            description_lines.push(rank_ichimoku_score(Some(base_score)).to_string());
-            description_lines.push(format!(
-                "📝 スコア調整値({:.1}) = スコア({}) × Weight({:.1})",
-                adjusted_score, base_score, weight
-            ));
-            AnalysisResult {
-                indicator_name: "一目均衡表".to_string(),
-                description: description_lines,
-                score: base_score as f64,
+/// 台帳を読み込む（ファイルがなければ初期資金で新規作成。壊れている場合は上書きせずエラー）
+fn load_paper_ledger(path: &Path, initial_cash: f64) -> Result<PaperLedger, Box<dyn Error>> {
+    if !path.exists() {
+        return Ok(PaperLedger::new(initial_cash));
+    }
+    let text = read_to_string(path)?;
+    serde_json::from_str(&text).map_err(|e| {
+        format!(
+            "❌ 台帳 {} を読み込めません（--paper reset で作り直せます）: {}",
+            path.display(),
+            e
+        )
+        .into()
+    })
+}
+
+/// 台帳を保存する（一時ファイルに書いてから置き換え、途中で失敗しても元の台帳を壊さない）
+fn save_paper_ledger(path: &Path, ledger: &PaperLedger) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        create_dir_all(dir)?;
+    }
+    let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+    std::fs::write(&tmp, serde_json::to_string_pretty(ledger)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// --paper auto|buy|sell：今回の終値で約定して台帳に記録する。
+/// auto は今回の判定（平滑化を指定していれば平滑化後）の向きで、ノーポジションなら買い、保有中なら手仕舞う
+fn apply_paper_trade(config: &Config, guard: &TechnicalDataGuard) -> Result<(), Box<dyn Error>> {
+    let Some(action) = config.paper else {
+        return Ok(());
+    };
+    if matches!(action, PaperAction::Report | PaperAction::Reset) {
+        return Ok(());
+    }
+    let snap = calculate_final_score_snapshot(config, guard);
+    let (ratio, judgement) = effective_judgement(guard, &config.stance, snap.score_ratio);
+    let path = paper_ledger_path(config);
+    let mut ledger = load_paper_ledger(&path, config.paper_cash)?;
+    let ticker = guard.get_ticker();
+    let held = ledger.position(ticker).is_some();
+    let reason = format!("{}（{:+.2}）", judgement, ratio);
+
+    let outcome = match action {
+        PaperAction::Auto => match judgement_direction(&config.stance, &judgement) {
+            Some(true) if !held => Some(ledger.buy(config, guard, None, &reason)),
+            Some(false) if held => Some(ledger.sell(config, guard, None, &reason)),
+            _ => None,
+        },
+        PaperAction::Buy => Some(ledger.buy(config, guard, config.paper_units, "手動")),
+        PaperAction::Sell => Some(ledger.sell(config, guard, config.paper_units, "手動")),
+        PaperAction::Report | PaperAction::Reset => None,
+    };
+    match outcome {
+        Some(Ok(message)) => {
+            save_paper_ledger(&path, &ledger)?;
+            if !config.silent {
+                println!("📒 ペーパートレード（{}）: {}", action, message);
+                println!("   現金 {:.2} ／ 台帳: {}", ledger.cash, path.display());
             }
         }
+        Some(Err(message)) => eprintln!("⚠️ ペーパートレード（{}）: {}", action, message),
         None => {
-            description_lines.push("⚠️ 一目均衡表スコア情報なし".to_string());
-            AnalysisResult {
-                indicator_name: "一目均衡表".to_string(),
-                description: description_lines,
-                score: 0.0,
+            if !config.silent {
+                println!(
+                    "📒 ペーパートレード（{}）: 売買なし — {}（{}）",
+                    action,
+                    reason,
+                    if held { "保有継続" } else { "ノーポジション" }
+                );
             }
         }
     }
+    Ok(())
 }
 
-/// 単極ゲージ（Seller/Buyerの見た目長さ差を解消）。例: 「Buyer [.....█████] Seller」
-fn render_unipolar_gauge_rtl(
-    percent: u8,
-    left_label: &str,
-    right_label: &str,
-    width: usize,
-) -> String {
-    // 充填は四捨五入で統一（Seller/Buyerの見た目長さ差を解消）
-    let w = width.max(10);
-    let filled = (((percent as usize) * w) + 50) / 100; // round(percent * w / 100)
-    let empty = w - filled;
-
-    // filled + empty = width を常に満たす
-    let bar = format!("[{}{}]", ".".repeat(empty), "█".repeat(filled));
-    format!(
-        "{left} {bar} {right}",
-        left = left_label,
-        right = right_label
-    )
+/// --paper reset：台帳を初期資金で作り直す（既存の台帳は .bak に退避）
+fn reset_paper_ledger(config: &Config) -> Result<(), Box<dyn Error>> {
+    let path = paper_ledger_path(config);
+    let backup = PathBuf::from(format!("{}.bak", path.display()));
+    let had_ledger = path.exists();
+    if had_ledger {
+        std::fs::rename(&path, &backup)?;
+    }
+    save_paper_ledger(&path, &PaperLedger::new(config.paper_cash))?;
+    println!("🧹 台帳を初期化しました（初期資金 {:.2}）: {}", config.paper_cash, path.display());
+    if had_ledger {
+        println!("   旧台帳: {}", backup.display());
+    }
+    Ok(())
 }
 
-/// 両極ゲージ（中央'|'、正は左へ・負は右へ塗る）。例: 「買い [█████|..........] 売り」
-/// 幅は呼び出し側で 50 指定（ここでは安全側で最小12を確保）。中央'|'を配置。
-#[allow(clippy::needless_range_loop)]
-fn render_bipolar_gauge_lr(score_ratio: f64, width: usize) -> String {
-    // 幅は呼び出し側で 50 指定（ここでは安全側で最小12を確保）。中央'|'を配置。
-    let w = width.max(12);
-    let mid = w / 2;
+/// 台帳の報告（現金・評価額・実現／含み損益・建玉・直近の約定）
+/// prices は銘柄ごとの最新の（日付, 終値）。取得できなかった銘柄は取得単価で評価する
+fn compose_paper_report_lines(
+    path: &Path,
+    ledger: &PaperLedger,
+    prices: &HashMap<String, (String, f64)>,
+) -> Vec<String> {
+    let mut lines = vec![
+        String::new(),
+        format!("📒 【ペーパートレード台帳】{}（作成 {}）", path.display(), ledger.created),
+    ];
 
-    // |score_ratio| を中央半幅にスケールし、丸めて段階化。上限は mid。
-    let blocks = ((score_ratio.abs() * mid as f64).round() as usize).min(mid);
+    let mut market_value = 0.0;
+    let mut unrealized = 0.0;
+    let mut position_lines = Vec::new();
+    for p in &ledger.positions {
+        let units = p.units as f64;
+        let (price_label, price) = match prices.get(&p.ticker) {
+            Some((date, close)) => (format!("{:.2}（{}）", close, date), *close),
+            None => ("取得失敗（取得単価で評価）".to_string(), p.cost),
+        };
+        let pnl = (price - p.cost) * units;
+        market_value += price * units;
+        unrealized += pnl;
+        position_lines.push(format!(
+            "  {:<10} {:>8}株  取得 {:.2} → 現在 {}  評価額 {:.2}  含み損益 {:+.2}（{:+.2}%）  建玉 {}",
+            p.ticker,
+            p.units,
+            p.cost,
+            price_label,
+            price * units,
+            pnl,
+            (price / p.cost - 1.0) * 100.0,
+            p.opened
+        ));
+    }
 
-    // ベースは '.' で埋め、中央に '|'。filled + empty = w を維持。
-    let mut v = vec!['.'; w];
-    if mid < w {
-        v[mid] = '|';
+    let equity = ledger.cash + market_value;
+    let sells: Vec<f64> = ledger.trades.iter().filter_map(|t| t.realized_pnl).collect();
+    lines.push(format!("初期資金 {:.2} ／ 現金 {:.2} ／ 保有評価額 {:.2}", ledger.initial_cash, ledger.cash, market_value));
+    lines.push(format!(
+        "資産合計 {:.2}（初期資金比 {:+.2}%）",
+        equity,
+        (equity / ledger.initial_cash - 1.0) * 100.0
+    ));
+    lines.push(format!(
+        "実現損益 {:+.2}（手仕舞い {}回、うち利益 {}回） ／ 含み損益 {:+.2}",
+        ledger.realized_pnl(),
+        sells.len(),
+        sells.iter().filter(|pnl| **pnl > 0.0).count(),
+        unrealized
+    ));
+
+    lines.push(String::new());
+    if position_lines.is_empty() {
+        lines.push("【保有】なし".to_string());
+    } else {
+        lines.push(format!("【保有】{}銘柄", position_lines.len()));
+        lines.extend(position_lines);
     }
 
-    // 向きは仕様固定：左=買い、右=売り。
-    // 正（買い）は左側（midの左）へ、負（売り）は右側（midの右）へ塗る。
-    if score_ratio > 0.0 {
-        for i in (mid.saturating_sub(blocks))..mid {
-            v[i] = '█';
+    lines.push(String::new());
+    if ledger.trades.is_empty() {
+        lines.push("【約定】なし".to_string());
+    } else {
+        let start = ledger.trades.len().saturating_sub(PAPER_RECENT_TRADES);
+        lines.push(format!("【約定】直近{}件／全{}件", ledger.trades.len() - start, ledger.trades.len()));
+        for t in &ledger.trades[start..] {
+            let pnl = t.realized_pnl.map(|p| format!("  損益 {:+.2}", p)).unwrap_or_default();
+            lines.push(format!(
+                "  {} {:<10} {} {:>8}株 @{:.2}  手数料 {:.2}{}  {}",
+                t.date,
+                t.ticker,
+                if t.side == "buy" { "買い" } else { "売り" },
+                t.units,
+                t.price,
+                t.fee,
+                pnl,
+                t.reason
+            ));
         }
-    } else if score_ratio < 0.0 {
-        for i in (mid + 1)..(mid + 1 + blocks) {
-            if i < w {
-                v[i] = '█';
-            }
+    }
+    lines.push(String::new());
+    // 通貨を記録する前の台帳には、通貨の異なる建玉が混在していることがある
+    let mut currencies: Vec<&str> = ledger.currency.as_deref().into_iter().collect();
+    for c in ledger.positions.iter().filter_map(|p| p.currency.as_deref()) {
+        if !currencies.contains(&c) {
+            currencies.push(c);
         }
     }
+    lines.push(match currencies.as_slice() {
+        [] if ledger.positions.is_empty() => "ℹ️ 台帳の通貨は最初に買った銘柄の通貨で決まります".to_string(),
+        [] => "ℹ️ 通貨が記録されていない台帳です（金額は各銘柄の通貨のまま合算しています）".to_string(),
+        [currency] => format!("ℹ️ 金額はすべて {} 建てです（通貨の異なる銘柄は --paper-file で台帳を分けてください）", currency),
+        mixed => format!(
+            "⚠️ 通貨の異なる建玉が混在しています（{}）。合計は通貨をまたいで合算した参考値です（--paper reset か --paper-file で台帳を分けてください）",
+            mixed.join(" / ")
+        ),
+    });
+    lines.push(String::new());
+    lines
+}
 
-    // バー色は判定色と一致させる（'█' のみ着色）。色名は get_color_for_score に準拠。
-    let color = get_color_for_score(score_ratio);
-    let bar: String = v
-        .into_iter()
-        .map(|c| {
-            if c == '█' {
-                match color {
-                    "green" => c.to_string().green().to_string(),
-                    "yellow" => c.to_string().yellow().to_string(),
-                    "orange" => c.to_string().truecolor(255, 165, 0).to_string(),
-                    "red" => c.to_string().red().to_string(),
-                    "white" => c.to_string().white().to_string(), // 中立近傍
-                    _ => c.to_string(),
+/// --paper report：建玉の最新値を取得して台帳を報告する
+async fn run_paper_report(config: &Config) -> Result<(), Box<dyn Error>> {
+    let path = paper_ledger_path(config);
+    if !path.exists() {
+        return Err(format!(
+            "❌ 台帳 {} がありません（--paper auto|buy で最初の約定時に作成されます）",
+            path.display()
+        )
+        .into());
+    }
+    let ledger = load_paper_ledger(&path, config.paper_cash)?;
+    let mut prices: HashMap<String, (String, f64)> = HashMap::new();
+    for p in &ledger.positions {
+        match fetch_market_data(&p.ticker, "5d").await {
+            Ok(mut data) => {
+                data.sort_by(|a, b| a.date.cmp(&b.date));
+                if let Some(last) = data.last() {
+                    prices.insert(p.ticker.clone(), (last.date.clone(), last.close));
                 }
-            } else {
-                c.to_string()
             }
-        })
-        .collect();
-
-    // 文言は仕様固定：「買い+100％[バー] -100% 売り」
-    format!("買い+100％[{bar}] -100% 売り")
+            Err(e) => eprintln!("⚠️ {} の最新値を取得できません（取得単価で評価）: {}", p.ticker, e),
+        }
+    }
+    print_lines_to_terminal(&compose_paper_report_lines(&path, &ledger, &prices));
+    Ok(())
 }
 
-fn stance_caption(s: &Stance) -> &'static str {
-    match s {
-        Stance::Buyer => "Buyer",
-        Stance::Holder => "Holder",
-        Stance::Seller => "Seller",
+/// 全期間の指標値・スコアを足ごとに評価し、日付順の時系列として書き出す。
+/// 指標の系列は分析時に全期間で計算したものを使い、各足はその足までの値だけで評価する（先読みなし）。
+/// プラグインは足ごとには実行しない（ステータス skipped）。必要本数に満たない足は出力しない。
+fn export_indicator_series(
+    config: &Config,
+    series: &IndicatorSeries,
+    data: &[MarketData],
+    ticker_name_map: &HashMap<String, String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let format = config.series_output.as_deref().unwrap_or("csv");
+    let mut rows: Vec<String> = Vec::new();
+    let mut skipped = 0;
+    let mut last_error: Option<String> = None;
+
+    for end in 2..=data.len() {
+        let (guard, bar_config) = match evaluate_bar(config, series, &data[..end], ticker_name_map) {
+            Ok(evaluated) => evaluated,
+            Err(e) => {
+                skipped += 1;
+                last_error = Some(e.to_string());
+                continue;
+            }
+        };
+
+        let snap = calculate_final_score_snapshot(&bar_config, &guard);
+        rows.push(match format {
+            "json" => generate_technical_json_string(&bar_config, &guard, &snap)?,
+            _ => generate_technical_csv_row(
+                &guard,
+                &collect_log_results(&bar_config, &guard),
+                &snap,
+            )?,
+        });
     }
-}
-///グラフ色分け
-fn get_color_for_score(score_ratio: f64) -> &'static str {
-    match score_ratio {
-        r if r >= 0.8 => "green", // 強い買い
-        r if r >= 0.6 => "green", // やや買い
-        r if r >= 0.4 => "green",
-        r if r >= 0.2 => "yellow",
-        r if r >= 0.0 => "yellow",
-        r if r >= -0.2 => "white",
-        r if r >= -0.4 => "orange",
-        r if r >= -0.6 => "orange",
-        r if r >= -0.8 => "red",
-        _ => "red",
+
+    if skipped > 0 {
+        eprintln!(
+            "ℹ️ 時系列出力: 指標の計算に必要な本数に満たない先頭{}本は出力しません（{}）",
+            skipped,
+            last_error.unwrap_or_default()
+        );
+    }
+    if rows.is_empty() {
+        return Err("❌ 時系列出力できる足がありません".into());
     }
-}
-///CSVヘッダー出力
-fn generate_csv_header(config: &Config) {
-    let mut headers = vec![
-        "ticker",
-        "date",
-        "datetime", 
-        "timezone",
-        "close",
-        "prev_close",
-        "diff",
-        "diff_pct",
-        "macd",
-        "signal",
-        "rsi",
-        "score",
-    ];
 
-    for indicator in &config.enabled_extensions {
-        match indicator {
-            ExtensionIndicator::Ema => {
-                headers.extend_from_slice(&["ema_short", "ema_long", "ema_score"]);
-            }
-            ExtensionIndicator::Sma => {
-                headers.extend_from_slice(&["sma_short", "sma_long", "sma_score"]);
-            }
-            ExtensionIndicator::Roc => {
-                headers.extend_from_slice(&["roc", "roc_score"]);
-            }
-            ExtensionIndicator::Adx => {
-                headers.extend_from_slice(&["adx", "adx_score"]);
-            }
-            ExtensionIndicator::Stochastics => {
-                headers.extend_from_slice(&["stoch_k", "stoch_d", "stoch_score"]);
-            }
-            ExtensionIndicator::Bollinger => {
-                headers.extend_from_slice(&[
-                    "bb_upper",
-                    "bb_lower",
-                    "percent_b",
-                    "bandwidth_%",
-                    "bb_score",
-                ]);
-            }
-            ExtensionIndicator::Fibonacci => {
-                headers.extend_from_slice(&["fibo_38_2", "fibo_50_0", "fibo_61_8", "fibo_score"]);
-            }
-            ExtensionIndicator::Vwap => {
-                headers.extend_from_slice(&["vwap", "vwap_score"]);
-            }
-            ExtensionIndicator::Ichimoku => {
-                headers.extend_from_slice(&["tenkan", "kijun", "ichimoku_score"]);
-            }
-        }
+    let mut lines: Vec<String> = Vec::with_capacity(rows.len() + 1);
+    if format == "csv" {
+        lines.push(build_csv_header(config));
+    }
+    lines.extend(rows);
+
+    if config.stdout_log {
+        print_lines_to_terminal(&lines);
+        return Ok(());
     }
 
-    headers.push("final_score");
-    println!("{}", headers.join(",")); // ✅ 出力ここで完結
+    let base_dir = Path::new(&config.log_dir);
+    let dir_path = if config.log_flat {
+        base_dir.to_path_buf()
+    } else {
+        base_dir.join(&config.ticker)
+    };
+    create_dir_all(&dir_path)?;
+    let file_path = dir_path.join(format!("{}_series.{}", config.ticker, format));
+    let mut writer = BufWriter::new(std::fs::File::create(&file_path)?);
+    for line in &lines {
+        writeln!(writer, "{}", line)?;
+    }
+    if !config.silent {
+        let bar_count = lines.len() - usize::from(format == "csv");
+        println!("💾 時系列出力: {}（{}本）", file_path.display(), bar_count);
+    }
+    Ok(())
 }
 
 // ログ保存処理（CSV/JSON対応、TechnicalDataGuard経由でセキュア設計）
@@ -3825,9 +10888,10 @@ fn generate_technical_csv_row(
         format!("{:+.2}", guard.get_price_diff_percent()),
         format!("{:.4}", guard.get_macd()),
         format!("{:.4}", guard.get_signal()),
-        format!("{:.2}", guard.get_rsi()),
-        (guard.get_signal_score() as i32).to_string(),
     ];
+    values.extend(latest_cross_log_cells(guard.get_macd_crosses()));
+    values.push(format!("{:.2}", guard.get_rsi()));
+    values.push((guard.get_signal_score() as i32).to_string());
 
     for res in results {
         /* 既存の match ブロックは変更なし */
@@ -3835,53 +10899,141 @@ fn generate_technical_csv_row(
             "EMA" => {
                 values.push(format!("{:.2}", guard.get_ema_short()));
                 values.push(format!("{:.2}", guard.get_ema_long()));
-                values.push(score_to_string(Some(res.score)));
+                values.extend(latest_cross_log_cells(guard.get_ema_crosses()));
+                values.push(score_to_string(res.score));
             }
             "SMA" => {
                 values.push(format!("{:.2}", guard.get_sma_short()));
                 values.push(format!("{:.2}", guard.get_sma_long()));
-                values.push(score_to_string(Some(res.score)));
+                values.extend(latest_cross_log_cells(guard.get_sma_crosses()));
+                values.push(score_to_string(res.score));
             }
             "ROC" => {
                 values.push(opt_f64(guard.get_roc()));
-                values.push(score_to_string(Some(res.score)));
+                values.push(score_to_string(res.score));
             }
             "ADX" => {
                 values.push(opt_f64(guard.get_adx()));
-                values.push(score_to_string(Some(res.score)));
+                values.push(score_to_string(res.score));
             }
             "Stochastics" => {
                 values.push(opt_f64(guard.get_stochastics_k()));
                 values.push(opt_f64(guard.get_stochastics_d()));
-                values.push(score_to_string(Some(res.score)));
+                values.extend(latest_cross_log_cells(guard.get_stoch_crosses()));
+                values.push(score_to_string(res.score));
             }
             "Bollinger" => {
                 values.push(format!("{:.2}", guard.get_bb_upper()));
                 values.push(format!("{:.2}", guard.get_bb_lower()));
                 values.push(format!("{:.2}", guard.get_bb_percent_b()));
                 values.push(format!("{:.2}", guard.get_bb_bandwidth()));
-                values.push(score_to_string(Some(res.score)));
+                values.push(score_to_string(res.score));
             }
             "Fibonacci" => {
+                let swing = guard.get_fib_swing();
+                values.push(
+                    swing
+                        .map(|s| if s.uptrend { "up" } else { "down" })
+                        .unwrap_or_default()
+                        .to_string(),
+                );
+                values.push(opt_f64(swing.map(|s| s.high)));
+                values.push(opt_f64(swing.map(|s| s.low)));
+                values.push(opt_f64(guard.get_fibo_23_6()));
                 values.push(opt_f64(guard.get_fibo_38_2()));
                 values.push(opt_f64(guard.get_fibo_50_0()));
                 values.push(opt_f64(guard.get_fibo_61_8()));
-                values.push(score_to_string(Some(res.score)));
+                values.push(opt_f64(guard.get_fibo_78_6()));
+                values.push(opt_f64(guard.get_fibo_ext_127_2()));
+                values.push(opt_f64(guard.get_fibo_ext_161_8()));
+                values.push(score_to_string(res.score));
             }
             "VWAP" => {
                 values.push(opt_f64(guard.get_vwap()));
-                values.push(score_to_string(Some(res.score)));
+                values.push(score_to_string(res.score));
             }
             "Ichimoku" => {
                 values.push(opt_f64(guard.get_tenkan_sen()));
                 values.push(opt_f64(guard.get_kijun_sen()));
-                values.push(score_to_string(Some(res.score)));
+                values.extend(latest_cross_log_cells(guard.get_ichimoku_crosses()));
+                values.push(score_to_string(res.score));
+            }
+            "Candlestick" => {
+                values.push(format_candle_patterns_for_log(guard.get_candle_patterns()));
+                values.push(score_to_string(res.score));
+            }
+            "SupportResistance" => {
+                let close = guard.get_close();
+                for level in [guard.get_nearest_support(), guard.get_nearest_resistance()] {
+                    values.push(opt_f64(level.map(|l| l.price)));
+                    values.push(opt_f64(level.map(|l| level_distance_pct(close, l.price))));
+                    values.push(level.map(|l| l.touches.to_string()).unwrap_or_default());
+                }
+                values.push(score_to_string(res.score));
+            }
+            "RelativeStrength" => {
+                let rs = guard.get_relative_strength();
+                values.push(rs.map(|r| r.benchmark.clone()).unwrap_or_default());
+                values.push(opt_f64(rs.map(|r| r.stock_return_pct)));
+                values.push(opt_f64(rs.map(|r| r.bench_return_pct)));
+                values.push(opt_f64(rs.map(|r| r.excess_return_pct)));
+                values.push(opt_f64(rs.and_then(|r| r.beta)));
+                values.push(opt_f64(rs.and_then(|r| r.correlation)));
+                values.push(opt_f64(rs.and_then(|r| Some(r.beta? - r.beta_prev?))));
+                values.push(score_to_string(res.score));
+            }
+            name if name.starts_with("Custom:") => {
+                let custom = guard.get_custom_result(&name["Custom:".len()..]);
+                values.push(custom.map(|r| format!("{:.4}", r.value)).unwrap_or_default());
+                values.push(score_to_string(res.score));
+            }
+            name if name.starts_with("Plugin:") => {
+                match guard.get_plugin_outcome(&name["Plugin:".len()..]).map(|o| &o.result) {
+                    Some(Ok(output)) => {
+                        values.push("ok".to_string());
+                        values.push(format_plugin_values_for_log(&output.values));
+                    }
+                    Some(Err(_)) => values.extend(["error".to_string(), String::new()]),
+                    None => values.extend(["missing".to_string(), String::new()]),
+                }
+                values.push(score_to_string(res.score));
             }
             _ => {}
         }
     }
 
     values.push(snap.total_score.to_string()); // 互換維持のため to_string() のまま
+    values.push(format!("{:.4}", snap.score_ratio));
+    values.push(format!("{:.2}", snap.coverage));
+    values.push(snap.missing.join(" | "));
+    let statuses: Vec<String> = guard
+        .get_indicator_statuses()
+        .iter()
+        .map(|(name, status)| format!("{}={}", name, status.label()))
+        .collect();
+    values.push(statuses.join(" | "));
+    values.push(snap.preset.clone().unwrap_or_default());
+    values.push(stance_caption(&snap.stance).to_lowercase());
+    let (_, judgement) = effective_judgement(guard, &snap.stance, snap.score_ratio);
+    let direction = judgement_direction(&snap.stance, &judgement);
+    values.push(judgement);
+    values.push(direction_key(direction).to_string());
+    for cat in &snap.categories {
+        values.push(format!("{:.2}", cat.score));
+        values.push(opt_f64(cat.ratio));
+    }
+    if let Some(regime) = guard.get_market_regime() {
+        let weights: Vec<String> = regime
+            .effective_weights
+            .iter()
+            .map(|(name, w)| format!("{}={:.2}", name, w))
+            .collect();
+        values.push(regime.kind.key().to_string());
+        values.push(opt_f64(regime.adx));
+        values.push(opt_f64(regime.bandwidth_pct));
+        values.push(regime.slope_pct.map(|v| format!("{:.3}", v)).unwrap_or_default());
+        values.push(weights.join(" | "));
+    }
 
     Ok(values.join(","))
 }
@@ -3945,21 +11097,41 @@ fn generate_technical_json_string(
         "diff_pct": guard.get_price_diff_percent(),
         "macd": guard.get_macd(),
         "signal": guard.get_signal(),
+        "macd_crosses": crosses_to_json(guard.get_macd_crosses()),
         "rsi": guard.get_rsi(),
         "score": guard.get_signal_score()
     });
 
+    if let Some(risk) = guard.get_risk_stats() {
+        json_obj["risk"] = json!({
+            "samples": risk.samples,
+            "volatility_annual_pct": risk.volatility_annual_pct,
+            "max_drawdown_pct": risk.max_drawdown_pct,
+            "max_drawdown_peak": risk.max_drawdown_peak,
+            "max_drawdown_trough": risk.max_drawdown_trough,
+            "current_drawdown_pct": risk.current_drawdown_pct,
+            "peak_date": risk.peak_date,
+            "sharpe": risk.sharpe,
+            "sortino": risk.sortino,
+            "rolling_window": risk.rolling_window,
+            "worst_day_pct": risk.worst_day_pct,
+            "worst_day_date": risk.worst_day_date,
+        });
+    }
+
     for ext in &config.enabled_extensions {
         /* 既存の match はそのまま */
         match ext {
             ExtensionIndicator::Ema => {
                 json_obj["ema_short"] = json!(guard.get_ema_short());
                 json_obj["ema_long"] = json!(guard.get_ema_long());
+                json_obj["ema_crosses"] = crosses_to_json(guard.get_ema_crosses());
                 json_obj["ema_score"] = json!(guard.get_ema_score());
             }
             ExtensionIndicator::Sma => {
                 json_obj["sma_short"] = json!(guard.get_sma_short());
                 json_obj["sma_long"] = json!(guard.get_sma_long());
+                json_obj["sma_crosses"] = crosses_to_json(guard.get_sma_crosses());
                 json_obj["sma_score"] = json!(guard.get_sma_score());
             }
             ExtensionIndicator::Roc => {
@@ -3973,6 +11145,7 @@ fn generate_technical_json_string(
             ExtensionIndicator::Stochastics => {
                 json_obj["stoch_k"] = json!(guard.get_stochastics_k());
                 json_obj["stoch_d"] = json!(guard.get_stochastics_d());
+                json_obj["stoch_crosses"] = crosses_to_json(guard.get_stoch_crosses());
                 json_obj["stoch_score"] = json!(guard.get_stochastics_score());
             }
             ExtensionIndicator::Bollinger => {
@@ -3982,28 +11155,377 @@ fn generate_technical_json_string(
                 json_obj["bandwidth_%"] = json!(guard.get_bb_bandwidth());
                 json_obj["bb_score"] = json!(guard.get_bollinger_score());
             }
-            ExtensionIndicator::Fibonacci => {
-                json_obj["fibo_38_2"] = json!(guard.get_fibo_38_2());
-                json_obj["fibo_50_0"] = json!(guard.get_fibo_50_0());
-                json_obj["fibo_61_8"] = json!(guard.get_fibo_61_8());
-                json_obj["fibo_score"] = json!(guard.get_fibonacci_score());
+            ExtensionIndicator::Fibonacci => {
+                json_obj["fibo_swing"] = json!(guard.get_fib_swing().map(|s| json!({
+                    "direction": if s.uptrend { "up" } else { "down" },
+                    "high": s.high,
+                    "high_date": s.high_date,
+                    "low": s.low,
+                    "low_date": s.low_date,
+                })));
+                json_obj["fibo_depth"] = json!(guard.get_fib_depth());
+                json_obj["fibo_23_6"] = json!(guard.get_fibo_23_6());
+                json_obj["fibo_38_2"] = json!(guard.get_fibo_38_2());
+                json_obj["fibo_50_0"] = json!(guard.get_fibo_50_0());
+                json_obj["fibo_61_8"] = json!(guard.get_fibo_61_8());
+                json_obj["fibo_78_6"] = json!(guard.get_fibo_78_6());
+                json_obj["fibo_ext_127_2"] = json!(guard.get_fibo_ext_127_2());
+                json_obj["fibo_ext_161_8"] = json!(guard.get_fibo_ext_161_8());
+                json_obj["fibo_score"] = json!(guard.get_fibonacci_score());
+            }
+            ExtensionIndicator::Vwap => {
+                json_obj["vwap"] = json!(guard.get_vwap());
+                json_obj["vwap_score"] = json!(guard.get_vwap_score());
+            }
+            ExtensionIndicator::Ichimoku => {
+                json_obj["tenkan"] = json!(guard.get_tenkan_sen());
+                json_obj["kijun"] = json!(guard.get_kijun_sen());
+                json_obj["ichimoku_crosses"] = crosses_to_json(guard.get_ichimoku_crosses());
+                json_obj["ichimoku_score"] = json!(guard.get_ichimoku_score());
+            }
+            ExtensionIndicator::Candlestick => {
+                json_obj["candle_patterns"] = json!(guard
+                    .get_candle_patterns()
+                    .iter()
+                    .map(|p| json!({ "name": p.name, "date": p.date, "score": p.score }))
+                    .collect::<Vec<_>>());
+                json_obj["candle_score"] = json!(guard.get_candlestick_score());
+            }
+            ExtensionIndicator::SupportResistance => {
+                let close = guard.get_close();
+                let level_json = |l: &PriceLevel| {
+                    json!({
+                        "price": l.price,
+                        "distance_pct": level_distance_pct(close, l.price),
+                        "touches": l.touches,
+                        "last_date": l.last_date,
+                    })
+                };
+                json_obj["sr_support"] = json!(guard.get_nearest_support().map(level_json));
+                json_obj["sr_resistance"] = json!(guard.get_nearest_resistance().map(level_json));
+                json_obj["sr_levels"] = json!(guard
+                    .get_sr_levels()
+                    .iter()
+                    .map(level_json)
+                    .collect::<Vec<_>>());
+                json_obj["sr_score"] = json!(guard.get_support_resistance_score());
+            }
+            ExtensionIndicator::RelativeStrength => {
+                json_obj["relative_strength"] = json!(guard.get_relative_strength().map(|r| {
+                    json!({
+                        "benchmark": r.benchmark,
+                        "lookback": RS_LOOKBACK,
+                        "stock_return_pct": r.stock_return_pct,
+                        "bench_return_pct": r.bench_return_pct,
+                        "excess_return_pct": r.excess_return_pct,
+                        "beta": r.beta,
+                        "correlation": r.correlation,
+                        "samples": r.samples,
+                        "beta_prev": r.beta_prev,
+                    })
+                }));
+                json_obj["rs_score"] = json!(guard.get_relative_strength_score());
+            }
+            ExtensionIndicator::Custom(index) => {
+                let custom = &config.custom_indicators[*index];
+                let result = guard.get_custom_result(&custom.name);
+                json_obj["custom"][custom.name.as_str()] = json!({
+                    "expr": custom.expr,
+                    "value": result.map(|r| r.value),
+                    "score": result.map(|r| r.score),
+                    "weight": custom.weight,
+                });
+            }
+            ExtensionIndicator::Plugin(index) => {
+                let plugin = &config.plugins[*index];
+                json_obj["plugins"][plugin.name.as_str()] =
+                    match guard.get_plugin_outcome(&plugin.name).map(|o| &o.result) {
+                        Some(Ok(output)) => json!({
+                            "status": "ok",
+                            "values": output.values.iter().cloned().collect::<HashMap<_, _>>(),
+                            "description": output.description,
+                            "score": output.score,
+                            "weight": plugin.weight,
+                        }),
+                        Some(Err(e)) => json!({
+                            "status": "error",
+                            "error": e,
+                            "score": Value::Null,
+                            "weight": plugin.weight,
+                        }),
+                        None => json!({ "status": "missing", "score": Value::Null }),
+                    };
+            }
+        }
+    }
+
+    // ✅ 再計算禁止：スナップショットから入れる
+    json_obj["final_score"] = json!(snap.total_score);
+    json_obj["score_ratio"] = json!(snap.score_ratio);
+    if let Some(smoothing) = guard.get_score_smoothing() {
+        let (smoothed, judgement) = smoothed_judgement(smoothing, &config.stance, snap.score_ratio);
+        json_obj["score_smoothing"] = json!({
+            "bars": smoothing.bars,
+            "hysteresis": smoothing.hysteresis,
+            "smoothed_ratio": smoothed,
+            "raw_judgement": stance_judgement(&config.stance, snap.score_ratio),
+            "smoothed_judgement": judgement,
+        });
+    }
+    json_obj["score_coverage"] = json!(snap.coverage);
+    json_obj["missing_indicators"] = json!(snap.missing);
+    let mut statuses = serde_json::Map::new();
+    for (name, status) in guard.get_indicator_statuses() {
+        statuses.insert(
+            name.clone(),
+            json!({ "status": status.label(), "reason": status.reason() }),
+        );
+    }
+    json_obj["indicator_status"] = Value::Object(statuses);
+    json_obj["preset"] = json!(snap.preset);
+    json_obj["stance"] = json!(stance_caption(&snap.stance).to_lowercase());
+    let (_, judgement) = effective_judgement(guard, &snap.stance, snap.score_ratio);
+    json_obj["direction"] = json!(direction_key(judgement_direction(&snap.stance, &judgement)));
+    json_obj["judgement"] = json!(judgement);
+    let mut categories = serde_json::Map::new();
+    for cat in &snap.categories {
+        categories.insert(
+            cat.key.to_string(),
+            json!({ "score": cat.score, "weight": cat.weight, "ratio": cat.ratio }),
+        );
+    }
+    json_obj["category_scores"] = Value::Object(categories);
+    json_obj["score_contributions"] = Value::Array(
+        snap.contributions
+            .iter()
+            .map(|c| {
+                json!({
+                    "indicator": c.indicator,
+                    "score": c.score,
+                    "weight": c.weight,
+                    "contribution": c.contribution,
+                    "reason_codes": c.reason_codes,
+                })
+            })
+            .collect(),
+    );
+    if let Some(regime) = guard.get_market_regime() {
+        let mut weights = serde_json::Map::new();
+        for (name, w) in &regime.effective_weights {
+            weights.insert(name.clone(), json!(w));
+        }
+        json_obj["regime"] = json!({
+            "kind": regime.kind.key(),
+            "adx": regime.adx,
+            "bandwidth_pct": regime.bandwidth_pct,
+            "slope_pct": regime.slope_pct,
+            "weight_basic": config.weight_basic,
+            "effective_weights": Value::Object(weights),
+        });
+    }
+
+    Ok(serde_json::to_string(&json_obj)?)
+}
+
+/// 過去ログ（<log_dir>/<ticker>/<ticker>.csv|json）を読み戻し、前回と N日前の実行を取り出す（今回分の保存前に呼ぶ）
+fn load_score_history(config: &Config, guard: &TechnicalDataGuard) -> Option<ScoreHistory> {
+    if config.history_days == 0 {
+        return None;
+    }
+    let (records, skipped_rows) = read_logged_runs(config, guard.get_ticker());
+    if records.is_empty() && skipped_rows == 0 {
+        return None;
+    }
+
+    // 末尾（最も新しい日付）を「前回」とする
+    let days_ago = parse_log_date(guard.get_date())
+        .map(|today| today - chrono::Duration::days(config.history_days as i64))
+        .and_then(|target| {
+            records
+                .iter()
+                .rev()
+                .find(|r| parse_log_date(&r.date).is_some_and(|d| d <= target))
+                .cloned()
+        });
+
+    Some(ScoreHistory {
+        previous: records.last().cloned(),
+        days_ago,
+        days: config.history_days,
+        skipped_rows,
+    })
+}
+
+/// 銘柄の過去ログ（CSV と JSON の両方）を読み、日付順（同日は記録順）に並べて返す。usize は読めなかった行の数。
+/// CSV はヘッダー行があればその列名で、なければ現在の設定の列構成と列数が一致する行だけを読む。
+fn read_logged_runs(config: &Config, ticker: &str) -> (Vec<ScoreHistoryRecord>, usize) {
+    let base_dir = Path::new(&config.log_dir);
+    let dir_path = if config.log_flat {
+        base_dir.to_path_buf()
+    } else {
+        base_dir.join(ticker)
+    };
+
+    let mut records: Vec<ScoreHistoryRecord> = Vec::new();
+    let mut skipped_rows = 0;
+
+    if let Ok(content) = read_to_string(dir_path.join(format!("{}.csv", ticker))) {
+        let current_header: Vec<String> =
+            build_csv_header(config).split(',').map(str::to_string).collect();
+        let mut header: Option<Vec<String>> = None;
+        for line in content.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let cells: Vec<&str> = line.split(',').collect();
+            if cells.first() == Some(&"ticker") {
+                header = Some(cells.iter().map(|c| c.to_string()).collect());
+                continue;
+            }
+            // 直前のヘッダー行 → 現在の列構成の順に、列数が一致するもので読む
+            let record = header
+                .iter()
+                .chain(std::iter::once(&current_header))
+                .find(|columns| columns.len() == cells.len())
+                .and_then(|columns| parse_history_csv_row(columns, &cells));
+            match record {
+                Some(record) => records.push(record),
+                None => skipped_rows += 1,
+            }
+        }
+    }
+    if let Ok(content) = read_to_string(dir_path.join(format!("{}.json", ticker))) {
+        for line in content.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            match serde_json::from_str::<Value>(line)
+                .ok()
+                .and_then(|v| parse_history_json(&v))
+            {
+                Some(record) => records.push(record),
+                None => skipped_rows += 1,
+            }
+        }
+    }
+    records.sort_by(|a, b| a.date.cmp(&b.date));
+    (records, skipped_rows)
+}
+
+/// ログの stance 列（"buyer"|"seller"|"holder"）を解釈する
+fn parse_logged_stance(value: &str) -> Option<Stance> {
+    ["buyer", "seller", "holder"]
+        .contains(&value)
+        .then(|| parse_stance(value))
+}
+
+/// ログの日付（先頭10文字 YYYY-MM-DD）を解釈する
+fn parse_log_date(date: &str) -> Option<chrono::NaiveDate> {
+    chrono::NaiveDate::parse_from_str(date.get(..10)?, "%Y-%m-%d").ok()
+}
+
+/// ログのスコア列名（JSONキー）→ 指標のログ名（例: "ema_score" → "EMA"、"score" → 基本解析）
+fn score_column_indicator(column: &str) -> Option<String> {
+    let name = match column {
+        "score" => "Basic",
+        "ema_score" => "EMA",
+        "sma_score" => "SMA",
+        "roc_score" => "ROC",
+        "adx_score" => "ADX",
+        "stoch_score" => "Stochastics",
+        "bb_score" => "Bollinger",
+        "fibo_score" => "Fibonacci",
+        "vwap_score" => "VWAP",
+        "ichimoku_score" => "Ichimoku",
+        "candle_score" => "Candlestick",
+        "sr_score" => "SupportResistance",
+        "rs_score" => "RelativeStrength",
+        _ => {
+            if let Some(name) = column.strip_prefix("custom_").and_then(|c| c.strip_suffix("_score")) {
+                return Some(format!("Custom:{}", name));
+            }
+            return column
+                .strip_prefix("plugin_")
+                .and_then(|c| c.strip_suffix("_score"))
+                .map(|name| format!("Plugin:{}", name));
+        }
+    };
+    Some(name.to_string())
+}
+
+/// CSVログの1行を列名で読む（final_score が読めない行は None）
+fn parse_history_csv_row(columns: &[String], cells: &[&str]) -> Option<ScoreHistoryRecord> {
+    let cell = |name: &str| {
+        columns
+            .iter()
+            .position(|c| c == name)
+            .map(|i| cells[i].trim())
+    };
+    let final_score = cell("final_score")?.parse::<f64>().ok()?;
+    // 空欄のスコア列は読み飛ばす。古いログは評価に失敗した指標のスコア列が 0 のため、ステータス列で除外する
+    let failed: Vec<&str> = cell("indicator_status")
+        .unwrap_or_default()
+        .split(" | ")
+        .filter_map(|entry| entry.split_once('='))
+        .filter(|(_, status)| *status != "ok")
+        .map(|(name, _)| name)
+        .collect();
+    let indicator_scores = columns
+        .iter()
+        .zip(cells)
+        .filter_map(|(column, value)| {
+            let name = score_column_indicator(column)?;
+            let score = value.trim().parse::<f64>().ok()?;
+            (!failed.contains(&name.as_str())).then_some((name, score))
+        })
+        .collect();
+
+    Some(ScoreHistoryRecord {
+        date: cell("date")?.to_string(),
+        final_score,
+        score_ratio: cell("score_ratio").and_then(|v| v.parse().ok()),
+        stance: cell("stance").and_then(parse_logged_stance),
+        judgement: cell("judgement")
+            .filter(|j| !j.is_empty())
+            .zip(cell("direction").and_then(parse_direction_key))
+            .map(|(j, d)| (j.to_string(), d)),
+        indicator_scores,
+    })
+}
+
+/// JSONログの1行を読む（score_contributions がない古いログは各スコアキーから拾う）
+fn parse_history_json(value: &Value) -> Option<ScoreHistoryRecord> {
+    let final_score = value["final_score"].as_f64()?;
+    let mut indicator_scores: Vec<(String, f64)> = Vec::new();
+    if let Some(contributions) = value["score_contributions"].as_array() {
+        for c in contributions {
+            if let (Some(name), Some(score)) = (c["indicator"].as_str(), c["score"].as_f64()) {
+                indicator_scores.push((name.to_string(), score));
             }
-            ExtensionIndicator::Vwap => {
-                json_obj["vwap"] = json!(guard.get_vwap());
-                json_obj["vwap_score"] = json!(guard.get_vwap_score());
+        }
+    } else if let Some(object) = value.as_object() {
+        for (key, v) in object {
+            if let (Some(name), Some(score)) = (score_column_indicator(key), v.as_f64()) {
+                indicator_scores.push((name, score));
             }
-            ExtensionIndicator::Ichimoku => {
-                json_obj["tenkan"] = json!(guard.get_tenkan_sen());
-                json_obj["kijun"] = json!(guard.get_kijun_sen());
-                json_obj["ichimoku_score"] = json!(guard.get_ichimoku_score());
+        }
+        for (group, prefix) in [("custom", "Custom"), ("plugins", "Plugin")] {
+            if let Some(entries) = value[group].as_object() {
+                for (name, entry) in entries {
+                    if let Some(score) = entry["score"].as_f64() {
+                        indicator_scores.push((format!("{}:{}", prefix, name), score));
+                    }
+                }
             }
         }
     }
 
-    // ✅ 再計算禁止：スナップショットから入れる
-    json_obj["final_score"] = json!(snap.total_score);
-
-    Ok(serde_json::to_string(&json_obj)?)
+    Some(ScoreHistoryRecord {
+        date: value["date"].as_str()?.to_string(),
+        final_score,
+        score_ratio: value["score_ratio"].as_f64(),
+        stance: value["stance"].as_str().and_then(parse_logged_stance),
+        judgement: value["judgement"]
+            .as_str()
+            .filter(|j| !j.is_empty())
+            .zip(value["direction"].as_str().and_then(parse_direction_key))
+            .map(|(j, d)| (j.to_string(), d)),
+        indicator_scores,
+    })
 }
 
 ///ニュース記事構造体
@@ -4347,6 +11869,14 @@ async fn compose_llm_prompt_lines(
         lines.push(String::new());
     }
 
+    if let Some(preset) = &config.preset {
+        lines.push(format!(
+            "🎛️ 戦略プリセット「{}」の重み・閾値で評価しています。レポートの時間軸と論調もこの戦略に合わせてください。",
+            preset
+        ));
+        lines.push(String::new());
+    }
+
     lines.push(format!(
         "📊 銘柄: {}（{}）",
         guard.get_name(),
@@ -4362,6 +11892,8 @@ async fn compose_llm_prompt_lines(
     ));
     lines.push(String::new());
 
+    lines.extend(compose_risk_lines(guard));
+
     let basic = render_basic(config, guard);
     lines.extend(basic.description.clone());
     lines.push(String::new());
@@ -4374,6 +11906,8 @@ async fn compose_llm_prompt_lines(
         }
     }
 
+    lines.extend(compose_regime_lines(guard));
+
     let snap = calculate_final_score_snapshot(config, guard);
     for line in compose_final_score_lines_stance(&snap, &config.stance, true) {
         if !line.is_empty() {
@@ -4381,6 +11915,8 @@ async fn compose_llm_prompt_lines(
         }
     }
     lines.push(String::new());
+    lines.extend(compose_score_smoothing_lines(guard, &snap, &config.stance));
+    lines.extend(compose_score_history_lines(guard, &snap));
 
     let mut news_task_directive =
         "対象が0件なら『株価に関係する評価対象ニュースはありません』と 1 行だけ記載。".to_string();
@@ -4396,7 +11932,7 @@ async fn compose_llm_prompt_lines(
                     "この実行ではニュース取得に失敗しスキップ。ニュース節には『取得失敗によりスキップ』と 1 行だけ記載。"
                         .to_string();
             }
-            Some(slice) if slice.is_empty() => {
+            Some([]) => {
                 // 正常に呼ばれ、該当記事がゼロ件だったケース
                 lines.push("【注記】対象期間に該当ニュースなし。".to_string());
                 lines.push(String::new());
@@ -4459,11 +11995,69 @@ async fn compose_llm_prompt_lines(
         (false, _) => "※『MACDマイナス許容』設定: 無効",
     };
     lines.push(macd_policy.to_string());
+    if !snap.missing.is_empty() {
+        lines.push(format!(
+            "- スコアを算出できなかった指標（{}）は根拠に含めない。総合判定はカバレッジ{:.0}%の結果である旨を明記。",
+            snap.missing.join(", "),
+            snap.coverage * 100.0
+        ));
+    }
     lines.push(
         "- ニュース0件時は“テクニカル主導”と明記。件数>0なら冒頭に要点の箇条書きから入る。"
             .to_string(),
     );
     lines.push("- 少なくとも2つのシナリオ（例：短期反発/続落/レンジ）を提示し、各々「条件→行動（エントリー/撤退/利確帯）」を具体化。".to_string());
+    if config
+        .enabled_extensions
+        .contains(&ExtensionIndicator::SupportResistance)
+    {
+        let close = guard.get_close();
+        if let Some(s) = guard.get_nearest_support() {
+            lines.push(format!(
+                "- 撤退/押し目の基準は直近サポート {:.2}（{:+.2}%、タッチ{}回）を使用。",
+                s.price,
+                level_distance_pct(close, s.price),
+                s.touches
+            ));
+        }
+        if let Some(r) = guard.get_nearest_resistance() {
+            lines.push(format!(
+                "- 利確/ブレイクの基準は直近レジスタンス {:.2}（{:+.2}%、タッチ{}回）を使用。",
+                r.price,
+                level_distance_pct(close, r.price),
+                r.touches
+            ));
+        }
+    }
+    if let Some(rs) = guard.get_relative_strength() {
+        let fmt_opt = |v: Option<f64>| v.map(|x| format!("{:.2}", x)).unwrap_or("-".into());
+        lines.push(format!(
+            "- 市場（{}）との比較：直近{}本の超過リターン {:+.2}%、ベータ {}、相関 {}。地合い要因と個別要因を区別して記述。",
+            rs.benchmark,
+            RS_LOOKBACK,
+            rs.excess_return_pct,
+            fmt_opt(rs.beta),
+            fmt_opt(rs.correlation)
+        ));
+    }
+    if config
+        .enabled_extensions
+        .iter()
+        .any(|e| matches!(e, ExtensionIndicator::Custom(_)))
+    {
+        lines.push("- カスタム指標はユーザー定義の式とスコア帯の結果として扱い、式に無い意味を推測で補わない。".to_string());
+    }
+    if let Some(regime) = guard.get_market_regime() {
+        if matches!(regime.kind, RegimeKind::Trending | RegimeKind::Ranging) {
+            lines.push(format!(
+                "- 総合スコアは【相場局面】（{}）に応じて重みを補正済み。局面に合わない指標のシグナルは割り引いて解釈する。",
+                regime.kind.label()
+            ));
+        }
+    }
+    if guard.get_risk_stats().is_some() {
+        lines.push("- リスクへの言及（値動きの荒さ・下落余地・損切り幅）は上の【リスク統計】の数値を根拠とする。".to_string());
+    }
     lines.push("- 小数は原則2桁。桁飛び・丸め過ぎ・矛盾記述は禁止。".to_string());
     lines.push("- 誰にも分かりやすくするため指標の略称は禁止。例えば、ボリンジャーバンドと正しく出力し、”BB”というように略称を使わないこと".to_string());
     lines.push("【記述順序ルール】".to_string());
@@ -4588,6 +12182,342 @@ fn save_prompt_to_file(prompt: &str) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// 価格取得後の1銘柄分の分析（相場局面・拡張指標・欠損スコア・平滑化・過去ログ比較）。
+/// 戻り値は相場局面で補正した実効設定
+fn complete_ticker_analysis(
+    base_config: &Config,
+    series: &IndicatorSeries,
+    guard: &mut TechnicalDataGuard,
+    sorted_data: &[MarketData],
+    ticker_name_map: &HashMap<String, String>,
+) -> Result<Config, Box<dyn std::error::Error>> {
+    // ✅ 相場局面に応じた実効Weight（--regime-weighting 時のみ補正）
+    let config = apply_market_regime(base_config, series, sorted_data, guard);
+
+    // ✅ 拡張分析スコアを必要に応じて格納（セキュア、指標ごとに失敗を隔離）
+    evaluate_all_selected_extensions(&config, series, sorted_data, guard, true);
+    report_indicator_failures(guard);
+
+    // ✅ 欠損スコアの扱い（--missing-score-policy=fail なら総合判定を出さずに終了）
+    check_missing_scores(&config, guard)?;
+
+    // ✅ スコア比率の平滑化・ヒステリシス（指定時のみ。直近の足をその足までのデータで再評価）
+    if let Some(smoothing) =
+        compute_score_smoothing(base_config, series, sorted_data, ticker_name_map)
+    {
+        guard.set_score_smoothing(smoothing);
+    }
+
+    // ✅ 過去ログとの比較（今回分を保存する前に読み戻す）
+    if let Some(history) = load_score_history(&config, guard) {
+        guard.set_score_history(history);
+    }
+    Ok(config)
+}
+
+/// 1銘柄分の出力（画面・ログ、ペーパートレード、全期間の時系列）
+fn emit_ticker_outputs(
+    base_config: &Config,
+    config: &Config,
+    guard: &TechnicalDataGuard,
+    series: &IndicatorSeries,
+    sorted_data: &[MarketData],
+    ticker_name_map: &HashMap<String, String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // ✅ 画面出力（構造体読み出しのみ）
+    select_output_target(config, guard)?;
+
+    // ✅ ペーパートレード（--paper auto|buy|sell 指定時のみ、今回の終値で約定して台帳に記録）
+    if let Err(e) = apply_paper_trade(config, guard) {
+        eprintln!("❌ ペーパートレードの記録に失敗しました: {}", e);
+    }
+
+    // ✅ 全期間の指標時系列（指定時のみ）
+    if config.series_output.is_some() {
+        // 足ごとに相場局面を判定し直すため、補正前の設定を渡す
+        if let Err(e) = export_indicator_series(base_config, series, sorted_data, ticker_name_map) {
+            eprintln!("❌ 時系列出力に失敗しました: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// 一括実行の一覧表の1行
+#[derive(Debug, Clone, Default)]
+struct BatchRow {
+    ticker: String,
+    name: String,
+    date: String,
+    close: Option<f64>,
+    score_ratio: Option<f64>, // 平滑化を指定していれば平滑化後
+    judgement: String,
+    coverage: Option<f64>,
+    error: Option<String>, // 取得・分析に失敗した理由（成功時は None）
+}
+
+/// 一括実行で分析まで済んだ銘柄（出力は後から銘柄順に行う）
+struct BatchTicker {
+    index: usize,               // ウォッチリスト上の位置
+    base_config: Config,        // 銘柄用の設定（相場局面の補正前）
+    config: Arc<Config>,        // 相場局面で補正した実効設定
+    guard: Arc<TechnicalDataGuard>,
+    series: IndicatorSeries, // 全期間で計算済みの指標系列（時系列出力で再利用）
+    data: Vec<MarketData>,
+}
+
+/// 複数銘柄の株価を並行して取得する（同時に走らせる取得は limit 件まで）。結果は引数の順で、日付昇順に並べ替え済み
+async fn fetch_market_data_concurrently(
+    tickers: &[String],
+    range: &str,
+    limit: usize,
+) -> Vec<Result<Vec<MarketData>, String>> {
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(limit.max(1)));
+    let mut tasks = tokio::task::JoinSet::new();
+    for (i, ticker) in tickers.iter().enumerate() {
+        let (ticker, range, semaphore) = (ticker.clone(), range.to_string(), Arc::clone(&semaphore));
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let result = match fetch_market_data(&ticker, &range).await {
+                Ok(mut data) => {
+                    data.sort_by(|a, b| a.date.cmp(&b.date));
+                    Ok(data)
+                }
+                Err(e) => Err(e.to_string()),
+            };
+            (i, result)
+        });
+    }
+    let mut results: Vec<Result<Vec<MarketData>, String>> =
+        vec![Err("取得が中断されました".to_string()); tickers.len()];
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((i, result)) = joined {
+            results[i] = result;
+        }
+    }
+    results
+}
+
+/// 複数銘柄のニュースを並行して取得する（同時に走らせる取得は limit 件まで）。結果は引数の順
+async fn fetch_news_concurrently(
+    targets: &[(Arc<Config>, Arc<TechnicalDataGuard>)],
+    limit: usize,
+) -> Vec<Vec<Article>> {
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(limit.max(1)));
+    let mut tasks = tokio::task::JoinSet::new();
+    for (i, (config, guard)) in targets.iter().enumerate() {
+        let (config, guard, semaphore) = (Arc::clone(config), Arc::clone(guard), Arc::clone(&semaphore));
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            (i, fetch_news_articles(&guard, &config).await)
+        });
+    }
+    let mut results: Vec<Vec<Article>> = vec![Vec::new(); targets.len()];
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((i, articles)) = joined {
+            results[i] = articles;
+        }
+    }
+    results
+}
+
+/// 一括実行の一覧表（スコア比率の高い順、失敗した銘柄は末尾）
+fn compose_batch_summary_lines(title: &str, rows: &[BatchRow]) -> Vec<String> {
+    let mut sorted: Vec<&BatchRow> = rows.iter().collect();
+    sorted.sort_by(|a, b| {
+        let key = |r: &BatchRow| r.score_ratio.unwrap_or(f64::NEG_INFINITY);
+        key(b).partial_cmp(&key(a)).unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let ok = rows.iter().filter(|r| r.error.is_none()).count();
+
+    let mut lines = vec![
+        String::new(),
+        format!("📋 【一括実行の一覧】{}（{}/{}銘柄）", title, ok, rows.len()),
+        format!(
+            "  {:<10} {:<10} {:>12} {:>8} {:>6}  {}",
+            "銘柄", "日付", "終値", "スコア", "被覆率", "判定"
+        ),
+    ];
+    for row in sorted {
+        match &row.error {
+            Some(e) => lines.push(format!("  {:<10} ❌ {}", row.ticker, e)),
+            None => lines.push(format!(
+                "  {:<10} {:<10} {:>12.2} {:>+8.2} {:>5.0}%  {}  {}",
+                row.ticker,
+                row.date,
+                row.close.unwrap_or(0.0),
+                row.score_ratio.unwrap_or(0.0),
+                row.coverage.unwrap_or(0.0) * 100.0,
+                row.judgement,
+                if row.name == row.ticker { "" } else { row.name.as_str() }
+            )),
+        }
+    }
+    lines.push(String::new());
+    lines
+}
+
+/// 一括実行（--watchlist / -t A,B,…）：銘柄ごとに通常の分析を一通り行い、最後に一覧表を出す。
+/// 株価とニュースは --concurrency 件まで並行して取得し、画面出力・ログ・台帳の更新は銘柄順に1つずつ行う
+async fn run_batch(
+    config: &Config,
+    ticker_name_map: &HashMap<String, String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if config.backtest || config.optimize {
+        return Err("❌ --backtest / --optimize は1銘柄ずつ実行してください".into());
+    }
+    // 手動の売買は1ポジションへの操作なので、全銘柄に同じ数量で約定させない（一括時は auto のみ）
+    if matches!(config.paper, Some(PaperAction::Buy | PaperAction::Sell)) && config.watchlist.len() > 1 {
+        return Err("❌ --paper buy / sell は1銘柄ずつ実行してください（一括実行では --paper auto を使ってください）".into());
+    }
+    let tickers = config.watchlist.clone();
+    let configs: Vec<Config> = tickers.iter().map(|t| config_for_ticker(config, t)).collect();
+    if !config.silent {
+        println!(
+            "⏳ {}銘柄の株価を取得中（同時 {}件まで）…",
+            tickers.len(),
+            config.concurrency
+        );
+    }
+
+    // ✅ 株価とベンチマークをまとめて並行取得（ベンチマークは重複を除く）
+    let uses_benchmark = config
+        .enabled_extensions
+        .contains(&ExtensionIndicator::RelativeStrength);
+    let mut benchmarks: Vec<String> = Vec::new();
+    if uses_benchmark {
+        for cfg in &configs {
+            if !benchmarks.contains(&cfg.benchmark) {
+                benchmarks.push(cfg.benchmark.clone());
+            }
+        }
+    }
+    let symbols: Vec<String> = tickers.iter().chain(benchmarks.iter()).cloned().collect();
+    let mut fetched = fetch_market_data_concurrently(&symbols, "3mo", config.concurrency).await;
+    let benchmark_data: HashMap<String, Vec<MarketData>> = benchmarks
+        .iter()
+        .zip(fetched.split_off(tickers.len()))
+        .filter_map(|(symbol, result)| match result {
+            Ok(data) => Some((symbol.clone(), data)),
+            Err(e) => {
+                eprintln!("⚠️ ベンチマーク（{}）の取得に失敗しました: {}", symbol, e);
+                None
+            }
+        })
+        .collect();
+
+    // ✅ 銘柄ごとの分析（取得・分析に失敗した銘柄は一覧に理由を残して続行）
+    let mut rows: Vec<BatchRow> = Vec::new();
+    let mut analyzed: Vec<BatchTicker> = Vec::new();
+    for (i, (cfg, result)) in configs.into_iter().zip(fetched).enumerate() {
+        rows.push(BatchRow {
+            ticker: tickers[i].clone(),
+            ..Default::default()
+        });
+        let data = match result {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("⚠️ {} の株価を取得できません: {}", tickers[i], e);
+                rows[i].error = Some(format!("株価の取得に失敗: {}", e));
+                continue;
+            }
+        };
+        let benchmark = benchmark_data.get(&cfg.benchmark).map(|d| d.as_slice());
+        let analysis = IndicatorSeries::new(&cfg, &data, benchmark).and_then(|series| {
+            let mut guard = build_basic_technical_entry(&cfg, &series, &data, ticker_name_map)?;
+            let effective = complete_ticker_analysis(&cfg, &series, &mut guard, &data, ticker_name_map)?;
+            Ok((effective, guard, series))
+        });
+        match analysis {
+            Ok((effective, guard, series)) => analyzed.push(BatchTicker {
+                index: i,
+                base_config: cfg,
+                config: Arc::new(effective),
+                guard: Arc::new(guard),
+                series,
+                data,
+            }),
+            Err(e) => {
+                eprintln!("⚠️ {} を分析できません: {}", tickers[i], e);
+                rows[i].error = Some(format!("分析に失敗: {}", e));
+            }
+        }
+    }
+    if analyzed.is_empty() {
+        return Err("❌ 分析できた銘柄がありません".into());
+    }
+
+    // ✅ ニュースを並行取得（通常の実行と同じく、キーがなければ空）
+    let news_targets: Vec<(Arc<Config>, Arc<TechnicalDataGuard>)> = analyzed
+        .iter()
+        .map(|t| (Arc::clone(&t.config), Arc::clone(&t.guard)))
+        .collect();
+    let news = fetch_news_concurrently(&news_targets, config.concurrency).await;
+
+    // ✅ 画面出力・ログ・台帳・LLM は銘柄順に1つずつ（出力が混ざらないように）
+    let total = analyzed.len();
+    for (n, (entry, articles)) in analyzed.into_iter().zip(news).enumerate() {
+        let (i, base_cfg, effective, guard) = (entry.index, &entry.base_config, &entry.config, &entry.guard);
+        if !config.silent {
+            println!();
+            println!("━━━━━━━━ [{}/{}] {} ━━━━━━━━", n + 1, total, tickers[i]);
+        }
+        if let Err(e) = emit_ticker_outputs(base_cfg, effective, guard, &entry.series, &entry.data, ticker_name_map) {
+            eprintln!("⚠️ {} の出力に失敗しました: {}", tickers[i], e);
+            rows[i].error = Some(format!("出力に失敗: {}", e));
+            continue;
+        }
+        display_news(guard, effective, &articles);
+        if !effective.no_llm {
+            let news_arg: Option<&[Article]> = if effective.no_news {
+                None
+            } else {
+                Some(articles.as_slice())
+            };
+            if let Err(e) = llm_flow_controller(effective, guard, news_arg).await {
+                eprintln!("⚠️ {} の LLM 分析に失敗しました: {}", tickers[i], e);
+            }
+        }
+
+        let snap = calculate_final_score_snapshot(effective, guard);
+        let (ratio, judgement) = effective_judgement(guard, &effective.stance, snap.score_ratio);
+        rows[i] = BatchRow {
+            ticker: tickers[i].clone(),
+            name: guard.get_name().to_string(),
+            date: guard.get_date().to_string(),
+            close: Some(guard.get_close()),
+            score_ratio: Some(ratio),
+            judgement,
+            coverage: Some(snap.coverage),
+            error: None,
+        };
+    }
+
+    // ✅ 一覧表（ログ保存を指定していれば CSV にも残す）
+    if !config.silent {
+        print_lines_to_terminal(&compose_batch_summary_lines(&watchlist_label(config), &rows));
+    }
+    if config.save_technical_log {
+        let mut lines = vec!["ticker,name,date,close,score_ratio,judgement,coverage,error".to_string()];
+        for row in &rows {
+            lines.push(
+                [
+                    row.ticker.clone(),
+                    row.name.replace(',', " "),
+                    row.date.clone(),
+                    row.close.map(|v| format!("{:.2}", v)).unwrap_or_default(),
+                    row.score_ratio.map(|v| format!("{:.4}", v)).unwrap_or_default(),
+                    row.judgement.clone(),
+                    row.coverage.map(|v| format!("{:.2}", v)).unwrap_or_default(),
+                    row.error.as_deref().unwrap_or_default().replace(',', " "),
+                ]
+                .join(","),
+            );
+        }
+        write_research_csv(config, "batch_summary", &lines)?;
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
 //    from_filename("tickwise.env").ok();
@@ -4597,28 +12527,97 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 // ✅ 初期化（設定・キー・CSVエイリアス）
     let (config, ticker, ticker_name_map) = initialize_environment_and_config()?;
 
+    // ✅ 指標スコア別の先読みリターン集計（指定時は銘柄ごとに長期の履歴を取得して集計のみ行う）
+    if config.hit_rate {
+        return run_hit_rate_report(&config, &ticker_name_map).await;
+    }
+    // ✅ 過去ログの判定の答え合わせ（指定時は採点のみ行う）
+    if config.track_outcomes {
+        return run_outcome_tracking(&config).await;
+    }
+    // ✅ ペーパートレード台帳の報告・初期化（指定時は台帳の操作のみ行う）
+    match config.paper {
+        Some(PaperAction::Report) => return run_paper_report(&config).await,
+        Some(PaperAction::Reset) => return reset_paper_ledger(&config),
+        _ => {}
+    }
+    // ✅ 一括実行（--watchlist / -t A,B,…。株価・ニュースを並行取得し、最後に一覧表）
+    if !config.watchlist.is_empty() {
+        return run_batch(&config, &ticker_name_map).await;
+    }
+
     // ✅ 株価データ取得
     //let market_data_list = fetch_market_data(&ticker, &marketstack_key,&config).await?;
-    let market_data_list = fetch_market_data(&ticker).await?;
+    // バックテスト・最適化は長期の履歴で足ごとに再評価する（通常は直近3か月）
+    let range = if config.backtest || config.optimize {
+        config.bt_range.as_str()
+    } else {
+        "3mo"
+    };
+    let market_data_list = fetch_market_data(&ticker, range).await?;
 
     let mut sorted_data = market_data_list.clone();
     sorted_data.sort_by(|a, b| a.date.cmp(&b.date));
 
+    // ✅ 相対力が有効ならベンチマークも取得（失敗しても他の指標は継続）
+    let benchmark_data = if config
+        .enabled_extensions
+        .contains(&ExtensionIndicator::RelativeStrength)
+    {
+        match fetch_market_data(&config.benchmark, range).await {
+            Ok(mut data) => {
+                data.sort_by(|a, b| a.date.cmp(&b.date));
+                Some(data)
+            }
+            Err(e) => {
+                eprintln!("⚠️ ベンチマーク（{}）の取得に失敗しました: {}", config.benchmark, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // ✅ 最適化・バックテスト（指定時は足ごとの再評価と売買シミュレーションのみ行って終了）
+    if config.optimize {
+        return run_optimization(&config, &sorted_data, benchmark_data.as_deref(), &ticker_name_map);
+    }
+    if config.backtest {
+        return run_backtest(&config, &sorted_data, benchmark_data.as_deref(), &ticker_name_map);
+    }
+
+    // ✅ 指標の系列を全期間で一度だけ計算（今回の分析・平滑化・時系列出力で共用）
+    let series = IndicatorSeries::new(&config, &sorted_data, benchmark_data.as_deref())?;
+
     // ✅ 基本分析の構造体（セキュア）生成
     let mut guard = build_basic_technical_entry(
         &config,
+        &series,
         &sorted_data,
         &ticker_name_map,
         //fetched_company_name,
     )?;
 
-    // ✅ 拡張分析スコアを必要に応じて格納（セキュア）
-    if let Err(e) = evaluate_all_selected_extensions(&config, &sorted_data, &mut guard) {
-        eprintln!("❌ 拡張テクニカル指標の評価に失敗しました: {}", e);
-    }
+    // ✅ 相場局面・拡張分析・欠損スコア・平滑化・過去ログ比較
+    let base_config = config;
+    let config = complete_ticker_analysis(
+        &base_config,
+        &series,
+        &mut guard,
+        &sorted_data,
+        &ticker_name_map,
+    )?;
+
+    // ✅ 画面出力・ログ・時系列・ペーパートレード
+    emit_ticker_outputs(
+        &base_config,
+        &config,
+        &guard,
+        &series,
+        &sorted_data,
+        &ticker_name_map,
+    )?;
 
-    // ✅ 画面出力（構造体読み出しのみ）
-    select_output_target(&config, &guard)?;
     // 画面表示＋記事の取得
     let articles = news_flow_controller(&guard, &config).await?;
 
@@ -4634,3 +12633,667 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 環境変数・CLI指定なしの既定の設定
+    fn test_config() -> Config {
+        build_config(&Args::parse_from(["tickwise", "-t", "TEST"]))
+    }
+
+    /// 終値だけを指定した日足（始値・高値・安値も終値と同じ）
+    fn bars_from_closes(closes: &[f64]) -> Vec<MarketData> {
+        closes
+            .iter()
+            .enumerate()
+            .map(|(i, &close)| MarketData {
+                date: format!("2024-01-{:02}", i + 1),
+                datetime: None,
+                timestamp: None,
+                timezone: None,
+                currency: None,
+                open: close,
+                high: close,
+                low: close,
+                close,
+                name: None,
+            })
+            .collect()
+    }
+
+    /// NaN 同士も一致とみなして系列を比べる
+    fn assert_series(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len(), "{:?} != {:?}", actual, expected);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!(
+                (a.is_nan() && e.is_nan()) || (a - e).abs() < 1e-9,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    fn eval_src(src: &str, closes: &[f64]) -> Vec<f64> {
+        eval_expr(&parse_expr(src).unwrap(), &bars_from_closes(closes)).unwrap()
+    }
+
+    #[test]
+    fn expr_operator_precedence() {
+        // 1 + (2 * 3) = 7、比較は加減より弱く、&& は比較より弱い
+        assert_series(&eval_src("1 + 2 * 3", &[0.0]), &[7.0]);
+        assert_series(&eval_src("(1 + 2) * 3", &[0.0]), &[9.0]);
+        assert_series(&eval_src("10 - 4 - 3", &[0.0]), &[3.0]);
+        assert_series(&eval_src("1 + 2 * 3 > 6 && !(close < 0)", &[5.0]), &[1.0]);
+        assert_series(&eval_src("0 || 1 && 0", &[0.0]), &[0.0]);
+        assert_series(&eval_src("-close + 1", &[1.0, 2.0]), &[0.0, -1.0]);
+    }
+
+    #[test]
+    fn expr_is_case_and_whitespace_insensitive() {
+        assert_series(&eval_src("SMA(Close,\t2)\n* 2", &[1.0, 3.0]), &[f64::NAN, 4.0]);
+    }
+
+    #[test]
+    fn expr_window_functions() {
+        let closes = [1.0, 2.0, 3.0, 4.0];
+        assert_series(&eval_src("sma(close, 2)", &closes), &[f64::NAN, 1.5, 2.5, 3.5]);
+        assert_series(&eval_src("prev(close, 1)", &closes), &[f64::NAN, 1.0, 2.0, 3.0]);
+        // (3/1 - 1) * 100 = 200、(4/2 - 1) * 100 = 100
+        assert_series(&eval_src("roc(close, 2)", &closes), &[f64::NAN, f64::NAN, 200.0, 100.0]);
+        assert_series(&eval_src("highest(close, 3)", &closes), &[f64::NAN, f64::NAN, 3.0, 4.0]);
+        assert_series(&eval_src("lowest(close, 3)", &closes), &[f64::NAN, f64::NAN, 1.0, 2.0]);
+        assert_series(&eval_src("max(close, 2.5)", &closes), &[2.5, 2.5, 3.0, 4.0]);
+        assert_series(&eval_src("abs(2 - close)", &closes), &[1.0, 0.0, 1.0, 2.0]);
+        // 入れ子の関数は内側が算出できた足から数える
+        assert_series(&eval_src("sma(prev(close, 1), 2)", &closes), &[f64::NAN, f64::NAN, 1.5, 2.5]);
+    }
+
+    #[test]
+    fn expr_division_by_zero_is_nan() {
+        assert_series(&eval_src("close / (close - 2)", &[1.0, 2.0, 4.0]), &[-1.0, f64::NAN, 2.0]);
+    }
+
+    #[test]
+    fn expr_required_bars() {
+        assert_eq!(parse_expr("close").unwrap().required_bars(), 1);
+        assert_eq!(parse_expr("sma(close, 5)").unwrap().required_bars(), 5);
+        assert_eq!(parse_expr("rsi(close, 14)").unwrap().required_bars(), 15);
+        // prev で 3 本、その上の sma(…, 5) で 4 本増える
+        assert_eq!(parse_expr("sma(prev(close, 3), 5)").unwrap().required_bars(), 8);
+        assert_eq!(parse_expr("max(sma(close, 3), ema(close, 10))").unwrap().required_bars(), 10);
+    }
+
+    #[test]
+    fn expr_rejects_malformed_input() {
+        for src in [
+            "sma(close)",
+            "sma(close, 1.5)",
+            "sma(close, 0)",
+            "close and 1",
+            "close +",
+            "(close",
+            "close @ 1",
+            "volume",
+            "median(close, 3)",
+            "close close",
+        ] {
+            assert!(parse_expr(src).is_err(), "{} は受け付けないはず", src);
+        }
+    }
+
+    /// 基本シグナルの閾値を固定した設定（RSI 30/70、MACD差 low 0.1% / mid 0.5%）
+    fn signal_config() -> Config {
+        let mut config = test_config();
+        config.buy_rsi = 30.0;
+        config.sell_rsi = 70.0;
+        config.macd_diff_low = 0.1;
+        config.macd_diff_mid = 0.5;
+        config.macd_minus_ok = false;
+        config.signal_rules = default_signal_rules();
+        config
+    }
+
+    #[test]
+    fn default_signal_rules_pick_the_first_match() {
+        let config = signal_config();
+        // 終値 100 なので MACD差（%）は MACD とシグナルの差そのもの
+        let score = |rsi: f64, macd: f64, signal: f64| basic_signal_score(&config, rsi, macd, signal, 100.0).0;
+        assert_eq!(score(25.0, 1.0, 0.5), 2.0); // RSI割安・MACD上昇
+        assert_eq!(score(25.0, 0.5, 1.0), 1.0); // RSI割安・MACD下降
+        assert_eq!(score(75.0, 1.0, 0.5), -1.0); // RSI過熱・MACD上昇（差 0.5%）
+        assert_eq!(score(75.0, 5.0, 1.0), -2.0); // RSI過熱・MACD上昇（差 4% > 異常乖離 3%）
+        assert_eq!(score(75.0, 0.5, 1.0), -1.0); // RSI過熱・MACD下降
+        assert_eq!(score(50.0, 1.05, 1.0), 1.0); // 中立・上昇（差 0.05% < low）
+        assert_eq!(score(50.0, 1.3, 1.0), 2.0); // 中立・上昇（差 0.3% < mid）
+        assert_eq!(score(50.0, 2.0, 1.0), 2.0); // 中立・上昇（差 1%）
+        assert_eq!(score(50.0, 1.0, 1.05), 0.0); // 中立・下降（差 0.05% < low）
+        assert_eq!(score(50.0, 1.0, 1.3), -1.0); // 中立・下降（差 0.3%）
+        assert_eq!(score(50.0, 1.0, 1.0), 0.0); // MACD = シグナル
+    }
+
+    #[test]
+    fn signal_rules_gate_macd_below_zero() {
+        let mut config = signal_config();
+        // MACD<0 の上抜けは買い方向に数えない
+        let (score, codes) = basic_signal_score(&config, 25.0, -0.5, -1.0, 100.0);
+        assert_eq!(score, 0.0);
+        assert_eq!(codes, vec!["MACD_ABOVE_SIGNAL", "MACD_BELOW_ZERO"]);
+
+        config.macd_minus_ok = true;
+        let (score, codes) = basic_signal_score(&config, 25.0, -0.5, -1.0, 100.0);
+        assert_eq!(score, 2.0);
+        assert_eq!(codes, vec!["RSI_OVERSOLD", "MACD_ABOVE_SIGNAL"]);
+    }
+
+    #[test]
+    fn custom_signal_rules_use_their_own_thresholds_in_order() {
+        use MacdCondition as M;
+        use RsiCondition as R;
+        let mut config = signal_config();
+        config.signal_rules = vec![
+            SignalRule {
+                diff_above: Some(DiffThreshold::Pct(1.0)),
+                ..SignalRule::new(M::Any, R::Any, 1.5, "")
+            },
+            SignalRule {
+                diff_below: Some(DiffThreshold::Mid),
+                ..SignalRule::new(M::Down, R::Any, -0.5, "")
+            },
+        ];
+        assert_eq!(basic_signal_score(&config, 50.0, 3.0, 1.0, 100.0).0, 1.5); // 差 2% > 1%
+        assert_eq!(basic_signal_score(&config, 50.0, 1.0, 1.2, 100.0).0, -0.5); // 下降・差 0.2% < mid
+        assert_eq!(basic_signal_score(&config, 50.0, 1.2, 1.0, 100.0).0, 0.0); // どのルールにも一致しない
+    }
+
+    #[test]
+    fn signal_rule_file_is_validated() {
+        let path = std::env::temp_dir().join(format!("tickwise_rules_{}.json", std::process::id()));
+        let load = |json: &str| {
+            std::fs::write(&path, json).unwrap();
+            load_signal_rules(path.to_str().unwrap())
+        };
+        let rules = load(r#"[{"macd": "up", "rsi": "low", "diff_above": "mid", "score": 2}, {"score": 0}]"#).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].macd, MacdCondition::Up);
+        assert_eq!(rules[0].rsi, RsiCondition::Low);
+        assert_eq!(rules[0].diff_above, Some(DiffThreshold::Mid));
+        assert_eq!(rules[1].macd, MacdCondition::Any);
+        assert!(load(r#"[{"score": 3}]"#).is_err());
+        assert!(load(r#"[{"diff_below": "huge", "score": 1}]"#).is_err());
+        assert!(load(r#"[{"diff_below": -1, "score": 1}]"#).is_err());
+        assert!(load(r#"[{"rsi": "any", "score": 1, "extra": true}]"#).is_err());
+        assert!(load("[]").is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// 閾値ルール（買い ≥ +0.5 ／ 手仕舞い ≤ −0.5）・平滑化なしのバックテスト設定
+    fn backtest_config(fee_pct: f64, slippage_pct: f64) -> Config {
+        let mut config = test_config();
+        config.bt_rule = BacktestRule::Threshold;
+        config.bt_entry = 0.5;
+        config.bt_exit = -0.5;
+        config.bt_fee_pct = fee_pct;
+        config.bt_slippage_pct = slippage_pct;
+        config.smoothing_bars = 0;
+        config
+    }
+
+    fn backtest_bar(date: &str, open: f64, close: f64, score_ratio: f64) -> BacktestBar {
+        BacktestBar { date: date.to_string(), open, close, score_ratio }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn backtest_trades_on_the_next_open_with_fees() {
+        // 足0の買いシグナル → 足1の始値100で買い（手数料1%で 0.0099 単位）
+        // 足2の手仕舞いシグナル → 足3の始値120で売り（0.0099 × 120 × 0.99 = 1.17612）
+        let bars = [
+            backtest_bar("2020-01-01", 100.0, 100.0, 0.6),
+            backtest_bar("2021-01-01", 100.0, 110.0, 0.0),
+            backtest_bar("2022-01-01", 110.0, 99.0, -0.6),
+            backtest_bar("2024-01-01", 120.0, 120.0, 0.0),
+        ];
+        let report = simulate_backtest(&backtest_config(1.0, 0.0), &bars);
+
+        assert_eq!(report.trades.len(), 1);
+        let trade = &report.trades[0];
+        assert_eq!((trade.entry_date.as_str(), trade.exit_date.as_str()), ("2021-01-01", "2024-01-01"));
+        assert_close(trade.entry_price, 100.0);
+        assert_close(trade.exit_price, 120.0);
+        assert_close(trade.return_pct, 17.612);
+        assert_eq!(trade.bars_held, 2);
+        assert!(!trade.open_at_end);
+
+        let actions: Vec<&str> = report.curve.iter().map(|p| p.action).collect();
+        assert_eq!(actions, vec!["", "buy", "", "sell"]);
+        assert_close(report.curve[1].equity, 1.089);
+        assert_close(report.curve[2].equity, 0.9801);
+        assert_close(report.total_return_pct, 17.612);
+        // 高値 1.089 → 0.9801 で −10%
+        assert_close(report.max_drawdown_pct, -10.0);
+        assert_close(report.exposure_pct, 50.0);
+        assert_close(report.buy_hold_return_pct, 20.0);
+        // 2020-01-01〜2024-01-01 は 1461日 = 4年：1.17612^(1/4) − 1
+        assert_close(report.cagr_pct.unwrap(), (1.17612_f64.powf(0.25) - 1.0) * 100.0);
+        assert!((report.cagr_pct.unwrap() - 4.1389).abs() < 1e-3);
+    }
+
+    #[test]
+    fn backtest_applies_slippage_against_the_trade() {
+        // 買いは 100 × 1.01 = 101、売りは 120 × 0.99 = 118.8 で約定
+        let bars = [
+            backtest_bar("2024-01-01", 100.0, 100.0, 0.6),
+            backtest_bar("2024-01-02", 100.0, 110.0, -0.6),
+            backtest_bar("2024-01-03", 120.0, 120.0, 0.0),
+        ];
+        let report = simulate_backtest(&backtest_config(0.0, 1.0), &bars);
+        let trade = &report.trades[0];
+        assert_close(trade.entry_price, 101.0);
+        assert_close(trade.exit_price, 118.8);
+        assert_close(trade.return_pct, (118.8 / 101.0 - 1.0) * 100.0);
+        assert_close(report.total_return_pct, (118.8 / 101.0 - 1.0) * 100.0);
+    }
+
+    #[test]
+    fn backtest_values_a_position_still_open_at_the_end_at_the_last_close() {
+        let bars = [
+            backtest_bar("2024-01-01", 100.0, 100.0, 0.6),
+            backtest_bar("2024-01-02", 100.0, 110.0, 0.0),
+        ];
+        let report = simulate_backtest(&backtest_config(1.0, 0.0), &bars);
+        let trade = &report.trades[0];
+        assert!(trade.open_at_end);
+        assert_eq!(trade.bars_held, 0);
+        // 0.0099 × 110 × 0.99 = 1.07811（手仕舞いの手数料込み）
+        assert_close(trade.return_pct, 7.811);
+        // 資産曲線は手仕舞いの手数料を含めない時価 0.0099 × 110 = 1.089
+        assert_close(report.total_return_pct, 8.9);
+    }
+
+    #[test]
+    fn backtest_without_signals_stays_in_cash() {
+        let bars = [
+            backtest_bar("2024-01-01", 100.0, 100.0, 0.0),
+            backtest_bar("2024-01-02", 100.0, 50.0, 0.4),
+        ];
+        let report = simulate_backtest(&backtest_config(1.0, 1.0), &bars);
+        assert!(report.trades.is_empty());
+        assert_close(report.total_return_pct, 0.0);
+        assert_close(report.max_drawdown_pct, 0.0);
+        assert_close(report.exposure_pct, 0.0);
+        assert_close(report.buy_hold_return_pct, -50.0);
+    }
+
+    #[test]
+    fn bars_before_the_basic_and_sma_warm_up_are_not_evaluated() {
+        let mut config = test_config();
+        config.enabled_extensions = vec![ExtensionIndicator::Sma];
+        config.missing_score_policy = MissingScorePolicy::Exclude;
+        let closes: Vec<f64> = (0..40).map(|i| 100.0 + (i as f64 * 0.7).sin() * 5.0).collect();
+        let data = bars_from_closes(&closes);
+        let series = IndicatorSeries::new(&config, &data, None).unwrap();
+        let names = HashMap::new();
+        let evaluate = |end: usize| evaluate_bar(&config, &series, &data[..end], &names);
+
+        assert!(evaluate(2).is_err());
+        assert!(evaluate(SMA_LONG_MIN_BARS - 1).is_err());
+        assert!(evaluate(MACD_MIN_BARS - 1).is_err());
+        assert!(evaluate(MACD_MIN_BARS).is_ok());
+
+        // 準備期間の足は必要本数不足として数える（2〜34本目の 33本）
+        let (bars, skipped) = replay_bars(&config, &data, None, &names, |_, _| ()).unwrap();
+        assert_eq!(skipped, MACD_MIN_BARS - 2);
+        assert_eq!(bars.len(), data.len() - MACD_MIN_BARS + 1);
+    }
+
+    #[test]
+    fn walk_forward_windows_train_then_test_on_the_next_segment() {
+        // 100本・3区間 → 25本ずつ4等分
+        assert_eq!(
+            walk_forward_windows(100, 3),
+            vec![(0..25, 25..50), (25..50, 50..75), (50..75, 75..100)]
+        );
+        // 割り切れない端数は最後の検証期間に含める
+        assert_eq!(walk_forward_windows(103, 3).last(), Some(&(50..75, 75..103)));
+        assert_eq!(walk_forward_windows(10, 1), vec![(0..5, 5..10)]);
+    }
+
+    /// 資産曲線と各トレードの損益率だけを持つ成績
+    fn report_with(equities: &[f64], trade_returns: &[f64]) -> BacktestReport {
+        BacktestReport {
+            trades: trade_returns
+                .iter()
+                .map(|&return_pct| BacktestTrade {
+                    entry_date: String::new(),
+                    entry_price: 0.0,
+                    exit_date: String::new(),
+                    exit_price: 0.0,
+                    return_pct,
+                    bars_held: 1,
+                    open_at_end: false,
+                })
+                .collect(),
+            curve: equities
+                .iter()
+                .map(|&equity| EquityPoint {
+                    date: String::new(),
+                    close: 0.0,
+                    score_ratio: 0.0,
+                    action: "",
+                    in_position: false,
+                    equity,
+                    drawdown_pct: 0.0,
+                })
+                .collect(),
+            total_return_pct: 0.0,
+            cagr_pct: None,
+            max_drawdown_pct: 0.0,
+            buy_hold_return_pct: 0.0,
+            exposure_pct: 0.0,
+        }
+    }
+
+    #[test]
+    fn out_of_sample_summary_chains_the_test_periods() {
+        // 1区間目: 1.1 → 0.99（高値から −10%）、2区間目は 0.99 から始めて ×1.2 = 1.188
+        let reports = [report_with(&[1.1, 0.99], &[-1.0]), report_with(&[1.0, 1.2], &[20.0, 0.0])];
+        let summary = summarize_oos(reports.iter());
+        assert_close(summary.total_return_pct, 18.8);
+        assert_close(summary.max_drawdown_pct, -10.0);
+        assert_eq!((summary.trades, summary.wins), (3, 1));
+    }
+
+    #[test]
+    fn optimizer_objective_and_random_candidates() {
+        let mut config = test_config();
+        let report = BacktestReport { total_return_pct: 12.0, max_drawdown_pct: -4.0, ..report_with(&[], &[]) };
+        config.opt_metric = OptMetric::Return;
+        assert_close(opt_objective(&config, &report), 12.0);
+        config.opt_metric = OptMetric::ReturnDrawdown;
+        assert_close(opt_objective(&config, &report), 3.0);
+        // ドローダウンが 1% 未満なら分母は 1%
+        let shallow = BacktestReport { max_drawdown_pct: -0.2, ..report };
+        assert_close(opt_objective(&config, &shallow), 12.0);
+
+        // 同じシードなら同じ値、値は範囲内で刻みに乗る
+        let picks = |seed: u64| {
+            let mut rng = OptRng(seed);
+            (0..50).map(|_| rng.pick(OPT_WEIGHT_RANGE)).collect::<Vec<f64>>()
+        };
+        assert_eq!(picks(7), picks(7));
+        assert_ne!(picks(7), picks(8));
+        for v in picks(7) {
+            assert!((0.5..=3.0).contains(&v));
+            assert_close((v * 10.0).round(), v * 10.0);
+        }
+    }
+
+    #[test]
+    fn hit_rates_skip_the_warm_up_and_count_forward_returns() {
+        let mut config = test_config();
+        config.enabled_extensions = Vec::new();
+        // 一本調子の上昇（先読みリターンはすべてプラス）
+        let closes: Vec<f64> = (0..60).map(|i| 100.0 + i as f64).collect();
+        let data = bars_from_closes(&closes);
+        let (table, evaluated, warmup) = collect_hit_rates(&config, &data, None, &HashMap::new()).unwrap();
+
+        // 2〜34本目は準備期間、35〜60本目の 26本を評価
+        assert_eq!(warmup, MACD_MIN_BARS - 2);
+        assert_eq!(evaluated, data.len() - MACD_MIN_BARS + 1);
+        assert_eq!(table.len(), 1);
+        assert_eq!(table[0].indicator, "Basic");
+
+        let levels = &table[0].levels;
+        assert_eq!(levels.iter().map(|l| l.bars).sum::<usize>(), evaluated);
+        // 1・5・20本先まで測れる足の数（末尾の足は先のデータがない）
+        for (h, horizon) in HIT_RATE_HORIZONS.iter().enumerate() {
+            assert_eq!(levels.iter().map(|l| l.samples[h]).sum::<usize>(), evaluated - horizon);
+        }
+        for (i, stats) in levels.iter().enumerate() {
+            let expected_hits = if i < 2 { [0; 3] } else { stats.samples };
+            assert_eq!(stats.hits, expected_hits);
+        }
+    }
+
+    #[test]
+    fn hit_rate_tables_merge_by_indicator() {
+        let stats = |bars: usize, ret: f64, hits: usize| HitRateStats {
+            bars,
+            samples: [bars; 3],
+            return_sum: [ret; 3],
+            hits: [hits; 3],
+        };
+        let row = |indicator: &str, level: usize, s: HitRateStats| {
+            let mut levels = [HitRateStats::default(); 5];
+            levels[level] = s;
+            IndicatorHitRate { indicator: indicator.to_string(), levels }
+        };
+        let mut merged = vec![row("Basic", 4, stats(2, 3.0, 1))];
+        merge_hit_rates(&mut merged, &[row("EMA", 0, stats(1, -1.0, 1)), row("Basic", 4, stats(3, 1.5, 2))]);
+
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].indicator, "Basic");
+        let basic = merged[0].levels[4];
+        assert_eq!((basic.bars, basic.samples[0], basic.hits[0]), (5, 5, 3));
+        assert_close(basic.return_sum[0], 4.5);
+        assert_eq!(merged[1].indicator, "EMA");
+        assert_eq!(merged[1].levels[0].bars, 1);
+    }
+
+    fn logged_run(date: &str, score_ratio: Option<f64>, judgement: Option<(&str, Option<bool>)>) -> ScoreHistoryRecord {
+        ScoreHistoryRecord {
+            date: date.to_string(),
+            final_score: 0.0,
+            score_ratio,
+            stance: Some(Stance::Buyer),
+            judgement: judgement.map(|(text, direction)| (text.to_string(), direction)),
+            indicator_scores: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn logged_calls_are_graded_against_later_closes() {
+        let config = test_config();
+        // 2024-01-01〜31 の終値 100, 101, …, 130
+        let closes: Vec<f64> = (0..31).map(|i| 100.0 + i as f64).collect();
+        let data = bars_from_closes(&closes);
+        let records = [
+            logged_run("2023-12-31", Some(0.5), Some(("early", Some(true)))), // 価格データより前
+            logged_run("2024-01-03", Some(0.5), Some(("first", Some(true)))),
+            logged_run("2024-01-03 15:00", Some(-0.5), Some(("🔴 last", Some(false)))), // 同じ日・スタンスは後の実行を採点
+            logged_run("2024-01-15", Some(0.1), Some(("neutral", None))),
+            logged_run("2024-01-20", None, None), // score_ratio のない古い行
+        ];
+        let (calls, without_ratio) = grade_logged_calls(&config, &records, &data);
+
+        assert_eq!(without_ratio, 1);
+        assert_eq!(calls.len(), 2);
+        let first = &calls[0];
+        assert_eq!((first.date.as_str(), first.judgement.as_str()), ("2024-01-03", "🔴 last"));
+        assert_close(first.close, 102.0);
+        // 5本後は 107、20本後は 122
+        assert_close(first.returns[0].unwrap(), (107.0 / 102.0 - 1.0) * 100.0);
+        assert_close(first.returns[1].unwrap(), (122.0 / 102.0 - 1.0) * 100.0);
+        assert_eq!((first.hit(0), first.hit(1)), (Some(false), Some(false)));
+
+        // 20本後はまだ来ていない。中立の判定は採点しない
+        let neutral = &calls[1];
+        assert_close(neutral.returns[0].unwrap(), (119.0 / 114.0 - 1.0) * 100.0);
+        assert_eq!(neutral.returns[1], None);
+        assert_eq!((neutral.hit(0), neutral.hit(1)), (None, None));
+
+        let mut stats = OutcomeStats::default();
+        for call in &calls {
+            stats.add(call);
+        }
+        assert_eq!(stats.calls, 2);
+        assert_eq!(stats.graded, [1, 1]);
+        assert_eq!(stats.hits, [0, 0]);
+        assert_eq!(stats.returns, [2, 1]);
+        assert_close(stats.return_sum[1], (122.0 / 102.0 - 1.0) * 100.0);
+    }
+
+    #[test]
+    fn old_logged_rows_reconstruct_the_judgement_from_the_ratio() {
+        let config = test_config();
+        let data = bars_from_closes(&[100.0; 10]);
+        let mut record = logged_run("2024-01-02", Some(0.9), None);
+        record.stance = None;
+        let (calls, _) = grade_logged_calls(&config, &[record], &data);
+        assert_eq!(calls[0].judgement, stance_judgement(&config.stance, 0.9));
+        assert_eq!(calls[0].direction, stance_direction(&config.stance, 0.9));
+        assert!(!calls[0].stance_logged);
+        assert_close(calls[0].returns[0].unwrap(), 0.0);
+    }
+
+    /// 指定した終値・通貨の足を1本だけ持つガード
+    fn quote(ticker: &str, close: f64, currency: &str) -> TechnicalDataGuard {
+        let mut guard = TechnicalDataGuard::new(ticker.to_string(), "2024-01-05".to_string());
+        guard.set_close(close);
+        guard.set_currency(currency);
+        guard
+    }
+
+    fn paper_config(fee_pct: f64, slippage_pct: f64) -> Config {
+        let mut config = test_config();
+        config.bt_fee_pct = fee_pct;
+        config.bt_slippage_pct = slippage_pct;
+        config.paper_size_pct = 10.0;
+        config
+    }
+
+    #[test]
+    fn paper_ledger_averages_cost_and_realizes_pnl_net_of_fees() {
+        let config = paper_config(1.0, 0.0);
+        let mut ledger = PaperLedger::new(10_000.0);
+
+        // 10株 @100（手数料 10）→ 取得単価 101
+        ledger.buy(&config, &quote("AAA", 100.0, "USD"), Some(10), "test").unwrap();
+        assert_close(ledger.cash, 8_990.0);
+        assert_close(ledger.position("AAA").unwrap().cost, 101.0);
+
+        // 10株 @120（手数料 12）→ (1010 + 1212) / 20 = 111.1
+        ledger.buy(&config, &quote("AAA", 120.0, "USD"), Some(10), "test").unwrap();
+        let position = ledger.position("AAA").unwrap();
+        assert_eq!(position.units, 20);
+        assert_close(position.cost, 111.1);
+        assert_close(ledger.cash, 7_778.0);
+
+        // 5株 @130：650 − 手数料 6.5 − 111.1 × 5 = 88
+        ledger.sell(&config, &quote("AAA", 130.0, "USD"), Some(5), "test").unwrap();
+        assert_close(ledger.trades[2].realized_pnl.unwrap(), 88.0);
+        assert_eq!(ledger.position("AAA").unwrap().units, 15);
+        assert_close(ledger.position("AAA").unwrap().cost, 111.1);
+        assert_close(ledger.cash, 8_421.5);
+
+        // 残り15株 @100：1500 − 15 − 111.1 × 15 = −181.5。建玉はなくなる
+        ledger.sell(&config, &quote("AAA", 100.0, "USD"), None, "test").unwrap();
+        assert_close(ledger.trades[3].realized_pnl.unwrap(), -181.5);
+        assert!(ledger.position("AAA").is_none());
+        assert_close(ledger.cash, 9_906.5);
+        // 実現損益の合計は現金の増減と一致する
+        assert_close(ledger.realized_pnl(), -93.5);
+    }
+
+    #[test]
+    fn paper_ledger_sizes_orders_and_applies_slippage() {
+        let config = paper_config(1.0, 1.0);
+        let mut ledger = PaperLedger::new(10_000.0);
+        // 初期資金の10% = 1000 を上限に、101 × 1.01 = 102.01 で割って 9株
+        ledger.buy(&config, &quote("AAA", 100.0, "USD"), None, "test").unwrap();
+        let trade = &ledger.trades[0];
+        assert_eq!(trade.units, 9);
+        assert_close(trade.price, 101.0);
+        assert_close(trade.fee, 9.09);
+
+        ledger.sell(&config, &quote("AAA", 100.0, "USD"), Some(100), "test").unwrap();
+        let trade = &ledger.trades[1];
+        assert_eq!(trade.units, 9);
+        assert_close(trade.price, 99.0);
+        // 891 − 8.91 − (909 + 9.09) = −36
+        assert_close(trade.realized_pnl.unwrap(), -36.0);
+    }
+
+    #[test]
+    fn paper_ledger_refuses_unaffordable_foreign_currency_and_missing_positions() {
+        let config = paper_config(1.0, 0.0);
+        let mut ledger = PaperLedger::new(10_000.0);
+        assert!(ledger.buy(&config, &quote("AAA", 100.0, "USD"), Some(100), "test").is_err());
+        assert!(ledger.sell(&config, &quote("AAA", 100.0, "USD"), None, "test").is_err());
+
+        ledger.buy(&config, &quote("AAA", 100.0, "USD"), Some(1), "test").unwrap();
+        assert_eq!(ledger.currency.as_deref(), Some("USD"));
+        assert!(ledger.buy(&config, &quote("7203.T", 100.0, "JPY"), Some(1), "test").is_err());
+        assert_eq!(ledger.trades.len(), 1);
+        assert_close(ledger.cash, 9_899.0);
+    }
+
+    fn test_plugin(script: &str) -> PluginSpec {
+        PluginSpec {
+            name: "test".to_string(),
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            timeout_ms: 2_000,
+            weight: 1.0,
+            params: Value::Null,
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn plugin_output_over_the_cap_is_reported_as_too_large() {
+        let spec = test_plugin("head -c 2000000 /dev/zero");
+        let err = run_plugin(&spec, &test_config(), &[]).unwrap_err();
+        assert!(err.starts_with("出力が大きすぎます"), "{}", err);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn plugin_whose_grandchild_keeps_stdout_open_times_out_instead_of_hanging() {
+        let mut spec = test_plugin("sleep 10 & echo '{\"score\": 1}'");
+        spec.timeout_ms = 300;
+        let started = std::time::Instant::now();
+        let err = run_plugin(&spec, &test_config(), &[]).unwrap_err();
+        assert!(err.starts_with("タイムアウト"), "{}", err);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn plugin_value_keys_outside_the_name_characters_are_rejected() {
+        let spec = test_plugin("cat > /dev/null; echo '{\"score\": 1, \"values\": {\"a,b\": 1}}'");
+        let err = run_plugin(&spec, &test_config(), &[]).unwrap_err();
+        assert!(err.starts_with("values のキーは英数字・_・- のみ使用できます"), "{}", err);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn plugin_json_output_is_parsed() {
+        let spec = test_plugin("cat > /dev/null; echo '{\"score\": 1.5, \"values\": {\"band_width-20\": 2}}'");
+        let output = run_plugin(&spec, &test_config(), &[]).unwrap();
+        assert_eq!(output.score, 1.5);
+        assert_eq!(output.values, vec![("band_width-20".to_string(), 2.0)]);
+    }
+
+    #[test]
+    fn preset_weight_keys_are_stored_canonical() {
+        let presets = parse_presets(r#"[{"name": "p", "weights": {"SR": 2.0, "EMA": 1.5, "Basic": 0.5}}]"#).unwrap();
+        let weights = &presets[0].weights;
+        assert_eq!(weights.get("support_resistance"), Some(&2.0));
+        assert_eq!(weights.get("ema"), Some(&1.5));
+        assert_eq!(weights.get("basic"), Some(&0.5));
+        assert_eq!(weights.len(), 3);
+    }
+
+    #[test]
+    fn preset_weight_aliases_of_the_same_indicator_are_rejected() {
+        let result = parse_presets(r#"[{"name": "p", "weights": {"rs": 2.0, "relative_strength": 1.0}}]"#);
+        assert!(result.is_err());
+    }
+}